@@ -0,0 +1,283 @@
+use std::process;
+
+use colored::*;
+
+use crate::git::config::Config;
+use crate::git::head::{Head, HeadTarget};
+use crate::git::object::commit::Commit;
+use crate::git::refs;
+use crate::git::revision;
+
+struct ListedBranch {
+    name: String,
+    ref_name: String,
+    hash: String,
+    current: bool,
+    remote: bool,
+}
+
+fn branch_ref(name: &str) -> String {
+    format!("refs/heads/{}", name)
+}
+
+fn current_branch() -> Option<String> {
+    let (name, _) = refs::resolve_ref("HEAD")?;
+    name.strip_prefix("refs/heads/").map(|branch| branch.to_string())
+}
+
+fn resolve_start_point(rev: &str) -> String {
+    match revision::resolve(rev).and_then(|hash| revision::peel(&hash, "commit")) {
+        Some(hash) => hash,
+        None => {
+            println!("fatal: not a valid object name: '{}'", rev);
+            process::exit(128);
+        }
+    }
+}
+
+fn check_branch_name(name: &str) {
+    if !refs::is_valid_ref_name(&branch_ref(name)) || name == "HEAD" {
+        println!("fatal: '{}' is not a valid branch name", name);
+        process::exit(128);
+    }
+}
+
+fn first_line(message: &str) -> &str {
+    message.lines().next().unwrap_or("")
+}
+
+/// The `[upstream: ahead N, behind M]` part of verbose listings, with the upstream name
+/// only shown for `-vv`.
+fn tracking_info(branch: &str, hash: &str, show_name: bool) -> Option<String> {
+    let upstream = revision::upstream(branch)?;
+    let short_name = upstream.strip_prefix("refs/heads/").or(upstream.strip_prefix("refs/remotes/")).unwrap_or(&upstream).to_string();
+    let Some(upstream_hash) = refs::read_ref(&upstream) else {
+        return Some(if show_name { format!("[{}: gone]", short_name.blue()) } else { "[gone]".to_string() });
+    };
+    let (ahead, behind) = revision::ahead_behind(hash, &upstream_hash);
+    let mut counts = Vec::new();
+    if ahead > 0 {
+        counts.push(format!("ahead {}", ahead));
+    }
+    if behind > 0 {
+        counts.push(format!("behind {}", behind));
+    }
+    match (show_name, counts.is_empty()) {
+        (true, true) => Some(format!("[{}]", short_name.blue())),
+        (true, false) => Some(format!("[{}: {}]", short_name.blue(), counts.join(", "))),
+        (false, true) => None,
+        (false, false) => Some(format!("[{}]", counts.join(", "))),
+    }
+}
+
+fn list_branches(verbose: usize, local: bool, remote: bool, filter: impl Fn(&str) -> bool) {
+    let current = current_branch();
+    let mut branches = Vec::new();
+    let head = Head::from_ref("HEAD");
+    if local {
+        if let Some(head) = head.as_ref().filter(|head| head.target == HeadTarget::Detached) {
+            branches.push(ListedBranch {
+                name: format!("(HEAD detached at {})", head.abbreviated_hash()),
+                ref_name: "HEAD".to_string(),
+                hash: head.head_hash.clone(),
+                current: true,
+                remote: false,
+            });
+        }
+        for (ref_name, hash) in refs::list_refs("refs/heads/") {
+            let name = ref_name["refs/heads/".len()..].to_string();
+            let current = current.as_deref() == Some(name.as_str());
+            branches.push(ListedBranch { name, ref_name, hash, current, remote: false });
+        }
+    }
+    if remote {
+        for (ref_name, hash) in refs::list_refs("refs/remotes/") {
+            let name = if local { ref_name["refs/".len()..].to_string() } else { ref_name["refs/remotes/".len()..].to_string() };
+            branches.push(ListedBranch { name, ref_name, hash, current: false, remote: true });
+        }
+    }
+    branches.retain(|branch| filter(&branch.hash));
+    let width = branches.iter().map(|branch| branch.name.len()).max().unwrap_or(0);
+    for branch in branches {
+        let marker = if branch.current { "*" } else { " " };
+        let padded = if verbose > 0 { format!("{:<width$}", branch.name, width = width) } else { branch.name.clone() };
+        let name = if branch.current {
+            padded.green()
+        } else if branch.remote {
+            padded.red()
+        } else {
+            padded.normal()
+        };
+        if verbose == 0 {
+            println!("{} {}", marker, name);
+            continue;
+        }
+        let commit = Commit::from_hash(&branch.hash);
+        let tracking = match branch.ref_name.strip_prefix("refs/heads/") {
+            Some(local_name) => tracking_info(local_name, &branch.hash, verbose > 1),
+            None => None,
+        };
+        match tracking {
+            Some(tracking) => println!("{} {} {} {} {}", marker, name, &branch.hash[..7], tracking, first_line(&commit.message())),
+            None => println!("{} {} {} {}", marker, name, &branch.hash[..7], first_line(&commit.message())),
+        }
+    }
+}
+
+fn create_branch(name: &str, start_point: &str, force: bool) {
+    check_branch_name(name);
+    if refs::read_ref(&branch_ref(name)).is_some() {
+        if !force {
+            println!("fatal: a branch named '{}' already exists", name);
+            process::exit(128);
+        }
+        if current_branch().as_deref() == Some(name) {
+            println!("fatal: cannot force update the current branch.");
+            process::exit(128);
+        }
+    }
+    let message = format!("branch: Created from {}", start_point);
+    refs::write_ref(&branch_ref(name), &resolve_start_point(start_point), &message);
+}
+
+fn delete_branch(name: &str, force: bool) -> bool {
+    let Some(hash) = refs::read_ref(&branch_ref(name)) else {
+        println!("error: branch '{}' not found.", name);
+        return false;
+    };
+    if current_branch().as_deref() == Some(name) {
+        println!("error: Cannot delete branch '{}' checked out at '{}'", name, std::env::current_dir().unwrap().display());
+        return false;
+    }
+    if !force {
+        // A branch with an upstream only has to be merged there, otherwise into HEAD
+        let target = revision::upstream(name).and_then(|upstream| refs::read_ref(&upstream)).or(refs::read_ref("HEAD"));
+        if !target.is_some_and(|target| revision::is_ancestor(&hash, &target)) {
+            println!("error: The branch '{}' is not fully merged.", name);
+            println!("If you are sure you want to delete it, run 'git branch -D {}'.", name);
+            return false;
+        }
+    }
+    refs::delete_ref(&branch_ref(name));
+    Config::rename_section(&format!("branch.{}", name), None);
+    println!("Deleted branch {} (was {}).", name, &hash[..7]);
+    true
+}
+
+fn rename_branch(old: &str, new: &str, force: bool) {
+    if refs::read_ref(&branch_ref(old)).is_none() {
+        println!("error: refname refs/heads/{} not found", old);
+        println!("fatal: branch rename failed");
+        process::exit(128);
+    }
+    check_branch_name(new);
+    if old != new && refs::read_ref(&branch_ref(new)).is_some() && !force {
+        println!("fatal: a branch named '{}' already exists", new);
+        process::exit(128);
+    }
+    let message = format!("Branch: renamed {} to {}", branch_ref(old), branch_ref(new));
+    if let Err(error) = refs::rename_ref(&branch_ref(old), &branch_ref(new), &message) {
+        println!("error: {}", error);
+        println!("fatal: Branch rename failed");
+        process::exit(128);
+    }
+    Config::rename_section(&format!("branch.{}", old), Some(&format!("branch.{}", new)));
+}
+
+pub fn branch(args: &Vec<String>) {
+    let mut verbose = 0;
+    let mut delete = None;
+    let mut rename = None;
+    let mut local = true;
+    let mut remote = false;
+    let mut contains = None;
+    let mut merged = None;
+    let mut no_merged = None;
+    let mut force = false;
+    let mut names = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        // Takes the commit after a filter option, which defaults to HEAD
+        let mut filter_commit = || match args.get(i + 1).filter(|next| !next.starts_with('-')) {
+            Some(next) => {
+                i += 1;
+                resolve_start_point(next)
+            }
+            None => resolve_start_point("HEAD"),
+        };
+        match arg {
+            "-v" | "--verbose" => verbose += 1,
+            "-vv" => verbose += 2,
+            "-d" | "--delete" => delete = Some(false),
+            "-D" => delete = Some(true),
+            "-m" | "--move" => rename = Some(false),
+            "-M" => rename = Some(true),
+            "-f" | "--force" => force = true,
+            "-r" | "--remotes" => (local, remote) = (false, true),
+            "-a" | "--all" => (local, remote) = (true, true),
+            "--contains" => contains = Some(filter_commit()),
+            "--merged" => merged = Some(filter_commit()),
+            "--no-merged" => no_merged = Some(filter_commit()),
+            _ => match arg.split_once('=') {
+                Some(("--contains", rev)) => contains = Some(resolve_start_point(rev)),
+                Some(("--merged", rev)) => merged = Some(resolve_start_point(rev)),
+                Some(("--no-merged", rev)) => no_merged = Some(resolve_start_point(rev)),
+                _ if arg.starts_with('-') => {
+                    println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                    process::exit(129);
+                }
+                _ => names.push(arg.to_string()),
+            },
+        }
+        i += 1;
+    }
+
+    if let Some(delete_force) = delete {
+        if names.is_empty() {
+            println!("fatal: branch name required");
+            process::exit(128);
+        }
+        let mut failed = false;
+        for name in names.iter() {
+            failed |= !delete_branch(name, delete_force || force);
+        }
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(rename_force) = rename {
+        match names.as_slice() {
+            [new] => match current_branch() {
+                Some(old) => rename_branch(&old, new, rename_force || force),
+                None => {
+                    println!("fatal: cannot rename the current branch while not on any.");
+                    process::exit(128);
+                }
+            },
+            [old, new] => rename_branch(old, new, rename_force || force),
+            _ => {
+                println!("fatal: too many arguments for a rename operation");
+                process::exit(128);
+            }
+        }
+        return;
+    }
+    let filtering = contains.is_some() || merged.is_some() || no_merged.is_some();
+    match names.as_slice() {
+        [] => {}
+        _ if filtering => {}
+        [name] => return create_branch(name, "HEAD", force),
+        [name, start_point] => return create_branch(name, start_point, force),
+        _ => {
+            println!("fatal: too many arguments to create a branch");
+            process::exit(128);
+        }
+    }
+    list_branches(verbose, local, remote, |hash| {
+        contains.as_ref().is_none_or(|commit| revision::is_ancestor(commit, hash))
+            && merged.as_ref().is_none_or(|commit| revision::is_ancestor(hash, commit))
+            && no_merged.as_ref().is_none_or(|commit| !revision::is_ancestor(hash, commit))
+    });
+}
@@ -1,18 +1,61 @@
 use std::process;
 
 use crate::git::head;
+use crate::git::hook;
+use crate::git::index::Index;
 use crate::git::object::commit;
 use crate::git::object::tree;
+use crate::git::refs;
+use crate::git::revision;
+use crate::git::sparse::SparseCheckout;
 
 pub fn checkout(args: &Vec<String>) {
-    if args.len() != 3 {
-        println!("Usage: {} checkout <branch>", args[0]);
+    let detach = args.iter().any(|arg| arg == "--detach");
+    let mut targets: Vec<&str> = args[2..].iter().map(|arg| arg.as_str()).filter(|arg| *arg != "--detach").collect();
+    // `--detach` on its own detaches at the current commit
+    if detach && targets.is_empty() {
+        targets.push("HEAD");
+    }
+    if targets.len() != 1 {
+        println!("Usage: {} checkout [--detach] <branch | commit>", args[0]);
         process::exit(1);
     }
-    let branch = &args[2];
-    let head = head::Head::from_branch(&branch);
-    let commit = commit::Commit::from_hash(&head.head_hash);
+    let target = targets[0];
+    // `-` and `@{-<n>}` switch back to the branch itself, not just its commit
+    let branch = match revision::resolve_ref_name(target) {
+        Some(name) if target != "HEAD" => name.strip_prefix("refs/heads/").map(|branch| branch.to_string()),
+        _ => None,
+    };
+    let commit_hash = match &branch {
+        Some(branch) => head::Head::from_branch(branch).head_hash,
+        None => match revision::resolve(target).and_then(|hash| revision::peel(&hash, "commit")) {
+            Some(hash) => hash,
+            None => {
+                println!("error: pathspec '{}' did not match any file(s) known to git", target);
+                process::exit(1);
+            }
+        },
+    };
+    let old_hash = refs::resolve_ref("HEAD").and_then(|(_, hash)| hash).unwrap_or("0".repeat(40));
+    let commit = commit::Commit::from_hash(&commit_hash);
     let tree = tree::Tree::from_hash(&commit.tree);
-    tree.sync_tree_to_dir(".");
-    head::Head::update_head_to_branch(&branch);
+    let sparse = SparseCheckout::from_file();
+    // The index is locked before the worktree changes, so that a held lock stops the checkout
+    Index::lock_or_exit();
+    let tracked = Index::read_index().entries.into_iter().map(|entry| entry.name).collect();
+    tree.sync_tree_to_dir(".", sparse.as_ref(), &tracked);
+    // The index follows the worktree to the new tree, so that the next commit starts from it
+    Index::from_tree(&commit.tree, sparse.as_ref()).write_locked_index();
+    match branch {
+        Some(branch) if !detach => head::Head::update_head_to_branch(&branch),
+        _ => {
+            head::Head::update_head_to_commit(&commit_hash, target);
+            println!("HEAD is now at {}", &commit_hash[..7]);
+        }
+    }
+    // The hook can't undo the checkout, but its status becomes ours; `1` says branches
+    // were switched rather than files checked out
+    if !hook::run("post-checkout", &[&old_hash, &commit_hash, "1"], &[], None) {
+        process::exit(1);
+    }
 }
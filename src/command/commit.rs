@@ -0,0 +1,387 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process;
+
+use crate::git::editor;
+use crate::git::hook;
+use crate::git::ident::{self, Role};
+use crate::git::index::{Index, IndexEntry, INDEX_LOCK_PATH};
+use crate::git::object::commit::Commit;
+use crate::git::object::objectwriter::ObjectWriter;
+use crate::git::ref_transaction::{RefAction, RefTransaction, RefUpdate};
+use crate::git::reflog;
+use crate::git::refs;
+use crate::git::signing;
+use crate::git::worktree;
+
+const EDITMSG_PATH: &str = ".git/COMMIT_EDITMSG";
+const INDEX_PATH: &str = ".git/index";
+
+/// Cleans up a message as git does by default: trailing whitespace goes, runs of blank
+/// lines collapse into one and leading and trailing blank lines are dropped. Messages from
+/// the editor also lose their `#` comment lines.
+fn cleanup_message(message: &str, strip_comments: bool) -> String {
+    let mut cleaned = String::new();
+    let mut pending_blank = false;
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push('\n');
+            pending_blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// Splits clusters of short options such as `-am msg` or `-mmsg` into separate arguments.
+fn expand_short_options(args: &[String]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    let mut takes_value = false;
+    for arg in args {
+        if takes_value || !arg.starts_with('-') || arg.starts_with("--") || arg.len() <= 2 {
+            takes_value = !takes_value && (arg == "-m" || arg == "-F");
+            expanded.push(arg.to_string());
+            continue;
+        }
+        for (i, c) in arg.char_indices().skip(1) {
+            // `-S` takes its key only when it's attached
+            if c == 'S' {
+                expanded.push(format!("-{}", &arg[i..]));
+                break;
+            }
+            expanded.push(format!("-{}", c));
+            if c == 'm' || c == 'F' {
+                let value = &arg[i + 1..];
+                if value.is_empty() {
+                    takes_value = true;
+                } else {
+                    expanded.push(value.to_string());
+                }
+                break;
+            }
+        }
+    }
+    expanded
+}
+
+/// Updates the index to the worktree version of every tracked file, as `commit -a` does,
+/// and drops the entries of files that are gone.
+fn stage_tracked_changes(index: &mut Index) {
+    let tracked: Vec<(String, u32, String)> = index.entries.iter()
+        .filter(|entry| !entry.is_skip_worktree() && entry.mode != 0o160000)
+        .map(|entry| (entry.name.clone(), entry.mode, entry.sha1.clone()))
+        .collect();
+    for (path, mode, hash) in tracked {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            _ => {
+                index.remove_entry(&path);
+                continue;
+            }
+        };
+        if worktree::file_matches(&path, mode, &hash) {
+            continue;
+        }
+        let (mode, content) = if metadata.file_type().is_symlink() {
+            (0o120000, fs::read_link(&path).unwrap().to_string_lossy().as_bytes().to_vec())
+        } else {
+            let mode = if metadata.permissions().mode() & 0o111 != 0 { 0o100755 } else { 0o100644 };
+            (mode, fs::read(&path).unwrap())
+        };
+        let mut entry = IndexEntry::from_object(&path, mode, &ObjectWriter::write("blob", &content));
+        entry.refresh_stat();
+        index.add_entry(entry);
+    }
+}
+
+/// Writes the message where the hooks and the editor expect it, followed by git's
+/// instructions as comments when it's going to be edited.
+fn write_editmsg(message: &str, use_editor: bool, branch: &str) {
+    let message = cleanup_message(message, false);
+    let content = if use_editor {
+        format!(
+            "{}\n# Please enter the commit message for your changes. Lines starting\n\
+             # with '#' will be ignored, and an empty message aborts the commit.\n#\n# {}\n#\n",
+            message, branch
+        )
+    } else {
+        message
+    };
+    fs::write(EDITMSG_PATH, content).unwrap();
+}
+
+/// Has the user write the message in the editor.
+fn launch_editor() {
+    if let Err(editor) = editor::edit(EDITMSG_PATH) {
+        println!("error: There was a problem with the editor '{}'.", editor);
+        println!("Please supply the message using either -m or -F option.");
+        unlock_and_exit(1);
+    }
+}
+
+/// Releases the index lock and exits, for every exit while the commit holds the lock.
+fn unlock_and_exit(code: i32) -> ! {
+    Index::unlock();
+    process::exit(code)
+}
+
+/// Runs a hook about the commit being prepared, with `GIT_INDEX_FILE` naming the index
+/// that's going to be committed. While the index is `locked`, that index is written to the
+/// lock file for the hook, as git does with `-a`, and read back in case the hook changed it.
+fn run_commit_hook(name: &str, args: &[&str], index: &mut Index, locked: bool, use_editor: bool) -> bool {
+    // The runner still points out a hook that isn't executable
+    if !hook::exists(name) {
+        return hook::run(name, args, &[], None);
+    }
+    let index_file = if locked { INDEX_LOCK_PATH } else { INDEX_PATH };
+    let mut env = vec![("GIT_INDEX_FILE", index_file)];
+    if !use_editor {
+        env.push(("GIT_EDITOR", ":"));
+    }
+    if locked {
+        index.write_index_to(INDEX_LOCK_PATH);
+    }
+    let success = hook::run(name, args, &env, None);
+    *index = Index::read_index_from(index_file);
+    success
+}
+
+/// Explains why there is nothing to commit, after the branch line as `status` prints it.
+fn nothing_to_commit(index: &Index, branch: &str, initial: bool, all: bool) -> ! {
+    println!("{}", branch);
+    if initial {
+        println!();
+        println!("No commits yet");
+        println!();
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+        unlock_and_exit(1);
+    }
+    let unstaged = index.entries.iter().any(|entry| !entry.is_skip_worktree() && !worktree::file_matches(&entry.name, entry.mode, &entry.sha1));
+    if unstaged && !all {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+    unlock_and_exit(1);
+}
+
+pub fn commit(args: &Vec<String>) {
+    let mut all = false;
+    let mut amend = false;
+    let mut allow_empty = false;
+    let mut quiet = false;
+    let mut no_edit = false;
+    let mut no_verify = false;
+    let mut sign = signing::sign_by_default("commit.gpgSign");
+    let mut signing_key = None;
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut file = None;
+    let options = expand_short_options(&args[2..]);
+    let mut i = 0;
+    while i < options.len() {
+        let arg = options[i].as_str();
+        match arg {
+            "-a" | "--all" => all = true,
+            "--amend" => amend = true,
+            "--allow-empty" => allow_empty = true,
+            "-q" | "--quiet" => quiet = true,
+            "--no-edit" => no_edit = true,
+            "-n" | "--no-verify" => no_verify = true,
+            "-S" | "--gpg-sign" => sign = true,
+            "--no-gpg-sign" => sign = false,
+            "-m" | "--message" | "-F" | "--file" => {
+                i += 1;
+                let Some(value) = options.get(i) else {
+                    println!("error: switch `{}' requires a value", arg.trim_start_matches('-'));
+                    process::exit(129);
+                };
+                if arg == "-m" || arg == "--message" {
+                    paragraphs.push(value.to_string());
+                } else {
+                    file = Some(value.to_string());
+                }
+            }
+            _ => {
+                if let Some(value) = arg.strip_prefix("--message=") {
+                    paragraphs.push(value.to_string());
+                } else if let Some(value) = arg.strip_prefix("--file=") {
+                    file = Some(value.to_string());
+                } else if let Some(key) = arg.strip_prefix("--gpg-sign=").or_else(|| arg.strip_prefix("-S")) {
+                    sign = true;
+                    signing_key = Some(key.to_string());
+                } else if arg.starts_with('-') {
+                    println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                    process::exit(129);
+                } else {
+                    println!("usage: {} commit [-a] [-q] [--amend] [--allow-empty] [--no-edit] [-n] [-S[<keyid>]] [-m <message> | -F <file>]", args[0]);
+                    process::exit(129);
+                }
+            }
+        }
+        i += 1;
+    }
+    if !paragraphs.is_empty() && file.is_some() {
+        println!("fatal: Option -m cannot be combined with -F.");
+        process::exit(128);
+    }
+
+    let Some((head_ref, head_hash)) = refs::resolve_ref("HEAD") else {
+        println!("fatal: not a git repository: .git/HEAD is invalid");
+        process::exit(128);
+    };
+    let head_commit = head_hash.map(|hash| Commit::from_hash(&hash));
+    if amend && head_commit.is_none() {
+        println!("fatal: You have nothing to amend.");
+        process::exit(128);
+    }
+    let branch_line = match head_ref.strip_prefix("refs/heads/") {
+        Some(branch) => format!("On branch {}", branch),
+        None => "Not currently on any branch.".to_string(),
+    };
+
+    let use_editor = file.is_none() && paragraphs.is_empty() && !(amend && no_edit);
+    // Reading a missing index as empty would commit the deletion of every tracked file
+    if head_commit.is_some() && !Path::new(INDEX_PATH).exists() {
+        println!("fatal: index file {} is missing", INDEX_PATH);
+        process::exit(128);
+    }
+    // The index stays locked until HEAD has moved, so nothing can change what is committed
+    Index::lock_or_exit();
+    let mut index = Index::read_index();
+    if all {
+        stage_tracked_changes(&mut index);
+    }
+    if !no_verify && !run_commit_hook("pre-commit", &[], &mut index, true, use_editor) {
+        unlock_and_exit(1);
+    }
+
+    let (message, source) = if let Some(file) = file {
+        let content = if file == "-" {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).ok().map(|_| content)
+        } else {
+            fs::read_to_string(&file).ok()
+        };
+        let content = content.unwrap_or_else(|| {
+            println!("fatal: could not read log file '{}'", file);
+            unlock_and_exit(128);
+        });
+        (content, vec!["message"])
+    } else if !paragraphs.is_empty() {
+        (paragraphs.join("\n\n"), vec!["message"])
+    } else {
+        match &head_commit {
+            Some(head_commit) if amend => (format!("{}\n", head_commit.message()), vec!["commit", "HEAD"]),
+            _ => (String::new(), vec![]),
+        }
+    };
+    write_editmsg(&message, use_editor, &branch_line);
+
+    let tree = index.write_tree(false).unwrap_or_else(|error| {
+        println!("error: {}", error);
+        println!("fatal: Error building trees");
+        unlock_and_exit(128);
+    });
+    let parents = match (&head_commit, amend) {
+        (Some(head_commit), true) => head_commit.parents.clone(),
+        (Some(head_commit), false) => vec![head_commit.hash.clone()],
+        (None, _) => Vec::new(),
+    };
+    // Amending compares with the parent, since the amended commit replaces HEAD; merges
+    // may be amended as they are
+    let amending_merge = amend && head_commit.as_ref().is_some_and(|head_commit| head_commit.is_merge());
+    if !allow_empty && !amending_merge {
+        let unchanged = match parents.first() {
+            Some(parent) if amend => Commit::from_hash(parent).tree == tree,
+            _ if amend => index.entries.is_empty(),
+            _ => head_commit.as_ref().map_or(index.entries.is_empty(), |head_commit| head_commit.tree == tree),
+        };
+        if unchanged && amend {
+            println!("You asked to amend the most recent commit, but doing so would make");
+            println!("it empty. You can repeat your command with --allow-empty, or you can");
+            println!("remove the commit entirely with \"git reset HEAD^\".");
+            println!("{}", branch_line);
+            println!("No changes");
+            unlock_and_exit(1);
+        }
+        if unchanged {
+            nothing_to_commit(&index, &branch_line, head_commit.is_none(), all);
+        }
+    }
+
+    let hook_args: Vec<&str> = [EDITMSG_PATH].into_iter().chain(source).collect();
+    if !run_commit_hook("prepare-commit-msg", &hook_args, &mut index, true, use_editor) {
+        unlock_and_exit(1);
+    }
+    if use_editor {
+        launch_editor();
+    }
+    if !no_verify && !run_commit_hook("commit-msg", &[EDITMSG_PATH], &mut index, true, use_editor) {
+        unlock_and_exit(1);
+    }
+    let message = cleanup_message(&fs::read_to_string(EDITMSG_PATH).unwrap_or_default(), use_editor);
+    if message.is_empty() {
+        println!("Aborting commit due to empty commit message.");
+        unlock_and_exit(1);
+    }
+
+    let signature = |role| ident::signature(role, true).unwrap_or_else(|error| {
+        Index::unlock();
+        ident::exit_with_error(role, error)
+    });
+    let author = match &head_commit {
+        Some(head_commit) if amend => head_commit.author.clone(),
+        _ => signature(Role::Author),
+    };
+    let committer = signature(Role::Committer);
+    let initial = parents.is_empty();
+    let mut commit = Commit::new(tree, parents, author, committer, &message);
+    if sign {
+        if let Err(error) = commit.sign(signing_key.as_deref()) {
+            println!("error: {}", error);
+            println!("fatal: failed to write commit object");
+            unlock_and_exit(128);
+        }
+    }
+    let hash = commit.write();
+
+    let subject = message.lines().next().unwrap_or("");
+    let action = if amend {
+        "commit (amend)"
+    } else if initial {
+        "commit (initial)"
+    } else if commit.is_merge() {
+        "commit (merge)"
+    } else {
+        "commit"
+    };
+    // HEAD only moves from the commit the new one was made on
+    let old = head_commit.as_ref().map_or(reflog::NULL_HASH, |head_commit| head_commit.hash.as_str());
+    let mut transaction = RefTransaction::new();
+    let update = RefUpdate::new(&head_ref, RefAction::Update(hash.clone()), Some(old), Some(&format!("{}: {}", action, subject)));
+    if let Err(error) = transaction.add(update).and_then(|_| transaction.commit()) {
+        println!("fatal: {}", error);
+        unlock_and_exit(128);
+    }
+    index.write_locked_index();
+    run_commit_hook("post-commit", &[], &mut index, false, use_editor);
+    if let (Some(head_commit), true) = (&head_commit, amend) {
+        hook::run("post-rewrite", &["amend"], &[], Some(format!("{} {}\n", head_commit.hash, hash).as_bytes()));
+    }
+    if !quiet {
+        let branch = head_ref.strip_prefix("refs/heads/").unwrap_or("detached HEAD");
+        let root = if initial { " (root-commit)" } else { "" };
+        println!("[{}{} {}] {}", branch, root, &hash[..7], subject);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use crate::git::ident::{self, Role};
+use crate::git::object::commit::Commit;
+use crate::git::revision;
+
+fn die(message: &str) -> ! {
+    println!("fatal: {}", message);
+    process::exit(128);
+}
+
+/// Resolves `rev` to an object that must be of `kind`, with git's messages otherwise.
+fn resolve_object(rev: &str, kind: &str) -> String {
+    let hash = revision::resolve(rev).unwrap_or_else(|| die(&format!("not a valid object name {}", rev)));
+    if revision::object_type(&hash) != Some(kind) {
+        die(&format!("{} is not a valid '{}' object", hash, kind));
+    }
+    hash
+}
+
+fn usage(program: &str) -> ! {
+    println!("usage: {} commit-tree [(-p <parent>)...] [(-m <message>)...] [(-F <file>)...] [-S[<keyid>]] <tree>", program);
+    process::exit(129);
+}
+
+pub fn commit_tree(args: &Vec<String>) {
+    let mut tree = None;
+    let mut parents: Vec<String> = Vec::new();
+    let mut message = String::new();
+    let mut has_message = false;
+    let mut sign = false;
+    let mut signing_key = None;
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-p" | "-m" | "-F" => {
+                i += 1;
+                let Some(value) = args.get(i) else {
+                    println!("error: switch `{}' requires a value", &arg[1..]);
+                    process::exit(129);
+                };
+                if arg == "-p" {
+                    let parent = resolve_object(value, "commit");
+                    if parents.contains(&parent) {
+                        println!("error: duplicate parent {} ignored", parent);
+                    } else {
+                        parents.push(parent);
+                    }
+                    i += 1;
+                    continue;
+                }
+                // Each message starts a new paragraph, and `-m` ones end with a newline
+                if !message.is_empty() {
+                    message.push('\n');
+                }
+                if arg == "-m" {
+                    message.push_str(value);
+                    if !message.ends_with('\n') {
+                        message.push('\n');
+                    }
+                } else if value == "-" {
+                    io::stdin().read_to_string(&mut message).unwrap();
+                } else {
+                    message.push_str(&fs::read_to_string(value).unwrap_or_else(|error| {
+                        die(&format!("could not open '{}' for reading: {}", value, error.to_string().split(" (os error").next().unwrap_or("")))
+                    }));
+                }
+                has_message = true;
+            }
+            "-S" | "--gpg-sign" => sign = true,
+            "--no-gpg-sign" => sign = false,
+            _ if arg.starts_with("-S") || arg.starts_with("--gpg-sign=") => {
+                sign = true;
+                signing_key = arg.strip_prefix("-S").or_else(|| arg.strip_prefix("--gpg-sign=")).map(|key| key.to_string());
+            }
+            _ if arg.starts_with('-') => usage(&args[0]),
+            _ if tree.is_some() => die("must give exactly one tree"),
+            _ => tree = Some(arg.to_string()),
+        }
+        i += 1;
+    }
+    let Some(tree) = tree else {
+        usage(&args[0]);
+    };
+    let tree = resolve_object(&tree, "tree");
+    if !has_message {
+        io::stdin().read_to_string(&mut message).unwrap();
+    }
+    let author = ident::signature_or_exit(Role::Author);
+    let committer = ident::signature_or_exit(Role::Committer);
+    let mut commit = Commit::new(tree, parents, author, committer, &message);
+    if sign {
+        if let Err(error) = commit.sign(signing_key.as_deref()) {
+            println!("error: {}", error);
+            die("failed to write commit object");
+        }
+    }
+    println!("{}", commit.write());
+}
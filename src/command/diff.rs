@@ -1,6 +1,6 @@
-use std::{collections::{HashSet, VecDeque}, fs, time::SystemTime};
+use std::{collections::{BTreeMap, BTreeSet, HashSet, VecDeque}, fs, time::SystemTime};
 use colored::*;
-use crate::git::{gitignore::GitIgnore, head::Head, index::{self, Index}, object::{blob::{compute_file_hash, Blob}, commit::Commit}};
+use crate::git::{fsmonitor::FsMonitorChanges, gitignore::GitIgnore, head::Head, index::{self, Index}, object::{blob::{compute_file_hash, Blob}, commit::Commit, tree::Tree}, revision};
 
 pub fn diff_blobs(blob1: &Blob, blob2: &Blob) -> String {
     let mut diff = String::new();
@@ -83,17 +83,20 @@ impl ChangedFile {
     }
 }
 
-fn get_unstaged_files(commit: &Commit, index: &Index, gitignore: &GitIgnore) -> Vec<ChangedFile> {
+fn get_unstaged_files(commit: &Commit, index: &Index, gitignore: &GitIgnore, fsmonitor: Option<&FsMonitorChanges>) -> Vec<ChangedFile> {
     let mut unstaged_files = Vec::new();
-    let commit_timestamp = commit.timestamp.parse::<u64>().unwrap();
-    for entry in index.entries.iter() {
+    let commit_timestamp = commit.committer.time as u64;
+    for (position, entry) in index.entries.iter().enumerate() {
+        if entry.is_skip_worktree() || fsmonitor.is_some_and(|changes| !changes.is_changed(position, &entry.name)) {
+            continue;
+        }
         if !fs::exists(&entry.name).unwrap() {
             unstaged_files.push(ChangedFile::new(entry.name.clone(), FileStatus::Deleted, Some(entry.sha1.clone()), None));
             continue;
         }
         let modified_time = fs::metadata(&entry.name).unwrap().modified().unwrap()
                     .duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-        if !gitignore.is_ignored(&entry.name) && modified_time > commit_timestamp {
+        if !gitignore.is_ignored(&entry.name) && (fsmonitor.is_some() || modified_time > commit_timestamp) {
             let hash = compute_file_hash(&entry.name);
             if entry.sha1 != hash {
                 unstaged_files.push(ChangedFile::new(entry.name.clone(), FileStatus::Modified, Some(entry.sha1.clone()), Some(hash)));
@@ -103,19 +106,83 @@ fn get_unstaged_files(commit: &Commit, index: &Index, gitignore: &GitIgnore) ->
     unstaged_files
 }
 
+fn flatten_tree(hash: &str) -> BTreeMap<String, String> {
+    let mut files = Vec::new();
+    Tree::from_hash(hash).flatten("", &mut files);
+    files.into_iter().map(|entry| (entry.name, entry.hash)).collect()
+}
+
+/// Hashes the worktree files tracked by the index or by `tree`; deleted ones are left out.
+fn worktree_files(index: &Index, tree: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let sparse_paths: HashSet<&String> = index.entries.iter().filter(|entry| entry.is_skip_worktree()).map(|entry| &entry.name).collect();
+    index.entries.iter().map(|entry| &entry.name).chain(tree.keys())
+        .filter(|path| !sparse_paths.contains(path) && fs::metadata(path).is_ok_and(|metadata| metadata.is_file()))
+        .map(|path| (path.clone(), compute_file_hash(path)))
+        .collect()
+}
+
+/// Prints every path whose content differs between `old` and `new`. With `worktree` the new
+/// side is read from the files themselves instead of the object store.
+fn diff_files(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>, worktree: bool) {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for path in paths {
+        if old.get(path) == new.get(path) {
+            continue;
+        }
+        let empty = || Blob::new(String::new(), Vec::new());
+        let old_blob = old.get(path).map(|hash| Blob::from_hash(hash)).unwrap_or_else(empty);
+        let new_blob = match new.get(path) {
+            Some(_) if worktree => Blob::from_file(path),
+            Some(hash) => Blob::from_hash(hash),
+            None => empty(),
+        };
+        println!("{}", path);
+        println!("{}", diff_blobs(&old_blob, &new_blob));
+    }
+}
+
 pub fn diff(args: &Vec<String>) {
-    if args.len() != 2 {
-        println!("Usage: {} diff", args[0]);
-        std::process::exit(1);
+    let revs: Vec<&String> = args[2..].iter().take_while(|arg| *arg != "--").collect();
+    let trees: Vec<String> = match revs.as_slice() {
+        [] => Vec::new(),
+        [range] if range.contains("...") => {
+            let (left, right) = range.split_once("...").unwrap();
+            let left = revision::resolve_commit(if left.is_empty() { "HEAD" } else { left });
+            let right = revision::resolve_commit(if right.is_empty() { "HEAD" } else { right });
+            let base = revision::merge_bases(&left, &right).into_iter().next().unwrap_or_else(|| revision::die_unknown_revision(range));
+            vec![revision::resolve_tree(&base), revision::resolve_tree(&right)]
+        }
+        [range] if range.contains("..") => {
+            let (left, right) = range.split_once("..").unwrap();
+            [left, right].iter().map(|rev| revision::resolve_tree(if rev.is_empty() { "HEAD" } else { rev })).collect()
+        }
+        [rev] => vec![revision::resolve_tree(rev)],
+        [old, new] => vec![revision::resolve_tree(old), revision::resolve_tree(new)],
+        _ => {
+            println!("Usage: {} diff [<commit> [<commit>]]", args[0]);
+            std::process::exit(1);
+        }
+    };
+    match trees.as_slice() {
+        [old, new] => diff_files(&flatten_tree(old), &flatten_tree(new), false),
+        [old] => {
+            let old = flatten_tree(old);
+            diff_files(&old, &worktree_files(&Index::read_index(), &old), true);
+        }
+        _ => diff_worktree(),
     }
+}
+
+fn diff_worktree() {
     let index = index::Index::read_index();
     let head = Head::from_head();
     let commit = Commit::from_hash(&head.head_hash);
     // let tree = tree::Tree::from_hash(&commit.tree);
     let gitignore = GitIgnore::from_file();
-    let unstaged_files = get_unstaged_files(&commit, &index, &gitignore);
+    let fsmonitor = FsMonitorChanges::query(&index);
+    let unstaged_files = get_unstaged_files(&commit, &index, &gitignore, fsmonitor.as_ref());
     for file in unstaged_files.iter() {
         println!("{}", file.path);
         println!("{}", diff_blobs(&Blob::from_hash(&file.old_hash.as_ref().unwrap()), &Blob::from_file(&file.path)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,106 @@
+use std::process;
+
+use crate::git::object::tag::Tag;
+use crate::git::ref_filter::{self, RefFormat, RefItem};
+use crate::git::refs;
+use crate::git::revision;
+
+fn resolve_commit_arg(option: &str, rev: &str) -> String {
+    match revision::resolve(rev).and_then(|hash| revision::peel(&hash, "commit")) {
+        Some(hash) => hash,
+        None => {
+            println!("error: malformed object name {}", rev);
+            println!("error: option `{}' must point to a commit", option);
+            process::exit(129);
+        }
+    }
+}
+
+pub fn for_each_ref(args: &Vec<String>) {
+    let mut format = ref_filter::DEFAULT_FORMAT.to_string();
+    let mut sort_keys = Vec::new();
+    let mut count = None;
+    let mut points_at = None;
+    let mut merged = None;
+    let mut no_merged = None;
+    let mut contains = None;
+    let mut patterns = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = |optional: bool| -> Option<String> {
+            if inline_value.is_some() {
+                return inline_value.clone();
+            }
+            match args.get(i + 1) {
+                Some(next) if !optional || !next.starts_with('-') => {
+                    i += 1;
+                    Some(next.to_string())
+                }
+                _ if optional => None,
+                _ => {
+                    println!("error: option `{}' requires a value", option.trim_start_matches('-'));
+                    process::exit(129);
+                }
+            }
+        };
+        match option {
+            "--format" => format = value(false).unwrap(),
+            "--sort" => sort_keys.push(value(false).unwrap()),
+            "--count" => {
+                let value = value(false).unwrap();
+                count = Some(value.parse::<usize>().unwrap_or_else(|_| {
+                    println!("error: option `count' expects a numerical value");
+                    process::exit(129);
+                }));
+            }
+            "--points-at" => {
+                let rev = value(false).unwrap();
+                points_at = Some(revision::resolve(&rev).unwrap_or_else(|| {
+                    println!("error: malformed object name {}", rev);
+                    process::exit(129);
+                }));
+            }
+            "--merged" => merged = Some(resolve_commit_arg("merged", &value(true).unwrap_or("HEAD".to_string()))),
+            "--no-merged" => no_merged = Some(resolve_commit_arg("no-merged", &value(true).unwrap_or("HEAD".to_string()))),
+            "--contains" => contains = Some(resolve_commit_arg("contains", &value(true).unwrap_or("HEAD".to_string()))),
+            _ if arg.starts_with('-') => {
+                println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                process::exit(129);
+            }
+            _ => patterns.push(arg.to_string()),
+        }
+        i += 1;
+    }
+    if sort_keys.is_empty() {
+        sort_keys.push("refname".to_string());
+    }
+
+    let format = RefFormat::parse(&format);
+    let mut items: Vec<RefItem> = refs::list_refs("refs/").into_iter()
+        .filter(|(name, _)| patterns.is_empty() || patterns.iter().any(|pattern| ref_filter::matches_pattern(name, pattern)))
+        .map(|(name, hash)| RefItem::new(name, hash))
+        .collect();
+    if let Some(object) = &points_at {
+        items.retain(|item| item.hash == *object || (revision::object_type(&item.hash) == Some("tag") && Tag::from_hash(&item.hash).object == *object));
+    }
+    if merged.is_some() || no_merged.is_some() || contains.is_some() {
+        // Commit filters only ever match refs that point to commits
+        items.retain(|item| {
+            let Some(commit) = revision::peel(&item.hash, "commit") else {
+                return false;
+            };
+            merged.as_ref().is_none_or(|merged| revision::is_ancestor(&commit, merged))
+                && no_merged.as_ref().is_none_or(|no_merged| !revision::is_ancestor(&commit, no_merged))
+                && contains.as_ref().is_none_or(|contains| revision::is_ancestor(contains, &commit))
+        });
+    }
+    ref_filter::sort(&mut items, &sort_keys);
+    for item in items.iter().take(count.unwrap_or(usize::MAX)) {
+        println!("{}", format.expand(item));
+    }
+}
@@ -0,0 +1,64 @@
+use std::env;
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crate::git::fsmonitor;
+
+pub fn fsmonitor_daemon(args: &Vec<String>) {
+    if args.len() < 3 {
+        println!("Usage: {} fsmonitor--daemon [start|run|stop|status|query <token>]", args[0]);
+        process::exit(1);
+    }
+    let worktree = env::current_dir().unwrap();
+    match args[2].as_str() {
+        "start" => {
+            if fsmonitor::is_daemon_running() {
+                println!("fsmonitor--daemon is already running '{}'", worktree.display());
+                process::exit(1);
+            }
+            Command::new(env::current_exe().unwrap())
+                .args(["fsmonitor--daemon", "run"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0)
+                .spawn()
+                .unwrap();
+            for _ in 0..100 {
+                if fsmonitor::is_daemon_running() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            println!("fsmonitor--daemon failed to start");
+            process::exit(1);
+        }
+        "run" => fsmonitor::run_daemon(),
+        "stop" => {
+            if !fsmonitor::stop_daemon() {
+                println!("fsmonitor--daemon is not running");
+                process::exit(1);
+            }
+        }
+        "status" => {
+            if fsmonitor::is_daemon_running() {
+                println!("fsmonitor-daemon is watching '{}'", worktree.display());
+            } else {
+                println!("fsmonitor-daemon is not watching '{}'", worktree.display());
+                process::exit(1);
+            }
+        }
+        // Hook-compatible output, so `core.fsmonitor` in git itself can point at a wrapper
+        "query" => match fsmonitor::query_daemon(args.get(3).map(|s| s.as_str()).unwrap_or("")) {
+            Some(response) => io::stdout().write_all(&response).unwrap(),
+            None => process::exit(1),
+        },
+        subcommand => {
+            println!("Unknown fsmonitor--daemon subcommand: {}", subcommand);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use crate::git::config::Config;
+use crate::git::trailer::{self, IfExists, IfMissing, NewTrailer, Where};
+
+struct Options {
+    in_place: bool,
+    trim_empty: bool,
+    only_trailers: bool,
+    only_input: bool,
+    unfold: bool,
+    no_divider: bool,
+}
+
+fn usage(program: &str) -> ! {
+    println!("usage: {} interpret-trailers [--in-place] [--trim-empty] [(--trailer <token>[(=|:)<value>])...] [--parse] [<file>...]", program);
+    process::exit(129);
+}
+
+/// Parses a `--trailer` argument, which unlike trailers in messages may also use `=`.
+fn parse_new_trailer(arg: &str, separators: &str) -> Option<(String, String)> {
+    let separators = format!("={}", separators);
+    match trailer::find_separator(arg, &separators) {
+        Some(0) => {
+            println!("error: empty trailer token in trailer '{}'", arg);
+            None
+        }
+        Some(position) => Some(trailer::split_trailer(arg, position)),
+        None => Some((arg.trim().to_string(), String::new())),
+    }
+}
+
+/// Rewrites `input` with the new trailers added to its trailer block.
+fn process(input: &str, new_trailers: &[NewTrailer], separators: &str, options: &Options) -> String {
+    let block = trailer::parse(input, separators, options.no_divider);
+    let mut trailers = block.trailers(separators, options.only_trailers, options.unfold);
+    let mut output = String::new();
+    if !options.only_trailers {
+        output.push_str(&input[..block.start]);
+        if !block.blank_line_before {
+            output.push('\n');
+        }
+    }
+    for new in new_trailers {
+        trailer::add(&mut trailers, new.clone());
+    }
+    for trailer in trailers {
+        if options.trim_empty && trailer.value.is_empty() {
+            continue;
+        }
+        if let Some(line) = trailer.format(separators) {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    if !options.only_trailers {
+        output.push_str(&input[block.end..]);
+    }
+    output
+}
+
+pub fn interpret_trailers(args: &Vec<String>) {
+    let config = Config::read();
+    let separators = trailer::separators();
+    let default_where = config.get("trailer.where").and_then(|value| Where::parse(&value)).unwrap_or(Where::End);
+    let default_if_exists = config.get("trailer.ifExists").and_then(|value| IfExists::parse(&value)).unwrap_or(IfExists::AddIfDifferentNeighbor);
+    let default_if_missing = config.get("trailer.ifMissing").and_then(|value| IfMissing::parse(&value)).unwrap_or(IfMissing::Add);
+    let (mut placement, mut if_exists, mut if_missing) = (default_where, default_if_exists, default_if_missing);
+    let mut options = Options { in_place: false, trim_empty: false, only_trailers: false, only_input: false, unfold: false, no_divider: false };
+    let mut new_trailers = Vec::new();
+    let mut files = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        match name {
+            "--in-place" => options.in_place = true,
+            "--trim-empty" => options.trim_empty = true,
+            "--only-trailers" => options.only_trailers = true,
+            "--only-input" => options.only_input = true,
+            "--unfold" => options.unfold = true,
+            "--no-divider" => options.no_divider = true,
+            "--parse" => {
+                options.only_trailers = true;
+                options.only_input = true;
+                options.unfold = true;
+            }
+            "--no-where" => placement = default_where,
+            "--no-if-exists" => if_exists = default_if_exists,
+            "--no-if-missing" => if_missing = default_if_missing,
+            "--trailer" | "--where" | "--if-exists" | "--if-missing" => {
+                let value = match inline_value {
+                    Some(value) => value,
+                    None => {
+                        i += 1;
+                        args.get(i).cloned().unwrap_or_else(|| {
+                            println!("error: option `{}' requires a value", &name[2..]);
+                            process::exit(129);
+                        })
+                    }
+                };
+                let invalid = || -> ! {
+                    println!("error: unknown value for {}: {}", name, value);
+                    process::exit(129);
+                };
+                match name {
+                    "--where" => placement = Where::parse(&value).unwrap_or_else(|| invalid()),
+                    "--if-exists" => if_exists = IfExists::parse(&value).unwrap_or_else(|| invalid()),
+                    "--if-missing" => if_missing = IfMissing::parse(&value).unwrap_or_else(|| invalid()),
+                    _ => {
+                        if let Some((token, value)) = parse_new_trailer(&value, &separators) {
+                            new_trailers.push(NewTrailer { token, value, placement, if_exists, if_missing });
+                        }
+                    }
+                }
+            }
+            _ if arg.starts_with('-') && arg != "-" => usage(&args[0]),
+            _ => files.push(arg.to_string()),
+        }
+        i += 1;
+    }
+    if options.only_input && !new_trailers.is_empty() {
+        println!("fatal: --trailer with --only-input does not make sense");
+        usage(&args[0]);
+    }
+
+    if files.is_empty() {
+        if options.in_place {
+            println!("fatal: no input file given for in-place editing");
+            process::exit(128);
+        }
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).unwrap();
+        print!("{}", process(&input, &new_trailers, &separators, &options));
+        return;
+    }
+    for file in files {
+        let input = fs::read_to_string(&file).unwrap_or_else(|error| {
+            println!("fatal: could not read input file '{}': {}", file, error.to_string().split(" (os error").next().unwrap_or(""));
+            process::exit(128);
+        });
+        let output = process(&input, &new_trailers, &separators, &options);
+        if options.in_place {
+            fs::write(&file, output).unwrap();
+        } else {
+            print!("{}", output);
+        }
+    }
+}
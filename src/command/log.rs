@@ -1,17 +1,348 @@
+use std::collections::HashSet;
+use std::path::Path;
 use std::process;
-use crate::git::head;
-use crate::git::object::commit;
+
+use chrono::Local;
+use colored::control::SHOULD_COLORIZE;
+
+use crate::git::combine_diff;
+use crate::git::date;
+use crate::git::diff_output::{self, DiffOptions, MergeDiff};
+use crate::git::graph::Graph;
+use crate::git::grep::{self, PatternSyntax};
+use crate::git::head::Head;
+use crate::git::object::commit::Commit;
+use crate::git::pathspec::Pathspec;
+use crate::git::pretty::{Format, PrettyOptions};
+use crate::git::refs;
+use crate::git::revision::{self, CommitFilter, CommitWalk, WalkOrder};
+use crate::git::tree_diff::{self, FileChange};
+
+/// Reads a count as git's `atoi` does, from its leading digits.
+fn parse_count(value: &str) -> usize {
+    let digits = value.trim_start().bytes().take_while(|byte| byte.is_ascii_digit()).count();
+    value.trim_start()[..digits].parse().unwrap_or(0)
+}
+
+/// How `commit` changes the file `log --follow` is following, if it does. The file becomes
+/// the one it was renamed from when the commit renamed it. Merges only count with
+/// `--first-parent`.
+fn followed_changes(commit: &Commit, path: &mut String, first_parent: bool) -> Option<Vec<FileChange>> {
+    if commit.parents.len() > 1 && !first_parent {
+        return None;
+    }
+    let parent_tree = commit.parents.first().map(|parent| Commit::from_hash(parent).tree);
+    let changes = tree_diff::diff_followed(parent_tree.as_deref(), Some(&commit.tree), path);
+    if let Some(rename) = changes.iter().find(|change| change.status == 'R') {
+        *path = rename.old.as_ref().unwrap().path.clone();
+    }
+    (!changes.is_empty()).then_some(changes)
+}
+
+/// The files `commit` changed from `parent`, or from nothing for a root commit, with renames.
+fn changes_from(commit: &Commit, parent: Option<&str>, pathspec: &Pathspec) -> Vec<FileChange> {
+    let parent_tree = parent.map(|parent| Commit::from_hash(parent).tree);
+    tree_diff::detect_renames(tree_diff::diff_trees(parent_tree.as_deref(), Some(&commit.tree), pathspec), |_| true)
+}
+
+/// The entries `log` shows for a commit, each the parent it is diffed against, if it's
+/// named, and the diff after the log message: one per parent for a merge with `-m`, and
+/// otherwise one, which may have no diff. `changes` are what `--follow` found.
+pub fn commit_entries(commit: &Commit, changes: Option<Vec<FileChange>>, pathspec: &Pathspec, options: &DiffOptions, pretty: &PrettyOptions, width: usize) -> Vec<(Option<String>, String)> {
+    if !options.shows_diff() {
+        return vec![(None, String::new())];
+    }
+    // A blank line, or `---` before a diffstat and patch, parts the message from the diff
+    let separator = if pretty.format == Format::Oneline || pretty.format.is_empty() {
+        ""
+    } else if options.shows_stat_and_patch() {
+        "---\n"
+    } else {
+        "\n"
+    };
+    let block = |changes: Vec<FileChange>| {
+        let diff = diff_output::format_diff(&changes, options, width);
+        if diff.is_empty() { diff } else { format!("{}{}", separator, diff) }
+    };
+    if let Some(changes) = changes {
+        return vec![(None, block(changes))];
+    }
+    if commit.parents.len() < 2 {
+        return vec![(None, block(changes_from(commit, commit.first_parent(), pathspec)))];
+    }
+    match options.merges {
+        MergeDiff::Off => vec![(None, String::new())],
+        MergeDiff::FirstParent => vec![(None, block(changes_from(commit, commit.first_parent(), pathspec)))],
+        MergeDiff::Separate => {
+            let entries: Vec<_> = commit
+                .parents
+                .iter()
+                .map(|parent| (Some(parent.clone()), block(changes_from(commit, Some(parent), pathspec))))
+                .filter(|(_, diff)| !diff.is_empty())
+                .collect();
+            if entries.is_empty() { vec![(None, String::new())] } else { entries }
+        }
+        MergeDiff::Combined | MergeDiff::DenseCombined => {
+            // The message always gets its blank line, even when nothing follows
+            let diff = combine_diff::format_combined(commit, pathspec, options.merges == MergeDiff::DenseCombined, options, width);
+            let separator = if pretty.format.is_empty() { "" } else { "\n" };
+            vec![(None, format!("{}{}", separator, diff))]
+        }
+    }
+}
 
 pub fn log(args: &Vec<String>) {
-    if args.len() != 2 {
-        println!("Usage: {} log", args[0]);
-        process::exit(1);
-    }
-    let head = head::Head::from_head();
-    let mut commit = commit::Commit::from_hash(&head.head_hash);
-    println!("{}", commit.format_log());
-    while commit.parent.is_some() {
-        commit = commit::Commit::from_hash(&commit.parent.unwrap());
-        println!("{}", commit.format_log());
-    }
-}
\ No newline at end of file
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    let mut first_parent = false;
+    let mut pretty = PrettyOptions::default();
+    let mut max_count = None;
+    let mut skip = 0;
+    let mut filter = CommitFilter::default();
+    let (mut authors, mut committers, mut greps) = (Vec::new(), Vec::new(), Vec::new());
+    let mut ignore_case = false;
+    let mut syntax = PatternSyntax::Basic;
+    let mut all = false;
+    let mut show_graph = false;
+    let mut full_history = false;
+    let mut simplify_merges = false;
+    let mut order = WalkOrder::default();
+    let mut follow = false;
+    let mut diff = DiffOptions::default();
+    let mut revisions = 0;
+    let mut paths = Vec::new();
+    // Past a `--`, everything is a path, and before it nothing is
+    let separator = args.iter().skip(2).position(|arg| arg == "--").map(|position| position + 2);
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if Some(i) == separator {
+            paths.extend(args[i + 1..].iter().cloned());
+            break;
+        }
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || -> String {
+            if let Some(value) = &inline_value {
+                return value.clone();
+            }
+            i += 1;
+            args.get(i).cloned().unwrap_or_else(|| {
+                println!("fatal: Option '{}' requires a value", option);
+                process::exit(128);
+            })
+        };
+        match option {
+            "--first-parent" => first_parent = true,
+            "--show-signature" => pretty.show_signature = true,
+            "--all" => all = true,
+            "--graph" => show_graph = true,
+            "--full-history" => full_history = true,
+            "--simplify-merges" => simplify_merges = true,
+            "--follow" => follow = true,
+            "--oneline" => {
+                pretty.format = Format::Oneline;
+                pretty.abbrev_commit = true;
+            }
+            "--pretty" | "--format" => {
+                let value = if option == "--pretty" { inline_value.clone().unwrap_or_else(|| "medium".to_string()) } else { value() };
+                pretty.format = Format::parse(&value).unwrap_or_else(|| {
+                    println!("fatal: invalid --pretty format: {}", value);
+                    process::exit(128);
+                });
+            }
+            "--abbrev-commit" => pretty.abbrev_commit = true,
+            "--no-abbrev-commit" => pretty.abbrev_commit = false,
+            "--decorate" => pretty.decorate = true,
+            "--no-decorate" => pretty.decorate = false,
+            "--date" => pretty.date_mode = value(),
+            "--relative-date" => pretty.date_mode = "relative".to_string(),
+            "-n" | "--max-count" => max_count = Some(parse_count(&value())),
+            "--skip" => skip = parse_count(&value()),
+            "--author" => authors.push(value()),
+            "--committer" => committers.push(value()),
+            "--grep" => greps.push(value()),
+            "-i" | "--regexp-ignore-case" => ignore_case = true,
+            "-E" | "--extended-regexp" => syntax = PatternSyntax::Extended,
+            "-F" | "--fixed-strings" => syntax = PatternSyntax::Fixed,
+            "--basic-regexp" => syntax = PatternSyntax::Basic,
+            "--all-match" => filter.all_match = true,
+            "--since" | "--after" | "--until" | "--before" => {
+                // Like git, a date that can't be read means now
+                let time = date::approxidate(&value()).unwrap_or_else(|| Local::now().timestamp());
+                if option == "--since" || option == "--after" {
+                    filter.since = Some(time);
+                } else {
+                    filter.until = Some(time);
+                }
+            }
+            "--merges" => filter.min_parents = 2,
+            "--no-merges" => filter.max_parents = Some(1),
+            _ if order.parse(arg) => {}
+            _ if diff.parse(arg) => {}
+            _ if arg.starts_with("-n") => max_count = Some(parse_count(&arg[2..])),
+            _ if arg.starts_with('-') && arg[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                max_count = Some(arg[1..].parse().unwrap_or_else(|_| {
+                    println!("fatal: '{}': not a non-negative integer", &arg[1..]);
+                    process::exit(128);
+                }));
+            }
+            _ if arg.starts_with('-') => {
+                println!("fatal: unrecognized argument: {}", arg);
+                process::exit(128);
+            }
+            _ => {
+                // Without a `--`, what isn't a revision may be a path, and so is everything after it
+                let specs = if separator.is_none() && !paths.is_empty() { None } else { revision::parse_range(arg) };
+                match specs {
+                    Some(specs) => {
+                        revisions += 1;
+                        for spec in specs {
+                            let hash = revision::peel(&spec.hash, "commit").unwrap_or_else(|| revision::die_unknown_revision(arg));
+                            if spec.excluded {
+                                exclude.push(hash);
+                            } else {
+                                include.push(hash);
+                            }
+                        }
+                    }
+                    None if separator.is_some() => {
+                        println!("fatal: bad revision '{}'", arg);
+                        process::exit(128);
+                    }
+                    None => {
+                        if !Path::new(arg).exists() {
+                            if paths.is_empty() {
+                                revision::die_unknown_revision(arg);
+                            }
+                            println!("fatal: {}: no such path in the working tree.", arg);
+                            println!("Use 'git <command> -- <path>...' to specify paths that do not exist locally.");
+                            process::exit(128);
+                        }
+                        paths.push(arg.to_string());
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    filter.authors = authors.iter().map(|pattern| grep::compile_pattern(pattern, syntax, ignore_case)).collect();
+    filter.committers = committers.iter().map(|pattern| grep::compile_pattern(pattern, syntax, ignore_case)).collect();
+    filter.greps = greps.iter().map(|pattern| grep::compile_pattern(pattern, syntax, ignore_case)).collect();
+    if all {
+        let tips = refs::list_refs("refs/").into_iter().map(|(_, hash)| hash).chain(refs::read_ref("HEAD"));
+        include.extend(tips.filter_map(|hash| revision::peel(&hash, "commit")));
+    } else if revisions == 0 {
+        include.push(Head::from_head().head_hash);
+    }
+    if follow && paths.len() != 1 {
+        println!("fatal: --follow requires exactly one pathspec");
+        process::exit(128);
+    }
+    // Following the first parent diffs merges against it, unless they are combined
+    if first_parent && !diff.merges.is_combined() {
+        diff.merges = MergeDiff::FirstParent;
+    }
+    diff.finish();
+    let pathspec = Pathspec::new(&paths);
+    let mut walk = CommitWalk::new(&include, &exclude);
+    if first_parent {
+        walk = walk.first_parent();
+    }
+    // Following renames changes the path as the walk goes, so history can't be simplified by it
+    if !paths.is_empty() && !follow {
+        walk = walk.limit_to_paths(pathspec.clone());
+    }
+    if full_history {
+        walk = walk.full_history();
+    }
+    if simplify_merges {
+        walk = walk.simplify_merges();
+    }
+    if show_graph {
+        walk = walk.rewrite_parents();
+    }
+    walk = order.apply(walk);
+    // The graph needs children before their parents, and knowing which parents are shown
+    let mut graph = None;
+    let colored = SHOULD_COLORIZE.should_colorize();
+    let commits: Box<dyn Iterator<Item = _>> = if simplify_merges || order == WalkOrder::Date {
+        // Simplifying merges and ordering by date take the whole history, and then what is
+        // shown is known
+        let ordered: Vec<_> = walk.into_topo_order().into_iter().filter(|commit| filter.matches(commit)).collect();
+        if show_graph {
+            let interesting: HashSet<String> = ordered.iter().map(|commit| commit.hash.clone()).collect();
+            graph = Some(Graph::new(Box::new(move |hash| interesting.contains(hash)), first_parent, colored));
+        }
+        Box::new(ordered.into_iter())
+    } else if show_graph {
+        // Parents are walked unless excluded, and shown if the filter takes them
+        let excluded = walk.excluded().clone();
+        let parent_filter = filter.clone();
+        let interesting = move |hash: &str| !excluded.contains(hash) && parent_filter.matches(&Commit::from_hash(hash));
+        graph = Some(Graph::new(Box::new(interesting), first_parent, colored));
+        Box::new(walk.into_topo_walk().filter(|commit| filter.matches(commit)))
+    } else if order.is_topological() {
+        Box::new(walk.into_topo_walk().filter(|commit| filter.matches(commit)))
+    } else {
+        Box::new(walk.filter(|commit| filter.matches(commit)))
+    };
+    let terminated = pretty.format.is_terminated();
+    let mut missing_newline = false;
+    let mut followed = paths.pop().map(|path| Pathspec::new(&[path]).items()[0].clone()).unwrap_or_default();
+    let mut shown = 0;
+    let mut printed = false;
+    for commit in commits.skip(skip) {
+        if shown == max_count.unwrap_or(usize::MAX) {
+            break;
+        }
+        // The graph also goes through the commits --follow doesn't show, and marks them with `...`
+        if let Some(graph) = &mut graph {
+            graph.update(&commit);
+        }
+        let changes = if follow {
+            match followed_changes(&commit, &mut followed, first_parent) {
+                Some(changes) => Some(changes),
+                None => continue,
+            }
+        } else {
+            None
+        };
+        let width = diff_output::term_columns().saturating_sub(graph.as_ref().map_or(0, Graph::width));
+        for (parent, diff) in commit_entries(&commit, changes, &pathspec, &diff, &pretty, width) {
+            // Lines between entries carry the graph on, unless an entry didn't end its line
+            if printed && !terminated {
+                if let (Some(graph), false) = (&mut graph, missing_newline) {
+                    graph.show_padding();
+                }
+                println!();
+            }
+            let log = commit.format_log(&pretty, parent.as_deref());
+            missing_newline = !log.ends_with('\n');
+            match &mut graph {
+                Some(graph) => {
+                    graph.show_commit();
+                    graph.show_commit_msg(&log);
+                }
+                None => print!("{}", log),
+            }
+            if terminated && !pretty.format.is_empty() {
+                if let (Some(graph), false) = (&mut graph, missing_newline) {
+                    graph.show_padding();
+                }
+                println!();
+            }
+            for line in diff.split_inclusive('\n') {
+                if let Some(graph) = &mut graph {
+                    graph.show_padding();
+                }
+                print!("{}", line);
+            }
+            missing_newline &= diff.is_empty();
+            printed = true;
+        }
+        shown += 1;
+    }
+}
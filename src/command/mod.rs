@@ -3,4 +3,22 @@ pub mod log;
 pub mod status;
 pub mod read_index;
 pub mod read_tree;
-pub mod diff;
\ No newline at end of file
+pub mod diff;
+pub mod restore;
+pub mod fsmonitor_daemon;
+pub mod sparse_checkout;
+pub mod rev_parse;
+pub mod branch;
+pub mod tag;
+pub mod reflog;
+pub mod update_ref;
+pub mod for_each_ref;
+pub mod write_tree;
+pub mod commit_tree;
+pub mod commit;
+pub mod var;
+pub mod verify_commit;
+pub mod verify_tag;
+pub mod interpret_trailers;
+pub mod show;
+pub mod rev_list;
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::process;
+
+use colored::*;
+
+use crate::git::config::Config;
+use crate::git::reflog::{self, ReflogEntry};
+use crate::git::refs;
+use crate::git::revision::{self, CommitWalk};
+
+/// Resolves a ref as given on the command line to the full name its reflog is stored under.
+fn reflog_ref_name(name: &str) -> String {
+    match revision::expand_ref_name(name) {
+        Some(full_name) => full_name,
+        None if reflog::exists(name) => name.to_string(),
+        None => revision::die_unknown_revision(name),
+    }
+}
+
+fn show(args: &[String]) {
+    let mut limit = usize::MAX;
+    let mut name = "HEAD".to_string();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "-n" || arg == "--max-count" {
+            i += 1;
+            limit = args.get(i).and_then(|count| count.parse().ok()).unwrap_or(limit);
+        } else if let Some(count) = arg.strip_prefix("--max-count=").or(arg.strip_prefix('-').filter(|count| count.parse::<usize>().is_ok())) {
+            limit = count.parse().unwrap_or(limit);
+        } else {
+            name = arg.to_string();
+        }
+        i += 1;
+    }
+    let entries = reflog::read(&reflog_ref_name(&name));
+    // Entries that deleted the ref keep their position but aren't shown
+    let shown = entries.iter().rev().enumerate().filter(|(_, entry)| entry.new != reflog::NULL_HASH).take(limit);
+    for (n, entry) in shown {
+        println!("{} {}@{{{}}}: {}", entry.new[..7].yellow(), name, n, entry.message);
+    }
+}
+
+/// Reads an expiry date option or config value; `never` and `false` keep everything.
+fn parse_expiry(value: &str) -> i64 {
+    if value == "never" || value == "false" {
+        return i64::MIN;
+    }
+    match reflog::parse_date(value) {
+        Some(date) => date,
+        None => {
+            println!("fatal: malformed expiration date '{}'", value);
+            process::exit(128);
+        }
+    }
+}
+
+fn expire(args: &[String]) {
+    let config = Config::read();
+    let mut expire_total = parse_expiry(&config.get("gc.reflogExpire").unwrap_or("90.days.ago".to_string()));
+    let mut expire_unreachable = parse_expiry(&config.get("gc.reflogExpireUnreachable").unwrap_or("30.days.ago".to_string()));
+    let mut all = false;
+    let mut dry_run = false;
+    let mut names = Vec::new();
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--expire=") {
+            expire_total = parse_expiry(value);
+        } else if let Some(value) = arg.strip_prefix("--expire-unreachable=") {
+            expire_unreachable = parse_expiry(value);
+        } else if arg == "--all" {
+            all = true;
+        } else if arg == "-n" || arg == "--dry-run" {
+            dry_run = true;
+        } else {
+            names.push(reflog_ref_name(arg));
+        }
+    }
+    if all {
+        names = reflog::list();
+    }
+    for name in names {
+        let entries = reflog::read(&name);
+        let tip = refs::read_ref(&name);
+        let mut reachable: Option<HashSet<String>> = None;
+        let keep: Vec<bool> = entries.iter().map(|entry| {
+            let timestamp = entry.timestamp();
+            if timestamp <= expire_total {
+                return false;
+            }
+            if timestamp > expire_unreachable {
+                return true;
+            }
+            let reachable = reachable.get_or_insert_with(|| match &tip {
+                Some(tip) => CommitWalk::new(std::slice::from_ref(tip), &[]).map(|commit| commit.hash).collect(),
+                None => HashSet::new(),
+            });
+            reachable.contains(&entry.new)
+        }).collect();
+        if dry_run {
+            for (entry, _) in entries.iter().zip(keep.iter()).filter(|(_, keep)| !**keep) {
+                println!("would prune {}", entry.message);
+            }
+        } else if keep.contains(&false) {
+            let kept: Vec<ReflogEntry> = entries.into_iter().zip(keep).filter(|(_, keep)| *keep).map(|(entry, _)| entry).collect();
+            reflog::write(&name, &kept);
+        }
+    }
+}
+
+fn delete(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "-n" || arg == "--dry-run");
+    for arg in args.iter().filter(|arg| !arg.starts_with('-')) {
+        let parsed = arg.strip_suffix('}').and_then(|rest| rest.rsplit_once("@{")).and_then(|(name, n)| Some((name, n.parse::<usize>().ok()?)));
+        let Some((name, n)) = parsed else {
+            println!("error: not a reflog: {}", arg);
+            process::exit(1);
+        };
+        let name = reflog_ref_name(if name.is_empty() { "HEAD" } else { name });
+        let mut entries = reflog::read(&name);
+        if n >= entries.len() {
+            println!("error: no reflog for '{}'", arg);
+            process::exit(1);
+        }
+        let index = entries.len() - 1 - n;
+        if dry_run {
+            println!("would prune {}", entries[index].message);
+            continue;
+        }
+        entries.remove(index);
+        reflog::write(&name, &entries);
+    }
+}
+
+pub fn reflog(args: &Vec<String>) {
+    match args.get(2).map(|arg| arg.as_str()) {
+        Some("show") => show(&args[3..]),
+        Some("expire") => expire(&args[3..]),
+        Some("delete") => delete(&args[3..]),
+        Some("exists") => {
+            let exists = args.get(3).is_some_and(|name| reflog::exists(name));
+            process::exit(if exists { 0 } else { 1 });
+        }
+        _ => show(&args[2..]),
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, HashSet};
+use std::process;
+
+use crate::git::index::{Index, IndexEntry};
+use crate::git::object::tree::Tree;
+use crate::git::revision;
+use crate::git::worktree;
+
+fn matches_pathspec(path: &str, pathspec: &str) -> bool {
+    let pathspec = pathspec.trim_end_matches('/');
+    if pathspec == "." || pathspec.is_empty() || path == pathspec || path.starts_with(&format!("{}/", pathspec)) {
+        return true;
+    }
+    glob::Pattern::new(pathspec).is_ok_and(|pattern| pattern.matches(path))
+}
+
+fn parse_mode(mode: &str) -> u32 {
+    u32::from_str_radix(mode, 8).unwrap()
+}
+
+pub fn restore(args: &Vec<String>) {
+    let mut staged = false;
+    let mut restore_worktree = false;
+    let mut source = None;
+    let mut pathspecs = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--staged" || arg == "-S" {
+            staged = true;
+        } else if arg == "--worktree" || arg == "-W" {
+            restore_worktree = true;
+        } else if let Some(rev) = arg.strip_prefix("--source=") {
+            source = Some(rev.to_string());
+        } else if arg == "--source" || arg == "-s" {
+            i += 1;
+            source = args.get(i).cloned();
+        } else if arg == "--" {
+            pathspecs.extend(args[i + 1..].iter().cloned());
+            break;
+        } else {
+            pathspecs.push(arg.to_string());
+        }
+        i += 1;
+    }
+    if pathspecs.is_empty() || source.as_deref() == Some("") {
+        println!("Usage: {} restore [--staged] [--worktree] [--source=<tree-ish>] <pathspec>...", args[0]);
+        process::exit(1);
+    }
+    if !staged {
+        restore_worktree = true;
+    }
+    // Staged restores default to HEAD, worktree-only restores default to the index
+    if staged && source.is_none() {
+        source = Some("HEAD".to_string());
+    }
+
+    Index::lock_or_exit();
+    let mut index = Index::read_index();
+    let source_files: BTreeMap<String, (u32, String)> = match &source {
+        Some(rev) => {
+            let mut files = Vec::new();
+            Tree::from_hash(&revision::resolve_tree(rev)).flatten("", &mut files);
+            files.into_iter().map(|entry| (entry.name, (parse_mode(&entry.mode), entry.hash))).collect()
+        }
+        None => index.entries.iter().map(|entry| (entry.name.clone(), (entry.mode, entry.sha1.clone()))).collect(),
+    };
+    let tracked_paths: Vec<String> = index.entries.iter().map(|entry| entry.name.clone()).collect();
+
+    for pathspec in pathspecs.iter() {
+        let known = source_files.keys().chain(tracked_paths.iter()).any(|path| matches_pathspec(path, pathspec));
+        if !known {
+            println!("error: pathspec '{}' did not match any file(s) known to git", pathspec);
+            Index::unlock();
+            process::exit(1);
+        }
+    }
+    let selected = |path: &str| pathspecs.iter().any(|pathspec| matches_pathspec(path, pathspec));
+
+    if staged {
+        for path in tracked_paths.iter().filter(|path| selected(path) && !source_files.contains_key(*path)) {
+            index.remove_entry(path);
+        }
+        for (path, (mode, hash)) in source_files.iter().filter(|(path, _)| selected(path)) {
+            let unchanged = index.get_entry(path).is_some_and(|entry| entry.mode == *mode && entry.sha1 == *hash);
+            if !unchanged {
+                index.add_entry(IndexEntry::from_object(path, *mode, hash));
+            }
+        }
+    }
+
+    if restore_worktree {
+        // Paths tracked in the index but missing from an explicit source are deleted to match it
+        if source.is_some() {
+            for path in tracked_paths.iter().filter(|path| selected(path) && !source_files.contains_key(*path)) {
+                worktree::remove_file(path);
+            }
+        }
+        // Entries outside the sparse checkout stay out of the worktree
+        let sparse_paths: HashSet<String> = index.entries.iter().filter(|entry| entry.is_skip_worktree()).map(|entry| entry.name.clone()).collect();
+        for (path, (mode, hash)) in source_files.iter().filter(|(path, _)| selected(path) && !sparse_paths.contains(*path)) {
+            if !worktree::file_matches(path, *mode, hash) {
+                worktree::write_file(path, *mode, hash);
+            }
+            if let Some(entry) = index.get_entry_mut(path) {
+                if entry.sha1 == *hash && entry.mode == *mode {
+                    entry.refresh_stat();
+                }
+            }
+        }
+    }
+
+    index.write_locked_index();
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::process;
+
+use crate::git::object::commit::Commit;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::tag::Tag;
+use crate::git::object::tree::Tree;
+use crate::git::pathspec::Pathspec;
+use crate::git::refs;
+use crate::git::revision::{self, CommitWalk, WalkOrder};
+
+fn usage(program: &str) -> ! {
+    println!("usage: {} rev-list [<options>] <commit>... [--] [<path>...]", program);
+    process::exit(129);
+}
+
+/// Reads a count as git's `atoi` does, from its leading digits.
+fn parse_count(value: &str) -> usize {
+    let digits = value.trim_start().bytes().take_while(|byte| byte.is_ascii_digit()).count();
+    value.trim_start()[..digits].parse().unwrap_or(0)
+}
+
+/// Adds every tree and blob under `tree` to `objects`.
+fn mark_tree(tree: &str, objects: &mut HashSet<String>) {
+    if !objects.insert(tree.to_string()) {
+        return;
+    }
+    for entry in Tree::from_hash(tree).entries {
+        match entry.mode.as_str() {
+            "40000" => mark_tree(&entry.hash, objects),
+            "160000" => {}
+            _ => {
+                objects.insert(entry.hash);
+            }
+        }
+    }
+}
+
+/// Lists a tree, then what is in it depth first, each with its path, skipping whatever was
+/// listed before or is `uninteresting`. Submodule commits aren't listed.
+fn list_tree(tree: &str, path: &str, seen: &mut HashSet<String>, uninteresting: &HashSet<String>) {
+    if uninteresting.contains(tree) || !seen.insert(tree.to_string()) {
+        return;
+    }
+    println!("{} {}", tree, path);
+    let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+    for entry in Tree::from_hash(tree).entries {
+        let entry_path = format!("{}{}", prefix, entry.name);
+        match entry.mode.as_str() {
+            "40000" => list_tree(&entry.hash, &entry_path, seen, uninteresting),
+            "160000" => {}
+            _ => {
+                if !uninteresting.contains(&entry.hash) && seen.insert(entry.hash.clone()) {
+                    println!("{} {}", entry.hash, entry_path);
+                }
+            }
+        }
+    }
+}
+
+pub fn rev_list(args: &Vec<String>) {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    // The left sides of `A...B`, and the tags, trees and blobs named, for `--objects`
+    let mut left_tips = Vec::new();
+    let mut tags = Vec::new();
+    let mut named_objects = Vec::new();
+    let mut order = WalkOrder::default();
+    let (mut reverse, mut count) = (false, false);
+    let (mut left_right, mut boundary, mut objects, mut all) = (false, false, false, false);
+    let mut max_count = None;
+    // `--not` flips whether the revisions after it are excluded
+    let mut not = false;
+    let mut revisions = 0;
+    let mut paths = Vec::new();
+    // Past a `--`, everything is a path, and before it nothing is
+    let separator = args.iter().skip(2).position(|arg| arg == "--").map(|position| position + 2);
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if Some(i) == separator {
+            paths.extend(args[i + 1..].iter().cloned());
+            break;
+        }
+        match arg {
+            "--reverse" => reverse = true,
+            "--count" => count = true,
+            "--left-right" => left_right = true,
+            "--boundary" => boundary = true,
+            "--objects" => objects = true,
+            "--all" => all = true,
+            "--not" => not = !not,
+            _ if order.parse(arg) => {}
+            "-n" | "--max-count" => {
+                i += 1;
+                max_count = Some(parse_count(args.get(i).unwrap_or_else(|| usage(&args[0]))));
+            }
+            _ if arg.starts_with("--max-count=") => max_count = Some(parse_count(&arg["--max-count=".len()..])),
+            _ if arg.starts_with("-n") => max_count = Some(parse_count(&arg[2..])),
+            _ if arg.starts_with('-') && arg[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                max_count = Some(arg[1..].parse().unwrap_or_else(|_| {
+                    println!("fatal: '{}': not a non-negative integer", &arg[1..]);
+                    process::exit(128);
+                }));
+            }
+            _ if arg.starts_with('-') => usage(&args[0]),
+            _ => {
+                // Without a `--`, what isn't a revision may be a path, and so is everything after it
+                let specs = if separator.is_none() && !paths.is_empty() { None } else { revision::parse_range(arg) };
+                match specs {
+                    Some(specs) => {
+                        revisions += 1;
+                        for spec in specs {
+                            if spec.excluded != not {
+                                exclude.push(revision::peel(&spec.hash, "commit").unwrap_or_else(|| revision::die_unknown_revision(arg)));
+                                continue;
+                            }
+                            if spec.left {
+                                left_tips.push(spec.hash.clone());
+                            }
+                            // A `rev:path` names its object by the path
+                            let path = revision::split_path(arg).map_or("", |(_, path)| path);
+                            named_objects.push((spec.hash, path.to_string()));
+                        }
+                    }
+                    None if separator.is_some() => {
+                        println!("fatal: bad revision '{}'", arg);
+                        process::exit(128);
+                    }
+                    None => {
+                        if !Path::new(arg).exists() {
+                            if paths.is_empty() {
+                                revision::die_unknown_revision(arg);
+                            }
+                            println!("fatal: {}: no such path in the working tree.", arg);
+                            println!("Use 'git <command> -- <path>...' to specify paths that do not exist locally.");
+                            process::exit(128);
+                        }
+                        paths.push(arg.to_string());
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    if all {
+        let tips = refs::list_refs("refs/").into_iter().map(|(_, hash)| hash).chain(refs::read_ref("HEAD"));
+        named_objects.extend(tips.map(|hash| (hash, String::new())));
+    } else if revisions == 0 {
+        usage(&args[0]);
+    }
+    // Tags are followed to what they point at; only commits are walked
+    for (mut hash, path) in std::mem::take(&mut named_objects) {
+        while ObjectReader::find_object_type(&hash) == "tag" {
+            let tag = Tag::from_hash(&hash);
+            tags.push((hash, tag.tag));
+            hash = tag.object;
+        }
+        if ObjectReader::find_object_type(&hash) == "commit" {
+            include.push(hash);
+        } else {
+            named_objects.push((hash, path));
+        }
+    }
+    let mut walk = CommitWalk::new(&include, &exclude);
+    if !paths.is_empty() {
+        walk = walk.limit_to_paths(Pathspec::new(&paths));
+    }
+    walk = order.apply(walk);
+    let excluded = walk.excluded().clone();
+    let walked: Vec<Commit> = if order.is_topological() { walk.into_topo_order() } else { walk.collect() };
+    let shown = &walked[..walked.len().min(max_count.unwrap_or(usize::MAX))];
+    // Boundary commits are the parents of shown commits that aren't shown themselves,
+    // ordered as git does after its walk
+    let mut boundary_commits = Vec::new();
+    if boundary {
+        let shown_hashes: HashSet<&str> = shown.iter().map(|commit| commit.hash.as_str()).collect();
+        let mut candidates = Vec::new();
+        for parent in shown.iter().flat_map(|commit| commit.parents.iter()) {
+            if !shown_hashes.contains(parent.as_str()) && !candidates.contains(parent) {
+                candidates.push(parent.clone());
+            }
+        }
+        let candidates = candidates.iter().rev().map(|hash| Commit::from_hash(hash)).collect();
+        boundary_commits = if order == WalkOrder::Date { revision::date_order(candidates) } else { revision::topo_order(candidates) };
+    }
+    let mut entries: Vec<(&str, &Commit)> = shown.iter().map(|commit| ("", commit)).chain(boundary_commits.iter().map(|commit| ("-", commit))).collect();
+    if reverse {
+        entries.reverse();
+    }
+    // Left commits are those reached from a left side, with their parents
+    let left: HashSet<String> = if left_tips.is_empty() {
+        HashSet::new()
+    } else {
+        CommitWalk::new(&left_tips, &exclude).flat_map(|commit| std::iter::once(commit.hash.clone()).chain(commit.parents)).collect()
+    };
+    if count {
+        let lefts = entries.iter().filter(|(_, commit)| left.contains(&commit.hash)).count();
+        if left_right {
+            println!("{}\t{}", lefts, entries.len() - lefts);
+        } else {
+            println!("{}", entries.len());
+        }
+        return;
+    }
+    for (mark, commit) in &entries {
+        let mark = match *mark {
+            "" if left_right => if left.contains(&commit.hash) { "<" } else { ">" },
+            mark => mark,
+        };
+        println!("{}{}", mark, commit.hash);
+    }
+    if !objects {
+        return;
+    }
+    // What the excluded parents of walked commits have isn't listed
+    let mut uninteresting = HashSet::new();
+    if !exclude.is_empty() {
+        let edges: HashSet<&String> = walked.iter().flat_map(|commit| commit.parents.iter()).filter(|parent| excluded.contains(*parent)).collect();
+        for edge in edges {
+            mark_tree(&Commit::from_hash(edge).tree, &mut uninteresting);
+        }
+    }
+    let mut seen = HashSet::new();
+    for (hash, name) in tags {
+        if seen.insert(hash.clone()) {
+            println!("{} {}", hash, name);
+        }
+    }
+    let trees = named_objects.into_iter().chain(entries.iter().map(|(_, commit)| (commit.tree.clone(), String::new())));
+    for (hash, path) in trees {
+        if ObjectReader::find_object_type(&hash) == "tree" {
+            list_tree(&hash, &path, &mut seen, &uninteresting);
+        } else if !uninteresting.contains(&hash) && seen.insert(hash.clone()) {
+            println!("{} {}", hash, path);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::process;
+
+use crate::git::ref_filter;
+use crate::git::revision::{self, RevisionSpec};
+
+/// The part of a revision before any `~`, `^` or `:`, which names a ref if anything does.
+fn ref_part(rev: &str) -> Option<&str> {
+    if rev.contains([':', '~', '^']) {
+        return None;
+    }
+    Some(rev)
+}
+
+pub fn rev_parse(args: &Vec<String>) {
+    let mut verify = false;
+    let mut quiet = false;
+    let mut short = None;
+    let mut abbrev_ref = false;
+    let mut symbolic_full_name = false;
+    let mut revs = Vec::new();
+    for arg in args[2..].iter() {
+        match arg.as_str() {
+            "--verify" => verify = true,
+            "-q" | "--quiet" => quiet = true,
+            "--short" => short = Some(7),
+            "--abbrev-ref" => abbrev_ref = true,
+            "--symbolic-full-name" => symbolic_full_name = true,
+            "--" => break,
+            _ => match arg.strip_prefix("--short=") {
+                Some(length) => short = Some(length.parse::<usize>().unwrap_or(7).clamp(4, 40)),
+                None => revs.push(arg.as_str()),
+            },
+        }
+    }
+
+    if abbrev_ref || symbolic_full_name {
+        for rev in revs {
+            match ref_part(rev).and_then(revision::resolve_ref_name) {
+                Some(name) if abbrev_ref => println!("{}", ref_filter::short_ref_name(&name)),
+                Some(name) => println!("{}", name),
+                None if revision::resolve(rev).is_some() => {}
+                None => revision::die_unknown_revision(rev),
+            }
+        }
+        return;
+    }
+
+    if verify {
+        let hash = match revs.as_slice() {
+            [rev] => revision::resolve(rev),
+            _ => None,
+        };
+        match hash {
+            Some(hash) => println!("{}", &hash[..short.unwrap_or(40)]),
+            None => {
+                if !quiet {
+                    println!("fatal: Needed a single revision");
+                }
+                process::exit(if quiet { 1 } else { 128 });
+            }
+        }
+        return;
+    }
+
+    for rev in revs {
+        let specs: Vec<RevisionSpec> = revision::parse_range(rev).unwrap_or_else(|| revision::die_unknown_revision(rev));
+        for spec in specs {
+            let hash = &spec.hash[..short.unwrap_or(40)];
+            if spec.excluded {
+                println!("^{}", hash);
+            } else {
+                println!("{}", hash);
+            }
+        }
+    }
+}
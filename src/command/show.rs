@@ -0,0 +1,222 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use colored::Colorize;
+
+use crate::command::log;
+use crate::git::date;
+use crate::git::diff_output::{self, DiffOptions, MergeDiff};
+use crate::git::ident::Signature;
+use crate::git::object::blob::Blob;
+use crate::git::object::commit::Commit;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::tag::Tag;
+use crate::git::object::tree::Tree;
+use crate::git::pathspec::Pathspec;
+use crate::git::pretty::{Format, PrettyOptions};
+use crate::git::revision::{self, CommitWalk};
+use crate::git::tree_diff;
+
+/// Prints commits as `log` does, each with its diff. `shown` is whether anything was shown
+/// before, which a separator then goes after.
+fn show_commits(commits: impl Iterator<Item = Commit>, pathspec: &Pathspec, diff: &DiffOptions, pretty: &PrettyOptions, shown: &mut bool) {
+    let terminated = pretty.format.is_terminated();
+    for commit in commits {
+        for (parent, diff) in log::commit_entries(&commit, None, pathspec, diff, pretty, diff_output::term_columns()) {
+            if *shown && !terminated {
+                println!();
+            }
+            print!("{}", commit.format_log(pretty, parent.as_deref()));
+            if terminated && !pretty.format.is_empty() {
+                println!();
+            }
+            print!("{}", diff);
+            *shown = true;
+        }
+    }
+}
+
+/// Whether `commit` changes the paths, which a merge only does if it differs from all of
+/// its parents.
+fn changes_paths(commit: &Commit, pathspec: &Pathspec) -> bool {
+    let parent_trees: Vec<Option<String>> = match commit.parents.is_empty() {
+        true => vec![None],
+        false => commit.parents.iter().map(|parent| Some(Commit::from_hash(parent).tree)).collect(),
+    };
+    parent_trees.iter().all(|tree| !tree_diff::diff_trees(tree.as_deref(), Some(&commit.tree), pathspec).is_empty())
+}
+
+/// Prints an annotated tag as git does: its name, its tagger as the format shows authors,
+/// then its message.
+fn show_tag(tag: &Tag, pretty: &PrettyOptions) {
+    println!("{} {}", "tag".yellow(), tag.tag.yellow());
+    if let Some(tagger) = tag.tagger.as_deref().and_then(Signature::parse) {
+        let date = || date::format(tagger.time, &tagger.offset, &pretty.date_mode).unwrap_or_default();
+        match pretty.format {
+            Format::Oneline => {}
+            Format::Medium => println!("Tagger: {}\nDate:   {}", tagger.identity(), date()),
+            Format::Fuller => println!("Tagger:     {}\nTaggerDate: {}", tagger.identity(), date()),
+            _ => println!("Tagger: {}", tagger.identity()),
+        }
+    }
+    print!("\n{}", tag.message);
+}
+
+/// Lists the entries of a tree by name, with a `/` after subtrees.
+fn show_tree(name: &str, tree: &Tree) {
+    println!("{} {}\n", "tree".yellow(), name.yellow());
+    for entry in &tree.entries {
+        println!("{}{}", entry.name, if entry.mode == "40000" { "/" } else { "" });
+    }
+}
+
+pub fn show(args: &Vec<String>) {
+    let mut pretty = PrettyOptions::default();
+    let mut diff = DiffOptions::default();
+    let mut merges_given = false;
+    let mut first_parent = false;
+    let mut objects = Vec::new();
+    let mut walked = false;
+    let mut paths = Vec::new();
+    let separator = args.iter().skip(2).position(|arg| arg == "--").map(|position| position + 2);
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if Some(i) == separator {
+            paths.extend(args[i + 1..].iter().cloned());
+            break;
+        }
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg, None),
+        };
+        match option {
+            "--oneline" => {
+                pretty.format = Format::Oneline;
+                pretty.abbrev_commit = true;
+            }
+            "--pretty" | "--format" => {
+                let value = match inline_value {
+                    Some(value) => value,
+                    None if option == "--pretty" => "medium".to_string(),
+                    None => {
+                        i += 1;
+                        args.get(i).cloned().unwrap_or_else(|| {
+                            println!("fatal: Option '{}' requires a value", option);
+                            process::exit(128);
+                        })
+                    }
+                };
+                pretty.format = Format::parse(&value).unwrap_or_else(|| {
+                    println!("fatal: invalid --pretty format: {}", value);
+                    process::exit(128);
+                });
+            }
+            "--abbrev-commit" => pretty.abbrev_commit = true,
+            "--no-abbrev-commit" => pretty.abbrev_commit = false,
+            "--decorate" => pretty.decorate = true,
+            "--no-decorate" => pretty.decorate = false,
+            "--show-signature" => pretty.show_signature = true,
+            "--first-parent" => first_parent = true,
+            _ if diff.parse(arg) => merges_given |= ["-m", "-c", "--cc", "--no-diff-merges"].contains(&arg) || arg.starts_with("--diff-merges="),
+            _ if arg.starts_with('-') => {
+                println!("fatal: unrecognized argument: {}", arg);
+                process::exit(128);
+            }
+            _ => {
+                // Without a `--`, what isn't a revision may be a path, and so is everything after it
+                let specs = if separator.is_none() && !paths.is_empty() { None } else { revision::parse_range(arg) };
+                match specs {
+                    Some(specs) => {
+                        walked |= specs.len() > 1 || specs.iter().any(|spec| spec.excluded);
+                        objects.extend(specs.into_iter().map(|spec| (arg.to_string(), spec)));
+                    }
+                    None if separator.is_some() => {
+                        println!("fatal: bad revision '{}'", arg);
+                        process::exit(128);
+                    }
+                    None => {
+                        if !Path::new(arg).exists() {
+                            if paths.is_empty() {
+                                revision::die_unknown_revision(arg);
+                            }
+                            println!("fatal: {}: no such path in the working tree.", arg);
+                            println!("Use 'git <command> -- <path>...' to specify paths that do not exist locally.");
+                            process::exit(128);
+                        }
+                        paths.push(arg.to_string());
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+    if objects.is_empty() {
+        objects.push(("HEAD".to_string(), revision::parse_range("HEAD").unwrap().remove(0)));
+    }
+    // Merges are shown with dense combined diffs unless asked otherwise, or against their
+    // first parent when that is all that is followed
+    if first_parent && !diff.merges.is_combined() {
+        diff.merges = MergeDiff::FirstParent;
+    } else if !merges_given {
+        diff.merges = MergeDiff::DenseCombined;
+    }
+    if !diff.shows_diff() && !diff.no_output {
+        diff.patch = true;
+    }
+    diff.finish();
+    let pathspec = Pathspec::new(&paths);
+    let mut shown = false;
+    // Ranges make `show` walk history like `log`, from everything it was given
+    if walked {
+        let peel = |hash: &str, arg: &str| revision::peel(hash, "commit").unwrap_or_else(|| revision::die_unknown_revision(arg));
+        let include: Vec<String> = objects.iter().filter(|(_, spec)| !spec.excluded).map(|(arg, spec)| peel(&spec.hash, arg)).collect();
+        let exclude: Vec<String> = objects.iter().filter(|(_, spec)| spec.excluded).map(|(arg, spec)| peel(&spec.hash, arg)).collect();
+        let mut walk = CommitWalk::new(&include, &exclude);
+        if first_parent {
+            walk = walk.first_parent();
+        }
+        if !paths.is_empty() {
+            walk = walk.limit_to_paths(pathspec.clone());
+        }
+        show_commits(walk, &pathspec, &diff, &pretty, &mut shown);
+        return;
+    }
+    for (name, spec) in objects {
+        let mut hash = spec.hash;
+        // A tag is followed by what it points at
+        loop {
+            match ObjectReader::find_object_type(&hash) {
+                "tag" => {
+                    if shown {
+                        println!();
+                    }
+                    let tag = Tag::from_hash(&hash);
+                    show_tag(&tag, &pretty);
+                    shown = true;
+                    hash = tag.object;
+                    continue;
+                }
+                "tree" => {
+                    if shown {
+                        println!();
+                    }
+                    show_tree(&name, &Tree::from_hash(&hash));
+                    shown = true;
+                }
+                "blob" => {
+                    io::stdout().write_all(&Blob::from_hash(&hash).content).unwrap();
+                }
+                _ => {
+                    // Commits that don't change the paths aren't shown
+                    let commit = Commit::from_hash(&hash);
+                    if paths.is_empty() || changes_paths(&commit, &pathspec) {
+                        show_commits(std::iter::once(commit), &pathspec, &diff, &pretty, &mut shown);
+                    }
+                }
+            }
+            break;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use crate::git::config::Config;
+use crate::git::index::Index;
+use crate::git::sparse::{SparseCheckout, SPARSE_CHECKOUT_PATH};
+use crate::git::worktree;
+
+/// Sets skip-worktree on entries outside the cone and removes their files, and brings back
+/// the files of entries that are inside it again. `None` materializes everything. The
+/// caller holds the index lock, which this releases.
+fn apply_sparse_checkout(sparse: Option<&SparseCheckout>) {
+    let mut index = Index::read_index();
+    for entry in index.entries.iter_mut() {
+        let included = sparse.is_none_or(|sparse| sparse.contains(&entry.name));
+        if included && entry.is_skip_worktree() {
+            entry.set_skip_worktree(false);
+            if fs::symlink_metadata(&entry.name).is_err() {
+                worktree::write_file(&entry.name, entry.mode, &entry.sha1);
+                entry.refresh_stat();
+            }
+        } else if !included && !entry.is_skip_worktree() {
+            if fs::symlink_metadata(&entry.name).is_ok() && !worktree::file_matches(&entry.name, entry.mode, &entry.sha1) {
+                println!("warning: not removing modified file outside the sparse checkout: {}", entry.name);
+                continue;
+            }
+            worktree::remove_file(&entry.name);
+            entry.set_skip_worktree(true);
+        }
+    }
+    index.write_locked_index();
+}
+
+fn enable_sparse_checkout(sparse: &SparseCheckout) {
+    Config::set_value("core.sparseCheckout", "true");
+    Config::set_value("core.sparseCheckoutCone", "true");
+    // Like git, the patterns are left alone when the index can't be updated to match them
+    Index::lock_or_exit();
+    sparse.write();
+    apply_sparse_checkout(Some(sparse));
+}
+
+fn read_directories(args: &[String]) -> BTreeSet<String> {
+    let directories: BTreeSet<String> = args.iter()
+        .filter(|arg| !arg.starts_with("--"))
+        .map(|arg| SparseCheckout::normalize_directory(arg))
+        .filter(|directory| !directory.is_empty())
+        .collect();
+    for directory in directories.iter() {
+        if Path::new(directory).is_file() {
+            println!("fatal: '{}' is not a directory; cone mode only matches directories", directory);
+            process::exit(128);
+        }
+    }
+    directories
+}
+
+fn current_sparse_checkout() -> SparseCheckout {
+    match SparseCheckout::from_file() {
+        Some(sparse) => sparse,
+        None => {
+            println!("fatal: this worktree is not sparse");
+            process::exit(128);
+        }
+    }
+}
+
+pub fn sparse_checkout(args: &Vec<String>) {
+    if args.len() < 3 {
+        println!("Usage: {} sparse-checkout (init | set | add | list | disable) [<directory>...]", args[0]);
+        process::exit(1);
+    }
+    if args.iter().any(|arg| arg == "--no-cone") {
+        println!("fatal: only cone mode sparse checkouts are supported");
+        process::exit(128);
+    }
+    match args[2].as_str() {
+        "init" => {
+            // Keep existing patterns so re-running init doesn't lose the cone
+            let sparse = if Path::new(SPARSE_CHECKOUT_PATH).exists() {
+                SparseCheckout::parse(&fs::read_to_string(SPARSE_CHECKOUT_PATH).unwrap())
+            } else {
+                SparseCheckout::default()
+            };
+            enable_sparse_checkout(&sparse);
+        }
+        "set" => enable_sparse_checkout(&SparseCheckout::new(read_directories(&args[3..]))),
+        "add" => {
+            let mut directories = current_sparse_checkout().directories;
+            directories.extend(read_directories(&args[3..]));
+            enable_sparse_checkout(&SparseCheckout::new(directories));
+        }
+        "list" => {
+            for directory in current_sparse_checkout().directories {
+                println!("{}", directory);
+            }
+        }
+        "disable" => {
+            Index::lock_or_exit();
+            apply_sparse_checkout(None);
+            Config::set_value("core.sparseCheckout", "false");
+        }
+        subcommand => {
+            println!("Unknown sparse-checkout subcommand: {}", subcommand);
+            process::exit(1);
+        }
+    }
+}
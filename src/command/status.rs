@@ -3,6 +3,8 @@ use std::fs;
 use std::time::SystemTime;
 use colored::*;
 
+use crate::git::config::Config;
+use crate::git::fsmonitor::FsMonitorChanges;
 use crate::git::gitignore::GitIgnore;
 use crate::git::head::Head;
 use crate::git::index::Index;
@@ -10,8 +12,55 @@ use crate::git::object::blob::compute_file_hash;
 use crate::git::object::commit::Commit;
 use crate::git::object::objectreader::ObjectReader;
 use crate::git::object::tree::Tree;
+use crate::git::sparse::SparseCheckout;
+use crate::git::untracked_cache::{StatData, UntrackedCache};
 
-fn get_untracked_files(index: &Index, gitignore: &GitIgnore) -> Vec<String> {
+/// Lists the untracked files and directories directly inside `directory`.
+fn list_untracked(directory: &str, tracked_directories: &HashSet<String>, tracked_files: &HashSet<String>, gitignore: &GitIgnore) -> Vec<String> {
+    let mut untracked_files = Vec::new();
+    let Ok(files) = fs::read_dir(directory) else {
+        return untracked_files;
+    };
+    for file in files {
+        let file = file.unwrap();
+        if file.path().file_name().unwrap().to_string_lossy().as_ref() == ".git" {
+            continue;
+        }
+        let mut path = file.path().to_string_lossy().to_string();
+        if directory == "." {
+            path = path[2..].to_string();
+        }
+        if gitignore.is_ignored(path.as_str()) {
+            continue;
+        }
+        if file.path().is_dir() && !tracked_directories.contains(path.as_str()) {
+            untracked_files.push(path + "/");
+        } else if file.path().is_file() && !tracked_files.contains(path.as_str()) {
+            untracked_files.push(path);
+        }
+    }
+    untracked_files
+}
+
+/// Returns the untracked entries of `directory` from the cache, rescanning it only when the
+/// monitor or its stat data says it changed since the cached listing was taken.
+fn cached_untracked(cache: &mut UntrackedCache, directory: &str, fsmonitor: Option<&FsMonitorChanges>, list: &dyn Fn() -> Vec<String>) -> Vec<String> {
+    let prefix = if directory == "." { String::new() } else { format!("{}/", directory) };
+    let cached = cache.dir_mut(directory);
+    let unchanged = fsmonitor.is_some_and(|changes| !changes.is_directory_changed(directory));
+    if !cached.valid || !unchanged {
+        // Take the stat data first so a change during the scan invalidates it next time
+        let stat = StatData::from_path(directory);
+        if !cached.valid || cached.stat != stat {
+            cached.untracked = list().iter().map(|path| path[prefix.len()..].to_string()).collect();
+            cached.stat = stat;
+            cached.valid = true;
+        }
+    }
+    cached.untracked.iter().map(|name| format!("{}{}", prefix, name)).collect()
+}
+
+fn get_untracked_files(index: &Index, gitignore: &GitIgnore, sparse: Option<&SparseCheckout>, fsmonitor: Option<&FsMonitorChanges>, mut untracked_cache: Option<&mut UntrackedCache>) -> Vec<String> {
     let mut untracked_files = Vec::new();
     let mut tracked_directories = HashSet::new();
     let mut tracked_files = HashSet::new();
@@ -31,26 +80,24 @@ fn get_untracked_files(index: &Index, gitignore: &GitIgnore) -> Vec<String> {
         }
     }
 
-    for directory in &tracked_directories {
-        let files = fs::read_dir(directory).unwrap();
-        for file in files {
-            let file = file.unwrap();
-            if file.path().file_name().unwrap().to_string_lossy().as_ref() == ".git" {
-                continue;
-            }
-            let mut path = file.path().to_string_lossy().to_string();
-            if directory == "." {
-                path = path[2..].to_string();
-            }
-            if gitignore.is_ignored(path.as_str()) {
-                continue;
-            }
-            if file.path().is_dir() && !tracked_directories.contains(path.as_str()) {
-                untracked_files.push(path + "/");
-            } else if file.path().is_file() && !tracked_files.contains(path.as_str()) {
-                untracked_files.push(path);
-            }
-        }
+    if let Some(cache) = untracked_cache.as_deref_mut() {
+        let gitignore_hash = index.get_entry(".gitignore").map(|entry| entry.sha1.as_str());
+        cache.check_exclude_hash(gitignore_hash);
+        cache.prune(&|path| tracked_directories.contains(path));
+    }
+    let mut directories: Vec<&String> = tracked_directories.iter().collect();
+    directories.sort();
+    for directory in directories {
+        let list = || list_untracked(directory, &tracked_directories, &tracked_files, gitignore);
+        let files = match untracked_cache.as_deref_mut() {
+            Some(cache) => cached_untracked(cache, directory, fsmonitor, &list),
+            None => list(),
+        };
+        // Paths outside the sparse checkout are not reported
+        untracked_files.extend(files.into_iter().filter(|path| sparse.is_none_or(|sparse| match path.strip_suffix('/') {
+            Some(directory) => sparse.contains_directory(directory),
+            None => sparse.contains(path),
+        })));
     }
     untracked_files
 }
@@ -75,17 +122,21 @@ impl ChangedFile {
     }
 }
 
-fn get_unstaged_files(commit: &Commit, index: &Index, gitignore: &GitIgnore) -> Vec<ChangedFile> {
+fn get_unstaged_files(commit: &Commit, index: &Index, gitignore: &GitIgnore, fsmonitor: Option<&FsMonitorChanges>) -> Vec<ChangedFile> {
     let mut unstaged_files = Vec::new();
-    let commit_timestamp = commit.timestamp.parse::<u64>().unwrap();
-    for entry in index.entries.iter() {
+    let commit_timestamp = commit.committer.time as u64;
+    for (position, entry) in index.entries.iter().enumerate() {
+        // Entries the monitor has not seen change since the last refresh are still clean
+        if entry.is_skip_worktree() || fsmonitor.is_some_and(|changes| !changes.is_changed(position, &entry.name)) {
+            continue;
+        }
         if !fs::exists(&entry.name).unwrap() {
             unstaged_files.push(ChangedFile::new(entry.name.clone(), FileStatus::Deleted));
             continue;
         }
         let modified_time = fs::metadata(&entry.name).unwrap().modified().unwrap()
                     .duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-        if !gitignore.is_ignored(&entry.name) && modified_time > commit_timestamp {
+        if !gitignore.is_ignored(&entry.name) && (fsmonitor.is_some() || modified_time > commit_timestamp) {
             let hash = compute_file_hash(&entry.name);
             if entry.sha1 != hash {
                 unstaged_files.push(ChangedFile::new(entry.name.clone(), FileStatus::Modified));
@@ -179,12 +230,40 @@ pub fn status(args: &Vec<String>) {
     let head = Head::from_head();
     let commit = Commit::from_hash(&head.head_hash);
     let tree = Tree::from_hash(&commit.tree);
-    let index = Index::read_index();
+    // The index is only written back if it could be locked before reading it, and status
+    // goes on without saving its caches when another process holds the lock, as in git
+    let locked = Index::lock().is_ok();
+    let mut index = Index::read_index();
     let gitignore = GitIgnore::from_file();
+    let fsmonitor = FsMonitorChanges::query(&index);
+    // core.untrackedCache adds or removes the cache, leaving it unset keeps whatever is there
+    let untracked_cache_setting = Config::read().get("core.untrackedCache").and_then(|value| Config::parse_bool(&value));
+    let had_untracked_cache = index.untracked_cache.is_some();
+    let mut untracked_cache = index.untracked_cache.take().filter(|cache| cache.is_usable());
+    match untracked_cache_setting {
+        Some(true) if untracked_cache.is_none() => untracked_cache = Some(UntrackedCache::new()),
+        Some(false) => untracked_cache = None,
+        _ => {}
+    }
     let staged_files = get_staged_files(&index, &tree, &gitignore);
-    let unstaged_files = get_unstaged_files(&commit, &index, &gitignore);
-    let untracked_files = get_untracked_files(&index, &gitignore);
-    println!("On branch {}", head.ref_path.file_name().unwrap().to_string_lossy());
+    let unstaged_files = get_unstaged_files(&commit, &index, &gitignore, fsmonitor.as_ref());
+    let untracked_files = get_untracked_files(&index, &gitignore, SparseCheckout::from_file().as_ref(), fsmonitor.as_ref(), untracked_cache.as_mut());
+    let mut index_changed = untracked_cache.is_some() || (had_untracked_cache && untracked_cache_setting == Some(false));
+    index.untracked_cache = untracked_cache;
+    if let Some(fsmonitor) = fsmonitor {
+        let dirty_paths = unstaged_files.iter().map(|file| file.path.clone()).collect();
+        index.fsmonitor = Some(fsmonitor.to_extension(&index, &dirty_paths));
+        index_changed = true;
+    }
+    if locked && index_changed {
+        index.write_locked_index();
+    } else if locked {
+        Index::unlock();
+    }
+    match head.branch_name() {
+        Some(branch) => println!("On branch {}", branch),
+        None => println!("HEAD detached at {}", head.abbreviated_hash()),
+    }
     if !staged_files.is_empty() {
         println!();
         println!("Changes to be committed:");
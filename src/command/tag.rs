@@ -0,0 +1,222 @@
+use std::fs;
+use std::process;
+
+use crate::git::ident::{self, Role};
+use crate::git::object::commit::Commit;
+use crate::git::object::tag::Tag;
+use crate::git::ref_filter;
+use crate::git::refs;
+use crate::git::revision;
+use crate::git::signing;
+
+struct ListedTag {
+    name: String,
+    hash: String,
+}
+
+impl ListedTag {
+    fn tag_object(&self) -> Option<Tag> {
+        match revision::object_type(&self.hash) {
+            Some("tag") => Some(Tag::from_hash(&self.hash)),
+            _ => None,
+        }
+    }
+
+    /// The tagger date of annotated tags and the commit date of lightweight ones.
+    fn creator_date(&self) -> i64 {
+        match self.tag_object() {
+            Some(tag) => tag.timestamp().unwrap_or(0),
+            None if revision::object_type(&self.hash) == Some("commit") => Commit::from_hash(&self.hash).committer.time,
+            None => 0,
+        }
+    }
+
+    /// The annotation, or the message of the tagged commit for lightweight tags.
+    fn message(&self) -> String {
+        match (self.tag_object(), revision::object_type(&self.hash)) {
+            (Some(tag), _) => tag.message,
+            (None, Some("commit")) => Commit::from_hash(&self.hash).message(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn tag_ref(name: &str) -> String {
+    format!("refs/tags/{}", name)
+}
+
+fn list_tags(patterns: &[String], sort: &str, lines: Option<usize>) {
+    let patterns: Vec<glob::Pattern> = patterns.iter().filter_map(|pattern| glob::Pattern::new(pattern).ok()).collect();
+    let mut tags: Vec<ListedTag> = refs::list_refs("refs/tags/").into_iter()
+        .map(|(name, hash)| ListedTag { name: name["refs/tags/".len()..].to_string(), hash })
+        .filter(|tag| patterns.is_empty() || patterns.iter().any(|pattern| pattern.matches(&tag.name)))
+        .collect();
+    let (reverse, key) = match sort.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, sort),
+    };
+    match key {
+        "refname" => {}
+        "version:refname" | "v:refname" => tags.sort_by(|a, b| ref_filter::version_cmp(&a.name, &b.name)),
+        "creatordate" | "taggerdate" => tags.sort_by_cached_key(|tag| tag.creator_date()),
+        _ => {
+            println!("fatal: unsupported sort key '{}'", sort);
+            process::exit(128);
+        }
+    }
+    if reverse {
+        tags.reverse();
+    }
+    for tag in tags {
+        let Some(lines) = lines else {
+            println!("{}", tag.name);
+            continue;
+        };
+        let message = tag.message();
+        let mut message_lines = message.trim_end().lines().take(lines);
+        println!("{:<15} {}", tag.name, message_lines.next().unwrap_or(""));
+        for line in message_lines {
+            println!("    {}", line);
+        }
+    }
+}
+
+fn delete_tags(names: &[String]) {
+    let mut failed = false;
+    for name in names {
+        match refs::read_ref(&tag_ref(name)) {
+            Some(hash) => {
+                refs::delete_ref(&tag_ref(name));
+                println!("Deleted tag '{}' (was {})", name, &hash[..7]);
+            }
+            None => {
+                println!("error: tag '{}' not found.", name);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Creates the tag, as an annotated one when there's a message, signed with
+/// `signing_key` when `sign` is set.
+fn create_tag(name: &str, rev: &str, message: Option<String>, force: bool, sign: bool, signing_key: Option<&str>) {
+    if !refs::is_valid_ref_name(&tag_ref(name)) {
+        println!("fatal: '{}' is not a valid tag name.", name);
+        process::exit(128);
+    }
+    let previous = refs::read_ref(&tag_ref(name));
+    if previous.is_some() && !force {
+        println!("fatal: tag '{}' already exists", name);
+        process::exit(128);
+    }
+    let Some(object) = revision::resolve(rev) else {
+        println!("fatal: Failed to resolve '{}' as a valid ref.", rev);
+        process::exit(128);
+    };
+    let hash = match message {
+        Some(message) => {
+            let kind = revision::object_type(&object).unwrap().to_string();
+            let mut tag = Tag::new(object, kind, name.to_string(), Some(ident::signature_or_exit(Role::Committer).to_string()), message);
+            if sign {
+                if let Err(error) = tag.sign(signing_key) {
+                    println!("error: {}", error);
+                    println!("fatal: unable to sign the tag");
+                    process::exit(128);
+                }
+            }
+            tag.write()
+        }
+        None => object,
+    };
+    refs::write_ref(&tag_ref(name), &hash, "");
+    if let Some(previous) = previous.filter(|previous| *previous != hash) {
+        println!("Updated tag '{}' (was {})", name, &previous[..7]);
+    }
+}
+
+pub fn tag(args: &Vec<String>) {
+    let mut list = false;
+    let mut delete = false;
+    let mut annotate = false;
+    let mut force = false;
+    let mut sign = signing::sign_by_default("tag.gpgSign");
+    let mut signing_key = None;
+    let mut lines = None;
+    let mut sort = "refname".to_string();
+    let mut paragraphs = Vec::new();
+    let mut names = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-l" | "--list" => list = true,
+            "-d" | "--delete" => delete = true,
+            "-a" | "--annotate" => annotate = true,
+            "-f" | "--force" => force = true,
+            "-s" | "--sign" => sign = true,
+            "--no-sign" => sign = false,
+            "-m" | "--message" | "-F" | "--file" | "-u" | "--local-user" => {
+                i += 1;
+                let Some(value) = args.get(i) else {
+                    println!("error: switch `{}' requires a value", arg.trim_start_matches('-'));
+                    process::exit(129);
+                };
+                if arg == "-u" || arg == "--local-user" {
+                    sign = true;
+                    signing_key = Some(value.to_string());
+                } else if arg == "-F" || arg == "--file" {
+                    paragraphs.push(fs::read_to_string(value).unwrap_or_else(|_| {
+                        println!("fatal: could not open or read '{}'", value);
+                        process::exit(128);
+                    }));
+                } else {
+                    paragraphs.push(value.to_string());
+                }
+            }
+            _ => {
+                if let Some(value) = arg.strip_prefix("--message=") {
+                    paragraphs.push(value.to_string());
+                } else if let Some(value) = arg.strip_prefix("--local-user=") {
+                    sign = true;
+                    signing_key = Some(value.to_string());
+                } else if let Some(value) = arg.strip_prefix("--sort=") {
+                    sort = value.to_string();
+                } else if let Some(count) = arg.strip_prefix("-n") {
+                    lines = Some(if count.is_empty() { 1 } else { count.parse().unwrap_or(1) });
+                } else if arg.starts_with('-') {
+                    println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                    process::exit(129);
+                } else {
+                    names.push(arg.to_string());
+                }
+            }
+        }
+        i += 1;
+    }
+
+    if delete {
+        delete_tags(&names);
+    } else if list || lines.is_some() || names.is_empty() {
+        list_tags(&names, &sort, lines);
+    } else if names.len() > 2 {
+        println!("fatal: too many arguments");
+        process::exit(128);
+    } else {
+        let rev = names.get(1).map(|rev| rev.as_str()).unwrap_or("HEAD");
+        let message = match (annotate || sign, paragraphs.is_empty()) {
+            (_, false) => {
+                let message = paragraphs.iter().map(|paragraph| paragraph.trim_end()).collect::<Vec<&str>>().join("\n\n");
+                Some(format!("{}\n", message))
+            }
+            (true, true) => {
+                println!("fatal: no tag message given; use -m or -F");
+                process::exit(128);
+            }
+            (false, true) => None,
+        };
+        create_tag(&names[0], rev, message, force, sign, signing_key.as_deref());
+    }
+}
@@ -0,0 +1,186 @@
+use std::io::{self, BufRead};
+use std::process;
+
+use crate::git::ref_transaction::{RefAction, RefTransaction, RefUpdate};
+use crate::git::reflog;
+use crate::git::revision;
+
+fn die(message: &str) -> ! {
+    println!("fatal: {}", message);
+    process::exit(128);
+}
+
+/// Resolves an object id argument, allowing the null id which stands for a missing ref.
+fn resolve_value(value: &str) -> Option<String> {
+    if value == reflog::NULL_HASH {
+        return Some(value.to_string());
+    }
+    revision::resolve(value)
+}
+
+/// Reads the next command with its arguments. With `-z` the arguments after the ref are
+/// NUL-terminated fields of their own, which are empty when an optional value is left out.
+fn read_command(input: &mut impl Iterator<Item = Vec<u8>>, nul: bool) -> Option<(String, Vec<String>)> {
+    let record = String::from_utf8_lossy(&input.next()?).to_string();
+    let (command, rest) = record.split_once(' ').unwrap_or((record.as_str(), ""));
+    if !nul {
+        return Some((command.to_string(), rest.split(' ').filter(|field| !field.is_empty()).map(|field| field.to_string()).collect()));
+    }
+    if rest.is_empty() {
+        return Some((command.to_string(), Vec::new()));
+    }
+    let mut fields = vec![rest.to_string()];
+    let values: &[&str] = match command {
+        "update" => &["<newvalue>", "<oldvalue>"],
+        "create" => &["<newvalue>"],
+        "delete" | "verify" => &["<oldvalue>"],
+        _ => &[],
+    };
+    for what in values {
+        match input.next() {
+            Some(value) => fields.push(String::from_utf8_lossy(&value).to_string()),
+            None => die(&format!("{} {}: unexpected end of input when reading {}", command, fields[0], what)),
+        }
+    }
+    Some((command.to_string(), fields))
+}
+
+/// Reads `update`, `create`, `delete` and `verify` commands from stdin into transactions,
+/// one per line or, with `nul`, NUL-terminated. Without `start` every command goes into one
+/// transaction that is committed at the end of the input, as is whatever follows the last
+/// `commit`.
+fn update_refs_stdin(message: &str, nul: bool) {
+    let stdin = io::stdin().lock();
+    let mut input = stdin.split(if nul { b'\0' } else { b'\n' }).map(|record| record.unwrap());
+    let mut transaction = RefTransaction::new();
+    let mut deref = true;
+    while let Some((command, fields)) = read_command(&mut input, nul) {
+        let command = command.as_str();
+        // An empty field is a value left out
+        let value = |i: usize, what: &str| -> Option<String> {
+            let value = fields.get(i).filter(|value| !value.is_empty())?;
+            match resolve_value(value) {
+                Some(hash) => Some(hash),
+                None => die(&format!("{} {}: invalid {}: {}", command, fields[0], what, value)),
+            }
+        };
+        let action = match command {
+            "start" | "prepare" | "commit" | "abort" => {
+                // A prepared transaction can only be committed or aborted
+                if let ("start" | "prepare", Err(error)) = (command, transaction.check_open()) {
+                    die(&error);
+                }
+                let result = match command {
+                    "start" => Ok(()),
+                    "prepare" => transaction.prepare(),
+                    "commit" => transaction.commit(),
+                    _ => {
+                        transaction.abort();
+                        Ok(())
+                    }
+                };
+                if let Err(error) = result {
+                    die(&format!("{}: {}", command, error));
+                }
+                if command == "commit" || command == "abort" {
+                    transaction = RefTransaction::new();
+                }
+                println!("{}: ok", command);
+                continue;
+            }
+            "option" => {
+                match fields.first().map(|option| option.as_str()) {
+                    Some("no-deref") => deref = false,
+                    _ => die(&format!("option unknown: {}", fields.join(" "))),
+                }
+                continue;
+            }
+            "update" | "create" | "delete" | "verify" if fields.first().is_none_or(|name| name.is_empty()) => die(&format!("{}: missing <ref>", command)),
+            "update" => match value(1, "<newvalue>") {
+                Some(new) => RefUpdate::new(&fields[0], RefAction::Update(new), value(2, "<oldvalue>").as_deref(), Some(message)),
+                // With `-z` the field is there, and an empty one deletes the ref
+                None if fields.len() > 1 => {
+                    println!("warning: update {}: missing <newvalue>, treating as zero", fields[0]);
+                    RefUpdate::new(&fields[0], RefAction::Delete, value(2, "<oldvalue>").as_deref(), Some(message))
+                }
+                None => die(&format!("update {}: missing <newvalue>", fields[0])),
+            },
+            "create" => match value(1, "<newvalue>") {
+                Some(new) => RefUpdate::new(&fields[0], RefAction::Update(new), Some(reflog::NULL_HASH), Some(message)),
+                None => die(&format!("create {}: missing <newvalue>", fields[0])),
+            },
+            "delete" => RefUpdate::new(&fields[0], RefAction::Delete, value(1, "<oldvalue>").as_deref(), Some(message)),
+            // Verifying without an old value checks that the ref doesn't exist
+            "verify" => RefUpdate::new(&fields[0], RefAction::Verify, Some(&value(1, "<oldvalue>").unwrap_or(reflog::NULL_HASH.to_string())), Some(message)),
+            _ => die(format!("unknown command: {} {}", command, fields.join(" ")).trim_end()),
+        };
+        if let Err(error) = transaction.add(RefUpdate { deref, ..action }) {
+            die(&error);
+        }
+        deref = true;
+    }
+    if let Err(error) = transaction.commit() {
+        die(&error);
+    }
+}
+
+pub fn update_ref(args: &Vec<String>) {
+    let mut message = None;
+    let mut delete = false;
+    let mut deref = true;
+    let mut stdin = false;
+    let mut nul = false;
+    let mut values = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-m" => {
+                i += 1;
+                match args.get(i) {
+                    Some(value) => message = Some(value.to_string()),
+                    None => {
+                        println!("error: switch `m' requires a value");
+                        process::exit(129);
+                    }
+                }
+            }
+            "-d" => delete = true,
+            "--no-deref" => deref = false,
+            "--stdin" => stdin = true,
+            "-z" => nul = true,
+            _ if arg.starts_with('-') => {
+                println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                process::exit(129);
+            }
+            _ => values.push(arg.to_string()),
+        }
+        i += 1;
+    }
+
+    if stdin {
+        return update_refs_stdin(message.as_deref().unwrap_or(""), nul);
+    }
+    let expected = if delete { 1..=2 } else { 2..=3 };
+    if !expected.contains(&values.len()) {
+        println!("usage: git update-ref [<options>] -d <refname> [<old-val>]");
+        println!("   or: git update-ref [<options>]    <refname> <new-val> [<old-val>]");
+        println!("   or: git update-ref [<options>] --stdin [-z]");
+        process::exit(129);
+    }
+    let name = values[0].as_str();
+    let resolve = |value: &str| resolve_value(value).unwrap_or_else(|| die(&format!("{}: not a valid SHA1", value)));
+    let old_index = if delete { 1 } else { 2 };
+    let old = values.get(old_index).map(|old| resolve(old));
+    let action = if delete { RefAction::Delete } else { RefAction::Update(resolve(&values[1])) };
+    let mut transaction = RefTransaction::new();
+    let update = RefUpdate::new(name, action, old.as_deref(), Some(message.as_deref().unwrap_or("")));
+    if let Err(error) = transaction.add(RefUpdate { deref, ..update }).and_then(|_| transaction.commit()) {
+        // Deleting reports a failed check as an error rather than dying
+        if delete {
+            println!("error: {}", error);
+            process::exit(1);
+        }
+        die(&format!("update_ref failed for ref '{}': {}", name, error));
+    }
+}
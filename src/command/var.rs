@@ -0,0 +1,17 @@
+use std::process;
+
+use crate::git::editor;
+use crate::git::ident::{self, Role};
+
+pub fn var(args: &Vec<String>) {
+    let value = match args.get(2).map(|arg| arg.as_str()) {
+        Some("GIT_AUTHOR_IDENT") if args.len() == 3 => ident::signature_or_exit(Role::Author).to_string(),
+        Some("GIT_COMMITTER_IDENT") if args.len() == 3 => ident::signature_or_exit(Role::Committer).to_string(),
+        Some("GIT_EDITOR") if args.len() == 3 => editor::editor(),
+        _ => {
+            println!("usage: {} var <variable>", args[0]);
+            process::exit(129);
+        }
+    };
+    println!("{}", value);
+}
@@ -0,0 +1,55 @@
+use std::process;
+
+use crate::git::object::commit::Commit;
+use crate::git::revision;
+
+pub fn verify_commit(args: &Vec<String>) {
+    let mut verbose = false;
+    let mut names = Vec::new();
+    for arg in args[2..].iter() {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            arg if arg.starts_with('-') => {
+                println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                println!("usage: {} verify-commit [-v | --verbose] <commit>...", args[0]);
+                process::exit(129);
+            }
+            arg => names.push(arg),
+        }
+    }
+    if names.is_empty() {
+        println!("usage: {} verify-commit [-v | --verbose] <commit>...", args[0]);
+        process::exit(129);
+    }
+
+    let mut failed = false;
+    for name in names {
+        let Some(hash) = revision::resolve(name) else {
+            println!("error: commit '{}' not found.", name);
+            failed = true;
+            continue;
+        };
+        match revision::object_type(&hash) {
+            Some("commit") => {}
+            kind => {
+                println!("error: {}: cannot verify a non-commit object of type {}.", name, kind.unwrap_or("unknown"));
+                failed = true;
+                continue;
+            }
+        }
+        let commit = Commit::from_hash(&hash);
+        if verbose {
+            print!("{}", String::from_utf8_lossy(&commit.signed_payload()));
+        }
+        match commit.check_signature() {
+            Some(check) => {
+                print!("{}", check.output);
+                failed |= !check.is_good();
+            }
+            None => failed = true,
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,58 @@
+use std::process;
+
+use crate::git::object::tag::Tag;
+use crate::git::revision;
+
+pub fn verify_tag(args: &Vec<String>) {
+    let mut verbose = false;
+    let mut names = Vec::new();
+    for arg in args[2..].iter() {
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            arg if arg.starts_with('-') => {
+                println!("error: unknown option `{}'", arg.trim_start_matches('-'));
+                println!("usage: {} verify-tag [-v | --verbose] <tag>...", args[0]);
+                process::exit(129);
+            }
+            arg => names.push(arg),
+        }
+    }
+    if names.is_empty() {
+        println!("usage: {} verify-tag [-v | --verbose] <tag>...", args[0]);
+        process::exit(129);
+    }
+
+    let mut failed = false;
+    for name in names {
+        let Some(hash) = revision::resolve(name) else {
+            println!("error: tag '{}' not found.", name);
+            failed = true;
+            continue;
+        };
+        match revision::object_type(&hash) {
+            Some("tag") => {}
+            kind => {
+                println!("error: {}: cannot verify a non-tag object of type {}.", name, kind.unwrap_or("unknown"));
+                failed = true;
+                continue;
+            }
+        }
+        let tag = Tag::from_hash(&hash);
+        if verbose {
+            print!("{}", tag.signed_payload());
+        }
+        match tag.check_signature() {
+            Some(check) => {
+                print!("{}", check.output);
+                failed |= !check.is_good();
+            }
+            None => {
+                println!("error: no signature found");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,31 @@
+use std::process;
+
+use crate::git::index::Index;
+
+pub fn write_tree(args: &Vec<String>) {
+    let mut missing_ok = false;
+    for arg in args[2..].iter() {
+        match arg.as_str() {
+            "--missing-ok" => missing_ok = true,
+            _ => {
+                println!("usage: {} write-tree [--missing-ok]", args[0]);
+                process::exit(129);
+            }
+        }
+    }
+    // The cache tree is written back to the index, so it's locked first as in git
+    Index::lock_or_exit();
+    let mut index = Index::read_index();
+    match index.write_tree(missing_ok) {
+        Ok(hash) => {
+            index.write_locked_index();
+            println!("{}", hash);
+        }
+        Err(error) => {
+            Index::unlock();
+            println!("error: {}", error);
+            println!("fatal: git-write-tree: error building trees");
+            process::exit(128);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::git::index::IndexEntry;
+use crate::git::object::tree::{Tree, TreeEntry};
+use crate::object_finder;
+
+/// The TREE extension: the tree object ids of directories whose index entries haven't
+/// changed since their tree was last written, so that writing a tree can reuse them.
+#[derive(Debug, Clone)]
+pub struct CacheTree {
+    /// The directory's name within its parent, empty for the root.
+    pub name: String,
+    /// How many index entries the tree covers, or -1 once a change under it invalidated it.
+    pub entry_count: i32,
+    pub hash: Option<String>,
+    /// Subdirectories, ordered by name length and then name as git keeps them.
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), entry_count: -1, hash: None, subtrees: Vec::new() }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.hash.is_some()
+    }
+
+    /// Each node is `<name>\0<entry count> <subtree count>\n`, followed by its tree id when
+    /// it is valid, and then its subtrees.
+    fn read(bytes: &[u8], pos: &mut usize) -> Option<Self> {
+        let name_len = bytes.get(*pos..)?.iter().position(|&x| x == b'\0')?;
+        let name = String::from_utf8_lossy(&bytes[*pos..*pos + name_len]).to_string();
+        *pos += name_len + 1;
+        let line_len = bytes.get(*pos..)?.iter().position(|&x| x == b'\n')?;
+        let line = String::from_utf8_lossy(&bytes[*pos..*pos + line_len]).to_string();
+        *pos += line_len + 1;
+        let (entry_count, subtree_count) = line.split_once(' ')?;
+        let mut tree = Self::new(&name);
+        tree.entry_count = entry_count.parse().ok()?;
+        if tree.entry_count >= 0 {
+            tree.hash = Some(hex::encode(bytes.get(*pos..*pos + 20)?));
+            *pos += 20;
+        }
+        for _ in 0..subtree_count.parse::<usize>().ok()? {
+            tree.subtrees.push(Self::read(bytes, pos)?);
+        }
+        Some(tree)
+    }
+
+    /// Parses the extension, treating anything unreadable as a tree that must be rebuilt.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::read(bytes, &mut 0).unwrap_or_else(|| Self::new(""))
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.push(b'\0');
+        bytes.extend_from_slice(format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes());
+        if let (true, Some(hash)) = (self.entry_count >= 0, &self.hash) {
+            bytes.extend_from_slice(&hex::decode(hash).unwrap());
+        }
+        for subtree in self.subtrees.iter() {
+            subtree.write(bytes);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes);
+        bytes
+    }
+
+    /// Invalidates every tree containing `path`. A subtree named by `path` itself is dropped,
+    /// since a file is replacing it.
+    pub fn invalidate_path(&mut self, path: &str) {
+        self.entry_count = -1;
+        match path.split_once('/') {
+            Some((name, rest)) => {
+                if let Some(subtree) = self.subtrees.iter_mut().find(|subtree| subtree.name == name) {
+                    subtree.invalidate_path(rest);
+                }
+            }
+            None => self.subtrees.retain(|subtree| subtree.name != path),
+        }
+    }
+
+    /// Writes the tree objects for `entries`, which all start with `prefix`, reusing valid
+    /// subtrees, and returns the id of the tree. Unmerged entries and, unless `missing_ok`,
+    /// entries whose objects don't exist are errors.
+    fn build(&mut self, entries: &[IndexEntry], prefix: &str, missing_ok: bool) -> Result<String, String> {
+        if self.is_valid() && self.entry_count as usize == entries.len() {
+            return Ok(self.hash.clone().unwrap());
+        }
+        let mut tree_entries = Vec::new();
+        let mut names = Vec::new();
+        let mut i = 0;
+        while i < entries.len() {
+            let entry = &entries[i];
+            let rest = &entry.name[prefix.len()..];
+            if let Some((name, _)) = rest.split_once('/') {
+                let subtree_prefix = format!("{}{}/", prefix, name);
+                let count = entries[i..].iter().take_while(|entry| entry.name.starts_with(&subtree_prefix)).count();
+                let position = match self.subtrees.iter().position(|subtree| subtree.name == name) {
+                    Some(position) => position,
+                    None => {
+                        self.subtrees.push(Self::new(name));
+                        self.subtrees.len() - 1
+                    }
+                };
+                let hash = self.subtrees[position].build(&entries[i..i + count], &subtree_prefix, missing_ok)?;
+                tree_entries.push(TreeEntry::new("40000".to_string(), name.to_string(), hash));
+                names.push(name.to_string());
+                i += count;
+                continue;
+            }
+            if entry.stage() != 0 {
+                return Err(format!("{}: unmerged ({})", entry.name, entry.sha1));
+            }
+            // Submodule commits live in another repository
+            if !missing_ok && entry.mode != 0o160000 && !object_finder::object_exists(&entry.sha1) {
+                return Err(format!("invalid object {:06o} {} for '{}'", entry.mode, entry.sha1, entry.name));
+            }
+            tree_entries.push(TreeEntry::new(format!("{:o}", entry.mode), rest.to_string(), entry.sha1.clone()));
+            i += 1;
+        }
+        self.subtrees.retain(|subtree| names.contains(&subtree.name));
+        self.subtrees.sort_by(|a, b| a.name.len().cmp(&b.name.len()).then_with(|| a.name.cmp(&b.name)));
+        let hash = Tree::new(String::new(), tree_entries).write();
+        self.hash = Some(hash.clone());
+        self.entry_count = entries.len() as i32;
+        Ok(hash)
+    }
+
+    /// Writes the tree of the whole index, which must be sorted as the index keeps it.
+    pub fn update(&mut self, entries: &[IndexEntry], missing_ok: bool) -> Result<String, String> {
+        self.build(entries, "", missing_ok)
+    }
+}
@@ -0,0 +1,589 @@
+//! Combined diffs of merges, as `-c` and `--cc` show them: each line of the merge result
+//! has a column per parent, marking the lines the parent lacked with `+` and the parent's
+//! lines the result lost with `-`.
+
+use colored::Colorize;
+
+use crate::git::diff_output::{self, DiffOptions};
+use crate::git::object::commit::Commit;
+use crate::git::pathspec::Pathspec;
+use crate::git::tree_diff::{self, DiffFile, FileChange};
+use crate::git::xdiff;
+
+/// A path that differs from every parent, with what each parent had there.
+struct CombinedPath {
+    path: String,
+    result: Option<DiffFile>,
+    /// Each parent's status for the path and its side of the change.
+    parents: Vec<(char, Option<DiffFile>)>,
+}
+
+/// A line of a parent that the result no longer has, and which parents had it.
+struct LostLine {
+    line: Vec<u8>,
+    parent_map: u64,
+}
+
+/// A line of the result. `flag` has a bit for each parent that lacked the line, and two
+/// more for marking lines to show; `lost` are the lines removed before it.
+#[derive(Default)]
+struct Sline<'a> {
+    bol: &'a [u8],
+    flag: u64,
+    lost: Vec<LostLine>,
+    parent_lost: Vec<LostLine>,
+    parent_lno: Vec<usize>,
+}
+
+/// Merges the lines one more parent lost into those already known, so that a line lost
+/// from several parents appears once, via their longest common subsequence.
+fn coalesce_lines(base: Vec<LostLine>, new: Vec<LostLine>, parent: usize) -> Vec<LostLine> {
+    if new.is_empty() {
+        return base;
+    }
+    if base.is_empty() {
+        return new;
+    }
+    let (n, m) = (base.len(), new.len());
+    // 0 for a match, 1 for a line only in the new list and 2 for one only in the base
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    let mut directions = vec![vec![0u8; m + 1]; n + 1];
+    for row in directions.iter_mut() {
+        row[0] = 2;
+    }
+    for direction in directions[0].iter_mut().skip(1) {
+        *direction = 1;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            if base[i - 1].line == new[j - 1].line {
+                lcs[i][j] = lcs[i - 1][j - 1] + 1;
+                directions[i][j] = 0;
+            } else if lcs[i][j - 1] >= lcs[i - 1][j] {
+                lcs[i][j] = lcs[i][j - 1];
+                directions[i][j] = 1;
+            } else {
+                lcs[i][j] = lcs[i - 1][j];
+                directions[i][j] = 2;
+            }
+        }
+    }
+    let mut base: Vec<Option<LostLine>> = base.into_iter().map(Some).collect();
+    let mut new: Vec<Option<LostLine>> = new.into_iter().map(Some).collect();
+    let mut merged = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i != 0 || j != 0 {
+        match directions[i][j] {
+            0 => {
+                let mut line = base[i - 1].take().unwrap();
+                line.parent_map |= 1 << parent;
+                merged.push(line);
+                i -= 1;
+                j -= 1;
+            }
+            1 => {
+                merged.push(new[j - 1].take().unwrap());
+                j -= 1;
+            }
+            _ => {
+                merged.push(base[i - 1].take().unwrap());
+                i -= 1;
+            }
+        }
+    }
+    merged.reverse();
+    merged
+}
+
+/// Records how `parent` differs from the result in the slines: the lines it lacks, the
+/// lines it lost, and the line numbers it has where each result line is shown.
+fn combine_parent(slines: &mut [Sline], cnt: usize, parent: &[u8], result: &[u8], n: usize) {
+    let nmask = 1u64 << n;
+    let (old_lines, _, changes) = xdiff::diff(parent, result);
+    for change in changes {
+        let nb = if change.new_count > 0 { change.new_start + 1 } else { change.new_start };
+        // Lines removed without any added hang on the line after them
+        let bucket = if change.new_count == 0 { nb } else { nb - 1 };
+        for line in &old_lines[change.old_start..change.old_start + change.old_count] {
+            let line = line.strip_suffix(b"\n").unwrap_or(line).to_vec();
+            slines[bucket].parent_lost.push(LostLine { line, parent_map: nmask });
+        }
+        for lno in nb..nb + change.new_count {
+            slines[lno - 1].flag |= nmask;
+        }
+    }
+    let mut p_lno = 1;
+    for (lno, sline) in slines.iter_mut().enumerate().take(cnt + 1) {
+        sline.parent_lno[n] = p_lno;
+        let lost = std::mem::take(&mut sline.lost);
+        let parent_lost = std::mem::take(&mut sline.parent_lost);
+        sline.lost = coalesce_lines(lost, parent_lost, n);
+        p_lno += sline.lost.iter().filter(|line| line.parent_map & nmask != 0).count();
+        if lno < cnt && sline.flag & nmask == 0 {
+            p_lno += 1;
+        }
+    }
+    slines[cnt + 1].parent_lno[n] = p_lno;
+}
+
+/// Shares what an earlier identical parent `j` found with parent `i`.
+fn reuse_parent(slines: &mut [Sline], cnt: usize, i: usize, j: usize) {
+    let (imask, jmask) = (1u64 << i, 1u64 << j);
+    for sline in slines[..=cnt].iter_mut() {
+        sline.parent_lno[i] = sline.parent_lno[j];
+        for line in sline.lost.iter_mut().filter(|line| line.parent_map & jmask != 0) {
+            line.parent_map |= imask;
+        }
+        if sline.flag & jmask != 0 {
+            sline.flag |= imask;
+        }
+    }
+    slines[cnt + 1].parent_lno[i] = slines[cnt + 1].parent_lno[j];
+}
+
+fn interesting(sline: &Sline, all_mask: u64) -> bool {
+    sline.flag & all_mask != 0 || !sline.lost.is_empty()
+}
+
+/// Where a hunk ending before line `i` really ends: a last line that is only there for
+/// the lines lost before it already gives a line of context.
+fn adjust_hunk_tail(slines: &[Sline], all_mask: u64, hunk_begin: usize, i: usize) -> usize {
+    if hunk_begin < i && slines[i - 1].flag & all_mask == 0 { i - 1 } else { i }
+}
+
+fn find_next(slines: &[Sline], mark: u64, mut i: usize, cnt: usize, look_for_uninteresting: bool) -> usize {
+    while i <= cnt {
+        if (slines[i].flag & mark == 0) == look_for_uninteresting {
+            return i;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Marks the lines of context around the marked lines, joining groups that are close.
+fn give_context(slines: &mut [Sline], cnt: usize, num_parent: usize, context: usize) -> bool {
+    let all_mask = (1u64 << num_parent) - 1;
+    let mark = 1u64 << num_parent;
+    let no_pre_delete = 2u64 << num_parent;
+    let mut i = find_next(slines, mark, 0, cnt, false);
+    if cnt < i {
+        return false;
+    }
+    while i <= cnt {
+        let mut j = i.saturating_sub(context);
+        while j < i {
+            if slines[j].flag & mark == 0 {
+                slines[j].flag |= no_pre_delete;
+            }
+            slines[j].flag |= mark;
+            j += 1;
+        }
+        loop {
+            let mut j = find_next(slines, mark, i, cnt, true);
+            if cnt < j {
+                return true;
+            }
+            let k = find_next(slines, mark, j, cnt, false);
+            j = adjust_hunk_tail(slines, all_mask, i, j);
+            if k < j + context {
+                // The gap is small enough to show as context
+                while j < k {
+                    slines[j].flag |= mark;
+                    j += 1;
+                }
+                i = k;
+                continue;
+            }
+            i = k;
+            let end = (j + context).min(cnt + 1);
+            while j < end {
+                slines[j].flag |= mark;
+                j += 1;
+            }
+            break;
+        }
+    }
+    true
+}
+
+/// Marks the lines to show. With `dense`, hunks where the result just took one of only
+/// two versions of the lines are left out.
+fn make_hunks(slines: &mut [Sline], cnt: usize, num_parent: usize, dense: bool, context: usize) -> bool {
+    let all_mask = (1u64 << num_parent) - 1;
+    let mark = 1u64 << num_parent;
+    for sline in slines[..=cnt].iter_mut() {
+        if interesting(sline, all_mask) {
+            sline.flag |= mark;
+        } else {
+            sline.flag &= !mark;
+        }
+    }
+    if !dense {
+        return give_context(slines, cnt, num_parent, context);
+    }
+    let mut i = 0;
+    while i <= cnt {
+        while i <= cnt && slines[i].flag & mark == 0 {
+            i += 1;
+        }
+        if cnt < i {
+            break;
+        }
+        let hunk_begin = i;
+        let mut j = i + 1;
+        while j <= cnt {
+            if slines[j].flag & mark == 0 {
+                // Look past the end for an interesting line within the context
+                let la = adjust_hunk_tail(slines, all_mask, hunk_begin, j);
+                let mut la = (la + context).min(cnt + 1);
+                let mut contin = false;
+                while la > 0 && j < la {
+                    la -= 1;
+                    if slines[la].flag & mark != 0 {
+                        contin = true;
+                        break;
+                    }
+                }
+                if !contin {
+                    break;
+                }
+                j = la;
+            }
+            j += 1;
+        }
+        let hunk_end = j;
+
+        // With only two versions of the lines, and the result being one of them, every
+        // change is against the same parents
+        let mut same_diff = 0;
+        let mut has_interesting = false;
+        for sline in &slines[i..hunk_end] {
+            let this_diff = sline.flag & all_mask;
+            if this_diff != 0 {
+                if same_diff == 0 {
+                    same_diff = this_diff;
+                } else if same_diff != this_diff {
+                    has_interesting = true;
+                    break;
+                }
+            }
+            for line in &sline.lost {
+                if same_diff == 0 {
+                    same_diff = line.parent_map;
+                } else if same_diff != line.parent_map {
+                    has_interesting = true;
+                    break;
+                }
+            }
+            if has_interesting {
+                break;
+            }
+        }
+        if !has_interesting && same_diff != all_mask {
+            for sline in slines[hunk_begin..hunk_end].iter_mut() {
+                sline.flag &= !mark;
+            }
+        }
+        i = hunk_end;
+    }
+    give_context(slines, cnt, num_parent, context)
+}
+
+fn is_hunk_comment_line(line: &[u8]) -> bool {
+    line.first().is_some_and(|&byte| byte.is_ascii_alphabetic() || byte == b'_' || byte == b'$')
+}
+
+fn line_text(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).to_string()
+}
+
+/// Prints the marked lines as hunks, each with a range for every parent and the result.
+fn dump_slines(slines: &[Sline], cnt: usize, num_parent: usize, context: usize) -> String {
+    let mark = 1u64 << num_parent;
+    let no_pre_delete = 2u64 << num_parent;
+    let markers = "@".repeat(num_parent + 1);
+    let mut output = String::new();
+    let mut lno = 0;
+    loop {
+        let mut hunk_comment = None;
+        while lno <= cnt && slines[lno].flag & mark == 0 {
+            if is_hunk_comment_line(slines[lno].bol) {
+                hunk_comment = Some(slines[lno].bol);
+            }
+            lno += 1;
+        }
+        if cnt < lno {
+            break;
+        }
+        let mut hunk_end = lno + 1;
+        while hunk_end <= cnt && slines[hunk_end].flag & mark != 0 {
+            hunk_end += 1;
+        }
+        let mut rlines = hunk_end - lno;
+        if cnt < hunk_end {
+            rlines -= 1;
+        }
+        let mut null_context = 0;
+        if context == 0 {
+            null_context = slines[lno..hunk_end].iter().filter(|sline| sline.flag & (mark - 1) == 0).count();
+            rlines -= null_context;
+        }
+        let mut header = markers.clone();
+        for n in 0..num_parent {
+            let (l0, l1) = (slines[lno].parent_lno[n], slines[hunk_end].parent_lno[n]);
+            header.push_str(&format!(" -{},{}", l0, l1 - l0 - null_context));
+        }
+        header.push_str(&format!(" +{},{} {}", lno + 1, rlines, markers));
+        output.push_str(&header.cyan().to_string());
+        if let Some(comment) = hunk_comment {
+            // Like git, up to but not including the last non-space of the first 40 bytes
+            let mut comment_end = 0;
+            for (i, &byte) in comment.iter().take(40).enumerate() {
+                if byte == b'\n' {
+                    break;
+                }
+                if !byte.is_ascii_whitespace() {
+                    comment_end = i;
+                }
+            }
+            if comment_end > 0 {
+                output.push_str(&format!(" {}", line_text(&comment[..comment_end])));
+            }
+        }
+        output.push('\n');
+        while lno < hunk_end {
+            let sline = &slines[lno];
+            lno += 1;
+            if sline.flag & no_pre_delete == 0 {
+                for line in &sline.lost {
+                    let columns: String = (0..num_parent).map(|j| if line.parent_map & (1 << j) != 0 { '-' } else { ' ' }).collect();
+                    output.push_str(&format!("{}{}", columns, line_text(&line.line)).red().to_string());
+                    output.push('\n');
+                }
+            }
+            if cnt < lno {
+                break;
+            }
+            let columns: String = (0..num_parent).map(|j| if sline.flag & (1 << j) != 0 { '+' } else { ' ' }).collect();
+            let text = format!("{}{}", columns, line_text(sline.bol));
+            if sline.flag & (mark - 1) == 0 {
+                // The line is only there to hang lost lines in front of
+                if context == 0 {
+                    continue;
+                }
+                output.push_str(&text);
+            } else {
+                output.push_str(&text.green().to_string());
+            }
+            output.push('\n');
+        }
+    }
+    output
+}
+
+fn meta(line: String) -> String {
+    format!("{}\n", line.bold())
+}
+
+fn full_mode(file: Option<&DiffFile>) -> String {
+    file.map_or("000000".to_string(), |file| format!("{:0>6}", file.mode))
+}
+
+fn abbrev(file: Option<&DiffFile>) -> &str {
+    file.map_or("0000000", |file| &file.hash[..7])
+}
+
+/// The `diff --cc` or `diff --combined` header of a path, with the `---` and `+++` lines
+/// when `file_header` is set.
+fn format_header(path: &CombinedPath, dense: bool, mode_differs: bool, file_header: bool) -> String {
+    let mut output = meta(format!("{}{}", if dense { "diff --cc " } else { "diff --combined " }, path.path));
+    let parents: Vec<&str> = path.parents.iter().map(|(_, file)| abbrev(file.as_ref())).collect();
+    output.push_str(&meta(format!("index {}..{}", parents.join(","), abbrev(path.result.as_ref()))));
+    let deleted = path.result.is_none();
+    let added = !deleted && path.parents.iter().all(|(status, _)| *status == 'A');
+    if mode_differs {
+        if added {
+            output.push_str(&meta(format!("new file mode {}", full_mode(path.result.as_ref()))));
+        } else {
+            let modes: Vec<String> = path.parents.iter().map(|(_, file)| full_mode(file.as_ref())).collect();
+            let result = path.result.as_ref().map(|result| format!("..{}", full_mode(Some(result)))).unwrap_or_default();
+            let line = format!("{}mode {}{}", if deleted { "deleted file " } else { "" }, modes.join(","), result);
+            output.push_str(&meta(line));
+        }
+    }
+    if file_header {
+        let old = if added { "/dev/null".to_string() } else { format!("a/{}", path.path) };
+        let new = if deleted { "/dev/null".to_string() } else { format!("b/{}", path.path) };
+        output.push_str(&meta(format!("--- {}", old)));
+        output.push_str(&meta(format!("+++ {}", new)));
+    }
+    output
+}
+
+fn format_path_patch(path: &CombinedPath, dense: bool, context: usize) -> String {
+    let result_mode = path.result.as_ref().map(|result| result.mode.as_str());
+    let mode_differs = path.parents.iter().any(|(_, file)| file.as_ref().map(|file| file.mode.as_str()) != result_mode);
+    let result = diff_output::content(path.result.as_ref());
+    let parents: Vec<Vec<u8>> = path.parents.iter().map(|(_, file)| diff_output::content(file.as_ref())).collect();
+    if diff_output::is_binary(&result) || parents.iter().any(|parent| diff_output::is_binary(parent)) {
+        return format_header(path, dense, mode_differs, false) + "Binary files differ\n";
+    }
+
+    match combined_hunks(&result, &parents, dense, context) {
+        Some(hunks) => format_header(path, dense, mode_differs, true) + &hunks,
+        None if mode_differs => format_header(path, dense, mode_differs, true),
+        None => String::new(),
+    }
+}
+
+/// The hunks comparing `result` with each of `parents`, or `None` when no line is worth
+/// showing.
+fn combined_hunks(result: &[u8], parents: &[Vec<u8>], dense: bool, context: usize) -> Option<String> {
+    let num_parent = parents.len();
+    let lines = xdiff::split_lines(result);
+    let cnt = lines.len();
+    let mut slines: Vec<Sline> = (0..cnt + 2).map(|_| Sline { parent_lno: vec![0; num_parent], ..Sline::default() }).collect();
+    for (sline, line) in slines.iter_mut().zip(&lines) {
+        sline.bol = line.strip_suffix(b"\n").unwrap_or(line);
+    }
+    for (i, parent) in parents.iter().enumerate() {
+        match (0..i).find(|&j| parents[j] == *parent) {
+            Some(j) => reuse_parent(&mut slines, cnt, i, j),
+            None => combine_parent(&mut slines, cnt, parent, result, i),
+        }
+    }
+    if !make_hunks(&mut slines, cnt, num_parent, dense, context) {
+        return None;
+    }
+    Some(dump_slines(&slines, cnt, num_parent, context))
+}
+
+/// The paths a merge changed from every one of its parents, in tree order.
+fn find_paths(commit: &Commit, pathspec: &Pathspec) -> (Vec<CombinedPath>, Vec<FileChange>) {
+    let tree_changes = |parent: &String| {
+        let parent_tree = Commit::from_hash(parent).tree;
+        tree_diff::detect_renames(tree_diff::diff_trees(Some(&parent_tree), Some(&commit.tree), pathspec), |_| true)
+    };
+    let first_changes = tree_changes(&commit.parents[0]);
+    let mut paths: Vec<CombinedPath> = first_changes
+        .iter()
+        .map(|change| CombinedPath { path: change.path().to_string(), result: change.new.clone(), parents: vec![(change.status, change.old.clone())] })
+        .collect();
+    for parent in &commit.parents[1..] {
+        let changes = tree_changes(parent);
+        paths.retain_mut(|path| match changes.iter().find(|change| change.path() == path.path) {
+            Some(change) => {
+                path.parents.push((change.status, change.old.clone()));
+                true
+            }
+            None => false,
+        });
+    }
+    (paths, first_changes)
+}
+
+/// A path's line in a combined `--raw`, `--name-status` or `--name-only` listing.
+fn format_name_line(path: &CombinedPath, options: &DiffOptions) -> String {
+    let mut line = String::new();
+    if options.raw {
+        line.push_str(&":".repeat(path.parents.len()));
+        for (_, file) in &path.parents {
+            line.push_str(&format!("{} ", full_mode(file.as_ref())));
+        }
+        line.push_str(&full_mode(path.result.as_ref()));
+        for (_, file) in &path.parents {
+            line.push_str(&format!(" {}", abbrev(file.as_ref())));
+        }
+        line.push_str(&format!(" {} ", abbrev(path.result.as_ref())));
+    }
+    if options.raw || options.name_status {
+        line.extend(path.parents.iter().map(|(status, _)| status));
+        line.push('\t');
+    }
+    format!("{}{}\n", line, path.path)
+}
+
+/// Everything `options` asks to show of a merge's combined diff, which `dense` makes
+/// `--cc`. A diffstat is against the first parent, as in git.
+pub fn format_combined(commit: &Commit, pathspec: &Pathspec, dense: bool, options: &DiffOptions, width: usize) -> String {
+    let (paths, first_changes) = find_paths(commit, pathspec);
+    let mut output = String::new();
+    if options.stat {
+        output.push_str(&diff_output::format_stat(&first_changes, width));
+    }
+    if paths.is_empty() {
+        return output;
+    }
+    let mut separate = options.stat;
+    if options.raw || options.name_status || options.name_only {
+        output.extend(paths.iter().map(|path| format_name_line(path, options)));
+        separate = true;
+    }
+    if options.patch {
+        if separate {
+            output.push('\n');
+        }
+        output.extend(paths.iter().map(|path| format_path_patch(path, dense, options.context)));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunks(result: &str, parents: &[&str], dense: bool, context: usize) -> Option<String> {
+        colored::control::set_override(false);
+        let parents: Vec<Vec<u8>> = parents.iter().map(|parent| parent.as_bytes().to_vec()).collect();
+        combined_hunks(result.as_bytes(), &parents, dense, context)
+    }
+
+    // Parents of merges whose combined diffs git 2.39 showed as the tests expect
+    const FIRST: &str = "zero\none\ntwo\nthree\nFOUR\nfive\n";
+    const SECOND: &str = "one\nTWO\nthree\nfour\nfive\nsix\n";
+
+    #[test]
+    fn shows_a_column_per_parent() {
+        let result = "zero\none\nTWO\nthree\nfour and FOUR\nfive\nsix\n";
+        let expected = "@@@ -1,6 -1,6 +1,7 @@@\n +zero\n  one\n- two\n+ TWO\n  three\n- FOUR\n -four\n++four and FOUR\n  five\n+ six\n";
+        assert_eq!(hunks(result, &[FIRST, SECOND], true, 3).as_deref(), Some(expected));
+        assert_eq!(hunks(result, &[FIRST, SECOND], false, 3).as_deref(), Some(expected));
+        assert_eq!(
+            hunks(result, &[FIRST, SECOND], true, 1).as_deref(),
+            Some("@@@ -4,3 -3,3 +4,3 @@@ TW\n  three\n- FOUR\n -four\n++four and FOUR\n  five\n"),
+        );
+    }
+
+    #[test]
+    fn lines_taken_from_one_parent_are_marked_for_the_other() {
+        let result = "zero\none\nTWO\nthree\nFOUR\nfive\nsix\n";
+        let expected = "@@@ -1,6 -1,6 +1,7 @@@\n +zero\n  one\n- two\n+ TWO\n  three\n -four\n +FOUR\n  five\n+ six\n";
+        assert_eq!(hunks(result, &[FIRST, SECOND], true, 3).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn dense_diffs_hide_hunks_taken_whole_from_a_parent() {
+        let base: Vec<String> = (1..=12).map(|i| i.to_string()).collect();
+        let lines = |replace: &[(usize, &str)]| {
+            let mut lines = base.clone();
+            for &(i, line) in replace {
+                lines[i] = line.to_string();
+            }
+            lines.join("\n") + "\n"
+        };
+        let (first, second) = (lines(&[(1, "two")]), lines(&[(10, "eleven")]));
+        let result = lines(&[(1, "two"), (10, "eleven")]);
+        assert_eq!(hunks(&result, &[&first, &second], true, 3), None);
+        assert_eq!(
+            hunks(&result, &[&first, &second], false, 3).as_deref(),
+            Some("@@@ -1,5 -1,5 +1,5 @@@\n  1\n -2\n +two\n  3\n  4\n  5\n@@@ -8,5 -8,5 +8,5 @@@\n  8\n  9\n  10\n- 11\n+ eleven\n  12\n"),
+        );
+    }
+
+    #[test]
+    fn identical_parents_share_their_columns() {
+        let expected = "@@@ -1,2 -1,2 +1,2 @@@\n  a\n--b\n++c\n";
+        assert_eq!(hunks("a\nc\n", &["a\nb\n", "a\nb\n"], true, 3).as_deref(), Some(expected));
+    }
+}
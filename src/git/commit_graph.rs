@@ -0,0 +1,82 @@
+use std::fs;
+
+pub const COMMIT_GRAPH_PATH: &str = ".git/objects/info/commit-graph";
+
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const COMMIT_DATA: &[u8; 4] = b"CDAT";
+/// A commit's entry in the data chunk: its tree, two parent positions, and its generation
+/// and date packed into 64 bits.
+const COMMIT_DATA_SIZE: usize = 36;
+
+/// Reader for the generation numbers in the commit-graph file `git commit-graph write`
+/// leaves in the object database.
+///
+/// A commit's generation is one more than the highest of its parents', so a commit can
+/// only be an ancestor of commits with a higher generation. Commits newer than the file
+/// have none.
+pub struct CommitGraph {
+    bytes: Vec<u8>,
+    fanout: usize,
+    lookup: usize,
+    commit_data: usize,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+impl CommitGraph {
+    /// Reads the commit-graph, if there is one this version of the format can read.
+    pub fn read() -> Option<Self> {
+        let bytes = fs::read(COMMIT_GRAPH_PATH).ok()?;
+        if bytes.len() < 8 || &bytes[0..4] != b"CGPH" || bytes[4] != 1 || bytes[5] != 1 {
+            return None;
+        }
+        // The table of contents has an entry per chunk, and one more marking where the last ends
+        let chunk_count = bytes[6] as usize;
+        let mut chunks = Vec::new();
+        for i in 0..chunk_count {
+            let entry = 8 + i * 12;
+            let offset = u64::from_be_bytes(bytes.get(entry + 4..entry + 12)?.try_into().unwrap()) as usize;
+            chunks.push((&bytes[entry..entry + 4], offset));
+        }
+        let chunk = |id: &[u8; 4]| chunks.iter().find(|(chunk_id, _)| chunk_id == id).map(|&(_, offset)| offset);
+        let (fanout, lookup, commit_data) = (chunk(OID_FANOUT)?, chunk(OID_LOOKUP)?, chunk(COMMIT_DATA)?);
+        if bytes.len() < fanout + 256 * 4 {
+            return None;
+        }
+        let count = read_u32(&bytes, fanout + 255 * 4) as usize;
+        if bytes.len() < lookup + count * 20 || bytes.len() < commit_data + count * COMMIT_DATA_SIZE {
+            return None;
+        }
+        Some(Self { bytes, fanout, lookup, commit_data })
+    }
+
+    /// Finds a commit's position in the file. Ids are sorted, and the fanout counts those
+    /// whose first byte is at most each value.
+    fn position(&self, hash: &str) -> Option<usize> {
+        let oid = hex::decode(hash).ok()?;
+        let first = *oid.first()? as usize;
+        let start = if first == 0 { 0 } else { read_u32(&self.bytes, self.fanout + (first - 1) * 4) as usize };
+        let end = read_u32(&self.bytes, self.fanout + first * 4) as usize;
+        let oid_at = |i: usize| &self.bytes[self.lookup + i * 20..self.lookup + (i + 1) * 20];
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let middle = (low + high) / 2;
+            match oid_at(middle).cmp(&oid[..]) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    /// The commit's generation, if the file has it.
+    pub fn generation(&self, hash: &str) -> Option<u32> {
+        let position = self.position(hash)?;
+        // The generation is the top 30 bits of the 64 after the tree and parents
+        Some(read_u32(&self.bytes, self.commit_data + position * COMMIT_DATA_SIZE + 28) >> 2)
+    }
+}
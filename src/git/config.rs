@@ -0,0 +1,227 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: String,
+}
+
+/// Configuration read from the global and repository config files. Keys are stored as
+/// `section.key` or `section.subsection.key` with the section and key lowercased.
+#[derive(Debug)]
+pub struct Config {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    pub fn new(entries: Vec<ConfigEntry>) -> Self {
+        Self { entries }
+    }
+
+    fn global_paths() -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if let Ok(xdg) = env::var("XDG_CONFIG_HOME") {
+            paths.push(PathBuf::from(xdg).join("git/config"));
+        } else if let Ok(home) = env::var("HOME") {
+            paths.push(PathBuf::from(home).join(".config/git/config"));
+        }
+        if let Ok(home) = env::var("HOME") {
+            paths.push(PathBuf::from(home).join(".gitconfig"));
+        }
+        paths
+    }
+
+    /// Reads the global config files followed by `.git/config`, so repository values win.
+    pub fn read() -> Self {
+        let mut entries = Vec::new();
+        let mut paths = Vec::new();
+        if env::var("GIT_CONFIG_NOSYSTEM").is_err() {
+            paths.push(PathBuf::from("/etc/gitconfig"));
+        }
+        match env::var("GIT_CONFIG_GLOBAL") {
+            Ok(path) => paths.push(PathBuf::from(path)),
+            Err(_) => paths.extend(Self::global_paths()),
+        }
+        paths.push(PathBuf::from(".git/config"));
+        for path in paths {
+            if let Ok(content) = fs::read_to_string(&path) {
+                entries.extend(Self::parse(&content));
+            }
+        }
+        Self::new(entries)
+    }
+
+    fn parse_value(raw: &str) -> String {
+        let mut value = String::new();
+        let mut in_quotes = false;
+        let mut chars = raw.trim().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(other) => value.push(other),
+                    None => {}
+                },
+                '#' | ';' if !in_quotes => break,
+                _ => value.push(c),
+            }
+        }
+        value.trim_end().to_string()
+    }
+
+    /// Parses a section header line such as `[core]` or `[branch "main"]` into its key prefix.
+    pub fn parse_section(line: &str) -> Option<String> {
+        let header = line.trim().strip_prefix('[')?;
+        let header = &header[..header.find(']')?];
+        match header.find('"') {
+            Some(quote) => {
+                let section = header[..quote].trim().to_lowercase();
+                let subsection = header[quote + 1..].trim_end_matches('"').replace("\\\"", "\"").replace("\\\\", "\\");
+                Some(format!("{}.{}", section, subsection))
+            }
+            // Legacy `[section.subsection]` syntax lowercases everything
+            None => Some(header.trim().to_lowercase()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Vec<ConfigEntry> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') {
+                section = Self::parse_section(line).unwrap_or_default();
+                continue;
+            }
+            let (name, value) = match line.find('=') {
+                Some(eq) => (line[..eq].trim(), Self::parse_value(&line[eq + 1..])),
+                // A key without a value is a boolean true
+                None => (line, "true".to_string()),
+            };
+            entries.push(ConfigEntry { key: format!("{}.{}", section, name.to_lowercase()), value });
+        }
+        entries
+    }
+
+    fn normalize_key(key: &str) -> String {
+        let first = key.find('.').unwrap_or(0);
+        let last = key.rfind('.').unwrap_or(key.len());
+        if first == last {
+            return key.to_lowercase();
+        }
+        format!("{}{}{}", key[..first].to_lowercase(), &key[first..last], key[last..].to_lowercase())
+    }
+
+    /// Returns the last value set for `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let key = Self::normalize_key(key);
+        self.entries.iter().rev().find(|entry| entry.key == key).map(|entry| entry.value.clone())
+    }
+
+    pub fn parse_bool(value: &str) -> Option<bool> {
+        match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn quote_value(value: &str) -> String {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t");
+        let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+        if needs_quotes { format!("\"{}\"", escaped) } else { escaped }
+    }
+
+    /// Splits `section[.subsection].name` into the section header prefix as stored in entries
+    /// and the variable name as given.
+    fn split_key(key: &str) -> (String, &str) {
+        let last = key.rfind('.').unwrap();
+        let prefix = match key[..last].split_once('.') {
+            Some((section, subsection)) => format!("{}.{}", section.to_lowercase(), subsection),
+            None => key[..last].to_lowercase(),
+        };
+        (prefix, &key[last + 1..])
+    }
+
+    fn section_header(prefix: &str) -> String {
+        match prefix.split_once('.') {
+            Some((section, subsection)) => format!("[{} \"{}\"]", section, subsection.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => format!("[{}]", prefix),
+        }
+    }
+
+    fn write_config(lines: &[String]) {
+        let mut content = lines.join("\n");
+        content.push('\n');
+        fs::write(".git/config.lock", content).unwrap();
+        fs::rename(".git/config.lock", ".git/config").unwrap();
+    }
+
+    fn read_config_lines() -> Vec<String> {
+        fs::read_to_string(".git/config").unwrap_or_default().lines().map(|line| line.to_string()).collect()
+    }
+
+    fn variable_name(line: &str) -> String {
+        let line = line.trim();
+        line[..line.find('=').unwrap_or(line.len())].trim().to_lowercase()
+    }
+
+    /// Sets `key` in `.git/config`, replacing the last existing value or adding it to the end
+    /// of its section, creating the section when needed.
+    pub fn set_value(key: &str, value: &str) {
+        let (prefix, name) = Self::split_key(key);
+        let mut lines = Self::read_config_lines();
+        let mut section = String::new();
+        let mut section_end = None;
+        let mut existing = None;
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().starts_with('[') {
+                section = Self::parse_section(line).unwrap_or_default();
+            } else if line.trim().is_empty() || line.trim().starts_with(['#', ';']) {
+                continue;
+            } else if section == prefix && Self::variable_name(line) == name.to_lowercase() {
+                existing = Some(i);
+            }
+            if section == prefix {
+                section_end = Some(i);
+            }
+        }
+        let new_line = format!("\t{} = {}", name, Self::quote_value(value));
+        match (existing, section_end) {
+            (Some(i), _) => lines[i] = new_line,
+            (None, Some(i)) => lines.insert(i + 1, new_line),
+            (None, None) => {
+                lines.push(Self::section_header(&prefix));
+                lines.push(new_line);
+            }
+        }
+        Self::write_config(&lines);
+    }
+
+    /// Renames the section `old` (such as `branch.topic`) to `new` in `.git/config`, or
+    /// removes it when `new` is `None`.
+    pub fn rename_section(old: &str, new: Option<&str>) {
+        let mut lines = Vec::new();
+        let mut in_section = false;
+        for line in Self::read_config_lines() {
+            if line.trim().starts_with('[') {
+                in_section = Self::parse_section(&line).is_some_and(|section| section == old);
+                if let (true, Some(new)) = (in_section, new) {
+                    lines.push(Self::section_header(new));
+                    continue;
+                }
+            }
+            if !in_section || new.is_some() {
+                lines.push(line);
+            }
+        }
+        Self::write_config(&lines);
+    }
+}
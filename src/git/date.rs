@@ -0,0 +1,161 @@
+use chrono::{DateTime, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, TimeZone};
+
+/// Parses a `+hhmm`/`-hhmm` offset as found in commit and tag identities.
+pub fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    let sign = match offset.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i32 = offset.get(1..3)?.parse().ok()?;
+    let minutes: i32 = offset.get(3..5)?.parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses a date given for an identity in `GIT_AUTHOR_DATE` or `GIT_COMMITTER_DATE`: git's
+/// raw `<unix time> <offset>`, optionally prefixed with `@`, RFC 2822 or ISO 8601. ISO dates
+/// without an offset are in local time. Returns the time and its `+hhmm` offset.
+pub fn parse_ident_date(date: &str) -> Option<(i64, String)> {
+    let date = date.trim();
+    let raw = date.strip_prefix('@').unwrap_or(date);
+    if let Some((timestamp, offset)) = raw.split_once(' ') {
+        if let (Ok(timestamp), Some(offset)) = (timestamp.parse::<i64>(), parse_offset(offset)) {
+            let date = offset.timestamp_opt(timestamp, 0).single()?;
+            return Some((timestamp, date.format("%z").to_string()));
+        }
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+        return Some((date.timestamp(), date.format("%z").to_string()));
+    }
+    let iso = match date.strip_suffix('Z') {
+        Some(date) => format!("{}+0000", date),
+        None => date.to_string(),
+    };
+    for format in ["%Y-%m-%dT%H:%M:%S%z", "%Y-%m-%d %H:%M:%S%z", "%Y-%m-%dT%H:%M:%S %z", "%Y-%m-%d %H:%M:%S %z"] {
+        if let Ok(date) = DateTime::parse_from_str(&iso, format) {
+            return Some((date.timestamp(), date.format("%z").to_string()));
+        }
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+            let date = Local.from_local_datetime(&date).single()?;
+            return Some((date.timestamp(), date.format("%z").to_string()));
+        }
+    }
+    None
+}
+
+/// Parses a date as `--since` and `--until` take it, roughly as git's approxidate does:
+/// anything `parse_ident_date` reads, a unix time after `@` or of more than 8 digits, a
+/// `YYYY-MM-DD` date at the current time of day, `now`, `yesterday` or `<n> <unit>s [ago]`.
+pub fn approxidate(date: &str) -> Option<i64> {
+    if let Some((timestamp, _)) = parse_ident_date(date) {
+        return Some(timestamp);
+    }
+    let date = date.trim().to_lowercase();
+    let now = Local::now();
+    if let Some(timestamp) = date.strip_prefix('@').and_then(|timestamp| timestamp.parse().ok()) {
+        return Some(timestamp);
+    }
+    if date.len() > 8 && date.bytes().all(|byte| byte.is_ascii_digit()) {
+        return date.parse().ok();
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+        return Some(Local.from_local_datetime(&day.and_time(now.time())).earliest()?.timestamp());
+    }
+    let words: Vec<&str> = date.split(|c: char| !c.is_ascii_alphanumeric()).filter(|word| !word.is_empty()).collect();
+    match words[..] {
+        ["now"] | ["today"] => Some(now.timestamp()),
+        ["yesterday"] => Some(now.timestamp() - 86400),
+        [count, unit] | [count, unit, "ago"] => {
+            let count: i64 = count.parse().ok()?;
+            let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+                "second" | "sec" => 1,
+                "minute" | "min" => 60,
+                "hour" => 3600,
+                "day" => 86400,
+                "week" => 7 * 86400,
+                "month" => return Some(now.checked_sub_months(Months::new(u32::try_from(count).ok()?))?.timestamp()),
+                "year" => return Some(now.checked_sub_months(Months::new(u32::try_from(count * 12).ok()?))?.timestamp()),
+                _ => return None,
+            };
+            Some(now.timestamp() - count * seconds)
+        }
+        _ => None,
+    }
+}
+
+/// Splits an identity line, `Name <email> <unix time> <offset>`, into the identity and its
+/// time and offset.
+pub fn split_ident(ident: &str) -> (&str, i64, &str) {
+    let mut parts = ident.rsplitn(3, ' ');
+    let offset = parts.next().unwrap_or("");
+    let timestamp = parts.next().and_then(|timestamp| timestamp.parse().ok());
+    match (timestamp, parts.next()) {
+        (Some(timestamp), Some(identity)) => (identity, timestamp, offset),
+        _ => (ident, 0, "+0000"),
+    }
+}
+
+/// Describes how long ago `timestamp` was, rounding like git's `--date=relative`.
+pub fn relative(timestamp: i64) -> String {
+    let plural = |count: i64, unit: &str| if count == 1 { format!("{} {}", count, unit) } else { format!("{} {}s", count, unit) };
+    let mut diff = Local::now().timestamp() - timestamp;
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+/// Formats a time in one of git's date modes: `default`, `iso`, `iso-strict`, `rfc`,
+/// `short`, `raw`, `unix` or `relative`. Times are shown in their own `offset`, or in local
+/// time for modes ending in `-local`.
+pub fn format(timestamp: i64, offset: &str, mode: &str) -> Option<String> {
+    let (mode, local) = match mode.strip_suffix("-local") {
+        Some(mode) => (mode, true),
+        None => (mode, mode == "local"),
+    };
+    let offset = if local { *Local::now().offset() } else { parse_offset(offset).unwrap_or(FixedOffset::east_opt(0)?) };
+    let date = offset.timestamp_opt(timestamp, 0).single()?;
+    let formatted = match mode {
+        "default" | "local" | "" => date.format("%a %b %-d %H:%M:%S %Y %z").to_string(),
+        "iso" | "iso8601" => date.format("%Y-%m-%d %H:%M:%S %z").to_string(),
+        "iso-strict" | "iso8601-strict" => date.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        "rfc" | "rfc2822" => date.format("%a, %-d %b %Y %H:%M:%S %z").to_string(),
+        "short" => date.format("%Y-%m-%d").to_string(),
+        "raw" => format!("{} {}", timestamp, date.format("%z")),
+        "unix" => timestamp.to_string(),
+        "relative" => relative(timestamp),
+        _ => return None,
+    };
+    Some(formatted)
+}
@@ -0,0 +1,455 @@
+use std::env;
+
+use colored::Colorize;
+
+use crate::git::object::blob::Blob;
+use crate::git::tree_diff::{DiffFile, FileChange};
+use crate::git::xdiff;
+
+const NULL_ABBREV: &str = "0000000";
+
+/// How merges are diffed: not at all, against their first parent, against each parent in
+/// turn as `-m` does, or combined as `-c` and `--cc` do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeDiff {
+    Off,
+    FirstParent,
+    Separate,
+    Combined,
+    DenseCombined,
+}
+
+impl MergeDiff {
+    pub fn is_combined(self) -> bool {
+        matches!(self, Self::Combined | Self::DenseCombined)
+    }
+}
+
+/// What `log` and `show` print about the files a commit changed. As in git, `--name-only`,
+/// `--name-status` and `-s` each replace the other formats.
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    pub patch: bool,
+    pub stat: bool,
+    pub raw: bool,
+    pub name_status: bool,
+    pub name_only: bool,
+    pub no_output: bool,
+    /// Lines of context around changes in patches.
+    pub context: usize,
+    pub merges: MergeDiff,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { patch: false, stat: false, raw: false, name_status: false, name_only: false, no_output: false, context: 3, merges: MergeDiff::Off }
+    }
+}
+
+impl DiffOptions {
+    /// Parses a diff option, returning whether it was one.
+    pub fn parse(&mut self, arg: &str) -> bool {
+        match arg {
+            "-p" | "-u" | "--patch" => {
+                self.patch = true;
+                self.no_output = false;
+            }
+            "--stat" => self.stat = true,
+            "--raw" => self.raw = true,
+            "--name-status" => self.name_status = true,
+            "--name-only" => self.name_only = true,
+            "-s" | "--no-patch" => *self = Self { no_output: true, context: self.context, merges: self.merges, ..Self::default() },
+            "-m" => self.merges = MergeDiff::Separate,
+            "-c" => self.merges = MergeDiff::Combined,
+            "--cc" => self.merges = MergeDiff::DenseCombined,
+            "--no-diff-merges" => self.merges = MergeDiff::Off,
+            _ if arg.starts_with("--diff-merges=") => {
+                self.merges = match &arg["--diff-merges=".len()..] {
+                    "off" | "none" => MergeDiff::Off,
+                    "first-parent" | "1" => MergeDiff::FirstParent,
+                    "on" | "m" | "separate" => MergeDiff::Separate,
+                    "combined" | "c" => MergeDiff::Combined,
+                    "dense-combined" | "cc" => MergeDiff::DenseCombined,
+                    value => {
+                        println!("fatal: invalid value for '--diff-merges': '{}'", value);
+                        std::process::exit(128);
+                    }
+                }
+            }
+            _ => match arg.strip_prefix("--unified=").or(arg.strip_prefix("-U")) {
+                Some(context) => {
+                    self.context = context.parse().unwrap_or_else(|_| {
+                        println!("error: switch `U' expects a numerical value");
+                        std::process::exit(129);
+                    });
+                    self.patch = true;
+                    self.no_output = false;
+                }
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// Settles which formats are shown once all options are read, dying if they clash.
+    /// Combined diffs are patches unless another format is asked for.
+    pub fn finish(&mut self) {
+        if self.merges.is_combined() && !self.shows_diff() && !self.no_output {
+            self.patch = true;
+        }
+        if [self.name_only, self.name_status, self.no_output].iter().filter(|&&set| set).count() > 1 {
+            println!("fatal: options '--name-only', '--name-status', '--check', and '-s' cannot be used together");
+            std::process::exit(128);
+        }
+        if self.name_only || self.name_status || self.no_output {
+            self.patch = false;
+            self.stat = false;
+            self.raw = false;
+        }
+    }
+
+    /// Whether anything is shown about changed files.
+    pub fn shows_diff(&self) -> bool {
+        self.patch || self.stat || self.raw || self.name_status || self.name_only
+    }
+
+    /// Whether both a diffstat and a patch are shown, which git separates from the log
+    /// message with `---`.
+    pub fn shows_stat_and_patch(&self) -> bool {
+        self.stat && self.patch
+    }
+}
+
+/// The columns a diffstat may fill: `$COLUMNS`, or 80.
+pub fn term_columns() -> usize {
+    env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).filter(|&columns| columns > 0).unwrap_or(80)
+}
+
+fn abbrev(file: Option<&DiffFile>) -> &str {
+    file.map_or(NULL_ABBREV, |file| &file.hash[..7])
+}
+
+/// What a side of a change holds, with a submodule shown as the commit it points at.
+pub fn content(file: Option<&DiffFile>) -> Vec<u8> {
+    match file {
+        None => Vec::new(),
+        Some(file) if file.mode == "160000" => format!("Subproject commit {}\n", file.hash).into_bytes(),
+        Some(file) => Blob::from_hash(&file.hash).content,
+    }
+}
+
+/// Content is binary if it has a NUL in its first 8000 bytes.
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(8000)].contains(&0)
+}
+
+/// Modes are shown with six digits, as git writes them in raw diffs.
+fn full_mode(file: Option<&DiffFile>) -> String {
+    file.map_or("000000".to_string(), |file| format!("{:0>6}", file.mode))
+}
+
+fn status(change: &FileChange) -> String {
+    if change.status == 'R' {
+        format!("R{:03}", change.similarity_index())
+    } else {
+        change.status.to_string()
+    }
+}
+
+/// A file's line in `--raw`, `--name-status` or `--name-only` output.
+fn format_name_line(change: &FileChange, options: &DiffOptions) -> String {
+    let paths = match (change.status, &change.old, &change.new) {
+        ('R', Some(old), Some(new)) => format!("{}\t{}", old.path, new.path),
+        _ => change.path().to_string(),
+    };
+    if options.name_only {
+        format!("{}\n", change.path())
+    } else if options.name_status {
+        format!("{}\t{}\n", status(change), paths)
+    } else {
+        let (old, new) = (change.old.as_ref(), change.new.as_ref());
+        format!(":{} {} {} {} {}\t{}\n", full_mode(old), full_mode(new), abbrev(old), abbrev(new), status(change), paths)
+    }
+}
+
+/// How git names a rename in a diffstat: the parts of the paths that differ in braces,
+/// as in `dir/{old => new}/file`.
+fn pprint_rename(a: &str, b: &str) -> String {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut prefix = 0;
+    let mut i = 0;
+    while i < a.len() && i < b.len() && a[i] == b[i] {
+        if a[i] == b'/' {
+            prefix = i + 1;
+        }
+        i += 1;
+    }
+    // Positions past the end stand for the NUL that ends C strings, which match
+    let at = |s: &[u8], position: usize| s.get(position).copied().unwrap_or(0);
+    let adjust = if prefix > 0 { 1 } else { 0 };
+    let mut suffix = 0;
+    let (mut old, mut new) = (a.len() as isize, b.len() as isize);
+    while (prefix as isize) - adjust <= old && (prefix as isize) - adjust <= new && at(a, old as usize) == at(b, new as usize) {
+        if at(a, old as usize) == b'/' {
+            suffix = a.len() - old as usize;
+        }
+        old -= 1;
+        new -= 1;
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).to_string();
+    if prefix + suffix > 0 {
+        format!("{}{{{} => {}}}{}", text(&a[..prefix]), text(&a[prefix..prefix + a_mid]), text(&b[prefix..prefix + b_mid]), text(&a[a.len() - suffix..]))
+    } else {
+        format!("{} => {}", text(&a[prefix..prefix + a_mid]), text(&b[prefix..prefix + b_mid]))
+    }
+}
+
+/// A file's counts for a diffstat: lines added and deleted, or the sizes of binary files.
+struct FileStat {
+    name: String,
+    added: usize,
+    deleted: usize,
+    binary: bool,
+}
+
+fn file_stat(change: &FileChange) -> FileStat {
+    let name = match (change.status, &change.old, &change.new) {
+        ('R', Some(old), Some(new)) => pprint_rename(&old.path, &new.path),
+        _ => change.path().to_string(),
+    };
+    let same = change.old.as_ref().zip(change.new.as_ref()).is_some_and(|(old, new)| old.hash == new.hash);
+    let (old, new) = (content(change.old.as_ref()), content(change.new.as_ref()));
+    if is_binary(&old) || is_binary(&new) {
+        let (added, deleted) = if same { (0, 0) } else { (new.len(), old.len()) };
+        return FileStat { name, added, deleted, binary: true };
+    }
+    let (added, deleted) = if same {
+        (0, 0)
+    } else {
+        let (_, _, changes) = xdiff::diff(&old, &new);
+        changes.iter().fold((0, 0), |(added, deleted), change| (added + change.new_count, deleted + change.old_count))
+    };
+    FileStat { name, added, deleted, binary: false }
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+/// Scales a count to a width, keeping at least one column for any change.
+fn scale_linear(it: usize, width: usize, max_change: usize) -> usize {
+    if it == 0 { 0 } else { 1 + it * (width - 1) / max_change }
+}
+
+/// The summary line that ends a diffstat.
+pub fn format_stat_summary(files: usize, insertions: usize, deletions: usize) -> String {
+    if files == 0 {
+        return " 0 files changed\n".to_string();
+    }
+    let mut summary = format!(" {} file{} changed", files, if files == 1 { "" } else { "s" });
+    if insertions > 0 || deletions == 0 {
+        summary.push_str(&format!(", {} insertion{}(+)", insertions, if insertions == 1 { "" } else { "s" }));
+    }
+    if deletions > 0 || insertions == 0 {
+        summary.push_str(&format!(", {} deletion{}(-)", deletions, if deletions == 1 { "" } else { "s" }));
+    }
+    summary + "\n"
+}
+
+/// A diffstat in `width` columns, its graph of `+` and `-` scaled to fit as git does.
+pub fn format_stat(changes: &[FileChange], width: usize) -> String {
+    if changes.is_empty() {
+        return String::new();
+    }
+    let stats: Vec<FileStat> = changes.iter().map(file_stat).collect();
+    let (mut max_change, mut max_len, mut number_width, mut bin_width) = (0, 0, 0, 0);
+    for stat in &stats {
+        max_len = max_len.max(stat.name.chars().count());
+        if stat.binary {
+            bin_width = bin_width.max(14 + decimal_width(stat.added) + decimal_width(stat.deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(stat.added + stat.deleted);
+        }
+    }
+    let number_width = number_width.max(decimal_width(max_change));
+    // Leave room for a graph of 6 and a name of 10 at the least
+    let width = width.max(16 + 6 + number_width);
+    let (width, number_width) = (width as isize, number_width as isize);
+    let mut graph_width = if max_change + 4 > bin_width { max_change } else { bin_width - 4 } as isize;
+    let mut name_width = max_len as isize;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > width * 3 / 8 - number_width - 6 {
+            graph_width = (width * 3 / 8 - number_width - 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+    let (name_width, number_width, graph_width) = (name_width.max(0) as usize, number_width as usize, graph_width.max(0) as usize);
+
+    let mut output = String::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for stat in &stats {
+        let mut name = stat.name.as_str();
+        let mut prefix = "";
+        let mut len = name_width;
+        if name_width < name.chars().count() {
+            // Cut the name from the front, to a directory boundary if there is one
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+        let name = format!(" {}{}{}", prefix, name, " ".repeat(padding));
+        if stat.binary {
+            output.push_str(&format!("{} | {:>width$}", name, "Bin", width = number_width));
+            if stat.added == 0 && stat.deleted == 0 {
+                output.push('\n');
+            } else {
+                output.push_str(&format!(" {} -> {} bytes\n", stat.deleted.to_string().red(), stat.added.to_string().green()));
+            }
+            continue;
+        }
+        insertions += stat.added;
+        deletions += stat.deleted;
+        let (mut add, mut del) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale_linear(add + del, graph_width, max_change);
+            if total < 2 && add > 0 && del > 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale_linear(add, graph_width, max_change);
+                del = total - add;
+            } else {
+                del = scale_linear(del, graph_width, max_change);
+                add = total - del;
+            }
+        }
+        let changed = stat.added + stat.deleted;
+        output.push_str(&format!("{} | {:>width$}{}", name, changed, if changed > 0 { " " } else { "" }, width = number_width));
+        if add > 0 {
+            output.push_str(&"+".repeat(add).green().to_string());
+        }
+        if del > 0 {
+            output.push_str(&"-".repeat(del).red().to_string());
+        }
+        output.push('\n');
+    }
+    output + &format_stat_summary(stats.len(), insertions, deletions)
+}
+
+fn format_hunks(old: &[u8], new: &[u8], context: usize) -> String {
+    let (old_lines, new_lines, changes) = xdiff::diff(old, new);
+    let mut output = String::new();
+    for hunk in xdiff::hunks(&old_lines, &new_lines, &changes, context) {
+        output.push_str(&hunk.header.cyan().to_string());
+        if !hunk.function.is_empty() {
+            output.push_str(&format!(" {}", hunk.function));
+        }
+        output.push('\n');
+        for line in hunk.lines {
+            let text = format!("{}{}", line.origin, line.text);
+            match line.origin {
+                '-' => output.push_str(&text.red().to_string()),
+                '+' => output.push_str(&text.green().to_string()),
+                _ => output.push_str(&text),
+            }
+            output.push('\n');
+            if line.missing_newline {
+                output.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    output
+}
+
+fn meta(line: String) -> String {
+    format!("{}\n", line.bold())
+}
+
+/// The patch for one side of a change to another. `index` is the line naming the blobs,
+/// which git leaves off the second half of a type change.
+fn format_file_patch(old: Option<&DiffFile>, new: Option<&DiffFile>, change: &FileChange, index: bool, context: usize) -> String {
+    let (old_path, new_path) = (old.or(new).unwrap().path.as_str(), new.or(old).unwrap().path.as_str());
+    let mut header = meta(format!("diff --git a/{} b/{}", old_path, new_path));
+    match (old, new) {
+        (None, Some(new)) => header.push_str(&meta(format!("new file mode {}", full_mode(Some(new))))),
+        (Some(old), None) => header.push_str(&meta(format!("deleted file mode {}", full_mode(Some(old))))),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.push_str(&meta(format!("old mode {}", full_mode(Some(old)))));
+            header.push_str(&meta(format!("new mode {}", full_mode(Some(new)))));
+        }
+        _ => {}
+    }
+    if change.status == 'R' {
+        header.push_str(&meta(format!("similarity index {}%", change.similarity_index())));
+        header.push_str(&meta(format!("rename from {}", old_path)));
+        header.push_str(&meta(format!("rename to {}", new_path)));
+    }
+    let (change_old, change_new) = (change.old.as_ref(), change.new.as_ref());
+    let hash = |file: Option<&DiffFile>| file.map(|file| file.hash.clone());
+    if index && hash(change_old) != hash(change_new) {
+        let mode = match (change_old, change_new) {
+            (Some(a), Some(b)) if a.mode == b.mode => format!(" {}", full_mode(Some(a))),
+            _ => String::new(),
+        };
+        header.push_str(&meta(format!("index {}..{}{}", abbrev(change_old), abbrev(change_new), mode)));
+    }
+    let (old_content, new_content) = (content(old), content(new));
+    if old_content == new_content && old.is_some() && new.is_some() {
+        return header;
+    }
+    if is_binary(&old_content) || is_binary(&new_content) {
+        let name = |file: Option<&DiffFile>, prefix: &str| file.map_or("/dev/null".to_string(), |file| format!("{}{}", prefix, file.path));
+        return header + &format!("Binary files {} and {} differ\n", name(old, "a/"), name(new, "b/"));
+    }
+    let hunks = format_hunks(&old_content, &new_content, context);
+    if hunks.is_empty() {
+        return header;
+    }
+    let old_name = old.map_or("/dev/null".to_string(), |_| format!("a/{}", old_path));
+    let new_name = new.map_or("/dev/null".to_string(), |_| format!("b/{}", new_path));
+    header + &meta(format!("--- {}", old_name)) + &meta(format!("+++ {}", new_name)) + &hunks
+}
+
+/// The patch for a change. A change of type, like a file becoming a symlink, is shown as
+/// the old file deleted and the new one added.
+pub fn format_patch(change: &FileChange, context: usize) -> String {
+    let (old, new) = (change.old.as_ref(), change.new.as_ref());
+    if change.status == 'T' {
+        return format_file_patch(old, None, change, true, context) + &format_file_patch(None, new, change, false, context);
+    }
+    format_file_patch(old, new, change, true, context)
+}
+
+/// Everything `options` asks to show about `changes`, as git lays it out: names or raw
+/// lines, then the diffstat in `width` columns, then the patches after a blank line.
+pub fn format_diff(changes: &[FileChange], options: &DiffOptions, width: usize) -> String {
+    let mut output = String::new();
+    if changes.is_empty() {
+        return output;
+    }
+    if options.raw || options.name_status || options.name_only {
+        output.extend(changes.iter().map(|change| format_name_line(change, options)));
+    }
+    if options.stat {
+        output.push_str(&format_stat(changes, width));
+    }
+    if options.patch {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.extend(changes.iter().map(|change| format_patch(change, options.context)));
+    }
+    output
+}
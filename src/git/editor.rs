@@ -0,0 +1,29 @@
+use std::env;
+use std::process::Command;
+
+use crate::git::config::Config;
+
+/// The editor to use, looked up like git: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`
+/// and finally `vi`.
+pub fn editor() -> String {
+    env::var("GIT_EDITOR").ok()
+        .or_else(|| Config::read().get("core.editor"))
+        .or_else(|| env::var("VISUAL").ok())
+        .or_else(|| env::var("EDITOR").ok())
+        .unwrap_or("vi".to_string())
+}
+
+/// Opens `path` in the editor through the shell, so that the editor may carry arguments.
+/// Returns the editor as the error if it could not be run or exited unsuccessfully.
+pub fn edit(path: &str) -> Result<(), String> {
+    let editor = editor();
+    // `:` is how scripts ask for the file to be left as it is
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$@\"", editor)).arg(&editor).arg(path).status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(editor),
+    }
+}
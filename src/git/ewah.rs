@@ -0,0 +1,177 @@
+/// Reader and writer for the EWAH compressed bitmaps git stores in index extensions.
+///
+/// The serialized form is the bit count, the word count, the 64-bit words and finally the
+/// position of the last run-length word. Each run-length word stores the running bit in
+/// bit 0, the number of clean words in the next 32 bits and the number of literal words
+/// that follow it in the top 31 bits.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EwahBitmap {
+    pub bits: Vec<bool>,
+}
+
+const MAX_RUNNING_LENGTH: u64 = (1 << 32) - 1;
+const MAX_LITERAL_WORDS: u64 = (1 << 31) - 1;
+
+impl EwahBitmap {
+    pub fn new(bits: Vec<bool>) -> Self {
+        Self { bits }
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.bits.get(i).copied().unwrap_or(false)
+    }
+
+    /// Drops the clear bits after the last set one. git's bitmaps only grow as bits are set,
+    /// so that is where the ones it writes end.
+    pub fn trimmed(mut self) -> Self {
+        let len = self.bits.iter().rposition(|&bit| bit).map_or(0, |last| last + 1);
+        self.bits.truncate(len);
+        self
+    }
+
+    /// Parses a bitmap from the start of `bytes`, returning it and the number of bytes read.
+    pub fn from_bytes(bytes: &[u8]) -> (Self, usize) {
+        let bit_size = u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let word_count = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        let words: Vec<u64> = (0..word_count)
+            .map(|i| u64::from_be_bytes(bytes[8 + i * 8..16 + i * 8].try_into().unwrap()))
+            .collect();
+        let mut bits = Vec::with_capacity(bit_size);
+        let mut i = 0;
+        while i < words.len() {
+            let rlw = words[i];
+            let running_bit = rlw & 1 == 1;
+            let running_length = (rlw >> 1) & MAX_RUNNING_LENGTH;
+            let literal_words = (rlw >> 33) as usize;
+            for _ in 0..running_length * 64 {
+                bits.push(running_bit);
+            }
+            for word in &words[i + 1..i + 1 + literal_words] {
+                for bit in 0..64 {
+                    bits.push(word >> bit & 1 == 1);
+                }
+            }
+            i += 1 + literal_words;
+        }
+        bits.resize(bit_size, false);
+        // The trailing 32-bit run-length word position is not needed for reading
+        (Self::new(bits), 8 + word_count * 8 + 4)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let raw: Vec<u64> = self.bits.chunks(64)
+            .map(|chunk| chunk.iter().enumerate().fold(0u64, |word, (bit, set)| word | (*set as u64) << bit))
+            .collect();
+        let mut words = Vec::new();
+        let mut last_rlw = 0;
+        let mut i = 0;
+        while i < raw.len() || words.is_empty() {
+            let running_bit = raw.get(i) == Some(&u64::MAX);
+            let clean = if running_bit { u64::MAX } else { 0 };
+            let mut running_length = 0;
+            while i < raw.len() && raw[i] == clean && running_length < MAX_RUNNING_LENGTH {
+                running_length += 1;
+                i += 1;
+            }
+            let literal_start = i;
+            while i < raw.len() && raw[i] != 0 && raw[i] != u64::MAX && ((i - literal_start) as u64) < MAX_LITERAL_WORDS {
+                i += 1;
+            }
+            last_rlw = words.len();
+            words.push(running_bit as u64 | running_length << 1 | ((i - literal_start) as u64) << 33);
+            words.extend_from_slice(&raw[literal_start..i]);
+        }
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.bits.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(words.len() as u32).to_be_bytes());
+        for word in words {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(last_rlw as u32).to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The delete and replace bitmaps of the `link` extension git 2.39 wrote for a split
+    /// index of 300 entries, after removing 5, 70, 71 and 200 to 299 and changing others.
+    const GIT_DELETE_BITMAP: &str = concat!(
+        "0000012c", "00000006",
+        "0000000400000000", "0000000000000020", "00000000000000c0",
+        "0000000400000002", "ffffffffffffff00", "00000fffffffffff",
+        "00000003",
+    );
+    const GIT_REPLACE_BITMAP: &str = concat!(
+        "000000c8", "00000005",
+        "0000000400000000", "ffffffffffffffdf", "ffffffffffffff3f",
+        "0000000200000003", "00000000000000ff",
+        "00000003",
+    );
+
+    fn set_bits(bitmap: &EwahBitmap) -> Vec<usize> {
+        (0..bitmap.bits.len()).filter(|&i| bitmap.get(i)).collect()
+    }
+
+    #[test]
+    fn reads_and_writes_bitmaps_as_git_does() {
+        let bytes = hex::decode(GIT_DELETE_BITMAP).unwrap();
+        let (deleted, len) = EwahBitmap::from_bytes(&bytes);
+        assert_eq!(len, bytes.len());
+        assert_eq!(deleted.bits.len(), 300);
+        assert_eq!(set_bits(&deleted), [5, 70, 71].into_iter().chain(200..300).collect::<Vec<_>>());
+        assert_eq!(deleted.to_bytes(), bytes);
+
+        let bytes = hex::decode(GIT_REPLACE_BITMAP).unwrap();
+        let (replaced, len) = EwahBitmap::from_bytes(&bytes);
+        assert_eq!(len, bytes.len());
+        assert_eq!(set_bits(&replaced), (0..200).filter(|i| ![5, 70, 71].contains(i)).collect::<Vec<_>>());
+        assert_eq!(replaced.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn empty_bitmaps_have_one_run_length_word() {
+        let bytes = EwahBitmap::default().to_bytes();
+        assert_eq!(bytes, hex::decode(concat!("00000000", "00000001", "0000000000000000", "00000000")).unwrap());
+        assert_eq!(EwahBitmap::from_bytes(&bytes), (EwahBitmap::default(), bytes.len()));
+    }
+
+    #[test]
+    fn trimmed_bitmaps_end_at_the_last_set_bit() {
+        assert_eq!(EwahBitmap::new(vec![false, true, false, false]).trimmed().bits, [false, true]);
+        assert_eq!(EwahBitmap::new(vec![false; 3]).trimmed(), EwahBitmap::default());
+    }
+
+    #[test]
+    fn runs_are_counted_in_clean_words() {
+        let mut bits = vec![true; 64 * 3];
+        bits.extend(vec![false; 64 * 2]);
+        bits.push(true);
+        let bytes = EwahBitmap::new(bits.clone()).to_bytes();
+        // Three words of ones, then two clean words of zeros followed by one literal
+        assert_eq!(&bytes[4..8], [0, 0, 0, 3]);
+        assert_eq!(u64::from_be_bytes(bytes[8..16].try_into().unwrap()), 1 | 3 << 1);
+        assert_eq!(u64::from_be_bytes(bytes[16..24].try_into().unwrap()), 2 << 1 | 1 << 33);
+        assert_eq!(u64::from_be_bytes(bytes[24..32].try_into().unwrap()), 1);
+        assert_eq!(EwahBitmap::from_bytes(&bytes).0.bits, bits);
+    }
+
+    #[test]
+    fn bitmaps_round_trip() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        for len in [1, 63, 64, 65, 127, 128, 1000, 4097] {
+            let bits: Vec<bool> = (0..len).map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                // Long stretches of the same bit between random ones
+                if (i / 200) % 2 == 0 { state.is_multiple_of(5) } else { i % 300 < 150 }
+            }).collect();
+            let bitmap = EwahBitmap::new(bits);
+            let bytes = bitmap.to_bytes();
+            assert_eq!(EwahBitmap::from_bytes(&bytes), (bitmap, bytes.len()));
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+
+use crate::git::config::Config;
+use crate::git::ewah::EwahBitmap;
+use crate::git::index::{FsMonitorExtension, Index};
+
+pub const SOCKET_PATH: &str = ".git/fsmonitor--daemon.ipc";
+
+/// Paths a file system monitor reported as changed since the token stored in the index.
+pub struct FsMonitorChanges {
+    pub token: String,
+    files: HashSet<String>,
+    directories: HashSet<String>,
+    // Directories whose listing changed: parents of every reported path
+    changed_directories: HashSet<String>,
+    everything: bool,
+    dirty: EwahBitmap,
+}
+
+impl FsMonitorChanges {
+    /// Parses a query response in the version 2 hook format: the new token followed by
+    /// NUL separated paths, where directories end with `/` and `/` alone means everything.
+    fn from_response(response: &[u8], dirty: EwahBitmap) -> Option<Self> {
+        let mut parts = response.split(|&x| x == b'\0');
+        let token = String::from_utf8_lossy(parts.next()?).to_string();
+        if token.is_empty() {
+            return None;
+        }
+        let mut changes = Self {
+            token,
+            files: HashSet::new(),
+            directories: HashSet::new(),
+            changed_directories: HashSet::new(),
+            everything: false,
+            dirty,
+        };
+        for path in parts.filter(|path| !path.is_empty()) {
+            let path = String::from_utf8_lossy(path);
+            if path == "/" {
+                changes.everything = true;
+                continue;
+            }
+            let path = match path.strip_suffix('/') {
+                Some(directory) => {
+                    changes.directories.insert(directory.to_string());
+                    changes.changed_directories.insert(directory.to_string());
+                    directory
+                }
+                None => {
+                    changes.files.insert(path.to_string());
+                    &path
+                }
+            };
+            let parent = path.rfind('/').map_or(".", |slash| &path[..slash]);
+            changes.changed_directories.insert(parent.to_string());
+        }
+        Some(changes)
+    }
+
+    fn query_hook(hook: &str, token: &str) -> Option<Vec<u8>> {
+        let output = Command::new(hook).arg("2").arg(token).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(output.stdout)
+    }
+
+    /// Asks the monitor configured in `core.fsmonitor` what changed since the index was last
+    /// refreshed. `true` uses the rgit daemon, any other value is run as a hook. Returns `None`
+    /// when no monitor is configured or it cannot answer, so callers fall back to a full scan.
+    pub fn query(index: &Index) -> Option<Self> {
+        let setting = Config::read().get("core.fsmonitor")?;
+        let (token, dirty) = match &index.fsmonitor {
+            Some(extension) => (extension.token.clone(), extension.dirty.clone()),
+            None => (String::new(), EwahBitmap::default()),
+        };
+        let response = match Config::parse_bool(&setting) {
+            Some(false) => return None,
+            Some(true) => query_daemon(&token)?,
+            None => Self::query_hook(&setting, &token)?,
+        };
+        let mut changes = Self::from_response(&response, dirty)?;
+        // Without a previous token nothing is known to be clean
+        if index.fsmonitor.is_none() {
+            changes.everything = true;
+        }
+        Some(changes)
+    }
+
+    /// Whether the index entry at `position` needs to be checked against the worktree.
+    pub fn is_changed(&self, position: usize, path: &str) -> bool {
+        if self.everything || self.dirty.get(position) || self.files.contains(path) {
+            return true;
+        }
+        path.match_indices('/').any(|(slash, _)| self.directories.contains(&path[..slash]))
+    }
+
+    /// Whether the listing of `directory` (`.` for the worktree root) may have changed.
+    pub fn is_directory_changed(&self, directory: &str) -> bool {
+        if self.everything || self.changed_directories.contains(directory) {
+            return true;
+        }
+        directory.match_indices('/').any(|(slash, _)| self.directories.contains(&directory[..slash]))
+    }
+
+    /// Builds the index extension recording the new token, with the given entries still dirty.
+    pub fn to_extension(&self, index: &Index, dirty_paths: &HashSet<String>) -> FsMonitorExtension {
+        let dirty = index.entries.iter().map(|entry| dirty_paths.contains(&entry.name)).collect();
+        FsMonitorExtension::new(self.token.clone(), EwahBitmap::new(dirty).trimmed())
+    }
+}
+
+const MAX_EVENTS: usize = 1_000_000;
+/// Cookie files are created in `.git` under this prefix to flush the event queue.
+const COOKIE_PREFIX: &str = "fsmonitor--daemon.cookie.";
+/// How long a query waits for its cookie before answering that everything may have changed.
+const COOKIE_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct DaemonState {
+    instance: String,
+    sequence: u64,
+    events: Vec<(u64, String)>,
+    // Tokens older than this predate a queue overflow or trimmed events
+    oldest_sequence: u64,
+    next_cookie: u64,
+    cookies_seen: HashSet<String>,
+}
+
+impl DaemonState {
+    fn record(&mut self, path: String) {
+        self.events.push((self.sequence, path));
+        self.sequence += 1;
+        if self.events.len() > MAX_EVENTS {
+            self.events.drain(..MAX_EVENTS / 2);
+            self.oldest_sequence = self.events[0].0;
+        }
+    }
+
+    fn token(&self) -> String {
+        format!("rgit:{}:{}", self.instance, self.sequence)
+    }
+
+    /// Answers with the current token and what changed since `token`. Unless `flushed`, events
+    /// for changes made before the query may still be queued, so everything is reported.
+    fn query(&self, token: &str, flushed: bool) -> Vec<u8> {
+        let since = token.strip_prefix("rgit:")
+            .and_then(|rest| rest.rsplit_once(':'))
+            .filter(|(instance, _)| *instance == self.instance)
+            .and_then(|(_, sequence)| sequence.parse::<u64>().ok())
+            .filter(|sequence| *sequence >= self.oldest_sequence && flushed);
+        let mut response = self.token().into_bytes();
+        response.push(b'\0');
+        match since {
+            Some(since) => {
+                let start = self.events.partition_point(|(sequence, _)| *sequence < since);
+                let paths: HashSet<&String> = self.events[start..].iter().map(|(_, path)| path).collect();
+                for path in paths {
+                    response.extend_from_slice(path.as_bytes());
+                    response.push(b'\0');
+                }
+            }
+            None => response.extend_from_slice(b"/\0"),
+        }
+        response
+    }
+}
+
+/// The monitor of the worktree at `root`: a thread records inotify events, and queries are
+/// answered from them.
+struct Daemon {
+    root: PathBuf,
+    state: Mutex<DaemonState>,
+    cookie_seen: Condvar,
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE | WatchMask::DELETE | WatchMask::MODIFY | WatchMask::ATTRIB
+        | WatchMask::CLOSE_WRITE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO | WatchMask::ONLYDIR
+}
+
+fn add_watches(inotify: &mut Inotify, root: &Path, directory: &str, watches: &mut HashMap<WatchDescriptor, String>) {
+    let fs_path = root.join(directory);
+    let Ok(wd) = inotify.watches().add(&fs_path, watch_mask()) else {
+        return;
+    };
+    watches.insert(wd, directory.to_string());
+    let Ok(entries) = fs::read_dir(&fs_path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == ".git" || !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            continue;
+        }
+        let path = if directory.is_empty() { name } else { format!("{}/{}", directory, name) };
+        add_watches(inotify, root, &path, watches);
+    }
+}
+
+impl Daemon {
+    /// Starts watching the worktree at `root`. Watches are registered before this returns,
+    /// so no query can miss an early change.
+    fn start(root: PathBuf) -> Arc<Self> {
+        let started = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let state = DaemonState {
+            instance: format!("{}.{}", process::id(), started),
+            sequence: 0,
+            events: Vec::new(),
+            oldest_sequence: 0,
+            next_cookie: 0,
+            cookies_seen: HashSet::new(),
+        };
+        let mut inotify = Inotify::init().unwrap();
+        let mut watches = HashMap::new();
+        add_watches(&mut inotify, &root, "", &mut watches);
+        // Only cookies are looked at in `.git`
+        let git_dir = inotify.watches().add(root.join(".git"), WatchMask::CREATE | WatchMask::ONLYDIR).unwrap();
+        let daemon = Arc::new(Self { root, state: Mutex::new(state), cookie_seen: Condvar::new() });
+        let watcher = Arc::clone(&daemon);
+        thread::spawn(move || watcher.watch_worktree(inotify, watches, git_dir));
+        daemon
+    }
+
+    fn watch_worktree(&self, mut inotify: Inotify, mut watches: HashMap<WatchDescriptor, String>, git_dir: WatchDescriptor) {
+        let mut buffer = [0; 64 * 1024];
+        loop {
+            let events = inotify.read_events_blocking(&mut buffer).unwrap();
+            let mut state = self.state.lock().unwrap();
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    state.oldest_sequence = state.sequence + 1;
+                    state.sequence += 1;
+                    continue;
+                }
+                if event.mask.contains(EventMask::IGNORED) {
+                    watches.remove(&event.wd);
+                    continue;
+                }
+                if event.wd == git_dir {
+                    let cookie = event.name.map(|name| name.to_string_lossy().to_string()).filter(|name| name.starts_with(COOKIE_PREFIX));
+                    if let Some(cookie) = cookie {
+                        state.cookies_seen.insert(cookie);
+                        self.cookie_seen.notify_all();
+                    }
+                    continue;
+                }
+                let (Some(directory), Some(name)) = (watches.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let name = name.to_string_lossy();
+                if directory.is_empty() && name == ".git" {
+                    continue;
+                }
+                let path = if directory.is_empty() { name.to_string() } else { format!("{}/{}", directory, name) };
+                if event.mask.contains(EventMask::ISDIR) {
+                    if event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                        add_watches(&mut inotify, &self.root, &path, &mut watches);
+                    }
+                    state.record(format!("{}/", path));
+                } else {
+                    state.record(path);
+                }
+            }
+        }
+    }
+
+    /// Answers a query as git's daemon does: a cookie file is created and the answer waits
+    /// until its event comes through, so every change made before the query has been seen.
+    fn query(&self, token: &str) -> Vec<u8> {
+        let cookie = {
+            let mut state = self.state.lock().unwrap();
+            state.next_cookie += 1;
+            format!("{}{}", COOKIE_PREFIX, state.next_cookie)
+        };
+        let cookie_path = self.root.join(".git").join(&cookie);
+        let created = fs::write(&cookie_path, b"").is_ok();
+        let state = self.state.lock().unwrap();
+        let (mut state, _) = self.cookie_seen
+            .wait_timeout_while(state, COOKIE_TIMEOUT, |state| created && !state.cookies_seen.contains(&cookie))
+            .unwrap();
+        let flushed = state.cookies_seen.remove(&cookie);
+        let _ = fs::remove_file(&cookie_path);
+        state.query(token, flushed)
+    }
+}
+
+fn handle_client(stream: UnixStream, daemon: &Daemon) {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    let request = request.trim_end();
+    let mut stream = &stream;
+    if let Some(token) = request.strip_prefix("query") {
+        let response = daemon.query(token.trim());
+        let _ = stream.write_all(&response);
+    } else if request == "quit" {
+        let _ = fs::remove_file(SOCKET_PATH);
+        process::exit(0);
+    }
+}
+
+/// Sends a query to the running daemon and returns its raw hook-format response.
+pub fn query_daemon(token: &str) -> Option<Vec<u8>> {
+    let mut stream = UnixStream::connect(SOCKET_PATH).ok()?;
+    stream.write_all(format!("query {}\n", token).as_bytes()).ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).ok()?;
+    Some(response)
+}
+
+pub fn is_daemon_running() -> bool {
+    UnixStream::connect(SOCKET_PATH).is_ok()
+}
+
+/// Runs the monitor daemon in the foreground, answering queries on `SOCKET_PATH`.
+pub fn run_daemon() {
+    if Path::new(SOCKET_PATH).exists() {
+        fs::remove_file(SOCKET_PATH).unwrap();
+    }
+    let daemon = Daemon::start(PathBuf::from("."));
+    let listener = UnixListener::bind(SOCKET_PATH).unwrap();
+    for stream in listener.incoming().flatten() {
+        handle_client(stream, &daemon);
+    }
+}
+
+pub fn stop_daemon() -> bool {
+    let Ok(mut stream) = UnixStream::connect(SOCKET_PATH) else {
+        return false;
+    };
+    stream.write_all(b"quit\n").is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(response: &[u8]) -> (String, Vec<String>) {
+        let mut parts = response.split(|&byte| byte == b'\0').filter(|part| !part.is_empty());
+        let token = String::from_utf8_lossy(parts.next().unwrap()).to_string();
+        (token, parts.map(|path| String::from_utf8_lossy(path).to_string()).collect())
+    }
+
+    #[test]
+    fn queries_see_files_written_just_before() {
+        let root = std::env::temp_dir().join(format!("rgit-fsmonitor-test-{}", process::id()));
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let daemon = Daemon::start(root.clone());
+        // Without a token everything may have changed
+        let (mut token, changed) = paths(&daemon.query(""));
+        assert_eq!(changed, ["/"]);
+        for i in 0..50 {
+            let name = format!("dir/file{}", i);
+            fs::write(root.join(&name), b"changed").unwrap();
+            let (next, changed) = paths(&daemon.query(&token));
+            assert!(changed.contains(&name), "{} missing from {:?}", name, changed);
+            token = next;
+        }
+        let (_, changed) = paths(&daemon.query(&token));
+        assert!(changed.is_empty());
+        // Cookies are cleaned up and not reported as changes
+        assert!(fs::read_dir(root.join(".git")).unwrap().next().is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
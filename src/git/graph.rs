@@ -0,0 +1,613 @@
+use std::mem;
+
+use crate::git::object::commit::Commit;
+
+/// The colors lanes cycle through, as git's default `color.graph` list.
+const COLUMN_COLORS: [&str; 12] = [
+    "\x1b[31m", "\x1b[32m", "\x1b[33m", "\x1b[34m", "\x1b[35m", "\x1b[36m",
+    "\x1b[1;31m", "\x1b[1;32m", "\x1b[1;33m", "\x1b[1;34m", "\x1b[1;35m", "\x1b[1;36m",
+];
+const COLOR_RESET: &str = "\x1b[m";
+
+/// Which kind of row the graph outputs next for the current commit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Every lane goes straight down; the commit is finished.
+    Padding,
+    /// The previous commit didn't finish, so `...` marks what's missing.
+    Skip,
+    /// The lanes right of an octopus merge move over to make room for it.
+    PreCommit,
+    Commit,
+    /// The lines from a merge out to its parents.
+    PostMerge,
+    /// Lanes moving left to where they belong.
+    Collapsing,
+}
+
+/// A lane of the graph and the commit it leads to.
+#[derive(Debug, Clone)]
+struct Column {
+    commit: String,
+    color: Option<usize>,
+}
+
+/// A row of graph output and its width on screen, which color codes don't add to.
+#[derive(Default)]
+struct Line {
+    text: String,
+    width: usize,
+}
+
+impl Line {
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.width += 1;
+    }
+
+    fn push_chars(&mut self, c: char, count: usize) {
+        for _ in 0..count {
+            self.push(c);
+        }
+    }
+
+    fn push_column(&mut self, column: &Column, c: char) {
+        match column.color {
+            Some(color) => {
+                self.text.push_str(COLUMN_COLORS[color]);
+                self.push(c);
+                self.text.push_str(COLOR_RESET);
+            }
+            None => self.push(c),
+        }
+    }
+}
+
+/// Draws the history graph left of `log` output one row at a time as commits are shown,
+/// the way git's `graph.c` does.
+///
+/// Commits are given to `update` in topological order, each followed by its rows until
+/// the commit is finished. `mapping` maps each screen column, two per lane, to the lane
+/// its line is heading for, or -1.
+pub struct Graph {
+    /// Whether a commit is shown, as only parents that are get lines drawn to them.
+    interesting: Box<dyn Fn(&str) -> bool>,
+    first_parent: bool,
+    colored: bool,
+    commit: Option<String>,
+    /// The parents of the commit that are shown.
+    parents: Vec<String>,
+    width: i32,
+    expansion_row: i32,
+    state: State,
+    prev_state: State,
+    commit_index: i32,
+    prev_commit_index: i32,
+    /// Whether a merge's first parent is to its left (0) or below it (1), or -1 before
+    /// it's known.
+    merge_layout: i32,
+    /// How many lanes the commit adds to its right.
+    edges_added: i32,
+    prev_edges_added: i32,
+    /// The lanes leading into the current commit's row.
+    columns: Vec<Column>,
+    /// The lanes leading out of it.
+    new_columns: Vec<Column>,
+    mapping: Vec<i32>,
+    old_mapping: Vec<i32>,
+    mapping_size: usize,
+    default_color: usize,
+}
+
+impl Graph {
+    pub fn new(interesting: Box<dyn Fn(&str) -> bool>, first_parent: bool, colored: bool) -> Self {
+        Self {
+            interesting,
+            first_parent,
+            colored,
+            commit: None,
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+            mapping_size: 0,
+            default_color: COLUMN_COLORS.len() - 1,
+        }
+    }
+
+    fn num_parents(&self) -> i32 {
+        self.parents.len() as i32
+    }
+
+    fn is_current(&self, commit: &str) -> bool {
+        self.commit.as_deref() == Some(commit)
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn current_color(&self) -> Option<usize> {
+        self.colored.then_some(self.default_color)
+    }
+
+    fn find_commit_color(&self, commit: &str) -> Option<usize> {
+        match self.columns.iter().find(|column| column.commit == commit) {
+            Some(column) => column.color,
+            None => self.current_color(),
+        }
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|column| column.commit == commit)
+    }
+
+    fn insert_into_new_columns(&mut self, commit: &str, index: i32) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i as i32,
+            None => {
+                let color = self.find_commit_color(commit);
+                self.new_columns.push(Column { commit: commit.to_string(), color });
+                self.new_columns.len() as i32 - 1
+            }
+        };
+        let mapping_index;
+        if self.num_parents() > 1 && index > -1 && self.merge_layout == -1 {
+            // The first parent of a merge picks the layout of the merge's lines by whether
+            // it's in a lane left of the merge
+            let distance = index - i;
+            let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+            self.merge_layout = if distance > 0 { 0 } else { 1 };
+            self.edges_added = self.num_parents() + self.merge_layout - 2;
+            mapping_index = self.width + (self.merge_layout - 1) * shift;
+            self.width += 2 * self.merge_layout;
+        } else if self.edges_added > 0 && i == self.mapping[(self.width - 2) as usize] {
+            // A lane added by a merge that joins the last existing lane joins it at once
+            mapping_index = self.width - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_index = self.width;
+            self.width += 2;
+        }
+        self.mapping[mapping_index as usize] = i;
+    }
+
+    fn update_columns(&mut self) {
+        mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        self.mapping = vec![-1; self.mapping_size];
+        if self.old_mapping.len() < self.mapping_size {
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        // Parents already in a lane keep it, and the commit gets a new lane at the end
+        // when nothing led to it
+        let mut seen_this = false;
+        let mut is_commit_in_columns = true;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                is_commit_in_columns = false;
+                self.commit.clone().unwrap_or_default()
+            } else {
+                self.columns[i].commit.clone()
+            };
+            if self.is_current(&column_commit) {
+                seen_this = true;
+                self.commit_index = i as i32;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    // Merges and new lanes without children each get a new color
+                    if self.num_parents() > 1 || !is_commit_in_columns {
+                        self.default_color = (self.default_color + 1) % COLUMN_COLORS.len();
+                    }
+                    self.insert_into_new_columns(&parent, i as i32);
+                }
+                // The commit takes up two screen columns even without any parents
+                if self.num_parents() == 0 {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column_commit, -1);
+            }
+        }
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    fn num_dashed_parents(&self) -> i32 {
+        self.num_parents() + self.merge_layout - 3
+    }
+
+    fn num_expansion_rows(&self) -> i32 {
+        self.num_dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.num_parents() >= 3 && self.commit_index < self.columns.len() as i32 - 1 && self.expansion_row < self.num_expansion_rows()
+    }
+
+    /// Moves on to `commit`, the next one shown.
+    pub fn update(&mut self, commit: &Commit) {
+        self.commit = Some(commit.hash.clone());
+        let parents = if self.first_parent { &commit.parents[..commit.parents.len().min(1)] } else { &commit.parents[..] };
+        self.parents = parents.iter().filter(|parent| (self.interesting)(parent)).cloned().collect();
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+        // No row for this state was output, so `prev_state` stays as it was
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    /// Whether every line is where it belongs, or one column right of it, which the next
+    /// row's `/` takes care of.
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size].iter().enumerate().all(|(i, &target)| target < 0 || target == i as i32 / 2)
+    }
+
+    fn pad_horizontally(&self, line: &mut Line) {
+        let width = self.width as usize;
+        if line.width < width {
+            line.push_chars(' ', width - line.width);
+        }
+    }
+
+    fn output_padding_line(&self, line: &mut Line) {
+        for column in &self.new_columns {
+            line.push_column(column, '|');
+            line.push(' ');
+        }
+    }
+
+    fn output_skip_line(&mut self, line: &mut Line) {
+        line.push_chars('.', 3);
+        let state = if self.needs_pre_commit_line() { State::PreCommit } else { State::Commit };
+        self.update_state(state);
+    }
+
+    fn output_pre_commit_line(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if self.is_current(&column.commit) {
+                seen_this = true;
+                line.push_column(column, '|');
+                line.push_chars(' ', self.expansion_row as usize);
+            } else if seen_this && self.expansion_row == 0 {
+                // Lanes drawn as `\` after a merge on the previous row keep going that way
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i as i32 {
+                    line.push_column(column, '\\');
+                } else {
+                    line.push_column(column, '|');
+                }
+            } else if seen_this && self.expansion_row > 0 {
+                line.push_column(column, '\\');
+            } else {
+                line.push_column(column, '|');
+            }
+            line.push(' ');
+        }
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    /// Draws the dashes from an octopus merge to its parents beyond the second, colored
+    /// as the lanes they end up in.
+    fn draw_octopus_merge(&self, line: &mut Line) {
+        let dashed_parents = self.num_dashed_parents();
+        for i in 0..dashed_parents {
+            let target = self.mapping[((self.commit_index + i + 2) * 2) as usize];
+            let column = &self.new_columns[target as usize];
+            line.push_column(column, '-');
+            line.push_column(column, if i == dashed_parents - 1 { '.' } else { '-' });
+        }
+    }
+
+    fn output_commit_line(&mut self, line: &mut Line) {
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                self.commit.clone().unwrap_or_default()
+            } else {
+                self.columns[i].commit.clone()
+            };
+            let index = i as i32;
+            if self.is_current(&column_commit) {
+                seen_this = true;
+                line.push('*');
+                if self.num_parents() > 2 {
+                    self.draw_octopus_merge(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push_column(&self.columns[i], '\\');
+            } else if seen_this && self.edges_added == 1 {
+                // This is the first row of the commit, so a lane drawn as `\` after the
+                // previous merge carries on as one
+                if self.prev_state == State::PostMerge && self.prev_edges_added > 0 && self.prev_commit_index < index {
+                    line.push_column(&self.columns[i], '\\');
+                } else {
+                    line.push_column(&self.columns[i], '|');
+                }
+            } else if self.prev_state == State::Collapsing && self.old_mapping[2 * i + 1] == index && self.mapping[2 * i] < index {
+                line.push_column(&self.columns[i], '/');
+            } else {
+                line.push_column(&self.columns[i], '|');
+            }
+            line.push(' ');
+        }
+        let state = if self.num_parents() > 1 {
+            State::PostMerge
+        } else if self.is_mapping_correct() {
+            State::Padding
+        } else {
+            State::Collapsing
+        };
+        self.update_state(state);
+    }
+
+    fn output_post_merge_line(&mut self, line: &mut Line) {
+        const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+        let first_parent = self.parents.first().cloned().unwrap_or_default();
+        let mut parent_column: Option<usize> = None;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column_commit = if i == self.columns.len() {
+                if seen_this {
+                    break;
+                }
+                self.commit.clone().unwrap_or_default()
+            } else {
+                self.columns[i].commit.clone()
+            };
+            if self.is_current(&column_commit) {
+                // Each parent's line is drawn in the color of the lane it goes to
+                seen_this = true;
+                let mut layout = self.merge_layout as usize;
+                for (j, parent) in self.parents.iter().enumerate() {
+                    let parent_index = self.find_new_column(parent).unwrap_or_default();
+                    line.push_column(&self.new_columns[parent_index], MERGE_CHARS[layout]);
+                    if layout == 2 {
+                        if self.edges_added > 0 || j < self.parents.len() - 1 {
+                            line.push(' ');
+                        }
+                    } else {
+                        layout += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push_column(&self.columns[i], if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push_column(&self.columns[i], '|');
+                if self.merge_layout != 0 || i as i32 != self.commit_index - 1 {
+                    match parent_column {
+                        Some(parent_column) => line.push_column(&self.columns[parent_column], '_'),
+                        None => line.push(' '),
+                    }
+                }
+            }
+            if column_commit == first_parent {
+                parent_column = Some(i);
+            }
+        }
+        let state = if self.is_mapping_correct() { State::Padding } else { State::Collapsing };
+        self.update_state(state);
+    }
+
+    fn output_collapsing_line(&mut self, line: &mut Line) {
+        let mut used_horizontal = false;
+        let mut horizontal_edge: i32 = -1;
+        let mut horizontal_edge_target: i32 = -1;
+        mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..self.mapping_size].fill(-1);
+
+        // Lines only ever move left, one column a row, and cross at most one other line
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            let index = i as i32;
+            if target * 2 == index {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                self.mapping[i - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = index;
+                    horizontal_edge_target = target;
+                    for j in ((target * 2 + 3)..(index - 2)).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // The line to the left goes to the same lane, so this one joins it
+            } else {
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = index - 1;
+                    for j in ((target * 2 + 3)..(index - 2)).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            }
+        }
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            let index = i as i32;
+            if target < 0 {
+                line.push(' ');
+            } else if target * 2 == index {
+                line.push_column(&self.new_columns[target as usize], '|');
+            } else if target == horizontal_edge_target && index != horizontal_edge - 1 {
+                // Only the first segment of a horizontal edge carries on to the next row
+                if index != target * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push_column(&self.new_columns[target as usize], '_');
+            } else {
+                if used_horizontal && index < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push_column(&self.new_columns[target as usize], '/');
+            }
+        }
+        // What the last collapsing row left is what the commit row draws on from
+        self.old_mapping.clone_from(&self.mapping);
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+
+    /// The next row of the graph, and whether it's the one with the commit on it.
+    fn next_line(&mut self) -> (String, bool) {
+        let mut line = Line::default();
+        let mut shown_commit_line = false;
+        match self.state {
+            State::Padding => self.output_padding_line(&mut line),
+            State::Skip => self.output_skip_line(&mut line),
+            State::PreCommit => self.output_pre_commit_line(&mut line),
+            State::Commit => {
+                self.output_commit_line(&mut line);
+                shown_commit_line = true;
+            }
+            State::PostMerge => self.output_post_merge_line(&mut line),
+            State::Collapsing => self.output_collapsing_line(&mut line),
+        }
+        self.pad_horizontally(&mut line);
+        (line.text, shown_commit_line)
+    }
+
+    /// A row that leaves every lane as it is, to go beside output that isn't about the
+    /// commit itself.
+    fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+        let mut line = Line::default();
+        for column in &self.columns {
+            line.push_column(column, '|');
+            if self.is_current(&column.commit) && self.num_parents() > 2 {
+                line.push_chars(' ', ((self.num_parents() - 2) * 2) as usize);
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad_horizontally(&mut line);
+        self.prev_state = State::Padding;
+        line.text
+    }
+
+    /// The columns the graph takes up beside each line of output.
+    pub fn width(&self) -> usize {
+        self.width as usize
+    }
+
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    /// Prints the rows up to and including the commit's, leaving the cursor after it.
+    pub fn show_commit(&mut self) {
+        if self.is_commit_finished() {
+            print!("{}", self.padding_line());
+            return;
+        }
+        loop {
+            let (line, shown_commit_line) = self.next_line();
+            print!("{}", line);
+            if shown_commit_line {
+                break;
+            }
+            println!();
+            if self.is_commit_finished() {
+                break;
+            }
+        }
+    }
+
+    /// Prints the next row, without a newline.
+    pub fn show_oneline(&mut self) {
+        let (line, _) = self.next_line();
+        print!("{}", line);
+    }
+
+    pub fn show_padding(&mut self) {
+        let line = self.padding_line();
+        print!("{}", line);
+    }
+
+    /// Prints the rows left for the commit, without a newline after the last.
+    fn show_remainder(&mut self) {
+        if self.is_commit_finished() {
+            return;
+        }
+        loop {
+            self.show_oneline();
+            if self.is_commit_finished() {
+                break;
+            }
+            println!();
+        }
+    }
+
+    /// Prints a commit's entry beside the graph, a row of graph before every line but the
+    /// first, which `show_commit` started, and then the rest of the commit's rows.
+    pub fn show_commit_msg(&mut self, text: &str) {
+        let mut lines = text.split_inclusive('\n').peekable();
+        while let Some(line) = lines.next() {
+            print!("{}", line);
+            if line.ends_with('\n') && lines.peek().is_some() {
+                self.show_oneline();
+            }
+        }
+        if self.is_commit_finished() {
+            return;
+        }
+        let newline_terminated = text.ends_with('\n');
+        if !newline_terminated {
+            println!();
+        }
+        self.show_remainder();
+        if newline_terminated {
+            println!();
+        }
+    }
+}
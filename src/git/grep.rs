@@ -0,0 +1,50 @@
+use std::process;
+
+use regex::{Regex, RegexBuilder};
+
+/// How `--grep`, `--author` and `--committer` patterns are read: as basic regular
+/// expressions by default, or as extended ones with `-E` and fixed strings with `-F`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternSyntax {
+    Basic,
+    Extended,
+    Fixed,
+}
+
+/// Rewrites a POSIX basic regular expression in the extended syntax, where `+ ? | ( ) { }`
+/// are special unless escaped rather than the other way around.
+fn basic_to_extended(pattern: &str) -> String {
+    let mut translated = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('+' | '?' | '|' | '(' | ')' | '{' | '}')) => translated.push(c),
+                Some(c) => {
+                    translated.push('\\');
+                    translated.push(c);
+                }
+                None => translated.push_str("\\\\"),
+            },
+            '+' | '?' | '|' | '(' | ')' | '{' | '}' => {
+                translated.push('\\');
+                translated.push(c);
+            }
+            _ => translated.push(c),
+        }
+    }
+    translated
+}
+
+/// Compiles a pattern given on the command line, exiting if it isn't a valid one.
+pub fn compile_pattern(pattern: &str, syntax: PatternSyntax, ignore_case: bool) -> Regex {
+    let translated = match syntax {
+        PatternSyntax::Basic => basic_to_extended(pattern),
+        PatternSyntax::Extended => pattern.to_string(),
+        PatternSyntax::Fixed => regex::escape(pattern),
+    };
+    RegexBuilder::new(&translated).case_insensitive(ignore_case).build().unwrap_or_else(|_| {
+        println!("fatal: command line, '{}': invalid regular expression", pattern);
+        process::exit(128);
+    })
+}
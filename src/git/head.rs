@@ -1,56 +1,105 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::git::reflog;
+use crate::git::refs::{self, RefValue};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadTarget {
+    /// A full ref name such as `refs/heads/feature/x`, reached by following symbolic refs.
+    Symbolic(String),
+    /// HEAD holds an object id directly.
+    Detached,
+}
 
 pub struct Head {
-    pub ref_path: PathBuf,
+    pub target: HeadTarget,
     pub head_hash: String,
 }
 
 impl Head {
-    fn new(ref_path: PathBuf, head_hash: String) -> Self {
-        Self { ref_path, head_hash }
-    }
-
-    pub fn from_file(path: &PathBuf) -> Self {
-        let file = File::open(&path).unwrap();
-        let mut reader = BufReader::new(file);
-        let mut buffer = [0; 1024];
-        let mut bytes: Vec<u8> = Vec::new();
-        loop {
-            let bytes_read = reader.read(&mut buffer).unwrap();
-            if bytes_read == 0 {
-                break;
+    fn new(target: HeadTarget, head_hash: String) -> Self {
+        Self { target, head_hash }
+    }
+
+    /// Resolves a full ref name, loose or packed, following symbolic refs. A `HEAD` that
+    /// holds an object id itself is detached.
+    pub fn from_ref(name: &str) -> Option<Self> {
+        let (name, hash) = refs::resolve_ref(name)?;
+        let target = if name == "HEAD" { HeadTarget::Detached } else { HeadTarget::Symbolic(name) };
+        Some(Self::new(target, hash?))
+    }
+
+    pub fn from_branch(branch: &str) -> Self {
+        match Self::from_ref(&format!("refs/heads/{}", branch)) {
+            Some(head) => head,
+            None => {
+                println!("error: pathspec '{}' did not match any file(s) known to git", branch);
+                process::exit(1);
             }
-            bytes.extend_from_slice(&buffer[..bytes_read]);
         }
-        Self::new(PathBuf::from(path), String::from_utf8_lossy(&bytes).trim().to_string())
     }
 
-    pub fn from_branch(branch: &str) -> Self {
-        let ref_path = PathBuf::from(format!(".git/refs/heads/{}", branch));
-        Self::from_file(&ref_path)
+    /// The branch HEAD is on, without the `refs/heads/` prefix.
+    pub fn branch_name(&self) -> Option<&str> {
+        match &self.target {
+            HeadTarget::Symbolic(name) => Some(name.strip_prefix("refs/heads/").unwrap_or(name)),
+            HeadTarget::Detached => None,
+        }
+    }
+
+    pub fn abbreviated_hash(&self) -> &str {
+        &self.head_hash[..7]
+    }
+
+    /// The old hash and message for logging a HEAD move, in git's `checkout: moving from <old>
+    /// to <target>` form, where `<old>` is the current branch or, when detached, commit.
+    fn checkout_entry(target: &str) -> Option<(String, String)> {
+        let (from, old_hash) = match refs::read_raw_ref("HEAD") {
+            Some(RefValue::Symbolic(name)) => (name.strip_prefix("refs/heads/").unwrap_or(&name).to_string(), refs::read_ref(&name)),
+            Some(RefValue::Direct(hash)) => (hash.clone(), Some(hash)),
+            None => return None,
+        };
+        Some((old_hash.unwrap_or(reflog::NULL_HASH.to_string()), format!("checkout: moving from {} to {}", from, target)))
+    }
+
+    /// Moves HEAD to `value` and logs the move once it is written.
+    fn move_head(value: RefValue, new_hash: &str, target: &str) {
+        let entry = Self::checkout_entry(target);
+        refs::write_raw_ref("HEAD", &value);
+        if let Some((old_hash, message)) = entry {
+            reflog::append("HEAD", &old_hash, new_hash, &message);
+        }
     }
 
     pub fn update_head_to_branch(branch: &str) {
-        std::fs::write(".git/HEAD", format!("ref: refs/heads/{}", branch)).unwrap();
-        std::fs::remove_file(".git/index").unwrap();
+        let hash = refs::read_ref(&format!("refs/heads/{}", branch)).unwrap_or(reflog::NULL_HASH.to_string());
+        Self::move_head(RefValue::Symbolic(format!("refs/heads/{}", branch)), &hash, branch);
+    }
+
+    /// Detaches HEAD at `hash`; `target` is the revision as the user named it, for the reflog.
+    pub fn update_head_to_commit(hash: &str, target: &str) {
+        Self::move_head(RefValue::Direct(hash.to_string()), hash, target);
     }
 
     pub fn from_head() -> Self {
-        let file = File::open(Path::new(".git/HEAD")).unwrap();
-        let mut reader = BufReader::new(file);
-        let mut buffer = [0; 1024];
-        let mut bytes: Vec<u8> = Vec::new();
-        loop {
-            let bytes_read = reader.read(&mut buffer).unwrap();
-            if bytes_read == 0 {
-                break;
+        let ref_name = match refs::read_raw_ref("HEAD") {
+            Some(RefValue::Direct(hash)) => return Self::new(HeadTarget::Detached, hash),
+            Some(RefValue::Symbolic(_)) => refs::resolve_ref("HEAD"),
+            None => {
+                println!("fatal: not a git repository: .git/HEAD is invalid");
+                process::exit(128);
+            }
+        };
+        match ref_name {
+            Some((name, Some(hash))) => Self::new(HeadTarget::Symbolic(name), hash),
+            Some((name, None)) => {
+                println!("fatal: your current branch '{}' does not have any commits yet", name.trim_start_matches("refs/heads/"));
+                process::exit(128);
+            }
+            None => {
+                println!("fatal: HEAD is a symbolic ref chain deeper than {} levels", refs::MAX_SYMREF_DEPTH);
+                process::exit(128);
             }
-            bytes.extend_from_slice(&buffer[..bytes_read]);
         }
-        let ref_path = &String::from_utf8_lossy(&bytes)[5..];
-        let ref_path = ref_path.trim();
-        Self::from_file(&PathBuf::from(format!(".git/{}", ref_path.to_string())))
     }
 }
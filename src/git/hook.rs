@@ -0,0 +1,55 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use is_executable::IsExecutable;
+
+use crate::git::config::Config;
+
+/// Where hooks live: `core.hooksPath`, relative to the top of the worktree, or
+/// `.git/hooks`.
+fn hooks_dir(config: &Config) -> PathBuf {
+    PathBuf::from(config.get("core.hooksPath").unwrap_or(".git/hooks".to_string()))
+}
+
+/// Whether there's an executable hook called `name`.
+pub fn exists(name: &str) -> bool {
+    hooks_dir(&Config::read()).join(name).is_executable()
+}
+
+/// The hook called `name`, if there's an executable one. A hook that exists but can't be
+/// run is pointed out, as git does, unless `advice.ignoredHook` is off.
+fn find(name: &str) -> Option<PathBuf> {
+    let config = Config::read();
+    let path = hooks_dir(&config).join(name);
+    if path.is_executable() {
+        return Some(path);
+    }
+    let advise = config.get("advice.ignoredHook").and_then(|value| Config::parse_bool(&value)).unwrap_or(true);
+    if Path::new(&path).is_file() && advise {
+        println!("hint: The '{}' hook was ignored because it's not set as executable.", path.display());
+        println!("hint: You can disable this warning with `git config advice.ignoredHook false`.");
+    }
+    None
+}
+
+/// Runs the hook called `name` from the top of the worktree with `args`, extra `env` and,
+/// when given, `stdin`. Returns whether it succeeded, which it trivially does when there's
+/// no such hook; only pre-hooks are expected to act on a failure.
+pub fn run(name: &str, args: &[&str], env: &[(&str, &str)], stdin: Option<&[u8]>) -> bool {
+    let Some(path) = find(name) else {
+        return true;
+    };
+    let mut command = Command::new(&path);
+    command.args(args).env("GIT_DIR", ".git").envs(env.iter().copied());
+    command.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() });
+    let Ok(mut child) = command.spawn() else {
+        println!("error: cannot run {}", path.display());
+        return false;
+    };
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // A hook that doesn't read its input is not an error
+        let _ = pipe.write_all(input);
+    }
+    child.wait().is_ok_and(|status| status.success())
+}
@@ -0,0 +1,189 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::process;
+
+use chrono::Local;
+
+use crate::git::config::Config;
+use crate::git::date;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(&self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(&self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+/// Why an identity couldn't be determined. Missing identities come with git's hint on
+/// configuring one.
+#[derive(Debug)]
+pub enum IdentError {
+    Unknown(String),
+    Invalid(String),
+}
+
+/// The login name and the full name from the GECOS field of the user's passwd entry.
+fn passwd_entry() -> Option<(String, String)> {
+    let uid = fs::metadata("/proc/self").ok()?.uid().to_string();
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    let fields: Vec<&str> = passwd.lines().map(|line| line.split(':').collect::<Vec<&str>>()).find(|fields| fields.get(2) == Some(&uid.as_str()))?;
+    let full_name = fields.get(4).and_then(|gecos| gecos.split(',').next()).unwrap_or("");
+    Some((fields[0].to_string(), full_name.to_string()))
+}
+
+/// `EMAIL`, or `<login>@<host name>`. Host names without a domain get git's `.(none)`,
+/// which makes the address bogus.
+fn default_email() -> (String, bool) {
+    if let Ok(email) = env::var("EMAIL") {
+        return (email, false);
+    }
+    let login = passwd_entry().map(|(login, _)| login).unwrap_or_default();
+    let host = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default().trim().to_string();
+    if host.contains('.') {
+        (format!("{}@{}", login, host), false)
+    } else {
+        (format!("{}@{}.(none)", login, host), true)
+    }
+}
+
+/// Resolves an identity like git: the name and email from `GIT_<ROLE>_NAME`/`_EMAIL`, then
+/// `<role>.name`/`.email` and `user.name`/`.email` in config, and the date from
+/// `GIT_<ROLE>_DATE` or the current time. Without `strict`, missing values are guessed from
+/// the system as git does for reflogs; with it, `user.useConfigOnly` forbids guessing and
+/// guesses that can't be right are errors.
+pub fn signature(role: Role, strict: bool) -> Result<Signature, IdentError> {
+    let config = Config::read();
+    let lookup = |key: &str| {
+        env::var(format!("{}_{}", role.env_prefix(), key.to_uppercase())).ok()
+            .or_else(|| config.get(&format!("{}.{}", role.config_section(), key)))
+            .or_else(|| config.get(&format!("user.{}", key)))
+    };
+    let config_only = strict && config.get("user.useConfigOnly").and_then(|value| Config::parse_bool(&value)) == Some(true);
+    let email = match lookup("email") {
+        Some(email) => email,
+        None if config_only => return Err(IdentError::Unknown("no email was given and auto-detection is disabled".to_string())),
+        None => match default_email() {
+            (email, true) if strict => return Err(IdentError::Unknown(format!("unable to auto-detect email address (got '{}')", email))),
+            (email, _) => email,
+        },
+    };
+    let name = match lookup("name") {
+        Some(name) => name,
+        None if config_only => return Err(IdentError::Unknown("no name was given and auto-detection is disabled".to_string())),
+        None => passwd_entry().map(|(_, full_name)| full_name).unwrap_or_default(),
+    };
+    if name.is_empty() && strict {
+        return Err(IdentError::Invalid(format!("empty ident name (for <{}>) not allowed", email)));
+    }
+    let name = if name.is_empty() { passwd_entry().map(|(login, _)| login).unwrap_or_default() } else { name };
+    let (time, offset) = match env::var(format!("{}_DATE", role.env_prefix())) {
+        Ok(value) => date::parse_ident_date(&value).ok_or(IdentError::Invalid(format!("invalid date format: {}", value)))?,
+        Err(_) => {
+            let now = Local::now();
+            (now.timestamp(), now.format("%z").to_string())
+        }
+    };
+    Ok(Signature::new(&name, &email, time, &offset))
+}
+
+/// The identity for `role`, exiting with git's explanation when there isn't a valid one.
+pub fn signature_or_exit(role: Role) -> Signature {
+    signature(role, true).unwrap_or_else(|error| exit_with_error(role, error))
+}
+
+/// Exits with git's explanation of why there is no identity for `role`.
+pub fn exit_with_error(role: Role, error: IdentError) -> ! {
+    match error {
+        IdentError::Unknown(message) => {
+            println!("{} identity unknown", if role == Role::Author { "Author" } else { "Committer" });
+            println!();
+            println!("*** Please tell me who you are.");
+            println!();
+            println!("Run");
+            println!();
+            println!("  git config --global user.email \"you@example.com\"");
+            println!("  git config --global user.name \"Your Name\"");
+            println!();
+            println!("to set your account's default identity.");
+            println!("Omit --global to set the identity only in this repository.");
+            println!();
+            println!("fatal: {}", message);
+            process::exit(128);
+        }
+        IdentError::Invalid(message) => {
+            println!("fatal: {}", message);
+            process::exit(128);
+        }
+    }
+}
+
+/// The author, committer or tagger of an object: `Name <email> <unix time> <offset>`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    /// The `+hhmm`/`-hhmm` offset as written, since `-0000` and `+0000` differ.
+    pub offset: String,
+    /// The identity exactly as read from an object, which is written back unchanged. Names
+    /// need not be UTF-8, and git keeps spacing it wouldn't write itself.
+    pub raw: Option<Vec<u8>>,
+}
+
+impl Signature {
+    pub fn new(name: &str, email: &str, time: i64, offset: &str) -> Self {
+        Self { name: name.to_string(), email: email.to_string(), time, offset: offset.to_string(), raw: None }
+    }
+
+    /// Parses an identity from an object header, keeping its bytes to serialize it with.
+    /// Whatever can't be parsed is left at its default.
+    pub fn from_bytes(ident: &[u8]) -> Self {
+        let mut signature = Self::parse(&String::from_utf8_lossy(ident)).unwrap_or_default();
+        signature.raw = Some(ident.to_vec());
+        signature
+    }
+
+    /// The identity as it goes in an object header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.raw.clone().unwrap_or_else(|| self.to_string().into_bytes())
+    }
+
+    /// Parses an identity line, taking the email from the first `<...>` and the name from
+    /// what precedes it.
+    pub fn parse(ident: &str) -> Option<Self> {
+        let (name, rest) = ident.split_once('<')?;
+        let (email, date) = rest.split_once('>')?;
+        let mut date = date.split_whitespace();
+        let time = date.next().and_then(|time| time.parse().ok()).unwrap_or(0);
+        let offset = date.next().unwrap_or("+0000");
+        Some(Self::new(name.strip_suffix(' ').unwrap_or(name), email, time, offset))
+    }
+
+    /// `Name <email>`, without the date.
+    pub fn identity(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.identity(), self.time, self.offset)
+    }
+}
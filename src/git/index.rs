@@ -1,4 +1,12 @@
-use std::{fs::File, io::{BufReader, Read}};
+use std::{env, fs::{self, File}, io::{BufReader, Read}, os::unix::fs::MetadataExt, process};
+
+use sha1::{Digest, Sha1};
+
+use crate::git::cache_tree::CacheTree;
+use crate::git::ewah::EwahBitmap;
+use crate::git::object::tree::Tree;
+use crate::git::sparse::SparseCheckout;
+use crate::git::untracked_cache::UntrackedCache;
 
 #[derive(Debug)]
 pub struct IndexEntry {
@@ -14,12 +22,114 @@ pub struct IndexEntry {
     pub size: u32,
     pub sha1: String,
     pub flags: u16,
+    pub extended_flags: u16,
     pub name: String,
 }
 
+pub const INDEX_LOCK_PATH: &str = ".git/index.lock";
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
 impl IndexEntry {
     pub fn new(ctime: u32, ctime_nsec: u32, mtime: u32, mtime_nsec: u32, device: u32, inode: u32, mode: u32, uid: u32, gid: u32, size: u32, sha1: String, flags: u16, name: String) -> Self {
-        Self { ctime, ctime_nsec, mtime, mtime_nsec, device, inode, mode, uid, gid, size, sha1, flags, name }
+        Self { ctime, ctime_nsec, mtime, mtime_nsec, device, inode, mode, uid, gid, size, sha1, flags, extended_flags: 0, name }
+    }
+
+    /// Creates an entry with zeroed stat data, forcing the next status check to rehash the file.
+    pub fn from_object(name: &str, mode: u32, sha1: &str) -> Self {
+        let flags = name.len().min(0xfff) as u16;
+        Self::new(0, 0, 0, 0, 0, 0, mode, 0, 0, 0, sha1.to_string(), flags, name.to_string())
+    }
+
+    pub fn refresh_stat(&mut self) {
+        let metadata = fs::symlink_metadata(&self.name).unwrap();
+        self.ctime = metadata.ctime() as u32;
+        self.ctime_nsec = metadata.ctime_nsec() as u32;
+        self.mtime = metadata.mtime() as u32;
+        self.mtime_nsec = metadata.mtime_nsec() as u32;
+        self.device = metadata.dev() as u32;
+        self.inode = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    /// The merge stage: 0 for a resolved entry, 1 to 3 for the sides of a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 3
+    }
+
+    pub fn is_skip_worktree(&self) -> bool {
+        self.extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub fn set_skip_worktree(&mut self, skip: bool) {
+        if skip {
+            self.extended_flags |= EXTENDED_FLAG_SKIP_WORKTREE;
+        } else {
+            self.extended_flags &= !EXTENDED_FLAG_SKIP_WORKTREE;
+        }
+        if self.extended_flags != 0 {
+            self.flags |= FLAG_EXTENDED;
+        } else {
+            self.flags &= !FLAG_EXTENDED;
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [self.ctime, self.ctime_nsec, self.mtime, self.mtime_nsec, self.device, self.inode, self.mode, self.uid, self.gid, self.size] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes.extend_from_slice(&hex::decode(&self.sha1).unwrap());
+        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        if self.flags & FLAG_EXTENDED != 0 {
+            bytes.extend_from_slice(&self.extended_flags.to_be_bytes());
+        }
+        let header_len = bytes.len();
+        bytes.extend_from_slice(self.name.as_bytes());
+        // Entries are NUL terminated and padded to a multiple of eight bytes
+        let padding = 8 - (header_len + self.name.len()) % 8;
+        bytes.extend(std::iter::repeat_n(0, padding));
+        bytes
+    }
+}
+
+/// The FSMN extension: the file system monitor token the index was last refreshed at and
+/// the entries that were not known to be clean at that point.
+#[derive(Debug)]
+pub struct FsMonitorExtension {
+    pub token: String,
+    pub dirty: EwahBitmap,
+}
+
+impl FsMonitorExtension {
+    pub fn new(token: String, dirty: EwahBitmap) -> Self {
+        Self { token, dirty }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let version = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        // Version 1 stores a timestamp instead of an opaque token, which rgit cannot query with
+        if version != 2 {
+            return None;
+        }
+        let token_len = bytes[4..].iter().position(|&x| x == b'\0')?;
+        let token = String::from_utf8_lossy(&bytes[4..4 + token_len]).to_string();
+        let (dirty, _) = EwahBitmap::from_bytes(&bytes[4 + token_len + 1 + 4..]);
+        Some(Self::new(token, dirty))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u32.to_be_bytes());
+        bytes.extend_from_slice(self.token.as_bytes());
+        bytes.push(b'\0');
+        let bitmap = self.dirty.to_bytes();
+        bytes.extend_from_slice(&(bitmap.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&bitmap);
+        bytes
     }
 }
 
@@ -28,15 +138,46 @@ pub struct Index {
     pub version: u32,
     pub num_entries: u32,
     pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    pub fsmonitor: Option<FsMonitorExtension>,
+    pub untracked_cache: Option<UntrackedCache>,
 }
 
 impl Index {
     pub fn new(version: u32, entries: Vec<IndexEntry>) -> Self {
-        Self { version, num_entries: entries.len() as u32, entries }
+        Self { version, num_entries: entries.len() as u32, entries, cache_tree: None, fsmonitor: None, untracked_cache: None }
+    }
+
+    /// Builds the index for a freshly checked out tree, with the stat data of the files just
+    /// written. Entries outside the sparse checkout's cone are marked skip-worktree.
+    pub fn from_tree(tree: &str, sparse: Option<&SparseCheckout>) -> Self {
+        let mut files = Vec::new();
+        Tree::from_hash(tree).flatten("", &mut files);
+        let mut entries = Vec::new();
+        for file in files {
+            let mode = u32::from_str_radix(&file.mode, 8).unwrap();
+            let mut entry = IndexEntry::from_object(&file.name, mode, &file.hash);
+            if sparse.is_some_and(|sparse| !sparse.contains(&file.name)) {
+                entry.set_skip_worktree(true);
+            } else if fs::symlink_metadata(&file.name).is_ok() {
+                entry.refresh_stat();
+            }
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+        Self::new(2, entries)
     }
 
+    /// Reads `.git/index`, which like in git is empty until something is staged.
     pub fn read_index() -> Self {
-        let file = File::open(".git/index").unwrap();
+        Self::read_index_from(".git/index")
+    }
+
+    /// Reads the index file at `path`, such as the one a hook was handed.
+    pub fn read_index_from(path: &str) -> Self {
+        let Ok(file) = File::open(path) else {
+            return Self::new(2, Vec::new());
+        };
         let mut reader = BufReader::new(file);
         let mut entries: Vec<IndexEntry> = Vec::new();
         let mut array: Vec<u8> = Vec::new();
@@ -47,7 +188,8 @@ impl Index {
             std::process::exit(1);
         }
         let version = Some(u32::from_be_bytes(array[4..8].try_into().unwrap()));
-        if version.unwrap() != 2 {
+        // Version 3 only adds the extended flags, version 4 prefix-compresses names
+        if version.unwrap() != 2 && version.unwrap() != 3 {
             println!("Unsupported index version: {}", version.unwrap());
             std::process::exit(1);
         }
@@ -67,18 +209,265 @@ impl Index {
             let file_size = u32::from_be_bytes(array[index + 36..index + 40].try_into().unwrap());
             let sha1 = hex::encode(&array[index + 40..index + 60]).to_string();
             let flags = u16::from_be_bytes(array[index + 60..index + 62].try_into().unwrap());
+            let mut header_len = 62;
+            let mut extended_flags = 0;
+            if flags & FLAG_EXTENDED != 0 {
+                extended_flags = u16::from_be_bytes(array[index + 62..index + 64].try_into().unwrap());
+                header_len = 64;
+            }
             let name_len = flags & 0xfff;
-            let name = String::from_utf8_lossy(&array[index + 62..index + 62 + name_len as usize]).to_string();
-            let mut offset = 62 + name_len as usize + 1;
+            let name = String::from_utf8_lossy(&array[index + header_len..index + header_len + name_len as usize]).to_string();
+            let mut offset = header_len + name_len as usize + 1;
             let offset_rem = (8 - (offset % 8)) % 8;
             offset = offset + offset_rem;
             index = index + offset;
-            entries.push(IndexEntry::new(ctime, ctime_nsec, mtime, mtime_nsec, device, inode, mode, uid, gid, file_size, sha1, flags, name));
+            let mut entry = IndexEntry::new(ctime, ctime_nsec, mtime, mtime_nsec, device, inode, mode, uid, gid, file_size, sha1, flags, name);
+            entry.extended_flags = extended_flags;
+            entries.push(entry);
         }
-        Self::new(version.unwrap(), entries)
+        let mut result = Self::new(version.unwrap(), entries);
+        // Extensions follow the entries, the last 20 bytes are the checksum
+        while index + 8 <= array.len() - 20 {
+            let signature = &array[index..index + 4];
+            let size = u32::from_be_bytes(array[index + 4..index + 8].try_into().unwrap()) as usize;
+            let data = &array[index + 8..index + 8 + size];
+            if signature == b"TREE" {
+                result.cache_tree = Some(CacheTree::from_bytes(data));
+            } else if signature == b"FSMN" {
+                result.fsmonitor = FsMonitorExtension::from_bytes(data);
+            } else if signature == b"UNTR" {
+                result.untracked_cache = Some(UntrackedCache::from_bytes(data));
+            }
+            index += 8 + size;
+        }
+        result
     }
 
     pub fn get_entry(&self, name: &str) -> Option<&IndexEntry> {
         self.entries.iter().find(|entry| entry.name == name)
     }
-}
\ No newline at end of file
+
+    pub fn get_entry_mut(&mut self, name: &str) -> Option<&mut IndexEntry> {
+        self.entries.iter_mut().find(|entry| entry.name == name)
+    }
+
+    /// Replaces the entry with the same name, or inserts it keeping entries sorted by name.
+    pub fn add_entry(&mut self, entry: IndexEntry) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate_path(&entry.name);
+        }
+        match self.entries.binary_search_by(|e| e.name.as_bytes().cmp(entry.name.as_bytes())) {
+            Ok(pos) => self.entries[pos] = entry,
+            Err(pos) => {
+                if let Some(untracked_cache) = &mut self.untracked_cache {
+                    untracked_cache.invalidate_path(&entry.name);
+                }
+                self.entries.insert(pos, entry);
+            }
+        }
+        self.num_entries = self.entries.len() as u32;
+        // The fsmonitor bitmap is positional, drop it rather than shifting it
+        self.fsmonitor = None;
+    }
+
+    pub fn remove_entry(&mut self, name: &str) {
+        self.entries.retain(|entry| entry.name != name);
+        self.num_entries = self.entries.len() as u32;
+        self.fsmonitor = None;
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate_path(name);
+        }
+        if let Some(untracked_cache) = &mut self.untracked_cache {
+            untracked_cache.invalidate_path(name);
+        }
+    }
+
+    /// Writes the tree objects the index describes and returns the root tree's id, keeping
+    /// the cache tree up to date so that unchanged directories are reused next time.
+    pub fn write_tree(&mut self, missing_ok: bool) -> Result<String, String> {
+        self.cache_tree.get_or_insert_with(|| CacheTree::new("")).update(&self.entries, missing_ok)
+    }
+
+    fn write_extension(bytes: &mut Vec<u8>, signature: &[u8; 4], data: &[u8]) {
+        bytes.extend_from_slice(signature);
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    /// Takes `.git/index.lock`. The error is git's explanation of a lock that is already held.
+    /// `write_locked_index` then writes through the lock and releases it.
+    pub fn lock() -> Result<(), String> {
+        match fs::OpenOptions::new().write(true).create_new(true).open(INDEX_LOCK_PATH) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!(
+                "Unable to create '{}': File exists.\n\n\
+                Another git process seems to be running in this repository, e.g.\n\
+                an editor opened by 'git commit'. Please make sure all processes\n\
+                are terminated then try again. If it still fails, a git process\n\
+                may have crashed in this repository earlier:\n\
+                remove the file manually to continue.",
+                env::current_dir().unwrap().join(INDEX_LOCK_PATH).display()
+            )),
+        }
+    }
+
+    /// Takes the lock as commands that change the index do before reading it, dying like
+    /// git when it is held.
+    pub fn lock_or_exit() {
+        if let Err(error) = Self::lock() {
+            println!("fatal: {}", error);
+            process::exit(128);
+        }
+    }
+
+    pub fn unlock() {
+        let _ = fs::remove_file(INDEX_LOCK_PATH);
+    }
+
+    /// Writes the index through the lock taken with `lock` and releases it. Only the
+    /// extensions rgit maintains are written, git rebuilds the others it needs.
+    pub fn write_locked_index(&self) {
+        self.write_index_to(INDEX_LOCK_PATH);
+        fs::rename(INDEX_LOCK_PATH, ".git/index").unwrap();
+    }
+
+    /// Takes the lock, writes the index back to `.git/index` and releases the lock.
+    pub fn write_index(&self) -> Result<(), String> {
+        Self::lock()?;
+        self.write_locked_index();
+        Ok(())
+    }
+
+    /// Writes the index to the file at `path` as it is, without taking a lock.
+    pub fn write_index_to(&self, path: &str) {
+        let mut bytes = Vec::new();
+        // Extended flags need at least version 3
+        let extended = self.entries.iter().any(|entry| entry.flags & FLAG_EXTENDED != 0);
+        let version = if extended { self.version.max(3) } else { self.version };
+        bytes.extend_from_slice(b"DIRC");
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in self.entries.iter() {
+            bytes.extend_from_slice(&entry.to_bytes());
+        }
+        if let Some(cache_tree) = &self.cache_tree {
+            Self::write_extension(&mut bytes, b"TREE", &cache_tree.to_bytes());
+        }
+        if let Some(untracked_cache) = &self.untracked_cache {
+            Self::write_extension(&mut bytes, b"UNTR", &untracked_cache.to_bytes());
+        }
+        if let Some(fsmonitor) = &self.fsmonitor {
+            Self::write_extension(&mut bytes, b"FSMN", &fsmonitor.to_bytes());
+        }
+        let checksum = Sha1::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        fs::write(path, &bytes).unwrap();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 3 index written by git 2.39.5 with `a` and a skip-worktree `d/b` staged,
+    /// `d/u` untracked, and the TREE, UNTR and FSMN extensions, the last from a hook that
+    /// answered with the token `tok1` and `d/b` as changed.
+    const GIT_INDEX: &str = concat!(
+        "4449524300000003000000026ad5f3160668d0436ad5f3160668d0430000fe000013402c000081a40000",
+        "0000000000000000000278981922613b2afb6025042ff6bd878ac1994e85000161006ad5f3160668d043",
+        "6ad5f3160668d0430000fe000013402d000081a400000000000000000000000261780798228d17af2d34",
+        "fce4cfbdf3555683247240034000642f620000000000545245450000000d002d3120310a64002d312030",
+        "0a554e5452000001361f4c6f636174696f6e202f746d702f69782c2073797374656d204c696e7578006a",
+        "d5f31605d55cdb6ad5f31605d55cdb0000fe00001340150000000000000000000000f000000000000000",
+        "000000000000000000000000000000000000000000000000000000000000000006cc30ca8b9b10bb92f8",
+        "e5c96ee94348c6c4ac93e600000000000000000000000000000000000000002e67697469676e6f726500",
+        "0200010001006400750000000002000000020000000200000000000000000000000300000000000000",
+        "000000000100000000000000000000000000000000000000010000000000000000000000006ad5f31606",
+        "25f6846ad5f3160625f6840000fe00001340010000000000000000000010006ad5f3160668d0436ad5f3",
+        "160668d0430000fe00001340280000000000000000000010000046534d4e0000002900000002746f6b31",
+        "000000001c000000020000000200000002000000000000000000000002000000",
+        "00d6f7853885a9c0d830cedbbecc5e84a4e26b3d1e",
+    );
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rgit-index-test-{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    /// Writes `bytes` as an index, reads it back and removes the file.
+    fn read_bytes(name: &str, bytes: &[u8]) -> Index {
+        let path = temp_path(name);
+        fs::write(&path, bytes).unwrap();
+        let index = Index::read_index_from(&path);
+        fs::remove_file(&path).unwrap();
+        index
+    }
+
+    fn write_bytes(name: &str, index: &Index) -> Vec<u8> {
+        let path = temp_path(name);
+        index.write_index_to(&path);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_extensions_written_by_git() {
+        let index = read_bytes("git-read", &hex::decode(GIT_INDEX).unwrap());
+        assert_eq!(index.version, 3);
+        let names: Vec<&str> = index.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["a", "d/b"]);
+        assert!(!index.entries[0].is_skip_worktree());
+        assert!(index.entries[1].is_skip_worktree());
+        let cache_tree = index.cache_tree.as_ref().unwrap();
+        assert!(!cache_tree.is_valid());
+        assert_eq!(cache_tree.subtrees[0].name, "d");
+        let fsmonitor = index.fsmonitor.as_ref().unwrap();
+        assert_eq!(fsmonitor.token, "tok1");
+        assert_eq!(fsmonitor.dirty.bits.len(), 2);
+        let untracked_cache = index.untracked_cache.as_ref().unwrap();
+        assert_eq!(untracked_cache.idents, ["Location /tmp/ix, system Linux"]);
+        assert_eq!(untracked_cache.exclude_per_dir, ".gitignore");
+        let root = untracked_cache.root.as_ref().unwrap();
+        assert_eq!(root.dirs[0].name, "d");
+        assert_eq!(root.dirs[0].untracked, ["u"]);
+        assert!(root.valid && root.dirs[0].valid);
+    }
+
+    #[test]
+    fn writes_back_what_git_wrote() {
+        let bytes = hex::decode(GIT_INDEX).unwrap();
+        let index = read_bytes("git-write", &bytes);
+        assert_eq!(write_bytes("git-write", &index), bytes);
+    }
+
+    #[test]
+    fn extended_flags_need_version_3() {
+        let mut entry = IndexEntry::from_object("file", 0o100644, "e69de29bb2d1d6434b8b29ae77e5c91fadbf357d");
+        entry.set_skip_worktree(true);
+        let mut index = Index::new(2, vec![entry]);
+        let bytes = write_bytes("extended", &index);
+        assert_eq!(&bytes[4..8], &3u32.to_be_bytes());
+        let read = read_bytes("extended", &bytes);
+        assert!(read.entries[0].is_skip_worktree());
+        assert_eq!(read.entries[0].name, "file");
+
+        // Clearing the flag drops the extra two bytes, and the version stays what it was
+        index.entries[0].set_skip_worktree(false);
+        let bytes = write_bytes("extended", &index);
+        assert_eq!(&bytes[4..8], &2u32.to_be_bytes());
+        assert_eq!(bytes.len(), 12 + 72 + 20);
+    }
+
+    #[test]
+    fn fsmonitor_round_trips() {
+        let extension = FsMonitorExtension::new("1:2:3".to_string(), EwahBitmap::new(vec![false, true, false, true]));
+        let bytes = extension.to_bytes();
+        let read = FsMonitorExtension::from_bytes(&bytes).unwrap();
+        assert_eq!(read.token, "1:2:3");
+        assert_eq!(read.dirty, extension.dirty);
+
+        // A version 1 extension holds a timestamp rgit can't use
+        let mut version1 = bytes.clone();
+        version1[3] = 1;
+        assert!(FsMonitorExtension::from_bytes(&version1).is_none());
+    }
+}
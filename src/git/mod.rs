@@ -1,4 +1,32 @@
 pub mod object;
 pub mod head;
 pub mod index;
-pub mod gitignore;
\ No newline at end of file
+pub mod gitignore;
+pub mod config;
+pub mod ewah;
+pub mod fsmonitor;
+pub mod sparse;
+pub mod worktree;
+pub mod untracked_cache;
+pub mod refs;
+pub mod revision;
+pub mod ident;
+pub mod reflog;
+pub mod ref_transaction;
+pub mod date;
+pub mod ref_filter;
+pub mod reftable;
+pub mod cache_tree;
+pub mod editor;
+pub mod signing;
+pub mod pretty;
+pub mod hook;
+pub mod trailer;
+pub mod grep;
+pub mod graph;
+pub mod pathspec;
+pub mod tree_diff;
+pub mod xdiff;
+pub mod diff_output;
+pub mod combine_diff;
+pub mod commit_graph;
\ No newline at end of file
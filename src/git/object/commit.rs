@@ -1,22 +1,28 @@
-use std::fs::File;
-use std::io::{Read};
-use std::io::{BufReader};
-use chrono::{Local, TimeZone, Utc};
-use flate2::read::ZlibDecoder;
 use std::fmt;
 use colored::*;
 
-use crate::object_finder;
+use crate::git::date;
+use crate::git::ident::Signature;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::objectwriter::ObjectWriter;
+use crate::git::pretty::{self, Format, PrettyOptions};
+use crate::git::signing::{self, SignatureCheck};
+use crate::git::trailer::{self, TrailerBlock};
 
 pub struct Commit {
     pub hash: String,
     pub tree: String,
-    pub parent: Option<String>,
-    pub author: String,
-    pub committer: String,
-    pub message: String,
-    pub timestamp: String,
-    pub timezone: String,
+    /// Parent commits in the order they were recorded; the first is the mainline.
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers after the committer, such as `encoding`, `gpgsig` and `mergetag`, in their
+    /// original order. Continuation lines are joined to their header's value with newlines.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// The message exactly as stored, which is only UTF-8 if `encoding` says so.
+    pub raw_message: Vec<u8>,
+    /// Whether the object ends with its headers, without the blank line before a message.
+    pub headers_only: bool,
 }
 
 impl fmt::Display for Commit {
@@ -26,99 +32,243 @@ impl fmt::Display for Commit {
 }
 
 impl Commit {
-    pub fn format_log(&self) -> String {
-        let date = Utc.timestamp_opt(self.timestamp.parse::<i64>().unwrap(), 0).unwrap();
-        let tzdate = date.with_timezone(&Local);
-        let format_date = tzdate.format("%a %b %d %H:%M:%S %Y %z").to_string();
-        format!("{} {}\nAuthor: {}\nDate:   {}\n\n    {}\n", 
-                "commit".yellow(), self.hash.yellow(), self.author, format_date, self.message)
+    /// The commit as `log` shows it in `options.format`, without the newline that ends or
+    /// separates entries. A signature check goes after the `commit` line, or the hash in
+    /// `oneline`, as git puts it. The built-in formats name `parent` when the diff after the
+    /// commit is against it, as `log -m` does.
+    pub fn format_log(&self, options: &PrettyOptions, parent: Option<&str>) -> String {
+        let abbrev = |hash: &str| if options.abbrev_commit { hash[..7].to_string() } else { hash.to_string() };
+        let from = parent.map(|parent| format!(" (from {})", abbrev(parent))).unwrap_or_default();
+        let hash = format!("{}{}", abbrev(&self.hash), from);
+        let decoration = if options.decorate { options.decorations().wrapped(&self.hash) } else { String::new() };
+        let check = options.show_signature.then(|| self.check_signature()).flatten().map(|check| check.output).unwrap_or_default();
+        let message = self.message();
+        let title = match options.format {
+            Format::User { ref format, .. } => return format!("{}{}", check, pretty::format_commit(self, format, options)),
+            Format::Oneline => return format!("{}{} {}{}", hash.yellow(), decoration, check, pretty::subject(&message)),
+            _ => format!("{} {}{}\n{}", "commit".yellow(), hash.yellow(), decoration, check),
+        };
+        let date = |signature: &Signature| date::format(signature.time, &signature.offset, &options.date_mode).unwrap_or_default();
+        let merge = if self.is_merge() {
+            let abbrevs: Vec<&str> = self.parents.iter().map(|parent| &parent[..7]).collect();
+            format!("Merge: {}\n", abbrevs.join(" "))
+        } else {
+            String::new()
+        };
+        let headers = match options.format {
+            Format::Short => format!("Author: {}\n", self.author.identity()),
+            Format::Full => format!("Author: {}\nCommit: {}\n", self.author.identity(), self.committer.identity()),
+            Format::Fuller => format!("Author:     {}\nAuthorDate: {}\nCommit:     {}\nCommitDate: {}\n",
+                                      self.author.identity(), date(&self.author), self.committer.identity(), date(&self.committer)),
+            _ => format!("Author: {}\nDate:   {}\n", self.author.identity(), date(&self.author)),
+        };
+        // Messages are indented, without the blank lines around them; `short` only has
+        // the subject
+        let lines: Vec<&str> = message.lines().skip_while(|line| line.trim().is_empty()).collect();
+        let end = match options.format {
+            Format::Short => lines.iter().position(|line| line.trim().is_empty()).unwrap_or(lines.len()),
+            _ => lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |last| last + 1),
+        };
+        let body: String = lines[..end].iter().map(|line| format!("    {}\n", line)).collect();
+        format!("{}{}{}\n{}", title, merge, headers, body)
     }
-    pub fn new(hash: String, tree: String, parent: Option<String>, author: String, committer: String, message: String, timestamp: String, timezone: String) -> Self {
-        Self { hash,tree, parent, author, committer, message, timestamp, timezone }
+
+    pub fn new(tree: String, parents: Vec<String>, author: Signature, committer: Signature, message: &str) -> Self {
+        Self { hash: String::new(), tree, parents, author, committer, extra_headers: Vec::new(), raw_message: message.as_bytes().to_vec(), headers_only: false }
+    }
+
+    pub fn first_parent(&self) -> Option<&str> {
+        self.parents.first().map(|parent| parent.as_str())
+    }
+
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
+    }
+
+    /// The message decoded for display, without its trailing newline. Latin-1 messages are
+    /// converted; any other encoding is read as UTF-8.
+    pub fn message(&self) -> String {
+        let latin1 = self.encoding().is_some_and(|encoding| matches!(encoding.to_lowercase().as_str(), "iso-8859-1" | "iso8859-1" | "latin1" | "latin-1"));
+        let message = if latin1 { self.raw_message.iter().map(|&byte| byte as char).collect() } else { String::from_utf8_lossy(&self.raw_message) };
+        message.strip_suffix('\n').unwrap_or(&message).to_string()
+    }
+
+    /// The trailer block at the end of the message. A `---` line in a commit message doesn't
+    /// start a patch, so the whole message is searched.
+    pub fn trailers(&self) -> TrailerBlock {
+        trailer::parse(&format!("{}\n", self.message()), &trailer::separators(), true)
+    }
+
+    /// The first extra header named `key`, if its value is UTF-8.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.extra_headers.iter().find(|(name, _)| name == key).and_then(|(_, value)| std::str::from_utf8(value).ok())
+    }
+
+    pub fn encoding(&self) -> Option<&str> {
+        self.header("encoding")
+    }
+
+    pub fn gpgsig(&self) -> Option<&str> {
+        self.header("gpgsig")
+    }
+
+    /// What a `gpgsig` signature covers: the commit without its signature headers.
+    pub fn signed_payload(&self) -> Vec<u8> {
+        self.serialize_headers(|key| key != "gpgsig" && key != "gpgsig-sha256")
+    }
+
+    /// Signs the commit as it stands, adding the `gpgsig` header after the others.
+    pub fn sign(&mut self, signing_key: Option<&str>) -> Result<(), String> {
+        let signature = signing::sign(&self.signed_payload(), signing_key)?;
+        self.extra_headers.push(("gpgsig".to_string(), signature.trim_end().as_bytes().to_vec()));
+        Ok(())
+    }
+
+    /// Checks the `gpgsig` header, if the commit has one.
+    pub fn check_signature(&self) -> Option<SignatureCheck> {
+        let signature = format!("{}\n", self.gpgsig()?);
+        Some(signing::verify(&self.signed_payload(), signature.as_bytes(), self.committer.time))
     }
 
     pub fn from_bytes(hash: &str, bytes: &[u8]) -> Self {
-        let content = String::from_utf8_lossy(bytes);
-        let lines: Vec<&str> = content.lines().collect();
-        
-        let mut tree = String::new();
-        let mut parent: Option<String> = None;
-        let mut author = String::new();
-        let mut committer = String::new();
-        let mut message = String::new();
-        let mut timestamp = String::new();
-        let mut timezone = String::new();
-        
-        let mut in_message = false;
-        
-        for line in lines {
-            if in_message {
-                message.push_str(line);
-                message.push('\n');
-            } else if line.starts_with("commit ") {
-                // Handle case where commit and tree are on the same line
-                // Format: "commit 256tree ff63d4cfd34fa7fa36d42aa90e55ae7cefad0f17"
-                let commit_line = &line[7..]; // Remove "commit " prefix
-                if commit_line.starts_with("tree ") {
-                    tree = commit_line[5..].to_string(); // Remove "tree " prefix
-                } else {
-                    // Check if it's in the format "commit <number>tree <hash>"
-                    // Find where "tree" starts after the number
-                    for i in 1..commit_line.len() {
-                        if commit_line[i..].starts_with("tree ") {
-                            tree = commit_line[i + 5..].to_string(); // Remove "tree " prefix
-                            break;
-                        }
-                    }
-                }
-            } else if line.starts_with("tree ") {
-                tree = line[5..].to_string();
-            } else if line.starts_with("parent ") {
-                parent = Some(line[7..].to_string());
-            } else if line.starts_with("author ") {
-                let author_line = &line[7..];
-                if let Some(space_pos) = author_line.rfind(' ') {
-                    if let Some(space_pos2) = author_line[..space_pos].rfind(' ') {
-                        author = author_line[..space_pos2].to_string();
-                        timestamp = author_line[space_pos2 + 1..space_pos].to_string();
-                        timezone = author_line[space_pos + 1..].to_string();
-                    }
+        let separator = bytes.windows(2).position(|window| window == b"\n\n");
+        let (headers, raw_message) = match separator {
+            Some(position) => (&bytes[..position + 1], &bytes[position + 2..]),
+            None => (bytes, &[][..]),
+        };
+        let mut commit = Self::new(String::new(), Vec::new(), Signature::default(), Signature::default(), "");
+        commit.hash = hash.to_string();
+        commit.raw_message = raw_message.to_vec();
+        commit.headers_only = separator.is_none();
+
+        // Headers are split on bytes, since values such as names needn't be UTF-8 and a
+        // `\r` before the newline belongs to the value
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+        for line in headers.strip_suffix(b"\n").unwrap_or(headers).split(|&byte| byte == b'\n') {
+            match line.strip_prefix(b" ") {
+                Some(continuation) if !fields.is_empty() => {
+                    let value = &mut fields.last_mut().unwrap().1;
+                    value.push(b'\n');
+                    value.extend_from_slice(continuation);
                 }
-            } else if line.starts_with("committer ") {
-                let committer_line = &line[10..];
-                if let Some(space_pos) = committer_line.rfind(' ') {
-                    if let Some(space_pos2) = committer_line[..space_pos].rfind(' ') {
-                        committer = committer_line[..space_pos2].to_string();
-                        // timestamp and timezone already set from author
-                    }
+                _ => {
+                    let space = line.iter().position(|&byte| byte == b' ');
+                    let (key, value) = match space {
+                        Some(space) => (&line[..space], &line[space + 1..]),
+                        None => (line, &[][..]),
+                    };
+                    fields.push((String::from_utf8_lossy(key).to_string(), value.to_vec()));
                 }
-            } else if line.is_empty() {
-                in_message = true;
             }
         }
-        
-        // Remove trailing newline from message
-        if message.ends_with('\n') {
-            message.pop();
+        for (key, value) in fields {
+            // The standard headers are only recognized before any extra ones, so that
+            // serializing keeps every header where it was
+            if !commit.extra_headers.is_empty() {
+                commit.extra_headers.push((key, value));
+                continue;
+            }
+            match key.as_str() {
+                "tree" => commit.tree = String::from_utf8_lossy(&value).to_string(),
+                "parent" => commit.parents.push(String::from_utf8_lossy(&value).to_string()),
+                "author" => commit.author = Signature::from_bytes(&value),
+                "committer" => commit.committer = Signature::from_bytes(&value),
+                _ => commit.extra_headers.push((key, value)),
+            }
         }
-        
-        Self { hash: hash.to_string(), tree, parent, author, committer, message, timestamp, timezone }
+        commit
     }
 
     pub fn from_hash(hash: &str) -> Self {
-        let path = object_finder::find_object_path(hash);
-        let file = File::open(path).unwrap();
-        let mut reader = BufReader::new(file);
-        let mut decompressor = ZlibDecoder::new(&mut reader);
-        let mut buffer = [0; 1024];
-        let mut bytes: Vec<u8> = Vec::new();
-        loop {
-            let bytes_read = decompressor.read(&mut buffer).unwrap();
-            if bytes_read == 0 {
-                break;
+        Self::from_bytes(hash, &ObjectReader::read_content(hash))
+    }
+
+    /// The object content, which for a parsed commit is byte for byte what was read.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_headers(|_| true)
+    }
+
+    fn serialize_headers(&self, keep: impl Fn(&str) -> bool) -> Vec<u8> {
+        let mut bytes = format!("tree {}\n", self.tree).into_bytes();
+        for parent in self.parents.iter() {
+            bytes.extend_from_slice(format!("parent {}\n", parent).as_bytes());
+        }
+        for (key, signature) in [("author", &self.author), ("committer", &self.committer)] {
+            bytes.extend_from_slice(format!("{} ", key).as_bytes());
+            bytes.extend_from_slice(&signature.to_bytes());
+            bytes.push(b'\n');
+        }
+        for (key, value) in self.extra_headers.iter().filter(|(key, _)| keep(key)) {
+            bytes.extend_from_slice(format!("{} ", key).as_bytes());
+            for (i, line) in value.split(|&byte| byte == b'\n').enumerate() {
+                if i > 0 {
+                    bytes.extend_from_slice(b"\n ");
+                }
+                bytes.extend_from_slice(line);
             }
-            bytes.extend_from_slice(&buffer[..bytes_read]);
+            bytes.push(b'\n');
+        }
+        if !self.headers_only {
+            bytes.push(b'\n');
+            bytes.extend_from_slice(&self.raw_message);
         }
-        Self::from_bytes(hash, &bytes)
+        bytes
+    }
+
+    /// Stores the commit in the object database and sets `hash` to its id.
+    pub fn write(&mut self) -> String {
+        self.hash = ObjectWriter::write("commit", &self.serialize());
+        self.hash.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADERS: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nparent 1111111111111111111111111111111111111111\n";
+
+    fn commit_bytes(rest: &[u8]) -> Vec<u8> {
+        [HEADERS, rest].concat()
+    }
+
+    fn assert_round_trip(bytes: &[u8]) {
+        assert_eq!(Commit::from_bytes("", bytes).serialize(), bytes);
+    }
+
+    #[test]
+    fn round_trips_latin1_names() {
+        let bytes = commit_bytes(b"author Ren\xe9 <r@e> 1700000000 +0100\ncommitter Ren\xe9 <r@e> 1700000000 +0100\nencoding ISO-8859-1\n\nd\xe9j\xe0 vu\n");
+        assert_round_trip(&bytes);
+        let commit = Commit::from_bytes("", &bytes);
+        assert_eq!(commit.author.email, "r@e");
+        assert_eq!(commit.message(), "d\u{e9}j\u{e0} vu");
+    }
+
+    #[test]
+    fn round_trips_unusual_ident_spacing() {
+        assert_round_trip(&commit_bytes(b"author Name<n@e> 1700000000 +0000\ncommitter  <n@e> 1700000000 -0000\n\nm\n"));
+        assert_round_trip(&commit_bytes(b"author <n@e> 1700000000 +0000\ncommitter broken\n\nm\n"));
+    }
+
+    #[test]
+    fn round_trips_without_message() {
+        assert_round_trip(&commit_bytes(b"author A <a@e> 1 +0000\ncommitter A <a@e> 1 +0000\n"));
+        assert_round_trip(&commit_bytes(b"author A <a@e> 1 +0000\ncommitter A <a@e> 1 +0000\n\n"));
+    }
+
+    #[test]
+    fn round_trips_carriage_returns() {
+        assert_round_trip(&commit_bytes(b"author A <a@e> 1 +0000\r\ncommitter A <a@e> 1 +0000\nx-note a\r\n b\r\n\nm\r\n"));
+    }
+
+    #[test]
+    fn signed_payload_drops_only_the_signature() {
+        let payload = commit_bytes(b"author A\xff <a@e> 1 +0000\ncommitter A <a@e> 1 +0000\nmergetag object 2222\n type commit\n\nm\n");
+        let signed = commit_bytes(b"author A\xff <a@e> 1 +0000\ncommitter A <a@e> 1 +0000\nmergetag object 2222\n type commit\ngpgsig -----BEGIN SSH SIGNATURE-----\n U1NIU0lH\n -----END SSH SIGNATURE-----\n\nm\n");
+        let commit = Commit::from_bytes("", &signed);
+        assert_eq!(commit.serialize(), signed);
+        assert_eq!(commit.signed_payload(), payload);
+        assert_eq!(commit.gpgsig(), Some("-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----"));
     }
 }
@@ -1,4 +1,6 @@
 pub mod commit;
 pub mod tree;
 pub mod blob;
-pub mod objectreader;
\ No newline at end of file
+pub mod objectreader;
+pub mod objectwriter;
+pub mod tag;
\ No newline at end of file
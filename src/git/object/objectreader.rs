@@ -26,7 +26,20 @@ impl ObjectReader {
             return "blob";
         } else if header == "tree" {
             return "tree";
+        } else if header == "tag " {
+            return "tag";
         }
         return "commit";
     }
+
+    /// Reads an object's content, without the `<type> <size>\0` header.
+    pub fn read_content(hash: &str) -> Vec<u8> {
+        let path = object_finder::find_object_path(hash);
+        let file = File::open(path).unwrap();
+        let mut decompressor = ZlibDecoder::new(BufReader::new(file));
+        let mut bytes: Vec<u8> = Vec::new();
+        decompressor.read_to_end(&mut bytes).unwrap();
+        let header_len = bytes.iter().position(|&x| x == b'\0').unwrap() + 1;
+        bytes.split_off(header_len)
+    }
 }
\ No newline at end of file
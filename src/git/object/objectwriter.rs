@@ -0,0 +1,32 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+
+use crate::object_finder;
+
+pub struct ObjectWriter;
+
+impl ObjectWriter {
+    /// Hashes `content` as an object of `kind` and stores it as a loose object, returning
+    /// its id. Objects that already exist are left alone.
+    pub fn write(kind: &str, content: &[u8]) -> String {
+        let mut bytes = format!("{} {}\0", kind, content.len()).into_bytes();
+        bytes.extend_from_slice(content);
+        let hash = hex::encode(Sha1::digest(&bytes));
+        let path = object_finder::find_object_path(&hash);
+        if Path::new(&path).exists() {
+            return hash;
+        }
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, encoder.finish().unwrap()).unwrap();
+        fs::rename(&temp_path, &path).unwrap();
+        hash
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::Local;
+
+use crate::git::ident::Signature;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::objectwriter::ObjectWriter;
+use crate::git::signing::{self, SignatureCheck};
+
+/// An annotated tag object.
+pub struct Tag {
+    pub hash: String,
+    pub object: String,
+    pub kind: String,
+    pub tag: String,
+    pub tagger: Option<String>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn new(object: String, kind: String, tag: String, tagger: Option<String>, message: String) -> Self {
+        Self { hash: String::new(), object, kind, tag, tagger, message }
+    }
+
+    pub fn from_bytes(hash: &str, bytes: &[u8]) -> Self {
+        let content = String::from_utf8_lossy(bytes);
+        let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+        let mut tag = Self::new(String::new(), String::new(), String::new(), None, message.to_string());
+        tag.hash = hash.to_string();
+        for line in headers.lines() {
+            match line.split_once(' ') {
+                Some(("object", value)) => tag.object = value.to_string(),
+                Some(("type", value)) => tag.kind = value.to_string(),
+                Some(("tag", value)) => tag.tag = value.to_string(),
+                Some(("tagger", value)) => tag.tagger = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        tag
+    }
+
+    pub fn from_hash(hash: &str) -> Self {
+        Self::from_bytes(hash, &ObjectReader::read_content(hash))
+    }
+
+    /// The unix time in the tagger line, if there is one.
+    pub fn timestamp(&self) -> Option<i64> {
+        self.tagger.as_ref()?.rsplit(' ').nth(1)?.parse().ok()
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut content = format!("object {}\ntype {}\ntag {}\n", self.object, self.kind, self.tag);
+        if let Some(tagger) = &self.tagger {
+            content.push_str(&format!("tagger {}\n", tagger));
+        }
+        content.push('\n');
+        content.push_str(&self.message);
+        content
+    }
+
+    /// Stores the tag in the object database and sets `hash` to its id.
+    pub fn write(&mut self) -> String {
+        self.hash = ObjectWriter::write("tag", self.serialize().as_bytes());
+        self.hash.clone()
+    }
+
+    /// Appends a signature over the tag as it stands to the message.
+    pub fn sign(&mut self, signing_key: Option<&str>) -> Result<(), String> {
+        let signature = signing::sign(self.serialize().as_bytes(), signing_key)?;
+        self.message.push_str(&signature);
+        Ok(())
+    }
+
+    /// The tag without the signature at the end of its message, which is what it covers.
+    pub fn signed_payload(&self) -> String {
+        let content = self.serialize();
+        match signing::split_signed_buffer(content.as_bytes()) {
+            Some((payload, _)) => String::from_utf8_lossy(payload).to_string(),
+            None => content,
+        }
+    }
+
+    /// Checks the signature at the end of the message, if there is one.
+    pub fn check_signature(&self) -> Option<SignatureCheck> {
+        let content = self.serialize();
+        let (payload, signature) = signing::split_signed_buffer(content.as_bytes())?;
+        // Tags without a tagger are checked as of now, as git does
+        let time = self.tagger.as_deref().and_then(Signature::parse).map_or_else(|| Local::now().timestamp(), |tagger| tagger.time);
+        Some(signing::verify(payload, signature, time))
+    }
+}
@@ -9,6 +9,8 @@ use sha1::{Sha1, Digest};
 use crate::git::gitignore::GitIgnore;
 use crate::git::object::blob::{compute_file_hash, Blob};
 use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::objectwriter::ObjectWriter;
+use crate::git::sparse::SparseCheckout;
 use crate::object_finder;
 
 #[derive(Debug)]
@@ -61,12 +63,26 @@ impl Tree {
         let mut entries = Vec::new();
         let mut i = bytes.iter().position(|&x| x == b'\0').unwrap() + 1;
         while i < bytes.len() {
-            entries.push(Tree::read_entry(&bytes[i..]));
-            i += entries.last().unwrap().name.len() + 8 + 20;
+            let entry = Tree::read_entry(&bytes[i..]);
+            // Modes are written without leading zeros, so subtrees have shorter ones
+            i += entry.mode.len() + entry.name.len() + 2 + 20;
+            entries.push(entry);
         }
         Self::new(hash.to_string(), entries)
     }
 
+    /// Recursively collects every non-tree entry, with `name` set to the path from the tree root.
+    pub fn flatten(&self, prefix: &str, files: &mut Vec<TreeEntry>) {
+        for entry in self.entries.iter() {
+            let path = if prefix.is_empty() { entry.name.clone() } else { format!("{}/{}", prefix, entry.name) };
+            if entry.mode == "40000" {
+                Tree::from_hash(&entry.hash).flatten(&path, files);
+            } else {
+                files.push(TreeEntry::new(entry.mode.clone(), path, entry.hash.clone()));
+            }
+        }
+    }
+
     fn write_dir_entry(entry: &TreeEntry) -> Vec<u8> {
         let mut entry_bytes = Vec::new();
         entry_bytes.extend_from_slice(entry.mode.as_bytes());
@@ -77,6 +93,14 @@ impl Tree {
         entry_bytes
     }
 
+    /// Stores the tree in the object database and sets `hash` to its id. Entries must
+    /// already be in git's order, which sorts directories as if their names ended in `/`.
+    pub fn write(&mut self) -> String {
+        let bytes: Vec<u8> = self.entries.iter().flat_map(Tree::write_dir_entry).collect();
+        self.hash = ObjectWriter::write("tree", &bytes);
+        self.hash.clone()
+    }
+
     fn read_dir_entry(entry: &fs::DirEntry) -> TreeEntry {
         let path = entry.path();
         if path.is_file() {
@@ -160,15 +184,33 @@ impl Tree {
         dir_files
     }
 
-    pub fn sync_tree_to_dir(&self, path: &str) {
+    /// Removes the `tracked` files below `dir`, then the directories that leaves empty.
+    /// Returns whether `dir` itself is gone, which it isn't when untracked files are left.
+    fn remove_tracked(dir: &str, tracked: &HashSet<String>) -> bool {
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            if entry.file_type().unwrap().is_dir() {
+                Tree::remove_tracked(&path, tracked);
+            } else if tracked.contains(&path[2..]) {
+                fs::remove_file(&path).unwrap();
+            }
+        }
+        fs::remove_dir(dir).is_ok()
+    }
+
+    /// Makes the directory at `path` match the tree. With a sparse checkout, paths outside
+    /// the cone are removed instead of written. Only `tracked` files are ever removed, so
+    /// untracked and ignored ones stay, along with the directories holding them.
+    pub fn sync_tree_to_dir(&self, path: &str, sparse: Option<&SparseCheckout>, tracked: &HashSet<String>) {
         let tree_files = self.entries.iter().map(|entry| entry.name.clone()).collect::<HashSet<String>>();
         let dir_files = Tree::read_dir_to_set(&path);
         let delete_files = &dir_files - &tree_files;
         for file in delete_files {
             let file_path = format!("{}/{}", path, file);
             if std::fs::metadata(&file_path).unwrap().is_dir() {
-                std::fs::remove_dir_all(file_path).unwrap();
-            } else {
+                Tree::remove_tracked(&file_path, tracked);
+            } else if tracked.contains(&file_path[2..]) {
                 std::fs::remove_file(file_path).unwrap();
             }
         }
@@ -177,7 +219,18 @@ impl Tree {
                 continue;
             }
             let file_path = format!("{}/{}", path, entry.name.clone());
+            let relative_path = &file_path[2..];
             match ObjectReader::find_object_type(&entry.hash) {
+                "blob" if sparse.is_some_and(|sparse| !sparse.contains(relative_path)) => {
+                    if tracked.contains(relative_path) && Path::new(&file_path).exists() {
+                        std::fs::remove_file(&file_path).unwrap();
+                    }
+                }
+                "tree" if sparse.is_some_and(|sparse| !sparse.contains_directory(relative_path)) => {
+                    if Path::new(&file_path).is_dir() && !Tree::remove_tracked(&file_path, tracked) {
+                        println!("warning: directory '{}/' contains untracked files, but is not in the sparse-checkout cone", relative_path);
+                    }
+                }
                 "blob" => {
                     if !Path::new(&file_path).exists() || compute_file_hash(&file_path) != entry.hash {
                         std::fs::write(&file_path, Blob::from_hash(&entry.hash).content).unwrap();
@@ -187,7 +240,7 @@ impl Tree {
                     if !Path::new(&file_path).exists() || Tree::hash_folder(&file_path) != entry.hash {
                         std::fs::create_dir_all(&file_path).unwrap();
                         let tree = Tree::from_hash(&entry.hash);
-                        tree.sync_tree_to_dir(&file_path);
+                        tree.sync_tree_to_dir(&file_path, sparse, tracked);
                     }
                 }
                 _ => {
@@ -0,0 +1,49 @@
+/// Paths given on the command line to limit what a command looks at, as in `log -- <path>`.
+/// A path matches if it is named, lies under a named directory, or matches a pattern with
+/// wildcards, whose `*` also matches `/`. No paths at all match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    items: Vec<String>,
+}
+
+fn has_wildcard(item: &str) -> bool {
+    item.contains(['*', '?', '['])
+}
+
+impl Pathspec {
+    pub fn new(items: &[String]) -> Self {
+        let items = items
+            .iter()
+            .map(|item| {
+                let item = item.strip_prefix("./").unwrap_or(item).trim_end_matches('/');
+                if item == "." { String::new() } else { item.to_string() }
+            })
+            .collect();
+        Self { items }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.items.is_empty()
+            || self.items.iter().any(|item| {
+                item.is_empty()
+                    || path == item
+                    || path.strip_prefix(item.as_str()).is_some_and(|rest| rest.starts_with('/'))
+                    || (has_wildcard(item) && glob::Pattern::new(item).is_ok_and(|pattern| pattern.matches(path)))
+            })
+    }
+
+    /// Whether anything under the directory `dir` could match, so that it is worth reading.
+    pub fn matches_dir(&self, dir: &str) -> bool {
+        let dir_prefix = format!("{}/", dir);
+        self.items.is_empty()
+            || self.items.iter().any(|item| match item.find(['*', '?', '[']) {
+                // Only the part of a pattern before its first wildcard limits where it reaches
+                Some(position) => dir_prefix.starts_with(&item[..position]) || item[..position].starts_with(&dir_prefix),
+                None => item.is_empty() || dir == item || item.starts_with(&dir_prefix) || dir.starts_with(&format!("{}/", item)),
+            })
+    }
+}
@@ -0,0 +1,354 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+
+use crate::git::config::Config;
+use crate::git::date;
+use crate::git::ident::Signature;
+use crate::git::object::commit::Commit;
+use crate::git::refs::{self, RefValue};
+use crate::git::revision;
+use crate::git::signing::SignatureCheck;
+use crate::git::trailer;
+
+/// A `--pretty` format: one of git's built-in formats or a user format.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    /// A `format:` string, whose entries are separated by newlines, or with `terminator`
+    /// a `tformat:` one, whose entries each end with one.
+    User { format: String, terminator: bool },
+}
+
+impl Format {
+    /// Parses the value of `--pretty`: a format name, `format:` or `tformat:` followed by a
+    /// format string, or a bare format string with a placeholder in it, which is a
+    /// `tformat:`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let format = match value.to_lowercase().as_str() {
+            "oneline" => Self::Oneline,
+            "short" => Self::Short,
+            "medium" => Self::Medium,
+            "full" => Self::Full,
+            "fuller" => Self::Fuller,
+            _ => match (value.strip_prefix("format:"), value.strip_prefix("tformat:")) {
+                (Some(format), _) => Self::User { format: format.to_string(), terminator: false },
+                (_, Some(format)) => Self::User { format: format.to_string(), terminator: true },
+                _ if value.is_empty() || value.contains('%') => Self::User { format: value.to_string(), terminator: true },
+                _ => return None,
+            },
+        };
+        Some(format)
+    }
+
+    /// Whether entries are empty, as with `--format=`, so that nothing at all is shown of them.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::User { format, .. } if format.is_empty())
+    }
+
+    /// Whether every entry ends with a newline, rather than entries being separated by one.
+    pub fn is_terminated(&self) -> bool {
+        matches!(self, Self::Oneline | Self::User { terminator: true, .. })
+    }
+}
+
+/// How `log` shows commits.
+pub struct PrettyOptions {
+    pub format: Format,
+    /// The `--date` mode of the built-in formats and `%ad` and `%cd`.
+    pub date_mode: String,
+    pub abbrev_commit: bool,
+    /// Whether the built-in formats show the refs pointing at commits.
+    pub decorate: bool,
+    pub show_signature: bool,
+    decorations: OnceCell<Decorations>,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        Self::new(Format::Medium)
+    }
+}
+
+impl PrettyOptions {
+    pub fn new(format: Format) -> Self {
+        Self { format, date_mode: "default".to_string(), abbrev_commit: false, decorate: false, show_signature: false, decorations: OnceCell::new() }
+    }
+
+    /// The refs to decorate commits with, which are only read once needed.
+    pub fn decorations(&self) -> &Decorations {
+        self.decorations.get_or_init(Decorations::read)
+    }
+}
+
+/// The refs pointing at each commit, named as `--decorate` shows them.
+pub struct Decorations {
+    names: HashMap<String, Vec<String>>,
+}
+
+impl Decorations {
+    /// Reads the branches, remote-tracking branches, tags and stash, newest names first as
+    /// git lists them, with HEAD ahead of them all. Tags decorate what they point at too.
+    pub fn read() -> Self {
+        let mut names: HashMap<String, Vec<String>> = HashMap::new();
+        let head_branch = match refs::read_raw_ref("HEAD") {
+            Some(RefValue::Symbolic(target)) => Some(target),
+            _ => None,
+        };
+        let head_hash = refs::read_ref("HEAD");
+        let decorated = ["refs/heads/", "refs/remotes/", "refs/tags/", "refs/stash"];
+        for (name, hash) in refs::list_refs("refs/").into_iter().rev() {
+            if !decorated.iter().any(|prefix| name.starts_with(prefix)) {
+                continue;
+            }
+            if head_branch.as_ref() == Some(&name) && head_hash.as_ref() == Some(&hash) {
+                continue;
+            }
+            let short_name = match (name.strip_prefix("refs/heads/"), name.strip_prefix("refs/remotes/"), name.strip_prefix("refs/tags/")) {
+                (Some(branch), _, _) | (_, Some(branch), _) => branch.to_string(),
+                (_, _, Some(tag)) => format!("tag: {}", tag),
+                _ => name.clone(),
+            };
+            if let Some(peeled) = revision::peel(&hash, "commit").filter(|peeled| *peeled != hash) {
+                names.entry(peeled).or_default().push(short_name.clone());
+            }
+            names.entry(hash).or_default().push(short_name);
+        }
+        if let Some(hash) = head_hash {
+            let head = match head_branch.as_deref().and_then(|branch| branch.strip_prefix("refs/heads/")) {
+                Some(branch) => format!("HEAD -> {}", branch),
+                None => "HEAD".to_string(),
+            };
+            names.entry(hash).or_default().insert(0, head);
+        }
+        Self { names }
+    }
+
+    /// The refs pointing at `hash` as `%D` shows them, comma-separated.
+    pub fn get(&self, hash: &str) -> String {
+        self.names.get(hash).map(|names| names.join(", ")).unwrap_or_default()
+    }
+
+    /// The refs pointing at `hash` as `%d` shows them, in parentheses after a space.
+    pub fn wrapped(&self, hash: &str) -> String {
+        match self.get(hash) {
+            names if names.is_empty() => names,
+            names => format!(" ({})", names),
+        }
+    }
+}
+
+/// The subject of a message: its first paragraph joined into one line.
+pub fn subject(message: &str) -> String {
+    let lines = message.lines().skip_while(|line| line.trim().is_empty());
+    let subject: Vec<&str> = lines.take_while(|line| !line.trim().is_empty()).map(|line| line.trim_end()).collect();
+    subject.join(" ")
+}
+
+/// The body of a message: what follows the subject and the blank lines after it.
+pub fn body(message: &str) -> String {
+    let lines = message.split_inclusive('\n').skip_while(|line| line.trim().is_empty());
+    lines.skip_while(|line| !line.trim().is_empty()).skip_while(|line| line.trim().is_empty()).collect()
+}
+
+/// Formats the date of `signature` for the letter after `%a` or `%c`, if it's a date one.
+fn format_ident_date(signature: &Signature, placeholder: u8, date_mode: &str) -> Option<String> {
+    let mode = match placeholder {
+        b'd' => date_mode,
+        b'D' => "rfc",
+        b'r' => "relative",
+        b't' => "unix",
+        b'i' => "iso",
+        b'I' => "iso-strict",
+        b's' => "short",
+        _ => return None,
+    };
+    date::format(signature.time, &signature.offset, mode)
+}
+
+/// Expands an `%a` or `%c` placeholder about `signature`.
+fn format_ident(signature: &Signature, placeholder: u8, date_mode: &str) -> Option<String> {
+    match placeholder {
+        b'n' => Some(signature.name.clone()),
+        b'e' => Some(signature.email.clone()),
+        b'l' => Some(signature.email.split('@').next().unwrap_or_default().to_string()),
+        _ => format_ident_date(signature, placeholder, date_mode),
+    }
+}
+
+/// Expands the `%` placeholders of a user format for `commit`. Unknown placeholders are
+/// kept as they are.
+pub fn format_commit(commit: &Commit, format: &str, options: &PrettyOptions) -> String {
+    let mut output = String::new();
+    // Checking a signature is slow, so it's done once and only when asked for
+    let mut check: Option<Option<SignatureCheck>> = None;
+    let abbrev = |hash: &str| hash[..hash.len().min(7)].to_string();
+    let mut rest = format;
+    while let Some(position) = rest.find('%') {
+        output.push_str(&rest[..position]);
+        rest = &rest[position + 1..];
+        let expanded = match rest.as_bytes() {
+            [b'%', ..] => Some(("%".to_string(), 1)),
+            [b'n', ..] => Some(("\n".to_string(), 1)),
+            [b'x', ..] => rest.get(1..3).and_then(|hex| u8::from_str_radix(hex, 16).ok()).map(|byte| ((byte as char).to_string(), 3)),
+            [b'H', ..] => Some((commit.hash.clone(), 1)),
+            [b'h', ..] => Some((abbrev(&commit.hash), 1)),
+            [b'T', ..] => Some((commit.tree.clone(), 1)),
+            [b't', ..] => Some((abbrev(&commit.tree), 1)),
+            [b'P', ..] => Some((commit.parents.join(" "), 1)),
+            [b'p', ..] => Some((commit.parents.iter().map(|parent| abbrev(parent)).collect::<Vec<_>>().join(" "), 1)),
+            [b'a', placeholder, ..] => format_ident(&commit.author, *placeholder, &options.date_mode).map(|expansion| (expansion, 2)),
+            [b'c', placeholder, ..] => format_ident(&commit.committer, *placeholder, &options.date_mode).map(|expansion| (expansion, 2)),
+            [b's', ..] => Some((subject(&commit.message()), 1)),
+            [b'b', ..] => Some((body(&format!("{}\n", commit.message())), 1)),
+            [b'B', ..] => Some((format!("{}\n", commit.message()), 1)),
+            [b'd', ..] => Some((options.decorations().wrapped(&commit.hash), 1)),
+            [b'D', ..] => Some((options.decorations().get(&commit.hash), 1)),
+            [b'G', placeholder @ (b'?' | b'G' | b'S' | b'K' | b'F'), ..] => {
+                let check = check.get_or_insert_with(|| commit.check_signature());
+                let expansion = match (placeholder, check) {
+                    (b'?', Some(check)) => check.result.to_string(),
+                    (b'?', None) => "N".to_string(),
+                    (b'G', Some(check)) => check.output.clone(),
+                    (b'S', Some(check)) => check.signer.clone().unwrap_or_default(),
+                    (b'K' | b'F', Some(check)) => check.fingerprint.clone().unwrap_or_default(),
+                    _ => String::new(),
+                };
+                Some((expansion, 2))
+            }
+            [b'(', ..] => rest.find(')').and_then(|end| Some((format_trailers(commit, &rest[1..end])?, end + 1))),
+            _ => None,
+        };
+        let (expansion, len) = expanded.unwrap_or(("%".to_string(), 0));
+        output.push_str(&expansion);
+        rest = &rest[len..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// How `%(trailers)` shows a commit's trailers.
+#[derive(Default)]
+struct TrailerOptions {
+    keys: Vec<String>,
+    only_trailers: bool,
+    unfold: bool,
+    key_only: bool,
+    value_only: bool,
+    separator: Option<String>,
+    key_value_separator: Option<String>,
+}
+
+impl TrailerOptions {
+    /// Parses the comma-separated options after `trailers:`, failing on unknown ones.
+    fn parse(args: &str) -> Option<Self> {
+        let mut options = Self::default();
+        for arg in args.split(',').filter(|arg| !arg.is_empty()) {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg, None),
+            };
+            let flag = || value.map_or(Some(true), Config::parse_bool);
+            match name {
+                "key" => {
+                    let key = value?;
+                    options.keys.push(key.strip_suffix(':').unwrap_or(key).to_string());
+                    options.only_trailers = true;
+                }
+                "separator" => options.separator = Some(expand_literals(value?)),
+                "key_value_separator" => options.key_value_separator = Some(expand_literals(value?)),
+                "only" => options.only_trailers = flag()?,
+                "unfold" => options.unfold = flag()?,
+                "keyonly" => options.key_only = flag()?,
+                "valueonly" => options.value_only = flag()?,
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+
+    fn is_default(&self) -> bool {
+        self.keys.is_empty() && !self.only_trailers && !self.unfold && !self.key_only && !self.value_only
+            && self.separator.is_none() && self.key_value_separator.is_none()
+    }
+}
+
+/// Expands the `%n` and `%xNN` literals allowed in `%(trailers)` separators.
+fn expand_literals(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(position) = rest.find('%') {
+        output.push_str(&rest[..position]);
+        rest = &rest[position + 1..];
+        let byte = match rest.as_bytes() {
+            [b'n', ..] => Some(('\n', 1)),
+            [b'x', ..] => rest.get(1..3).and_then(|hex| u8::from_str_radix(hex, 16).ok()).map(|byte| (byte as char, 3)),
+            _ => None,
+        };
+        match byte {
+            Some((c, len)) => {
+                output.push(c);
+                rest = &rest[len..];
+            }
+            None => output.push('%'),
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Expands `%(trailers[:options])`, or returns `None` for any other `%(...)` placeholder
+/// or unknown options so that it's kept as it is.
+fn format_trailers(commit: &Commit, placeholder: &str) -> Option<String> {
+    let options = match placeholder.strip_prefix("trailers") {
+        Some("") => TrailerOptions::default(),
+        Some(args) => TrailerOptions::parse(args.strip_prefix(':')?)?,
+        None => return None,
+    };
+    let block = commit.trailers();
+    if options.is_default() {
+        return Some(block.lines.concat());
+    }
+    let separators = trailer::separators();
+    let mut output = String::new();
+    for line in block.lines.iter().filter(|line| !line.starts_with('#')) {
+        match trailer::find_separator(line, &separators) {
+            Some(position) if position >= 1 => {
+                let (token, value) = trailer::split_trailer(line, position);
+                if !options.keys.is_empty() && !options.keys.iter().any(|key| key.eq_ignore_ascii_case(&token)) {
+                    continue;
+                }
+                let value = if options.unfold { trailer::unfold_value(&value) } else { value };
+                if let (Some(separator), false) = (&options.separator, output.is_empty()) {
+                    output.push_str(separator);
+                }
+                if !options.value_only {
+                    output.push_str(&token);
+                }
+                if !options.key_only && !options.value_only {
+                    output.push_str(options.key_value_separator.as_deref().unwrap_or(": "));
+                }
+                if !options.key_only {
+                    output.push_str(&value);
+                }
+                if options.separator.is_none() {
+                    output.push('\n');
+                }
+            }
+            _ if !options.only_trailers => {
+                if let (Some(separator), false) = (&options.separator, output.is_empty()) {
+                    output.push_str(separator);
+                }
+                match options.separator {
+                    Some(_) => output.push_str(line.trim_end()),
+                    None => output.push_str(line),
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(output)
+}
@@ -0,0 +1,437 @@
+use std::cmp::Ordering;
+use std::process;
+
+use crate::git::date;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::tag::Tag;
+use crate::git::refs;
+use crate::git::revision;
+
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+fn die(message: &str) -> ! {
+    println!("fatal: {}", message);
+    process::exit(128);
+}
+
+/// Shortens a full ref name by dropping `refs/heads/`, `refs/tags/`, `refs/remotes/` or `refs/`.
+pub fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"].iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Compares names like `v1.10` and `v1.9` by the value of their runs of digits.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let chunks = |s: &str| -> Vec<String> {
+        let mut chunks: Vec<String> = Vec::new();
+        for c in s.chars() {
+            match chunks.last_mut() {
+                Some(chunk) if chunk.chars().last().unwrap().is_ascii_digit() == c.is_ascii_digit() => chunk.push(c),
+                _ => chunks.push(c.to_string()),
+            }
+        }
+        chunks
+    };
+    for (x, y) in chunks(a).iter().zip(chunks(b).iter()) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    chunks(a).len().cmp(&chunks(b).len())
+}
+
+/// Whether a ref matches a `for-each-ref` pattern: either a prefix ending at a `/` or a glob.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    if let Some(rest) = name.strip_prefix(pattern) {
+        if rest.is_empty() || pattern.ends_with('/') || rest.starts_with('/') {
+            return true;
+        }
+    }
+    glob::Pattern::new(pattern).is_ok_and(|glob| glob.matches(name))
+}
+
+/// The parts of an object that atoms are read from. Commits and tags are split into their
+/// header lines and message; other objects only have a type and size.
+struct ObjectInfo {
+    kind: String,
+    size: usize,
+    headers: Vec<(String, String)>,
+    message: String,
+}
+
+impl ObjectInfo {
+    fn read(hash: &str) -> Option<Self> {
+        let kind = revision::object_type(hash)?.to_string();
+        let content = ObjectReader::read_content(hash);
+        let size = content.len();
+        let mut info = Self { kind, size, headers: Vec::new(), message: String::new() };
+        if info.kind == "commit" || info.kind == "tag" {
+            let content = String::from_utf8_lossy(&content);
+            let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+            info.headers = headers.lines().filter_map(|line| line.split_once(' ')).map(|(key, value)| (key.to_string(), value.to_string())).collect();
+            info.message = message.to_string();
+        }
+        Some(info)
+    }
+
+    fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    /// The first paragraph of the message joined into one line.
+    fn subject(&self) -> String {
+        let paragraph = self.message.trim_start_matches('\n').split("\n\n").next().unwrap_or("");
+        paragraph.lines().map(|line| line.trim()).collect::<Vec<&str>>().join(" ")
+    }
+
+    /// The message after the subject paragraph.
+    fn body(&self) -> String {
+        match self.message.trim_start_matches('\n').split_once("\n\n") {
+            Some((_, body)) => body.trim_start_matches('\n').to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// The identity line an atom like `author` or `taggerdate` reads, with `creator` being
+    /// the tagger of tags and the committer of commits.
+    fn ident(&self, role: &str) -> Option<&str> {
+        match role {
+            "creator" if self.kind == "tag" => self.header("tagger"),
+            "creator" => self.header("committer"),
+            _ => self.header(role),
+        }
+    }
+}
+
+/// A ref listed by `for-each-ref`, `branch` or `tag`.
+pub struct RefItem {
+    pub name: String,
+    pub hash: String,
+}
+
+impl RefItem {
+    pub fn new(name: String, hash: String) -> Self {
+        Self { name, hash }
+    }
+
+    fn upstream_atom(&self, modifier: Option<&str>) -> String {
+        let Some(upstream) = self.name.strip_prefix("refs/heads/").and_then(revision::upstream) else {
+            return String::new();
+        };
+        let (kind, options) = match modifier {
+            Some(modifier) => modifier.split_once(',').map(|(kind, options)| (kind, Some(options))).unwrap_or((modifier, None)),
+            None => ("", None),
+        };
+        let brackets = |text: String| if options == Some("nobracket") { text } else { format!("[{}]", text) };
+        match kind {
+            "" => upstream,
+            "short" => short_ref_name(&upstream).to_string(),
+            "track" | "trackshort" => {
+                let Some(upstream_hash) = refs::read_ref(&upstream) else {
+                    return if kind == "track" { brackets("gone".to_string()) } else { String::new() };
+                };
+                let (ahead, behind) = revision::ahead_behind(&self.hash, &upstream_hash);
+                match (kind, ahead, behind) {
+                    ("trackshort", 0, 0) => "=".to_string(),
+                    ("trackshort", _, 0) => ">".to_string(),
+                    ("trackshort", 0, _) => "<".to_string(),
+                    ("trackshort", _, _) => "<>".to_string(),
+                    (_, 0, 0) => String::new(),
+                    (_, _, 0) => brackets(format!("ahead {}", ahead)),
+                    (_, 0, _) => brackets(format!("behind {}", behind)),
+                    _ => brackets(format!("ahead {}, behind {}", ahead, behind)),
+                }
+            }
+            _ => die(&format!("unrecognized %(upstream) argument: {}", modifier.unwrap_or(""))),
+        }
+    }
+
+    fn refname_atom(&self, modifier: Option<&str>) -> String {
+        let components: Vec<&str> = self.name.split('/').collect();
+        let count = |value: &str| value.parse::<usize>().unwrap_or_else(|_| die(&format!("positive value expected refname:{}", modifier.unwrap_or(""))));
+        match modifier {
+            None => self.name.clone(),
+            Some("short") => short_ref_name(&self.name).to_string(),
+            Some(modifier) => match modifier.split_once('=') {
+                Some(("strip" | "lstrip", n)) => components[count(n).min(components.len())..].join("/"),
+                Some(("rstrip", n)) => components[..components.len() - count(n).min(components.len())].join("/"),
+                _ => die(&format!("unrecognized %(refname) argument: {}", modifier)),
+            },
+        }
+    }
+
+    /// Evaluates one atom, the text between `%(` and `)`. A leading `*` reads the object an
+    /// annotated tag points to instead of the tag.
+    pub fn atom(&self, atom: &str) -> String {
+        let (deref, atom) = match atom.strip_prefix('*') {
+            Some(atom) => (true, atom),
+            None => (false, atom),
+        };
+        let (name, modifier) = match atom.split_once(':') {
+            Some((name, modifier)) => (name, Some(modifier)),
+            None => (atom, None),
+        };
+        match name {
+            "refname" => return self.refname_atom(modifier),
+            "upstream" => return self.upstream_atom(modifier),
+            "HEAD" => return if refs::resolve_ref("HEAD").is_some_and(|(target, _)| target == self.name) { "*" } else { " " }.to_string(),
+            "symref" => {
+                return match refs::resolve_ref(&self.name) {
+                    Some((target, _)) if target != self.name => if modifier == Some("short") { short_ref_name(&target).to_string() } else { target },
+                    _ => String::new(),
+                };
+            }
+            _ => {}
+        }
+        let hash = if deref {
+            match revision::object_type(&self.hash) {
+                Some("tag") => Tag::from_hash(&self.hash).object,
+                _ => return String::new(),
+            }
+        } else {
+            self.hash.clone()
+        };
+        let Some(info) = ObjectInfo::read(&hash) else {
+            return String::new();
+        };
+        match (name, modifier) {
+            ("objectname", None) => hash,
+            ("objectname", Some("short")) => hash[..7].to_string(),
+            ("objectname", Some(modifier)) => match modifier.strip_prefix("short=").and_then(|length| length.parse::<usize>().ok()) {
+                Some(length) => hash[..length.clamp(4, 40)].to_string(),
+                None => die(&format!("unrecognized %(objectname) argument: {}", modifier)),
+            },
+            ("objecttype", _) => info.kind,
+            ("objectsize", _) => info.size.to_string(),
+            ("tree" | "object" | "type" | "tag", _) => info.header(name).unwrap_or("").to_string(),
+            ("parent", _) => info.headers.iter().filter(|(key, _)| key == "parent").map(|(_, parent)| parent.as_str()).collect::<Vec<&str>>().join(" "),
+            ("numparent", _) if info.kind != "commit" => String::new(),
+            ("numparent", _) => info.headers.iter().filter(|(key, _)| key == "parent").count().to_string(),
+            ("subject", _) | ("contents", Some("subject")) => info.subject(),
+            ("body", _) | ("contents", Some("body")) => info.body(),
+            ("contents", None) => info.message,
+            _ => self.ident_atom(&info, name, modifier).unwrap_or_else(|| die(&format!("unknown field name: {}", atom))),
+        }
+    }
+
+    /// Evaluates `author`, `authorname`, `authoremail`, `authordate` and the same atoms for
+    /// `committer`, `tagger` and `creator`. Returns None for other atoms.
+    fn ident_atom(&self, info: &ObjectInfo, name: &str, modifier: Option<&str>) -> Option<String> {
+        let (role, part) = ["author", "committer", "tagger", "creator"].iter()
+            .find_map(|role| name.strip_prefix(role).map(|part| (*role, part)))?;
+        if !["", "name", "email", "date"].contains(&part) {
+            return None;
+        }
+        let Some(ident) = info.ident(role) else {
+            return Some(String::new());
+        };
+        let (identity, timestamp, offset) = date::split_ident(ident);
+        let value = match part {
+            "" => ident.to_string(),
+            "name" => identity.split(" <").next().unwrap_or("").to_string(),
+            "email" => {
+                let email = identity.find('<').map(|start| &identity[start..]).unwrap_or("");
+                match modifier {
+                    Some("trim") => email.trim_start_matches('<').trim_end_matches('>').to_string(),
+                    _ => email.to_string(),
+                }
+            }
+            _ => {
+                let mode = modifier.unwrap_or("default");
+                date::format(timestamp, offset, mode).unwrap_or_else(|| die(&format!("unknown date format {}", mode)))
+            }
+        };
+        Some(value)
+    }
+
+    /// The time an atom like `committerdate` holds, for sorting by date.
+    fn timestamp(&self, atom: &str) -> i64 {
+        let atom = atom.split(':').next().unwrap_or(atom);
+        let role = atom.strip_suffix("date").unwrap_or(atom);
+        let hash = match role.strip_prefix('*') {
+            Some(_) if revision::object_type(&self.hash) == Some("tag") => Tag::from_hash(&self.hash).object,
+            Some(_) => return 0,
+            None => self.hash.clone(),
+        };
+        let role = role.trim_start_matches('*');
+        match ObjectInfo::read(&hash) {
+            Some(info) => info.ident(role).map(|ident| date::split_ident(ident).1).unwrap_or(0),
+            None => 0,
+        }
+    }
+}
+
+enum FormatNode {
+    Literal(String),
+    Atom(String),
+    If { condition: String, test: Vec<FormatNode>, then: Vec<FormatNode>, otherwise: Vec<FormatNode> },
+}
+
+/// A parsed `--format` string: literal text, atoms and `%(if)...%(then)...%(else)...%(end)`.
+pub struct RefFormat {
+    nodes: Vec<FormatNode>,
+}
+
+impl RefFormat {
+    fn tokenize(format: &str) -> Vec<FormatNode> {
+        let mut nodes = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('%') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('(') {
+                let Some(end) = after.find(')') else {
+                    die(&format!("malformed format string {}", format));
+                };
+                if !literal.is_empty() {
+                    nodes.push(FormatNode::Literal(std::mem::take(&mut literal)));
+                }
+                nodes.push(FormatNode::Atom(after[..end].to_string()));
+                rest = &after[end + 1..];
+            } else if let Some(byte) = rest.get(..2).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                literal.push(byte as char);
+                rest = &rest[2..];
+            } else {
+                literal.push('%');
+            }
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            nodes.push(FormatNode::Literal(literal));
+        }
+        nodes
+    }
+
+    /// Builds the tree of nodes up to one of the `terminators`, which is returned too.
+    fn nest(tokens: &mut std::vec::IntoIter<FormatNode>, terminators: &[&str]) -> (Vec<FormatNode>, Option<String>) {
+        let mut nodes = Vec::new();
+        while let Some(token) = tokens.next() {
+            let FormatNode::Atom(atom) = &token else {
+                nodes.push(token);
+                continue;
+            };
+            let name = atom.split(':').next().unwrap_or("");
+            if terminators.contains(&name) {
+                return (nodes, Some(name.to_string()));
+            }
+            match name {
+                "if" => {
+                    let condition = atom.clone();
+                    let (test, terminator) = Self::nest(tokens, &["then", "else"]);
+                    match terminator.as_deref() {
+                        Some("then") => {}
+                        Some(_) => die("format: %(else) atom used without a %(then) atom"),
+                        None => die("format: %(end) atom missing"),
+                    }
+                    let (then, terminator) = Self::nest(tokens, &["else", "end"]);
+                    let otherwise = match terminator.as_deref() {
+                        Some("else") => {
+                            let (otherwise, terminator) = Self::nest(tokens, &["end"]);
+                            if terminator.is_none() {
+                                die("format: %(end) atom missing");
+                            }
+                            otherwise
+                        }
+                        Some(_) => Vec::new(),
+                        None => die("format: %(end) atom missing"),
+                    };
+                    nodes.push(FormatNode::If { condition, test, then, otherwise });
+                }
+                "then" => die("format: %(then) atom used without a %(if) atom"),
+                "else" => die("format: %(else) atom used without a %(if) atom"),
+                "end" => die("format: %(end) atom used without corresponding atom"),
+                _ => nodes.push(token),
+            }
+        }
+        (nodes, None)
+    }
+
+    pub fn parse(format: &str) -> Self {
+        let mut tokens = Self::tokenize(format).into_iter();
+        let (nodes, _) = Self::nest(&mut tokens, &[]);
+        Self { nodes }
+    }
+
+    fn expand_nodes(nodes: &[FormatNode], item: &RefItem, output: &mut String) {
+        for node in nodes {
+            match node {
+                FormatNode::Literal(text) => output.push_str(text),
+                FormatNode::Atom(atom) => output.push_str(&item.atom(atom)),
+                FormatNode::If { condition, test, then, otherwise } => {
+                    let mut value = String::new();
+                    Self::expand_nodes(test, item, &mut value);
+                    let satisfied = match condition.strip_prefix("if:") {
+                        Some(modifier) => match modifier.split_once('=') {
+                            Some(("equals", expected)) => value == expected,
+                            Some(("notequals", expected)) => value != expected,
+                            _ => die(&format!("unrecognized %(if) argument: {}", modifier)),
+                        },
+                        None => !value.trim().is_empty(),
+                    };
+                    Self::expand_nodes(if satisfied { then } else { otherwise }, item, output);
+                }
+            }
+        }
+    }
+
+    pub fn expand(&self, item: &RefItem) -> String {
+        let mut output = String::new();
+        Self::expand_nodes(&self.nodes, item, &mut output);
+        output
+    }
+}
+
+enum SortValue {
+    Number(i64),
+    Text(String),
+}
+
+/// Sorts refs by `--sort` keys, the last key given being the most significant. A leading
+/// `-` reverses a key, `version:` compares digits by value, and dates compare as times.
+/// Ties are broken by ref name.
+pub fn sort(items: &mut Vec<RefItem>, keys: &[String]) {
+    let keys: Vec<(bool, &str)> = keys.iter().rev().map(|key| match key.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, key.as_str()),
+    }).collect();
+    let mut keyed: Vec<(Vec<SortValue>, RefItem)> = items.drain(..).map(|item| {
+        let values = keys.iter().map(|(_, key)| {
+            let atom = key.strip_prefix("version:").or(key.strip_prefix("v:")).unwrap_or(key);
+            let name = atom.split(':').next().unwrap_or(atom);
+            if name.ends_with("date") {
+                SortValue::Number(item.timestamp(atom))
+            } else if name == "objectsize" || name == "numparent" {
+                SortValue::Number(item.atom(atom).parse().unwrap_or(0))
+            } else {
+                SortValue::Text(item.atom(atom))
+            }
+        }).collect();
+        (values, item)
+    }).collect();
+    keyed.sort_by(|(a_values, a), (b_values, b)| {
+        for ((reverse, key), (x, y)) in keys.iter().zip(a_values.iter().zip(b_values.iter())) {
+            let version = key.starts_with("version:") || key.starts_with("v:");
+            let ordering = match (x, y) {
+                (SortValue::Number(x), SortValue::Number(y)) => x.cmp(y),
+                (SortValue::Text(x), SortValue::Text(y)) if version => version_cmp(x, y),
+                (SortValue::Text(x), SortValue::Text(y)) => x.cmp(y),
+                _ => Ordering::Equal,
+            };
+            let ordering = if *reverse { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        a.name.cmp(&b.name)
+    });
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+}
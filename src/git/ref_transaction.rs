@@ -0,0 +1,391 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::git::reflog::{self, ReflogEntry};
+use crate::git::refs::{self, PackedRefs};
+use crate::git::reftable;
+use crate::git::reftable::record::{LogRecord, RefRecord, RefRecordValue};
+use crate::git::reftable::stack::Stack;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefAction {
+    Update(String),
+    Delete,
+    /// Points the ref at another ref, as HEAD points at the current branch.
+    Symbolic(String),
+    /// Only checks the old value, leaving the ref as it is.
+    Verify,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub name: String,
+    pub action: RefAction,
+    /// The value the ref must have beforehand; `NULL_HASH` requires that it doesn't exist.
+    pub old: Option<String>,
+    /// The reflog message, or `None` to leave the reflog alone.
+    pub message: Option<String>,
+    /// Whether a symbolic ref is followed so that the ref it points to is updated instead.
+    pub deref: bool,
+    /// A ref whose reflog this one takes over, as when a branch is renamed. Its entries are
+    /// read once the ref is locked, and the new entry logs a move from its value.
+    pub reflog_from: Option<String>,
+}
+
+impl RefUpdate {
+    pub fn new(name: &str, action: RefAction, old: Option<&str>, message: Option<&str>) -> Self {
+        Self { name: name.to_string(), action, old: old.map(|old| old.to_string()), message: message.map(|message| message.to_string()), deref: true, reflog_from: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum TransactionState {
+    #[default]
+    Open,
+    Prepared,
+    Closed,
+}
+
+/// A set of ref updates applied all-or-nothing. `prepare` takes a `<ref>.lock` file for every
+/// ref and checks the expected old values, so `commit` only has to rename the locks into place.
+#[derive(Default)]
+pub struct RefTransaction {
+    updates: Vec<RefUpdate>,
+    /// The value of each ref when it was locked, for the reflog.
+    previous: Vec<Option<String>>,
+    /// The value and reflog entries of each update's `reflog_from` ref when it was locked.
+    moved_logs: Vec<(Option<String>, Vec<ReflogEntry>)>,
+    locks: Vec<String>,
+    packed_refs_locked: bool,
+    /// Set instead of `locks` when refs are stored in reftables, which are locked as a whole.
+    stack_locked: bool,
+    state: TransactionState,
+}
+
+fn lock_path(name: &str) -> String {
+    format!("{}.lock", refs::loose_ref_path(name))
+}
+
+fn lock_error(name: &str, path: &str) -> String {
+    let path = env::current_dir().unwrap().join(path);
+    format!(
+        "cannot lock ref '{}': Unable to create '{}': File exists.\n\n\
+        Another git process seems to be running in this repository, e.g.\n\
+        an editor opened by 'git commit'. Please make sure all processes\n\
+        are terminated then try again. If it still fails, a git process\n\
+        may have crashed in this repository earlier:\n\
+        remove the file manually to continue.",
+        name,
+        path.display()
+    )
+}
+
+impl RefTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails once the transaction is prepared or closed, as updates can't join it then.
+    pub fn check_open(&self) -> Result<(), String> {
+        match self.state {
+            TransactionState::Open => Ok(()),
+            TransactionState::Prepared => Err("prepared transactions can only be closed".to_string()),
+            TransactionState::Closed => Err("transaction is closed".to_string()),
+        }
+    }
+
+    pub fn add(&mut self, update: RefUpdate) -> Result<(), String> {
+        self.check_open()?;
+        self.updates.push(update);
+        Ok(())
+    }
+
+    fn release(&mut self) {
+        for lock in self.locks.drain(..) {
+            let _ = fs::remove_file(lock);
+        }
+        if self.packed_refs_locked {
+            PackedRefs::unlock();
+            self.packed_refs_locked = false;
+        }
+        if self.stack_locked {
+            Stack::unlock();
+            self.stack_locked = false;
+        }
+        self.state = TransactionState::Closed;
+    }
+
+    /// Checks that creating the ref doesn't clash with an existing one.
+    fn check_conflicts(update: &RefUpdate, current: &Option<String>) -> Result<(), String> {
+        if let (RefAction::Update(_), None) = (&update.action, current) {
+            if let Some(conflict) = refs::find_conflicting_ref(&update.name) {
+                return Err(format!("cannot lock ref '{}': '{}' exists; cannot create '{}'", update.name, conflict, update.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_old_value(update: &RefUpdate, current: &Option<String>) -> Result<(), String> {
+        let name = &update.name;
+        match (&update.old, current) {
+            (Some(old), Some(_)) if old == reflog::NULL_HASH => Err(format!("cannot lock ref '{}': reference already exists", name)),
+            (Some(old), None) if old != reflog::NULL_HASH => Err(format!("cannot lock ref '{}': unable to resolve reference '{}'", name, name)),
+            (Some(old), Some(current)) if old != current => Err(format!("cannot lock ref '{}': is at {} but expected {}", name, current, old)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the ref's lock, with the new value written to it.
+    fn lock(&mut self, index: usize) -> Result<(), String> {
+        let update = &self.updates[index];
+        let name = update.name.clone();
+        let path = lock_path(&name);
+        if fs::create_dir_all(Path::new(&path).parent().unwrap()).is_err() {
+            // A ref is in the way of the directory
+            return match refs::find_conflicting_ref(&name) {
+                Some(conflict) => Err(format!("cannot lock ref '{}': '{}' exists; cannot create '{}'", name, conflict, name)),
+                None => Err(format!("cannot lock ref '{}': unable to create directory for {}", name, path)),
+            };
+        }
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(_) => return Err(lock_error(&name, &path)),
+        };
+        self.locks.push(path);
+        match &update.action {
+            RefAction::Update(new) => file.write_all(format!("{}\n", new).as_bytes()).unwrap(),
+            RefAction::Symbolic(target) => file.write_all(format!("ref: {}\n", target).as_bytes()).unwrap(),
+            RefAction::Delete | RefAction::Verify => {}
+        }
+        Ok(())
+    }
+
+    /// Checks every ref against its update, remembering the values for the reflog. Refs are
+    /// only read once they are locked, so nothing can change them before the commit.
+    fn verify(&mut self) -> Result<(), String> {
+        refs::reload();
+        for update in self.updates.iter() {
+            let current = refs::read_ref(&update.name);
+            Self::check_conflicts(update, &current)?;
+            Self::check_old_value(update, &current)?;
+            self.previous.push(current);
+            self.moved_logs.push(match &update.reflog_from {
+                Some(from) => (refs::read_ref(from), reflog::read(from)),
+                None => (None, Vec::new()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Locks every ref and verifies its old value. On failure all locks are released and
+    /// the transaction is closed.
+    pub fn prepare(&mut self) -> Result<(), String> {
+        self.check_open()?;
+        for update in self.updates.iter_mut().filter(|update| update.deref) {
+            match refs::resolve_ref(&update.name) {
+                Some((target, _)) => update.name = target,
+                None => {
+                    self.state = TransactionState::Closed;
+                    return Err(format!("cannot lock ref '{}': unable to resolve reference '{}'", update.name, update.name));
+                }
+            }
+        }
+        for (i, update) in self.updates.iter().enumerate() {
+            if self.updates[..i].iter().any(|other| other.name == update.name) {
+                self.state = TransactionState::Closed;
+                return Err(format!("multiple updates for ref '{}' not allowed", update.name));
+            }
+        }
+        if reftable::is_enabled() {
+            return self.prepare_reftable();
+        }
+        for i in 0..self.updates.len() {
+            if let Err(error) = self.lock(i) {
+                self.release();
+                return Err(error);
+            }
+        }
+        if let Err(error) = self.verify() {
+            self.release();
+            return Err(error);
+        }
+        let packed_refs = PackedRefs::read();
+        let deletes_packed = self.updates.iter().any(|update| update.action == RefAction::Delete && packed_refs.get(&update.name).is_some());
+        if deletes_packed {
+            if let Err(path) = PackedRefs::lock() {
+                self.release();
+                return Err(format!("Unable to create '{}': File exists.", env::current_dir().unwrap().join(path).display()));
+            }
+            self.packed_refs_locked = true;
+        }
+        self.state = TransactionState::Prepared;
+        Ok(())
+    }
+
+    /// Locks the whole reftable stack and checks every update against it.
+    fn prepare_reftable(&mut self) -> Result<(), String> {
+        if let Err(path) = Stack::lock() {
+            self.state = TransactionState::Closed;
+            return Err(format!("Unable to create '{}': File exists.", env::current_dir().unwrap().join(path).display()));
+        }
+        self.stack_locked = true;
+        if let Err(error) = self.verify() {
+            self.release();
+            return Err(error);
+        }
+        self.state = TransactionState::Prepared;
+        Ok(())
+    }
+
+    /// The branch HEAD points to, which logs its updates to HEAD's reflog as well.
+    fn head_target() -> Option<String> {
+        refs::resolve_ref("HEAD").map(|(target, _)| target).filter(|target| target != "HEAD")
+    }
+
+    /// Writes every update, with its reflog entries, as one new table. Log records take
+    /// consecutive update indexes, so a ref can get several entries in the same table.
+    fn commit_reftable(&mut self) {
+        let mut stack = Stack::read();
+        let update_index = stack.next_update_index();
+        let mut log_index = update_index;
+        let mut head_target = Self::head_target();
+        let mut ref_records = Vec::new();
+        let mut log_records = Vec::new();
+        let mut deletions = Vec::new();
+        let mut log = |name: &str, entry: ReflogEntry| {
+            log_records.push(LogRecord::new(name, log_index, Some(entry)));
+            log_index += 1;
+        };
+        for ((update, previous), (moved_from, moved_entries)) in self.updates.iter().zip(self.previous.iter()).zip(self.moved_logs.iter()) {
+            let old = previous.as_deref().unwrap_or(reflog::NULL_HASH);
+            let logs_head = head_target.as_deref() == Some(update.name.as_str());
+            match &update.action {
+                RefAction::Update(new) => {
+                    ref_records.push(RefRecord::new(&update.name, update_index, RefRecordValue::Direct(new.clone())));
+                    if update.reflog_from.is_some() {
+                        deletions.extend(reflog::deletions(&stack, &update.name));
+                        for entry in moved_entries {
+                            log(&update.name, entry.clone());
+                        }
+                    }
+                    if let Some(message) = &update.message {
+                        if !moved_entries.is_empty() || reflog::should_log(&update.name) {
+                            log(&update.name, reflog::new_entry(moved_from.as_deref().unwrap_or(old), new, message));
+                        }
+                        if logs_head {
+                            log("HEAD", reflog::new_entry(old, new, message));
+                        }
+                    }
+                }
+                RefAction::Delete => {
+                    ref_records.push(RefRecord::new(&update.name, update_index, RefRecordValue::Deletion));
+                    deletions.extend(reflog::deletions(&stack, &update.name));
+                    if let (Some(message), true) = (&update.message, logs_head) {
+                        log("HEAD", reflog::new_entry(old, reflog::NULL_HASH, message));
+                    }
+                }
+                RefAction::Symbolic(target) => {
+                    ref_records.push(RefRecord::new(&update.name, update_index, RefRecordValue::Symbolic(target.clone())));
+                    if update.name == "HEAD" {
+                        head_target = Some(target.clone());
+                    }
+                }
+                RefAction::Verify => {}
+            }
+        }
+        log_records.extend(deletions);
+        if !ref_records.is_empty() || !log_records.is_empty() {
+            stack.add_table(ref_records, log_records);
+        } else {
+            Stack::unlock();
+        }
+        self.stack_locked = false;
+    }
+
+    /// Applies every update, preparing the transaction first if needed.
+    pub fn commit(&mut self) -> Result<(), String> {
+        match self.state {
+            TransactionState::Open => self.prepare()?,
+            TransactionState::Prepared => {}
+            TransactionState::Closed => return Err("transaction is closed".to_string()),
+        }
+        if self.stack_locked {
+            self.commit_reftable();
+            self.state = TransactionState::Closed;
+            refs::reload();
+            return Ok(());
+        }
+        if self.packed_refs_locked {
+            let mut packed_refs = PackedRefs::read();
+            packed_refs.refs.retain(|packed_ref| !self.updates.iter().any(|update| update.action == RefAction::Delete && update.name == packed_ref.name));
+            packed_refs.commit_lock();
+            self.packed_refs_locked = false;
+        }
+        let mut head_target = Self::head_target();
+        for ((update, previous), (moved_from, moved_entries)) in self.updates.iter().zip(self.previous.iter()).zip(self.moved_logs.iter()) {
+            let path = refs::loose_ref_path(&update.name);
+            let old = previous.as_deref().unwrap_or(reflog::NULL_HASH);
+            let logs_head = head_target.as_deref() == Some(update.name.as_str());
+            match &update.action {
+                RefAction::Update(new) => {
+                    fs::rename(lock_path(&update.name), &path).unwrap();
+                    if update.reflog_from.is_some() {
+                        reflog::delete(&update.name);
+                        if !moved_entries.is_empty() {
+                            reflog::write(&update.name, moved_entries);
+                        }
+                    }
+                    if let Some(message) = &update.message {
+                        reflog::append(&update.name, moved_from.as_deref().unwrap_or(old), new, message);
+                        if logs_head {
+                            reflog::append("HEAD", old, new, message);
+                        }
+                    }
+                }
+                RefAction::Delete => {
+                    fs::remove_file(lock_path(&update.name)).unwrap();
+                    if Path::new(&path).is_file() {
+                        fs::remove_file(&path).unwrap();
+                        // Remove directories left empty below the category, such as .git/refs/heads
+                        let mut parent = Path::new(&path).parent();
+                        while let Some(dir) = parent {
+                            if dir.components().count() <= 3 || fs::remove_dir(dir).is_err() {
+                                break;
+                            }
+                            parent = dir.parent();
+                        }
+                    }
+                    reflog::delete(&update.name);
+                    if let (Some(message), true) = (&update.message, logs_head) {
+                        reflog::append("HEAD", old, reflog::NULL_HASH, message);
+                    }
+                }
+                RefAction::Symbolic(target) => {
+                    fs::rename(lock_path(&update.name), &path).unwrap();
+                    if update.name == "HEAD" {
+                        head_target = Some(target.clone());
+                    }
+                }
+                RefAction::Verify => fs::remove_file(lock_path(&update.name)).unwrap(),
+            }
+        }
+        self.locks.clear();
+        self.state = TransactionState::Closed;
+        refs::reload();
+        Ok(())
+    }
+
+    pub fn abort(&mut self) {
+        self.release();
+    }
+}
+
+impl Drop for RefTransaction {
+    fn drop(&mut self) {
+        if self.state != TransactionState::Closed {
+            self.release();
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+
+use crate::git::config::Config;
+use crate::git::ident::{self, Role};
+use crate::git::reftable;
+use crate::git::reftable::record::LogRecord;
+use crate::git::reftable::stack::Stack;
+
+/// The object id recorded for a ref that didn't exist before or doesn't exist after an update.
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// One line of a reflog: `<old> <new> <identity> <time> <offset>\t<message>`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub identity: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn new(old: String, new: String, identity: String, message: String) -> Self {
+        Self { old, new, identity, message }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = header.split_once(' ')?;
+        let (new, identity) = rest.split_once(' ')?;
+        Some(Self::new(old.to_string(), new.to_string(), identity.to_string(), message.to_string()))
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.identity.rsplit(' ').nth(1).and_then(|timestamp| timestamp.parse().ok()).unwrap_or(0)
+    }
+
+    pub fn to_line(&self) -> String {
+        if self.message.is_empty() {
+            return format!("{} {} {}\n", self.old, self.new, self.identity);
+        }
+        format!("{} {} {}\t{}\n", self.old, self.new, self.identity, self.message)
+    }
+}
+
+fn log_path(ref_name: &str) -> String {
+    format!(".git/logs/{}", ref_name)
+}
+
+pub fn exists(ref_name: &str) -> bool {
+    if reftable::is_enabled() {
+        return !Stack::read().logs(ref_name).is_empty();
+    }
+    Path::new(&log_path(ref_name)).is_file()
+}
+
+/// Whether updates to `ref_name` are logged, following `core.logAllRefUpdates`: by default
+/// HEAD, branches, remote-tracking refs and notes, plus any ref that already has a log.
+pub fn should_log(ref_name: &str) -> bool {
+    if exists(ref_name) {
+        return true;
+    }
+    match Config::read().get("core.logAllRefUpdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        Some(value) if Config::parse_bool(&value) == Some(false) => false,
+        _ => ref_name == "HEAD" || ["refs/heads/", "refs/remotes/", "refs/notes/"].iter().any(|prefix| ref_name.starts_with(prefix)),
+    }
+}
+
+/// An entry for an update made now by the committer.
+pub fn new_entry(old: &str, new: &str, message: &str) -> ReflogEntry {
+    let identity = match ident::signature(Role::Committer, false) {
+        Ok(signature) => signature.to_string(),
+        Err(_) => format!("unknown <unknown> {} +0000", Local::now().timestamp()),
+    };
+    // Messages are a single line, as in git
+    let message = message.lines().map(|line| line.trim()).collect::<Vec<&str>>().join(" ");
+    ReflogEntry::new(old.to_string(), new.to_string(), identity, message)
+}
+
+/// Records an update of `ref_name` from `old` to `new` if the ref is logged.
+pub fn append(ref_name: &str, old: &str, new: &str, message: &str) {
+    if !should_log(ref_name) {
+        return;
+    }
+    let entry = new_entry(old, new, message);
+    if reftable::is_enabled() {
+        Stack::update(|_, update_index| (Vec::new(), vec![LogRecord::new(ref_name, update_index, Some(entry))]));
+        return;
+    }
+    let path = log_path(ref_name);
+    fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+    file.write_all(entry.to_line().as_bytes()).unwrap();
+}
+
+/// Reads the entries of a reflog, oldest first.
+pub fn read(ref_name: &str) -> Vec<ReflogEntry> {
+    if reftable::is_enabled() {
+        return Stack::read().logs(ref_name).into_iter().map(|(_, entry)| entry).collect();
+    }
+    fs::read_to_string(log_path(ref_name)).unwrap_or_default().lines().filter_map(ReflogEntry::parse).collect()
+}
+
+/// Replaces the entries of a reflog through a lock file.
+pub fn write(ref_name: &str, entries: &[ReflogEntry]) {
+    if reftable::is_enabled() {
+        Stack::update(|stack, update_index| {
+            let mut logs = deletions(stack, ref_name);
+            logs.extend(entries.iter().zip(update_index..).map(|(entry, update_index)| LogRecord::new(ref_name, update_index, Some(entry.clone()))));
+            (Vec::new(), logs)
+        });
+        return;
+    }
+    let path = log_path(ref_name);
+    fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    let lock_path = format!("{}.lock", path);
+    let content: String = entries.iter().map(|entry| entry.to_line()).collect();
+    fs::write(&lock_path, content).unwrap();
+    fs::rename(&lock_path, &path).unwrap();
+}
+
+/// Records that hide every entry in the reftable reflog of `ref_name`.
+pub fn deletions(stack: &Stack, ref_name: &str) -> Vec<LogRecord> {
+    stack.logs(ref_name).into_iter().map(|(update_index, _)| LogRecord::new(ref_name, update_index, None)).collect()
+}
+
+/// Removes a reflog along with the directories it leaves empty below `.git/logs/refs`.
+pub fn delete(ref_name: &str) {
+    if reftable::is_enabled() {
+        if exists(ref_name) {
+            Stack::update(|stack, _| (Vec::new(), deletions(stack, ref_name)));
+        }
+        return;
+    }
+    let path = log_path(ref_name);
+    if fs::remove_file(&path).is_err() {
+        return;
+    }
+    let mut parent = Path::new(&path).parent();
+    while let Some(dir) = parent {
+        if dir.components().count() <= 4 || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+/// Lists every ref that has a reflog, HEAD first.
+pub fn list() -> Vec<String> {
+    fn collect(dir: &Path, name: &str, names: &mut Vec<String>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let child = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            if entry.path().is_dir() {
+                collect(&entry.path(), &child, names);
+            } else if !child.ends_with(".lock") {
+                names.push(child);
+            }
+        }
+    }
+    let mut names = Vec::new();
+    if reftable::is_enabled() {
+        names = Stack::read().log_names();
+        names.retain(|name| name != "HEAD");
+    } else {
+        collect(Path::new(".git/logs/refs"), "refs", &mut names);
+        names.sort();
+    }
+    if exists("HEAD") {
+        names.insert(0, "HEAD".to_string());
+    }
+    names
+}
+
+/// Parses the dates accepted in `<ref>@{<date>}` and `--expire`: `now`, `yesterday`, unix
+/// times, `YYYY-MM-DD [HH:MM[:SS]]` and relative dates like `2.weeks.ago` or `3 days ago`.
+pub fn parse_date(date: &str) -> Option<i64> {
+    let now = Local::now().timestamp();
+    let date = date.trim();
+    match date {
+        "now" | "all" => return Some(now),
+        "yesterday" => return Some(now - 86400),
+        _ => {}
+    }
+    if let Some(timestamp) = date.strip_prefix('@') {
+        return timestamp.parse().ok();
+    }
+    // Short numbers are too easily confused with reflog positions to be taken as times
+    if date.len() > 8 && date.chars().all(|c| c.is_ascii_digit()) {
+        return date.parse().ok();
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(date, format) {
+            return Local.from_local_datetime(&datetime).single().map(|datetime| datetime.timestamp());
+        }
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Local.from_local_datetime(&day.and_hms_opt(0, 0, 0)?).single().map(|datetime| datetime.timestamp());
+    }
+    let words: Vec<&str> = date.split(['.', ' ']).filter(|word| !word.is_empty()).collect();
+    let [count, unit, "ago"] = words.as_slice() else {
+        return None;
+    };
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" => 30 * 86400,
+        "year" => 365 * 86400,
+        _ => return None,
+    };
+    Some(now - count.parse::<i64>().ok()? * seconds)
+}
@@ -0,0 +1,311 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+
+use crate::git::ref_transaction::{RefAction, RefTransaction, RefUpdate};
+use crate::git::reftable;
+use crate::git::reftable::record::RefRecordValue;
+use crate::git::reftable::stack::Stack;
+
+pub const PACKED_REFS_PATH: &str = ".git/packed-refs";
+
+#[derive(Debug, Clone)]
+pub struct PackedRef {
+    pub name: String,
+    pub hash: String,
+    pub peeled: Option<String>,
+}
+
+impl PackedRef {
+    pub fn new(name: String, hash: String, peeled: Option<String>) -> Self {
+        Self { name, hash, peeled }
+    }
+}
+
+/// The contents of `.git/packed-refs`. `traits` come from the `# pack-refs with:` header;
+/// `peeled` means annotated tags are followed by a `^<hash>` line with their target.
+#[derive(Debug, Default)]
+pub struct PackedRefs {
+    pub traits: Vec<String>,
+    pub refs: Vec<PackedRef>,
+}
+
+impl PackedRefs {
+    pub fn parse(content: &str) -> Self {
+        let mut packed_refs = Self::default();
+        for line in content.lines() {
+            if let Some(traits) = line.strip_prefix("# pack-refs with:") {
+                packed_refs.traits = traits.split_whitespace().map(|t| t.to_string()).collect();
+            } else if let Some(peeled) = line.strip_prefix('^') {
+                if let Some(last) = packed_refs.refs.last_mut() {
+                    last.peeled = Some(peeled.trim().to_string());
+                }
+            } else if let Some((hash, name)) = line.split_once(' ') {
+                packed_refs.refs.push(PackedRef::new(name.trim().to_string(), hash.to_string(), None));
+            }
+        }
+        if !packed_refs.traits.iter().any(|t| t == "sorted") {
+            packed_refs.refs.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        packed_refs
+    }
+
+    pub fn read() -> Self {
+        match fs::read_to_string(PACKED_REFS_PATH) {
+            Ok(content) => Self::parse(&content),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackedRef> {
+        self.refs.binary_search_by(|r| r.name.as_str().cmp(name)).ok().map(|i| &self.refs[i])
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for packed_ref in self.refs.iter() {
+            content.push_str(&format!("{} {}\n", packed_ref.hash, packed_ref.name));
+            if let Some(peeled) = &packed_ref.peeled {
+                content.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        content
+    }
+
+    /// Takes `.git/packed-refs.lock`, returning its path as the error if it is already held.
+    pub fn lock() -> Result<(), String> {
+        let lock_path = format!("{}.lock", PACKED_REFS_PATH);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(lock_path),
+        }
+    }
+
+    pub fn unlock() {
+        let _ = fs::remove_file(format!("{}.lock", PACKED_REFS_PATH));
+    }
+
+    /// Writes the refs into the lock taken by `lock` and renames it into place. The refs are
+    /// kept sorted, so the `sorted` trait always holds for files rgit writes.
+    pub fn commit_lock(&self) {
+        let lock_path = format!("{}.lock", PACKED_REFS_PATH);
+        fs::write(&lock_path, self.serialize()).unwrap();
+        fs::rename(&lock_path, PACKED_REFS_PATH).unwrap();
+    }
+}
+
+pub fn loose_ref_path(name: &str) -> String {
+    format!(".git/{}", name)
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 40 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Checks `name` against git's ref name rules: no `..`, `@{`, control characters, spaces or
+/// any of `~^:?*[\`, and no component starting with `.` or ending with `.lock`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.starts_with(['-', '/']) || name.ends_with(['/', '.']) {
+        return false;
+    }
+    if name.contains("..") || name.contains("@{") || name.contains("//") {
+        return false;
+    }
+    if name.chars().any(|c| c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')) {
+        return false;
+    }
+    name.split('/').all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+/// How many symbolic refs are followed before giving up, matching git's limit.
+pub const MAX_SYMREF_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefValue {
+    Direct(String),
+    Symbolic(String),
+}
+
+/// The refs that aren't loose files: `packed-refs`, or the reftable stack that holds every
+/// ref when reftables are enabled.
+enum RefStore {
+    Files(PackedRefs),
+    Reftable(Stack),
+}
+
+thread_local! {
+    /// The ref store as first read by this command, so that lookups don't parse it again.
+    static REF_STORE: RefCell<Option<Rc<RefStore>>> = const { RefCell::new(None) };
+}
+
+fn ref_store() -> Rc<RefStore> {
+    REF_STORE.with(|store| {
+        store.borrow_mut().get_or_insert_with(|| {
+            Rc::new(if reftable::is_enabled() { RefStore::Reftable(Stack::read()) } else { RefStore::Files(PackedRefs::read()) })
+        }).clone()
+    })
+}
+
+/// Forgets the ref store read so far, so that lookups see the refs as they are now. Ref
+/// updates call it once they are written, and transactions once their refs are locked.
+pub fn reload() {
+    REF_STORE.with(|store| store.borrow_mut().take());
+}
+
+/// Reads the value stored in a ref without following it, preferring the loose ref over
+/// the packed one. Packed refs are never symbolic.
+pub fn read_raw_ref(name: &str) -> Option<RefValue> {
+    let packed_refs = match ref_store().as_ref() {
+        RefStore::Reftable(stack) => {
+            return match stack.ref_value(name)? {
+                RefRecordValue::Direct(hash) | RefRecordValue::Peeled(hash, _) => Some(RefValue::Direct(hash.clone())),
+                RefRecordValue::Symbolic(target) => Some(RefValue::Symbolic(target.clone())),
+                RefRecordValue::Deletion => None,
+            };
+        }
+        RefStore::Files(packed_refs) => packed_refs.get(name).map(|packed_ref| RefValue::Direct(packed_ref.hash.clone())),
+    };
+    let path = loose_ref_path(name);
+    if Path::new(&path).is_file() {
+        let content = fs::read_to_string(&path).ok()?;
+        let content = content.trim();
+        if let Some(target) = content.strip_prefix("ref:") {
+            return Some(RefValue::Symbolic(target.trim().to_string()));
+        }
+        return if is_hash(content) { Some(RefValue::Direct(content.to_string())) } else { None };
+    }
+    packed_refs
+}
+
+/// Follows symbolic refs starting at `name`, returning the name of the last ref reached and
+/// its object id, which is `None` when that ref doesn't exist, as on an unborn branch.
+/// Returns `None` when the chain is deeper than `MAX_SYMREF_DEPTH`.
+pub fn resolve_ref(name: &str) -> Option<(String, Option<String>)> {
+    let mut name = name.to_string();
+    for _ in 0..=MAX_SYMREF_DEPTH {
+        match read_raw_ref(&name) {
+            Some(RefValue::Direct(hash)) => return Some((name, Some(hash))),
+            Some(RefValue::Symbolic(target)) => name = target,
+            None => return Some((name, None)),
+        }
+    }
+    None
+}
+
+/// Resolves a full ref name such as `refs/heads/main` to an object id, following symbolic refs.
+pub fn read_ref(name: &str) -> Option<String> {
+    resolve_ref(name)?.1
+}
+
+fn collect_loose_refs(dir: &Path, name: &str, names: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let child = format!("{}/{}", name, file_name);
+        if entry.path().is_dir() {
+            collect_loose_refs(&entry.path(), &child, names);
+        } else if !file_name.ends_with(".lock") {
+            names.push(child);
+        }
+    }
+}
+
+/// Lists every ref whose name starts with `prefix`, loose and packed, sorted by name and
+/// paired with the object id it resolves to. Symbolic refs that don't resolve are skipped.
+pub fn list_refs(prefix: &str) -> Vec<(String, String)> {
+    let mut names = Vec::new();
+    match ref_store().as_ref() {
+        RefStore::Reftable(stack) => names.extend(stack.refs(prefix).into_iter().map(|record| record.name)),
+        RefStore::Files(packed_refs) => {
+            // Only the directory the prefix ends in can hold matching loose refs
+            let dir = prefix.rfind('/').map_or("refs", |slash| &prefix[..slash]);
+            collect_loose_refs(Path::new(&loose_ref_path(dir)), dir, &mut names);
+            let start = packed_refs.refs.partition_point(|packed_ref| packed_ref.name.as_str() < prefix);
+            names.extend(packed_refs.refs[start..].iter().take_while(|packed_ref| packed_ref.name.starts_with(prefix)).map(|packed_ref| packed_ref.name.clone()));
+        }
+    }
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+    names.dedup();
+    names.into_iter().filter_map(|name| read_ref(&name).map(|hash| (name, hash))).collect()
+}
+
+/// Finds an existing ref that conflicts with creating `name`, either because it is a
+/// directory prefix of `name` or because `name` would be its directory.
+pub fn find_conflicting_ref(name: &str) -> Option<String> {
+    for (slash, _) in name.match_indices('/').skip(1) {
+        let prefix = &name[..slash];
+        if read_raw_ref(prefix).is_some() {
+            return Some(prefix.to_string());
+        }
+    }
+    list_refs(&format!("{}/", name)).into_iter().next().map(|(conflict, _)| conflict)
+}
+
+/// Whether HEAD is a symbolic ref to `name`, so that updates to `name` also move HEAD.
+pub fn is_head_target(name: &str) -> bool {
+    name != "HEAD" && resolve_ref("HEAD").is_some_and(|(target, _)| target == name)
+}
+
+/// Stores `value` in `name` without logging the change, as when HEAD moves to a branch. The
+/// ref is written through its lock, so it fails like any other update while the lock is held.
+pub fn write_raw_ref(name: &str, value: &RefValue) {
+    let action = match value {
+        RefValue::Direct(hash) => RefAction::Update(hash.clone()),
+        RefValue::Symbolic(target) => RefAction::Symbolic(target.clone()),
+    };
+    let mut transaction = RefTransaction::new();
+    let result = transaction.add(RefUpdate { deref: false, ..RefUpdate::new(name, action, None, None) }).and_then(|_| transaction.commit());
+    if let Err(error) = result {
+        match value {
+            RefValue::Direct(_) => println!("fatal: update_ref failed for ref '{}': {}", name, error),
+            RefValue::Symbolic(_) => {
+                let prefix = format!("cannot lock ref '{}': ", name);
+                println!("error: {}", error.strip_prefix(&prefix).unwrap_or(&error));
+                println!("fatal: unable to update {}", name);
+            }
+        }
+        process::exit(128);
+    }
+}
+
+fn commit_or_exit(update: RefUpdate) {
+    let mut transaction = RefTransaction::new();
+    if let Err(error) = transaction.add(update).and_then(|_| transaction.commit()) {
+        println!("fatal: {}", error);
+        process::exit(128);
+    }
+}
+
+/// Points `name` at `hash` as a loose ref, shadowing any packed value, and records the
+/// update with `message` in its reflog and, when HEAD is on it, in HEAD's.
+pub fn write_ref(name: &str, hash: &str, message: &str) {
+    commit_or_exit(RefUpdate::new(name, RefAction::Update(hash.to_string()), None, Some(message)));
+}
+
+/// Deletes `name` both as a loose ref and from `packed-refs`, so the packed value doesn't
+/// reappear once the loose file is gone, along with its reflog.
+pub fn delete_ref(name: &str) {
+    commit_or_exit(RefUpdate::new(name, RefAction::Delete, None, None));
+}
+
+/// Moves the ref `old` and its reflog to `new` in one transaction, keeping HEAD on it if it
+/// was. HEAD's log sees the old branch go away and the new one appear.
+pub fn rename_ref(old: &str, new: &str, message: &str) -> Result<(), String> {
+    let hash = read_ref(old).unwrap();
+    let mut transaction = RefTransaction::new();
+    if old != new {
+        transaction.add(RefUpdate { deref: false, ..RefUpdate::new(old, RefAction::Delete, Some(&hash), Some(message)) })?;
+        if is_head_target(old) {
+            transaction.add(RefUpdate { deref: false, ..RefUpdate::new("HEAD", RefAction::Symbolic(new.to_string()), None, None) })?;
+        }
+    }
+    let update = RefUpdate::new(new, RefAction::Update(hash), None, Some(message));
+    transaction.add(RefUpdate { deref: false, reflog_from: Some(old.to_string()), ..update })?;
+    transaction.commit()
+}
@@ -0,0 +1,219 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress};
+
+use crate::git::reftable::record::{get_varint, put_varint, Record};
+
+pub const BLOCK_TYPE_REF: u8 = b'r';
+pub const BLOCK_TYPE_LOG: u8 = b'l';
+pub const BLOCK_TYPE_OBJ: u8 = b'o';
+pub const BLOCK_TYPE_INDEX: u8 = b'i';
+
+/// Every 16th record stores its full key, so readers can binary search the restart points.
+const RESTART_INTERVAL: usize = 16;
+
+fn put_uint24(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+}
+
+fn get_uint24(data: &[u8], pos: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos + 3)?;
+    Some(((bytes[0] as usize) << 16) | ((bytes[1] as usize) << 8) | bytes[2] as usize)
+}
+
+/// Builds one block: `<type> <uint24 length>`, prefix-compressed records, the restart
+/// offsets and their count. `header_offset` is the size of the file header for the first
+/// block of a table, which counts towards its length and restart offsets.
+pub struct BlockWriter {
+    kind: u8,
+    header_offset: usize,
+    block_size: usize,
+    records: Vec<u8>,
+    restarts: Vec<usize>,
+    last_key: Vec<u8>,
+    count: usize,
+}
+
+impl BlockWriter {
+    pub fn new(kind: u8, header_offset: usize, block_size: usize) -> Self {
+        Self { kind, header_offset, block_size, records: Vec::new(), restarts: Vec::new(), last_key: Vec::new(), count: 0 }
+    }
+
+    fn offset(&self) -> usize {
+        self.header_offset + 4 + self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Adds a record, returning false without adding it when the block is full. The first
+    /// record is always taken so that oversized records still get a block of their own.
+    pub fn add<R: Record>(&mut self, record: &R, min_update_index: u64) -> bool {
+        let key = record.key();
+        let restart = self.count.is_multiple_of(RESTART_INTERVAL);
+        let prefix = if restart { 0 } else { self.last_key.iter().zip(key.iter()).take_while(|(a, b)| a == b).count() };
+        let mut encoded = Vec::new();
+        put_varint(&mut encoded, prefix as u64);
+        put_varint(&mut encoded, (((key.len() - prefix) as u64) << 3) | record.value_type() as u64);
+        encoded.extend_from_slice(&key[prefix..]);
+        record.encode_value(&mut encoded, min_update_index);
+        let restarts = self.restarts.len() + usize::from(restart);
+        if self.count > 0 && self.offset() + encoded.len() + 3 * restarts + 2 > self.block_size {
+            return false;
+        }
+        if restart {
+            self.restarts.push(self.offset());
+        }
+        self.records.extend(encoded);
+        self.last_key = key;
+        self.count += 1;
+        true
+    }
+
+    /// Serializes the block, without the file header. Log blocks are deflated after their
+    /// type and length, and their length is that of the inflated block.
+    pub fn finish(self) -> Vec<u8> {
+        let mut payload = self.records;
+        for restart in self.restarts.iter() {
+            put_uint24(&mut payload, *restart);
+        }
+        payload.extend_from_slice(&(self.restarts.len() as u16).to_be_bytes());
+        let mut block = vec![self.kind];
+        put_uint24(&mut block, self.header_offset + 4 + payload.len());
+        if self.kind == BLOCK_TYPE_LOG {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&payload).unwrap();
+            block.extend(encoder.finish().unwrap());
+        } else {
+            block.extend(payload);
+        }
+        block
+    }
+}
+
+/// A block read from a table, with log blocks inflated. `data` starts at the beginning of
+/// the block, which for the first block of a table is the file header.
+pub struct Block {
+    pub kind: u8,
+    data: Vec<u8>,
+    header_offset: usize,
+    /// Where the block ends in the file, before any padding.
+    pub end: usize,
+}
+
+impl Block {
+    pub fn read(table: &[u8], offset: usize, header_offset: usize) -> Option<Self> {
+        let kind = *table.get(offset + header_offset)?;
+        let len = get_uint24(table, offset + header_offset + 1)?;
+        let start = offset + header_offset + 4;
+        if kind != BLOCK_TYPE_LOG {
+            let data = table.get(offset..offset + len)?.to_vec();
+            return Some(Self { kind, data, header_offset, end: offset + len });
+        }
+        let mut data = table.get(offset..start)?.to_vec();
+        data.resize(len, 0);
+        let mut decompress = Decompress::new(true);
+        decompress.decompress(table.get(start..)?, &mut data[start - offset..], FlushDecompress::Finish).ok()?;
+        if decompress.total_out() as usize != len - (start - offset) {
+            return None;
+        }
+        Some(Self { kind, data, header_offset, end: start + decompress.total_in() as usize })
+    }
+
+    pub fn records<R: Record>(&self, min_update_index: u64) -> Option<Vec<R>> {
+        let restart_count = u16::from_be_bytes(self.data.get(self.data.len().checked_sub(2)?..)?.try_into().ok()?) as usize;
+        let end = self.data.len().checked_sub(2 + 3 * restart_count)?;
+        let mut pos = self.header_offset + 4;
+        let mut key: Vec<u8> = Vec::new();
+        let mut records = Vec::new();
+        while pos < end {
+            let prefix = get_varint(&self.data, &mut pos)? as usize;
+            let suffix_and_type = get_varint(&self.data, &mut pos)?;
+            let suffix_len = (suffix_and_type >> 3) as usize;
+            key.truncate(prefix);
+            key.extend_from_slice(self.data.get(pos..pos + suffix_len)?);
+            pos += suffix_len;
+            records.push(R::decode(&key, (suffix_and_type & 7) as u8, &self.data, &mut pos, min_update_index)?);
+        }
+        Some(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::reftable::record::{IndexRecord, RefRecord, RefRecordValue};
+
+    fn refs(count: usize) -> Vec<RefRecord> {
+        (0..count).map(|i| RefRecord::new(&format!("refs/heads/b{:02}", i), 1, RefRecordValue::Direct(format!("{:040x}", i)))).collect()
+    }
+
+    #[test]
+    fn keys_are_prefix_compressed_between_restarts() {
+        let mut writer = BlockWriter::new(BLOCK_TYPE_REF, 0, 4096);
+        for record in refs(40) {
+            assert!(writer.add(&record, 1));
+        }
+        let bytes = writer.finish();
+        assert_eq!(&bytes[..4], [b'r', 0x00, (bytes.len() >> 8) as u8, bytes.len() as u8]);
+        // 40 records restart at the 1st, 17th and 33rd
+        assert_eq!(&bytes[bytes.len() - 2..], [0, 3]);
+        let restart = |i: usize| get_uint24(&bytes, bytes.len() - 2 - 3 * (3 - i)).unwrap();
+        assert_eq!(restart(0), 4);
+        // A restart stores its whole key, and the record after it only what differs
+        assert_eq!(&bytes[4..20], b"\x00\x71refs/heads/b00");
+        let second = restart(0) + 2 + 14 + 1 + 20;
+        assert_eq!(&bytes[second..second + 3], [13, (1 << 3) | 1, b'1']);
+        assert_eq!(&bytes[restart(1)..restart(1) + 16], b"\x00\x71refs/heads/b16");
+        let block = Block::read(&bytes, 0, 0).unwrap();
+        let records = block.records::<RefRecord>(1).unwrap();
+        assert_eq!(records.iter().map(|record| &record.name).collect::<Vec<_>>(), refs(40).iter().map(|record| &record.name).collect::<Vec<_>>());
+        assert_eq!(block.end, bytes.len());
+    }
+
+    #[test]
+    fn full_blocks_refuse_records_but_take_the_first() {
+        let mut writer = BlockWriter::new(BLOCK_TYPE_REF, 0, 256);
+        let added = refs(20).iter().take_while(|record| writer.add(*record, 1)).count();
+        assert!(added > 0 && added < 20);
+        assert!(writer.finish().len() <= 256);
+        let mut writer = BlockWriter::new(BLOCK_TYPE_REF, 0, 16);
+        assert!(writer.add(&refs(1)[0], 1));
+    }
+
+    #[test]
+    fn log_blocks_are_deflated_after_their_header() {
+        use crate::git::reflog::ReflogEntry;
+        use crate::git::reftable::record::LogRecord;
+        let entry = ReflogEntry::new("0".repeat(40), "1".repeat(40), "T <t@e> 1700000000 +0100".to_string(), "commit: x".to_string());
+        let mut writer = BlockWriter::new(BLOCK_TYPE_LOG, 24, 4096);
+        assert!(writer.add(&LogRecord::new("HEAD", 3, Some(entry.clone())), 1));
+        let mut bytes = vec![0; 24];
+        bytes.extend(writer.finish());
+        assert_eq!(bytes[24], b'l');
+        // Zlib streams start with 0x78
+        assert_eq!(bytes[28], 0x78);
+        let block = Block::read(&bytes, 0, 24).unwrap();
+        assert_eq!(block.end, bytes.len());
+        let logs = block.records::<LogRecord>(1).unwrap();
+        assert_eq!((logs[0].update_index, logs[0].entry.as_ref().unwrap().identity.as_str()), (3, entry.identity.as_str()));
+    }
+
+    #[test]
+    fn index_blocks_round_trip() {
+        let mut writer = BlockWriter::new(BLOCK_TYPE_INDEX, 0, 4096);
+        let index: Vec<IndexRecord> = (0..20).map(|i| IndexRecord { last_key: format!("refs/tags/v{:03}", i).into_bytes(), position: i * 4096 }).collect();
+        for record in index.iter() {
+            assert!(writer.add(record, 0));
+        }
+        assert_eq!(writer.last_key(), b"refs/tags/v019");
+        let bytes = writer.finish();
+        assert_eq!(Block::read(&bytes, 0, 0).unwrap().records::<IndexRecord>(0).unwrap(), index);
+    }
+}
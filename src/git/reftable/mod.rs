@@ -0,0 +1,12 @@
+use crate::git::config::Config;
+
+pub mod record;
+pub mod block;
+pub mod table;
+pub mod stack;
+
+/// Whether refs and reflogs are stored in reftables under `.git/reftable` instead of files,
+/// as set by `extensions.refStorage`.
+pub fn is_enabled() -> bool {
+    Config::read().get("extensions.refStorage").is_some_and(|value| value.eq_ignore_ascii_case("reftable"))
+}
\ No newline at end of file
@@ -0,0 +1,349 @@
+use crate::git::date;
+use crate::git::reflog::ReflogEntry;
+
+pub const HASH_SIZE: usize = 20;
+
+/// Appends `value` as a reftable varint, the same encoding git uses for offsets in packs:
+/// each continuation byte stores one less than its value so that encodings are unique.
+pub fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.into_iter().rev());
+}
+
+pub fn get_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut byte = *data.get(*pos)?;
+    *pos += 1;
+    let mut value = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = *data.get(*pos)?;
+        *pos += 1;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Some(value)
+}
+
+fn get_bytes<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let bytes = data.get(*pos..*pos + len)?;
+    *pos += len;
+    Some(bytes)
+}
+
+fn get_string(data: &[u8], pos: &mut usize) -> Option<String> {
+    let len = get_varint(data, pos)? as usize;
+    Some(String::from_utf8_lossy(get_bytes(data, pos, len)?).to_string())
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn get_hash(data: &[u8], pos: &mut usize) -> Option<String> {
+    Some(hex::encode(get_bytes(data, pos, HASH_SIZE)?))
+}
+
+fn put_hash(out: &mut Vec<u8>, hash: &str) {
+    out.extend(hex::decode(hash).unwrap());
+}
+
+/// A record in a ref or log block. Keys are prefix-compressed against the previous record,
+/// and the 3-bit value type is stored alongside the key's suffix length.
+pub trait Record: Sized {
+    fn key(&self) -> Vec<u8>;
+    fn value_type(&self) -> u8;
+    fn encode_value(&self, out: &mut Vec<u8>, min_update_index: u64);
+    fn decode(key: &[u8], value_type: u8, data: &[u8], pos: &mut usize, min_update_index: u64) -> Option<Self>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefRecordValue {
+    /// Hides the ref in older tables of the stack.
+    Deletion,
+    Direct(String),
+    /// An annotated tag and the object it peels to.
+    Peeled(String, String),
+    Symbolic(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RefRecord {
+    pub name: String,
+    pub update_index: u64,
+    pub value: RefRecordValue,
+}
+
+impl RefRecord {
+    pub fn new(name: &str, update_index: u64, value: RefRecordValue) -> Self {
+        Self { name: name.to_string(), update_index, value }
+    }
+}
+
+impl Record for RefRecord {
+    fn key(&self) -> Vec<u8> {
+        self.name.as_bytes().to_vec()
+    }
+
+    fn value_type(&self) -> u8 {
+        match self.value {
+            RefRecordValue::Deletion => 0,
+            RefRecordValue::Direct(_) => 1,
+            RefRecordValue::Peeled(_, _) => 2,
+            RefRecordValue::Symbolic(_) => 3,
+        }
+    }
+
+    fn encode_value(&self, out: &mut Vec<u8>, min_update_index: u64) {
+        put_varint(out, self.update_index - min_update_index);
+        match &self.value {
+            RefRecordValue::Deletion => {}
+            RefRecordValue::Direct(hash) => put_hash(out, hash),
+            RefRecordValue::Peeled(hash, peeled) => {
+                put_hash(out, hash);
+                put_hash(out, peeled);
+            }
+            RefRecordValue::Symbolic(target) => put_string(out, target),
+        }
+    }
+
+    fn decode(key: &[u8], value_type: u8, data: &[u8], pos: &mut usize, min_update_index: u64) -> Option<Self> {
+        let update_index = min_update_index + get_varint(data, pos)?;
+        let value = match value_type {
+            0 => RefRecordValue::Deletion,
+            1 => RefRecordValue::Direct(get_hash(data, pos)?),
+            2 => RefRecordValue::Peeled(get_hash(data, pos)?, get_hash(data, pos)?),
+            3 => RefRecordValue::Symbolic(get_string(data, pos)?),
+            _ => return None,
+        };
+        Some(Self { name: String::from_utf8_lossy(key).to_string(), update_index, value })
+    }
+}
+
+/// A reflog entry keyed by ref name and update index, or with no entry a deletion that
+/// hides the entry with the same key in older tables.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub name: String,
+    pub update_index: u64,
+    pub entry: Option<ReflogEntry>,
+}
+
+impl LogRecord {
+    pub fn new(name: &str, update_index: u64, entry: Option<ReflogEntry>) -> Self {
+        Self { name: name.to_string(), update_index, entry }
+    }
+}
+
+impl Record for LogRecord {
+    /// `<name>\0<reversed update index>`, so that a ref's newest entry sorts first.
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.name.as_bytes().to_vec();
+        key.push(0);
+        key.extend_from_slice(&(u64::MAX - self.update_index).to_be_bytes());
+        key
+    }
+
+    fn value_type(&self) -> u8 {
+        if self.entry.is_some() { 1 } else { 0 }
+    }
+
+    fn encode_value(&self, out: &mut Vec<u8>, _min_update_index: u64) {
+        let Some(entry) = &self.entry else {
+            return;
+        };
+        let (identity, timestamp, offset) = date::split_ident(&entry.identity);
+        let (name, email) = identity.split_once(" <").unwrap_or((identity, ""));
+        let offset = date::parse_offset(offset).map(|offset| offset.local_minus_utc() / 60).unwrap_or(0);
+        put_hash(out, &entry.old);
+        put_hash(out, &entry.new);
+        put_string(out, name);
+        put_string(out, email.trim_end_matches('>'));
+        put_varint(out, timestamp as u64);
+        out.extend_from_slice(&(offset as i16).to_be_bytes());
+        // Messages are stored with a single trailing newline, as git writes them
+        put_string(out, &format!("{}\n", entry.message));
+    }
+
+    fn decode(key: &[u8], value_type: u8, data: &[u8], pos: &mut usize, _min_update_index: u64) -> Option<Self> {
+        let (name, update_index) = key.split_at(key.len().checked_sub(9)?);
+        let update_index = u64::MAX - u64::from_be_bytes(update_index[1..].try_into().ok()?);
+        let name = String::from_utf8_lossy(name).to_string();
+        if value_type == 0 {
+            return Some(Self { name, update_index, entry: None });
+        }
+        let old = get_hash(data, pos)?;
+        let new = get_hash(data, pos)?;
+        let ident_name = get_string(data, pos)?;
+        let email = get_string(data, pos)?;
+        let timestamp = get_varint(data, pos)?;
+        let offset = i16::from_be_bytes(get_bytes(data, pos, 2)?.try_into().ok()?);
+        let message = get_string(data, pos)?;
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.unsigned_abs();
+        let identity = format!("{} <{}> {} {}{:02}{:02}", ident_name, email, timestamp, sign, offset / 60, offset % 60);
+        let entry = ReflogEntry::new(old, new, identity, message.trim_end_matches('\n').to_string());
+        Some(Self { name, update_index, entry: Some(entry) })
+    }
+}
+
+/// An object record: the positions of the ref blocks with refs pointing at objects whose
+/// ids start with `prefix`. The positions are left out when they don't fit in a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjRecord {
+    pub prefix: Vec<u8>,
+    pub positions: Vec<u64>,
+}
+
+impl Record for ObjRecord {
+    fn key(&self) -> Vec<u8> {
+        self.prefix.clone()
+    }
+
+    /// Counts of 1 to 7 positions are stored here, and other counts before the positions.
+    fn value_type(&self) -> u8 {
+        if self.positions.len() < 8 { self.positions.len() as u8 } else { 0 }
+    }
+
+    fn encode_value(&self, out: &mut Vec<u8>, _min_update_index: u64) {
+        if self.value_type() == 0 {
+            put_varint(out, self.positions.len() as u64);
+        }
+        // Positions are sorted, and each after the first is stored as the distance from the last
+        let mut last = 0;
+        for position in self.positions.iter() {
+            put_varint(out, position - last);
+            last = *position;
+        }
+    }
+
+    fn decode(key: &[u8], value_type: u8, data: &[u8], pos: &mut usize, _min_update_index: u64) -> Option<Self> {
+        let count = if value_type == 0 { get_varint(data, pos)? } else { value_type as u64 };
+        let mut positions = Vec::new();
+        let mut last = 0u64;
+        for _ in 0..count {
+            last = last.checked_add(get_varint(data, pos)?)?;
+            positions.push(last);
+        }
+        Some(Self { prefix: key.to_vec(), positions })
+    }
+}
+
+/// An index record: the position of a block and the last key in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRecord {
+    pub last_key: Vec<u8>,
+    pub position: u64,
+}
+
+impl Record for IndexRecord {
+    fn key(&self) -> Vec<u8> {
+        self.last_key.clone()
+    }
+
+    fn value_type(&self) -> u8 {
+        0
+    }
+
+    fn encode_value(&self, out: &mut Vec<u8>, _min_update_index: u64) {
+        put_varint(out, self.position);
+    }
+
+    fn decode(key: &[u8], value_type: u8, data: &[u8], pos: &mut usize, _min_update_index: u64) -> Option<Self> {
+        if value_type != 0 {
+            return None;
+        }
+        Some(Self { last_key: key.to_vec(), position: get_varint(data, pos)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_varint(&mut out, value);
+        out
+    }
+
+    #[test]
+    fn varints_use_the_pack_offset_encoding() {
+        assert_eq!(varint(0), [0x00]);
+        assert_eq!(varint(127), [0x7f]);
+        assert_eq!(varint(128), [0x80, 0x00]);
+        assert_eq!(varint(4096), [0x9f, 0x00]);
+        assert_eq!(varint(16511), [0xff, 0x7f]);
+        assert_eq!(varint(16512), [0x80, 0x80, 0x00]);
+        for value in [0, 1, 127, 128, 300, 16511, 16512, 1 << 32, u64::MAX] {
+            let encoded = varint(value);
+            let mut pos = 0;
+            assert_eq!(get_varint(&encoded, &mut pos), Some(value));
+            assert_eq!(pos, encoded.len());
+        }
+    }
+
+    #[test]
+    fn truncated_varints_are_rejected() {
+        let mut pos = 0;
+        assert_eq!(get_varint(&[0x80], &mut pos), None);
+        assert_eq!(get_varint(&[], &mut 0), None);
+    }
+
+    #[test]
+    fn object_records_count_few_positions_in_the_value_type() {
+        let record = ObjRecord { prefix: vec![0xab, 0xcd], positions: vec![0, 4096, 8192] };
+        let mut value = Vec::new();
+        record.encode_value(&mut value, 0);
+        assert_eq!(record.value_type(), 3);
+        assert_eq!(value, [0x00, 0x9f, 0x00, 0x9f, 0x00]);
+        assert_eq!(ObjRecord::decode(&record.key(), 3, &value, &mut 0, 0), Some(record));
+    }
+
+    #[test]
+    fn object_records_store_larger_counts_before_the_positions() {
+        let record = ObjRecord { prefix: vec![0x01, 0x02], positions: (0..8).map(|i| i * 4096).collect() };
+        let mut value = Vec::new();
+        record.encode_value(&mut value, 0);
+        assert_eq!(record.value_type(), 0);
+        assert_eq!(value[0], 8);
+        assert_eq!(ObjRecord::decode(&record.key(), 0, &value, &mut 0, 0), Some(record));
+        let empty = ObjRecord { prefix: vec![0x01, 0x02], positions: Vec::new() };
+        let mut value = Vec::new();
+        empty.encode_value(&mut value, 0);
+        assert_eq!((empty.value_type(), value), (0, vec![0]));
+    }
+
+    #[test]
+    fn ref_records_round_trip() {
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        let values = [
+            RefRecordValue::Deletion,
+            RefRecordValue::Direct(hash.to_string()),
+            RefRecordValue::Peeled(hash.to_string(), "89abcdef0123456789abcdef0123456789abcdef".to_string()),
+            RefRecordValue::Symbolic("refs/heads/main".to_string()),
+        ];
+        for value in values {
+            let record = RefRecord::new("refs/heads/topic", 7, value);
+            let mut encoded = Vec::new();
+            record.encode_value(&mut encoded, 5);
+            // The update index is stored relative to the table's minimum
+            assert_eq!(encoded[0], 2);
+            let decoded = RefRecord::decode(&record.key(), record.value_type(), &encoded, &mut 0, 5).unwrap();
+            assert_eq!((decoded.name, decoded.update_index, decoded.value), (record.name, 7, record.value));
+        }
+    }
+
+    #[test]
+    fn log_keys_sort_newest_first() {
+        let older = LogRecord::new("HEAD", 1, None).key();
+        let newer = LogRecord::new("HEAD", 2, None).key();
+        assert!(newer < older);
+        let decoded = LogRecord::decode(&newer, 0, &[], &mut 0, 0).unwrap();
+        assert_eq!((decoded.name.as_str(), decoded.update_index), ("HEAD", 2));
+    }
+}
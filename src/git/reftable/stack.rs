@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git::reflog::ReflogEntry;
+use crate::git::reftable::record::{LogRecord, RefRecord, RefRecordValue};
+use crate::git::reftable::table::Table;
+
+pub const REFTABLE_DIR: &str = ".git/reftable";
+const TABLES_LIST_PATH: &str = ".git/reftable/tables.list";
+
+/// The tables named in `tables.list`, oldest first. Records in newer tables override those
+/// with the same key in older ones, and deletion records hide them.
+pub struct Stack {
+    names: Vec<String>,
+    tables: Vec<Table>,
+}
+
+impl Stack {
+    fn read_names() -> Vec<String> {
+        fs::read_to_string(TABLES_LIST_PATH).unwrap_or_default().lines().map(|name| name.to_string()).collect()
+    }
+
+    /// Reads the tables in `tables.list`. Another process compacting the stack may remove
+    /// tables after the list was read, and then the list has changed and is read again.
+    pub fn read() -> Self {
+        let mut names = Self::read_names();
+        loop {
+            let mut tables = Vec::new();
+            for name in names.iter() {
+                match Table::read(&format!("{}/{}", REFTABLE_DIR, name)) {
+                    Some(table) => tables.push(table),
+                    None => break,
+                }
+            }
+            if tables.len() == names.len() {
+                return Self { names, tables };
+            }
+            let current = Self::read_names();
+            if current == names {
+                println!("fatal: could not read reftable '{}'", names[tables.len()]);
+                process::exit(128);
+            }
+            names = current;
+        }
+    }
+
+    pub fn next_update_index(&self) -> u64 {
+        self.tables.last().map(|table| table.max_update_index + 1).unwrap_or(1)
+    }
+
+    pub fn ref_value(&self, name: &str) -> Option<&RefRecordValue> {
+        // Each table's refs are sorted by name
+        let record = self.tables.iter().rev().find_map(|table| {
+            table.refs.binary_search_by(|record| record.name.as_str().cmp(name)).ok().map(|i| &table.refs[i])
+        })?;
+        if record.value == RefRecordValue::Deletion { None } else { Some(&record.value) }
+    }
+
+    /// Every ref that exists whose name starts with `prefix`, sorted by name.
+    pub fn refs(&self, prefix: &str) -> Vec<RefRecord> {
+        let mut merged = BTreeMap::new();
+        for table in self.tables.iter() {
+            let start = table.refs.partition_point(|record| record.name.as_str() < prefix);
+            for record in table.refs[start..].iter().take_while(|record| record.name.starts_with(prefix)) {
+                merged.insert(record.name.clone(), record);
+            }
+        }
+        merged.into_values().filter(|record| record.value != RefRecordValue::Deletion).cloned().collect()
+    }
+
+    fn merged_logs(&self) -> BTreeMap<(&str, u64), Option<&ReflogEntry>> {
+        let mut merged = BTreeMap::new();
+        for log in self.tables.iter().flat_map(|table| table.logs.iter()) {
+            merged.insert((log.name.as_str(), log.update_index), log.entry.as_ref());
+        }
+        merged
+    }
+
+    /// The reflog of `name` with its update indexes, oldest first.
+    pub fn logs(&self, name: &str) -> Vec<(u64, ReflogEntry)> {
+        self.merged_logs().into_iter()
+            .filter(|((log_name, _), _)| *log_name == name)
+            .filter_map(|((_, update_index), entry)| Some((update_index, entry?.clone())))
+            .collect()
+    }
+
+    /// Every ref with a reflog, sorted by name.
+    pub fn log_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.merged_logs().into_iter().filter(|(_, entry)| entry.is_some()).map(|((name, _), _)| name.to_string()).collect();
+        names.dedup();
+        names
+    }
+
+    /// Takes `tables.list.lock`, returning its path as the error if it is already held.
+    pub fn lock() -> Result<(), String> {
+        let lock_path = format!("{}.lock", TABLES_LIST_PATH);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(lock_path),
+        }
+    }
+
+    pub fn unlock() {
+        let _ = fs::remove_file(format!("{}.lock", TABLES_LIST_PATH));
+    }
+
+    fn write_table(table: &Table) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let name = format!("0x{:012x}-0x{:012x}-{:08x}.ref", table.min_update_index, table.max_update_index, nanos ^ process::id());
+        let path = format!("{}/{}", REFTABLE_DIR, name);
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, table.serialize()).unwrap();
+        fs::rename(&temp_path, &path).unwrap();
+        name
+    }
+
+    /// Merges `tables[start..]` into one table. Deletion records are only kept while older
+    /// tables remain for them to hide records in.
+    fn merge(&self, start: usize) -> Table {
+        let tables = &self.tables[start..];
+        let mut refs = BTreeMap::new();
+        let mut logs = BTreeMap::new();
+        for table in tables {
+            refs.extend(table.refs.iter().map(|record| (record.name.clone(), record.clone())));
+            logs.extend(table.logs.iter().map(|log| ((log.name.clone(), log.update_index), log.clone())));
+        }
+        let mut refs: Vec<RefRecord> = refs.into_values().collect();
+        let mut logs: Vec<LogRecord> = logs.into_values().collect();
+        if start == 0 {
+            refs.retain(|record| record.value != RefRecordValue::Deletion);
+            logs.retain(|log| log.entry.is_some());
+        }
+        Table::new(tables[0].min_update_index, tables[tables.len() - 1].max_update_index, refs, logs)
+    }
+
+    /// Keeps the stack short by merging the newest tables until each table has at least
+    /// twice as many records as all the tables above it, as git's auto-compaction does.
+    fn compact(&mut self) -> Vec<String> {
+        let size = |table: &Table| table.refs.len() + table.logs.len();
+        let mut start = self.tables.len() - 1;
+        let mut records = size(&self.tables[start]);
+        while start > 0 && size(&self.tables[start - 1]) < 2 * records {
+            start -= 1;
+            records += size(&self.tables[start]);
+        }
+        if start + 1 == self.tables.len() {
+            return Vec::new();
+        }
+        let table = self.merge(start);
+        let name = Self::write_table(&table);
+        self.tables.truncate(start);
+        self.tables.push(table);
+        let removed = self.names.split_off(start);
+        self.names.push(name);
+        removed
+    }
+
+    /// Writes the records as a new table on top of the stack, through the lock taken by
+    /// `lock`. Records should use `next_update_index` or, for deletions of reflog entries,
+    /// the index of the entry they delete.
+    pub fn add_table(&mut self, refs: Vec<RefRecord>, logs: Vec<LogRecord>) {
+        let min_update_index = self.next_update_index();
+        let max_update_index = refs.iter().map(|record| record.update_index).chain(logs.iter().map(|log| log.update_index)).fold(min_update_index, u64::max);
+        let table = Table::new(min_update_index, max_update_index, refs, logs);
+        self.names.push(Self::write_table(&table));
+        self.tables.push(table);
+        let removed = self.compact();
+        let lock_path = format!("{}.lock", TABLES_LIST_PATH);
+        let list: String = self.names.iter().map(|name| format!("{}\n", name)).collect();
+        fs::write(&lock_path, list).unwrap();
+        fs::rename(&lock_path, TABLES_LIST_PATH).unwrap();
+        for name in removed {
+            let _ = fs::remove_file(format!("{}/{}", REFTABLE_DIR, name));
+        }
+    }
+
+    /// Adds a table outside of a ref transaction, building its records from the stack as it
+    /// is once locked and the update index they should use.
+    pub fn update(build: impl FnOnce(&Stack, u64) -> (Vec<RefRecord>, Vec<LogRecord>)) {
+        if let Err(lock_path) = Self::lock() {
+            println!("fatal: Unable to create '{}': File exists.", lock_path);
+            process::exit(128);
+        }
+        let mut stack = Self::read();
+        let (refs, logs) = build(&stack, stack.next_update_index());
+        stack.add_table(refs, logs);
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::git::reftable::block::{Block, BlockWriter, BLOCK_TYPE_INDEX, BLOCK_TYPE_LOG, BLOCK_TYPE_OBJ, BLOCK_TYPE_REF};
+use crate::git::reftable::record::{IndexRecord, LogRecord, ObjRecord, Record, RefRecord, RefRecordValue};
+
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Sizes of the header and footer for format versions 1 and 2. Version 2 adds a 4-byte
+/// hash function id to both.
+fn header_size(version: u8) -> Option<usize> {
+    match version {
+        1 => Some(24),
+        2 => Some(28),
+        _ => None,
+    }
+}
+
+fn footer_size(version: u8) -> Option<usize> {
+    Some(header_size(version)? + 44)
+}
+
+fn get_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+/// Lays out a table's blocks one after another. Blocks other than log blocks are padded to
+/// the block size, but as git does, the padding is only written once another block follows.
+struct TableWriter {
+    out: Vec<u8>,
+    header_size: usize,
+    padding: usize,
+}
+
+impl TableWriter {
+    /// Where the next block starts. The first block starts with the file header.
+    fn next_position(&self) -> u64 {
+        if self.out.len() == self.header_size { 0 } else { (self.out.len() + self.padding) as u64 }
+    }
+
+    fn new_block(&self, kind: u8) -> BlockWriter {
+        BlockWriter::new(kind, if self.next_position() == 0 { self.header_size } else { 0 }, BLOCK_SIZE)
+    }
+
+    fn flush(&mut self, kind: u8, writer: BlockWriter, index: &mut Vec<IndexRecord>) {
+        let position = self.next_position();
+        index.push(IndexRecord { last_key: writer.last_key().to_vec(), position });
+        self.out.resize(self.out.len() + self.padding, 0);
+        self.out.extend(writer.finish());
+        let len = self.out.len() - position as usize;
+        self.padding = if kind == BLOCK_TYPE_LOG { 0 } else { BLOCK_SIZE.saturating_sub(len) };
+    }
+
+    /// Writes the records in as many blocks as they need, returning an index record for each
+    /// block and the position of the block each record went in.
+    fn write_blocks<R: Record>(&mut self, kind: u8, records: &[R], min_update_index: u64) -> (Vec<IndexRecord>, Vec<u64>) {
+        let mut index = Vec::new();
+        let mut positions = Vec::new();
+        let mut writer = self.new_block(kind);
+        for record in records {
+            if !writer.add(record, min_update_index) {
+                self.flush(kind, writer, &mut index);
+                writer = self.new_block(kind);
+                writer.add(record, min_update_index);
+            }
+            positions.push(self.next_position());
+        }
+        if !writer.is_empty() {
+            self.flush(kind, writer, &mut index);
+        }
+        (index, positions)
+    }
+
+    /// Indexes a section of more than three blocks, returning where the index starts. An
+    /// index of more than three blocks is indexed in turn, and then the position is that of
+    /// the top level, which readers start from.
+    fn write_index(&mut self, mut index: Vec<IndexRecord>) -> u64 {
+        let mut position = 0;
+        while index.len() > 3 {
+            position = self.next_position();
+            index = self.write_blocks(BLOCK_TYPE_INDEX, &index, 0).0;
+        }
+        position
+    }
+}
+
+/// Maps the ids refs point at to the ref blocks they are in, with the ids cut to the length
+/// that tells them apart, but at least 2 bytes.
+fn object_records(refs: &[RefRecord], positions: &[u64]) -> (Vec<ObjRecord>, usize) {
+    let mut ids: BTreeMap<Vec<u8>, Vec<u64>> = BTreeMap::new();
+    for (record, position) in refs.iter().zip(positions) {
+        let hashes = match &record.value {
+            RefRecordValue::Direct(hash) => vec![hash],
+            RefRecordValue::Peeled(hash, peeled) => vec![hash, peeled],
+            _ => Vec::new(),
+        };
+        for hash in hashes {
+            let block_positions = ids.entry(hex::decode(hash).unwrap()).or_default();
+            if block_positions.last() != Some(position) {
+                block_positions.push(*position);
+            }
+        }
+    }
+    let keys: Vec<&Vec<u8>> = ids.keys().collect();
+    let common = keys.windows(2).map(|pair| pair[0].iter().zip(pair[1].iter()).take_while(|(a, b)| a == b).count());
+    let id_len = common.fold(1, usize::max) + 1;
+    let objects = ids.into_iter().map(|(id, positions)| {
+        let mut record = ObjRecord { prefix: id[..id_len].to_vec(), positions };
+        let mut value = Vec::new();
+        record.encode_value(&mut value, 0);
+        // Leaving room for the block's header, the key's lengths and a restart
+        if id_len + value.len() + 16 > BLOCK_SIZE {
+            record.positions.clear();
+        }
+        record
+    }).collect();
+    (objects, id_len)
+}
+
+/// Decodes a block's records into `records`, returning the last key in the block.
+fn read_records<R: Record>(block: &Block, min_update_index: u64, records: &mut Vec<R>) -> Option<Vec<u8>> {
+    let block_records = block.records::<R>(min_update_index)?;
+    let last_key = block_records.last()?.key();
+    records.extend(block_records);
+    Some(last_key)
+}
+
+/// One reftable file: ref records sorted by name followed by log records sorted by name
+/// and then newest first. Tables are written with object blocks and indexes as git writes
+/// them, and these are checked when reading, but as every record is kept in memory they
+/// aren't used for lookups.
+#[derive(Debug, Default)]
+pub struct Table {
+    pub min_update_index: u64,
+    pub max_update_index: u64,
+    pub refs: Vec<RefRecord>,
+    pub logs: Vec<LogRecord>,
+}
+
+impl Table {
+    pub fn new(min_update_index: u64, max_update_index: u64, mut refs: Vec<RefRecord>, mut logs: Vec<LogRecord>) -> Self {
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        logs.sort_by_key(|log| log.key());
+        Self { min_update_index, max_update_index, refs, logs }
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..4)? != b"REFT" {
+            return None;
+        }
+        let version = *data.get(4)?;
+        let header_size = header_size(version)?;
+        let footer_start = data.len().checked_sub(footer_size(version)?)?;
+        if footer_start < header_size || data[footer_start..footer_start + header_size] != data[..header_size] {
+            return None;
+        }
+        let crc_start = data.len() - 4;
+        if crc32fast::hash(&data[footer_start..crc_start]) != u32::from_be_bytes(data[crc_start..].try_into().ok()?) {
+            return None;
+        }
+        let mut table = Self { min_update_index: get_u64(data, 8)?, max_update_index: get_u64(data, 16)?, ..Self::default() };
+        // The kind and last key of the block at each position
+        let mut blocks = HashMap::new();
+        let (mut objects, mut indexes) = (Vec::new(), Vec::new());
+        let mut offset = 0;
+        while offset + if offset == 0 { header_size } else { 0 } < footer_start {
+            let block = Block::read(&data[..footer_start], offset, if offset == 0 { header_size } else { 0 })?;
+            let last_key = match block.kind {
+                BLOCK_TYPE_REF => read_records(&block, table.min_update_index, &mut table.refs)?,
+                BLOCK_TYPE_LOG => read_records(&block, table.min_update_index, &mut table.logs)?,
+                BLOCK_TYPE_OBJ => read_records(&block, 0, &mut objects)?,
+                BLOCK_TYPE_INDEX => read_records(&block, 0, &mut indexes)?,
+                _ => return None,
+            };
+            blocks.insert(offset as u64, (block.kind, last_key));
+            offset = block.end;
+            // Blocks other than log blocks may be padded to the block size
+            while offset < footer_start && data[offset] == 0 {
+                offset += 1;
+            }
+        }
+        // Index records name the last key of their block, and object records ref blocks
+        let kind_at = |position: u64| blocks.get(&position).map(|(kind, _)| *kind);
+        if !indexes.iter().all(|index: &IndexRecord| blocks.get(&index.position).is_some_and(|(_, key)| *key == index.last_key)) {
+            return None;
+        }
+        let footer = footer_start + header_size;
+        let obj_id_len = (get_u64(data, footer + 8)? & 0x1f) as usize;
+        let object_valid = |object: &ObjRecord| object.prefix.len() == obj_id_len && object.positions.iter().all(|position| kind_at(*position) == Some(BLOCK_TYPE_REF));
+        if !objects.iter().all(object_valid) {
+            return None;
+        }
+        // Sections start where the footer says, with 0 for those a table doesn't have
+        let sections = [(0, BLOCK_TYPE_INDEX), (8, BLOCK_TYPE_OBJ), (16, BLOCK_TYPE_INDEX), (24, BLOCK_TYPE_LOG), (32, BLOCK_TYPE_INDEX)];
+        for (field, kind) in sections {
+            let position = get_u64(data, footer + field)?;
+            let position = if field == 8 { position >> 5 } else { position };
+            if position != 0 && kind_at(position) != Some(kind) {
+                return None;
+            }
+        }
+        Some(table)
+    }
+
+    pub fn read(path: &str) -> Option<Self> {
+        Self::parse(&fs::read(path).ok()?)
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = b"REFT".to_vec();
+        header.push(1);
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+        header.extend_from_slice(&self.min_update_index.to_be_bytes());
+        header.extend_from_slice(&self.max_update_index.to_be_bytes());
+        header
+    }
+
+    /// Encodes the table in version 1 of the format. Refs are indexed when they take more
+    /// than three blocks, and then followed by object records, which are indexed likewise,
+    /// and logs come last with their own index.
+    pub fn serialize(&self) -> Vec<u8> {
+        let header = self.header();
+        let mut writer = TableWriter { header_size: header.len(), out: header, padding: 0 };
+        let (ref_blocks, ref_positions) = writer.write_blocks(BLOCK_TYPE_REF, &self.refs, self.min_update_index);
+        let ref_index_position = writer.write_index(ref_blocks);
+        let (mut obj_position, mut obj_id_len, mut obj_index_position) = (0, 0, 0);
+        if ref_index_position != 0 {
+            let (objects, id_len) = object_records(&self.refs, &ref_positions);
+            obj_id_len = id_len as u64;
+            if !objects.is_empty() {
+                obj_position = writer.next_position();
+            }
+            let obj_blocks = writer.write_blocks(BLOCK_TYPE_OBJ, &objects, 0).0;
+            obj_index_position = writer.write_index(obj_blocks);
+        }
+        let log_position = if self.logs.is_empty() { 0 } else { writer.next_position() };
+        let log_blocks = writer.write_blocks(BLOCK_TYPE_LOG, &self.logs, self.min_update_index).0;
+        let log_index_position = writer.write_index(log_blocks);
+        let mut out = writer.out;
+        let mut footer = self.header();
+        for field in [ref_index_position, (obj_position << 5) | obj_id_len, obj_index_position, log_position, log_index_position] {
+            footer.extend_from_slice(&field.to_be_bytes());
+        }
+        footer.extend_from_slice(&crc32fast::hash(&footer).to_be_bytes());
+        out.extend(footer);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table holding only `HEAD` pointing at `refs/heads/main`, laid out by hand from the
+    /// reftable format documentation: the header, one unpadded ref block with a single
+    /// restart, and the footer with no indexes, objects or logs.
+    const SYMREF_TABLE: &str = concat!(
+        "5245465401001000", "0000000000000001", "0000000000000001",
+        "72000038", "00234845414400", "0f", "726566732f68656164732f6d61696e", "00001c", "0001",
+        "5245465401001000", "0000000000000001", "0000000000000001",
+        "0000000000000000", "0000000000000000", "0000000000000000", "0000000000000000", "0000000000000000",
+        "b6bff78a",
+    );
+
+    fn footer_field(data: &[u8], field: usize) -> u64 {
+        get_u64(data, data.len() - 44 + field).unwrap()
+    }
+
+    fn tagged(count: usize, suffix: &str) -> Table {
+        let refs = (0..count).map(|i| {
+            RefRecord::new(&format!("refs/tags/t{:06}{}", i, suffix), 1, RefRecordValue::Direct(format!("{:08x}{:032x}", i * 2654435761 % (1 << 32), i)))
+        }).collect();
+        Table::new(1, 1, refs, Vec::new())
+    }
+
+    #[test]
+    fn symref_table_matches_the_documented_layout() {
+        let data = hex::decode(SYMREF_TABLE).unwrap();
+        let table = Table::parse(&data).unwrap();
+        assert_eq!((table.min_update_index, table.max_update_index), (1, 1));
+        assert_eq!(table.refs.len(), 1);
+        assert_eq!((table.refs[0].name.as_str(), &table.refs[0].value), ("HEAD", &RefRecordValue::Symbolic("refs/heads/main".to_string())));
+        assert_eq!(table.serialize(), data);
+    }
+
+    #[test]
+    fn corrupt_tables_are_rejected() {
+        let mut data = hex::decode(SYMREF_TABLE).unwrap();
+        let len = data.len();
+        data[len - 1] ^= 1;
+        assert!(Table::parse(&data).is_none());
+        let mut data = hex::decode(SYMREF_TABLE).unwrap();
+        // An unknown block type
+        data[24] = b'x';
+        assert!(Table::parse(&data).is_none());
+    }
+
+    #[test]
+    fn small_tables_have_no_indexes_or_objects() {
+        let data = tagged(100, "").serialize();
+        for field in [0, 8, 16, 24, 32] {
+            assert_eq!(footer_field(&data, field), 0);
+        }
+    }
+
+    #[test]
+    fn large_tables_index_refs_and_objects() {
+        let table = tagged(2000, "");
+        let data = table.serialize();
+        let ref_index = footer_field(&data, 0) as usize;
+        let (obj_position, obj_id_len) = ((footer_field(&data, 8) >> 5) as usize, footer_field(&data, 8) & 0x1f);
+        assert!(ref_index > 0 && ref_index.is_multiple_of(BLOCK_SIZE));
+        assert!(obj_position.is_multiple_of(BLOCK_SIZE));
+        assert!(obj_position > ref_index);
+        assert!(obj_id_len >= 2);
+        // Each index record names the last ref of a block, and each object record the block
+        // its ref is in
+        let index = Block::read(&data, ref_index, 0).unwrap().records::<IndexRecord>(0).unwrap();
+        assert_eq!(index.last().unwrap().last_key, table.refs.last().unwrap().key());
+        for record in index.iter() {
+            let header_offset = if record.position == 0 { 24 } else { 0 };
+            let block = Block::read(&data, record.position as usize, header_offset).unwrap();
+            assert_eq!(block.records::<RefRecord>(1).unwrap().last().unwrap().key(), record.last_key);
+        }
+        let objects = Block::read(&data, obj_position, 0).unwrap().records::<ObjRecord>(0).unwrap();
+        let first = &table.refs[0];
+        let RefRecordValue::Direct(hash) = &first.value else { unreachable!() };
+        let prefix = &hex::decode(hash).unwrap()[..obj_id_len as usize];
+        assert_eq!(objects.iter().find(|object| object.prefix == prefix).unwrap().positions, [0]);
+        let parsed = Table::parse(&data).unwrap();
+        assert_eq!(parsed.refs.len(), 2000);
+        assert!(parsed.refs.iter().zip(table.refs.iter()).all(|(a, b)| a.name == b.name && a.value == b.value));
+    }
+
+    #[test]
+    fn large_indexes_are_indexed_in_turn() {
+        // Long names that differ early take whole index records
+        let data = tagged(2000, &"x".repeat(300)).serialize();
+        let ref_index = footer_field(&data, 0) as usize;
+        let top = Block::read(&data, ref_index, 0).unwrap().records::<IndexRecord>(0).unwrap();
+        // The top level points at index blocks
+        assert_eq!(data[top[0].position as usize], BLOCK_TYPE_INDEX);
+        assert_eq!(Table::parse(&data).unwrap().refs.len(), 2000);
+    }
+
+    #[test]
+    fn index_records_must_match_their_blocks() {
+        let mut data = tagged(2000, "").serialize();
+        let ref_index = footer_field(&data, 0) as usize;
+        let first = Block::read(&data, ref_index, 0).unwrap().records::<IndexRecord>(0).unwrap().remove(0);
+        let key = ref_index + data[ref_index..].windows(first.last_key.len()).position(|window| window == first.last_key).unwrap();
+        data[key + first.last_key.len() - 1] ^= 1;
+        let footer_start = data.len() - 68;
+        let crc = crc32fast::hash(&data[footer_start..data.len() - 4]);
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&crc.to_be_bytes());
+        assert!(Table::parse(&data).is_none());
+    }
+
+    /// Runs git in `dir`, returning whether it succeeded.
+    fn git(dir: &std::path::Path, args: &[&str], input: &str) -> bool {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+        let child = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .envs([("GIT_AUTHOR_NAME", "T"), ("GIT_AUTHOR_EMAIL", "t@e"), ("GIT_COMMITTER_NAME", "T"), ("GIT_COMMITTER_EMAIL", "t@e")])
+            .envs([("GIT_AUTHOR_DATE", "1700000000 +0000"), ("GIT_COMMITTER_DATE", "1700000000 +0000")])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return false;
+        };
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        child.wait().is_ok_and(|status| status.success())
+    }
+
+    /// Reads a stack written by git, which needs git 2.45 or later for `--ref-format`. The
+    /// first table is large enough for an indexed ref section, objects and logs, and the ones
+    /// after it are kept apart by turning off auto-compaction. The test passes without
+    /// checking anything when git can't write reftables.
+    #[test]
+    fn tables_written_by_git_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rgit-reftable-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        if !git(&dir, &["init", "-q", "--ref-format=reftable"], "") {
+            fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        assert!(git(&dir, &["config", "reftable.autoCompaction", "false"], ""));
+        assert!(git(&dir, &["config", "core.logAllRefUpdates", "always"], ""));
+        assert!(git(&dir, &["commit", "-q", "--allow-empty", "-m", "a"], ""));
+        let tags: String = (0..2000).map(|i| format!("create refs/tags/t{:06} HEAD\n", i)).collect();
+        assert!(git(&dir, &["update-ref", "-m", "tags", "--stdin"], &tags));
+        assert!(git(&dir, &["branch", "side"], ""));
+        assert!(git(&dir, &["tag", "-d", "t000000"], ""));
+        assert!(git(&dir, &["symbolic-ref", "HEAD", "refs/heads/side"], ""));
+
+        let reftable = dir.join(".git/reftable");
+        let names: Vec<String> = fs::read_to_string(reftable.join("tables.list")).unwrap().lines().map(|name| name.to_string()).collect();
+        assert!(names.len() > 3);
+        let mut indexed = false;
+        for name in names {
+            let data = fs::read(reftable.join(&name)).unwrap();
+            let table = Table::parse(&data).unwrap_or_else(|| panic!("{} doesn't parse", name));
+            assert_eq!(table.serialize(), data, "{} doesn't round trip", name);
+            indexed |= [0, 8, 24].iter().all(|&field| footer_field(&data, field) != 0);
+        }
+        assert!(indexed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
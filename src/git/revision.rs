@@ -0,0 +1,1214 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::process;
+
+use regex::Regex;
+
+use crate::git::commit_graph::CommitGraph;
+use crate::git::config::Config;
+use crate::git::ident::Signature;
+use crate::git::index::Index;
+use crate::git::object::commit::Commit;
+use crate::git::object::objectreader::ObjectReader;
+use crate::git::object::tag::Tag;
+use crate::git::object::tree::Tree;
+use crate::git::pathspec::Pathspec;
+use crate::git::reflog;
+use crate::git::refs;
+use crate::git::tree_diff;
+use crate::object_finder;
+
+/// Abbreviated object ids shorter than this are never looked up.
+const MIN_ABBREV: usize = 4;
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 40 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Prints git's message for an argument that is neither a revision nor a path, and exits.
+pub fn die_unknown_revision(arg: &str) -> ! {
+    println!("fatal: ambiguous argument '{}': unknown revision or path not in the working tree.", arg);
+    println!("Use '--' to separate paths from revisions, like this:");
+    println!("'git <command> [<revision>...] -- [<file>...]'");
+    process::exit(128);
+}
+
+/// Expands a ref shorthand such as `main` or `tags/v1` to the first existing full ref name,
+/// trying the same prefixes as git in the same order.
+pub fn expand_ref_name(name: &str) -> Option<String> {
+    if name == "@" {
+        return Some("HEAD".to_string());
+    }
+    let mut candidates = Vec::new();
+    // Only names like HEAD or ORIG_HEAD are looked up directly at the top of .git
+    if name.starts_with("refs/") || name.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+        candidates.push(name.to_string());
+    }
+    for prefix in ["refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+        candidates.push(format!("{}{}", prefix, name));
+    }
+    candidates.push(format!("refs/remotes/{}/HEAD", name));
+    candidates.into_iter().find(|candidate| refs::read_ref(candidate).is_some())
+}
+
+/// The branch checked out `n` switches ago, read from the `checkout: moving from` entries
+/// of the HEAD reflog. This is a branch name or, after a detached checkout, an object id.
+pub fn previous_branch(n: usize) -> Option<String> {
+    let entries = reflog::read("HEAD");
+    entries.iter().rev()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .nth(n.checked_sub(1)?)
+        .and_then(|moved| moved.split_once(" to "))
+        .map(|(from, _)| from.to_string())
+}
+
+fn current_branch_ref() -> Option<String> {
+    match refs::resolve_ref("HEAD")? {
+        (name, _) if name != "HEAD" => Some(name),
+        _ => None,
+    }
+}
+
+/// The ref `branch` is configured to follow through `branch.<name>.remote` and
+/// `branch.<name>.merge`, which is a remote-tracking ref unless the remote is `.`.
+pub fn upstream(branch: &str) -> Option<String> {
+    let config = Config::read();
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    if remote == "." {
+        return Some(merge);
+    }
+    Some(format!("refs/remotes/{}/{}", remote, merge.strip_prefix("refs/heads/").unwrap_or(&merge)))
+}
+
+/// The value of `ref_name` as of `spec` in its reflog: the `n`th previous value for a number,
+/// otherwise the value it had at that date.
+fn reflog_lookup(ref_name: &str, spec: &str) -> Option<String> {
+    let entries = reflog::read(ref_name);
+    if let Ok(n) = spec.parse::<usize>() {
+        return entries.iter().rev().nth(n).map(|entry| entry.new.clone());
+    }
+    let date = reflog::parse_date(spec)?;
+    match entries.iter().rev().find(|entry| entry.timestamp() <= date) {
+        Some(entry) => Some(entry.new.clone()),
+        // Before the log starts, the oldest known value is the best answer
+        None => entries.first().map(|entry| if entry.old == reflog::NULL_HASH { entry.new.clone() } else { entry.old.clone() }),
+    }
+}
+
+/// Resolves the part of a revision before any `~` or `^` suffix to the full name of the ref
+/// it refers to, for `@{-<n>}`, `<branch>@{upstream}` and plain ref names.
+pub fn resolve_ref_name(base: &str) -> Option<String> {
+    let base = if base == "-" { "@{-1}" } else { base };
+    if let Some(n) = base.strip_prefix("@{-").and_then(|rest| rest.strip_suffix('}')) {
+        let branch = previous_branch(n.parse().ok()?)?;
+        return Some(format!("refs/heads/{}", branch)).filter(|name| refs::read_ref(name).is_some());
+    }
+    if let Some((name, spec)) = base.split_once("@{") {
+        let spec = spec.strip_suffix('}')?.to_lowercase();
+        if spec != "u" && spec != "upstream" {
+            return None;
+        }
+        let branch_ref = if name.is_empty() || name == "HEAD" { current_branch_ref()? } else { expand_ref_name(name)? };
+        let branch = branch_ref.strip_prefix("refs/heads/").unwrap_or(&branch_ref);
+        return match upstream(branch) {
+            Some(upstream) => Some(upstream),
+            None => {
+                println!("fatal: no upstream configured for branch '{}'", branch);
+                process::exit(128);
+            }
+        };
+    }
+    let name = expand_ref_name(base)?;
+    match name.as_str() {
+        "HEAD" => Some(current_branch_ref().unwrap_or(name)),
+        _ => Some(name),
+    }
+}
+
+fn resolve_base(base: &str) -> Option<String> {
+    if is_hash(base) {
+        return Some(base.to_lowercase());
+    }
+    if let Some(n) = base.strip_prefix("@{-").and_then(|rest| rest.strip_suffix('}')) {
+        let previous = previous_branch(n.parse().ok()?)?;
+        return resolve_base(&previous);
+    }
+    if let Some((name, spec)) = base.split_once("@{") {
+        let spec = spec.strip_suffix('}')?;
+        let lowercase_spec = spec.to_lowercase();
+        if !matches!(lowercase_spec.as_str(), "u" | "upstream") {
+            // `@{<n>}` on its own reads the current branch's reflog rather than HEAD's
+            let ref_name = match name {
+                "" => current_branch_ref().unwrap_or("HEAD".to_string()),
+                "HEAD" | "@" => "HEAD".to_string(),
+                _ => expand_ref_name(name)?,
+            };
+            return reflog_lookup(&ref_name, spec);
+        }
+        return refs::read_ref(&resolve_ref_name(base)?);
+    }
+    if let Some(name) = expand_ref_name(base) {
+        return refs::read_ref(&name);
+    }
+    if base.len() >= MIN_ABBREV && base.chars().all(|c| c.is_ascii_hexdigit()) {
+        let matches = object_finder::find_objects_by_prefix(base);
+        if matches.len() > 1 {
+            println!("error: short object ID {} is ambiguous", base);
+        }
+        if matches.len() == 1 {
+            return matches.into_iter().next();
+        }
+    }
+    None
+}
+
+pub fn object_type(hash: &str) -> Option<&'static str> {
+    if !object_finder::object_exists(hash) {
+        return None;
+    }
+    Some(match ObjectReader::find_object_type(hash) {
+        "blob" => "blob",
+        "tree" => "tree",
+        "tag" => "tag",
+        _ => "commit",
+    })
+}
+
+/// Follows tags, and commits to their trees, until reaching an object of `kind`. An empty
+/// `kind` peels tags only, `object` accepts anything.
+pub fn peel(hash: &str, kind: &str) -> Option<String> {
+    let mut hash = hash.to_string();
+    loop {
+        let current = object_type(&hash)?;
+        if current == kind || kind == "object" || (kind.is_empty() && current != "tag") {
+            return Some(hash);
+        }
+        hash = match current {
+            "tag" => Tag::from_hash(&hash).object,
+            "commit" if kind == "tree" => Commit::from_hash(&hash).tree,
+            _ => return None,
+        };
+    }
+}
+
+fn find_by_message(starts: Vec<String>, pattern: &str) -> Option<String> {
+    let regex = Regex::new(pattern).ok()?;
+    CommitWalk::new(&starts, &[]).find(|commit| regex.is_match(&commit.message())).map(|commit| commit.hash)
+}
+
+fn lookup_path(tree_hash: &str, path: &str) -> Option<String> {
+    let mut hash = tree_hash.to_string();
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let tree = Tree::from_hash(&peel(&hash, "tree")?);
+        hash = tree.entries.into_iter().find(|entry| entry.name == component)?.hash;
+    }
+    Some(hash)
+}
+
+fn apply_suffixes(mut hash: String, suffixes: &str) -> Option<String> {
+    let mut chars = suffixes.chars().peekable();
+    while let Some(operator) = chars.next() {
+        if operator == '^' && chars.peek() == Some(&'{') {
+            chars.next();
+            let mut kind = String::new();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                kind.push(c);
+            }
+            hash = match kind.strip_prefix('/') {
+                Some(pattern) => find_by_message(vec![peel(&hash, "commit")?], pattern)?,
+                None => peel(&hash, &kind)?,
+            };
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            chars.next();
+        }
+        let n: usize = if digits.is_empty() { 1 } else { digits.parse().ok()? };
+        hash = peel(&hash, "commit")?;
+        match operator {
+            '~' => {
+                for _ in 0..n {
+                    hash = Commit::from_hash(&hash).first_parent()?.to_string();
+                }
+            }
+            '^' if n > 0 => hash = Commit::from_hash(&hash).parents.into_iter().nth(n - 1)?,
+            '^' => {}
+            _ => return None,
+        }
+    }
+    Some(hash)
+}
+
+/// Splits `<rev>:<path>` at the first colon outside braces, so that `@{...}` and `^{...}`
+/// can hold colons, as in `HEAD@{2024-01-01 10:00:00}:README`.
+pub fn split_path(rev: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let (colon, _) = rev.char_indices().find(|(_, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        depth == 0 && *c == ':'
+    })?;
+    Some((&rev[..colon], &rev[colon + 1..]))
+}
+
+/// Resolves a single revision expression, such as `main~2^{tree}` or `HEAD:src/main.rs`,
+/// to an object id.
+pub fn resolve(rev: &str) -> Option<String> {
+    if let Some(pattern) = rev.strip_prefix(":/") {
+        let mut starts: Vec<String> = refs::list_refs("refs/").into_iter().map(|(_, hash)| hash).collect();
+        starts.extend(refs::read_ref("HEAD"));
+        starts.retain(|hash| object_type(hash) == Some("commit"));
+        return find_by_message(starts, pattern);
+    }
+    if let Some(path) = rev.strip_prefix(':') {
+        let path = path.strip_prefix("0:").unwrap_or(path);
+        return Index::read_index().get_entry(path).map(|entry| entry.sha1.clone());
+    }
+    if let Some((tree_ish, path)) = split_path(rev) {
+        return lookup_path(&peel(&resolve(tree_ish)?, "tree")?, path);
+    }
+    // The base runs up to the first `~` or `^`; `@{...}` belongs to it
+    let mut depth = 0;
+    let split = rev.char_indices().find(|(_, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        depth == 0 && (*c == '~' || *c == '^')
+    }).map(|(i, _)| i).unwrap_or(rev.len());
+    let base = if split == 0 { "HEAD" } else { &rev[..split] };
+    apply_suffixes(resolve_base(base)?, &rev[split..])
+}
+
+/// Resolves `rev` to a commit, exiting with git's message if it doesn't name one.
+pub fn resolve_commit(rev: &str) -> String {
+    resolve(rev).and_then(|hash| peel(&hash, "commit")).unwrap_or_else(|| die_unknown_revision(rev))
+}
+
+/// Resolves `rev` to a tree, exiting with git's message if it doesn't name one.
+pub fn resolve_tree(rev: &str) -> String {
+    resolve(rev).and_then(|hash| peel(&hash, "tree")).unwrap_or_else(|| die_unknown_revision(rev))
+}
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Walks down from `one` and `two` newest first as git's `paint_down_to_common` does,
+/// painting each commit with the sides it is reached from. A commit reached from both is a
+/// candidate, and its ancestors are painted stale; the walk stops once every queued commit
+/// is stale. Returns the candidates not painted stale themselves, newest first.
+fn paint_down_to_common(one: &str, two: &str) -> Vec<String> {
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let mut inserted = 0;
+    let mut push = |hash: &str, queue: &mut BinaryHeap<(i64, Reverse<usize>, String)>| {
+        queue.push((Commit::from_hash(hash).committer.time, Reverse(inserted), hash.to_string()));
+        inserted += 1;
+    };
+    flags.insert(one.to_string(), PARENT1);
+    *flags.entry(two.to_string()).or_default() |= PARENT2;
+    push(one, &mut queue);
+    push(two, &mut queue);
+    let mut results = Vec::new();
+    while queue.iter().any(|(_, _, hash)| flags[hash] & STALE == 0) {
+        let (time, _, hash) = queue.pop().unwrap();
+        let mut painted = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+        if painted == PARENT1 | PARENT2 {
+            if flags[&hash] & RESULT == 0 {
+                *flags.get_mut(&hash).unwrap() |= RESULT;
+                results.push((time, hash.clone()));
+            }
+            painted |= STALE;
+        }
+        for parent in Commit::from_hash(&hash).parents {
+            let parent_flags = flags.entry(parent.clone()).or_default();
+            if *parent_flags & painted == painted {
+                continue;
+            }
+            *parent_flags |= painted;
+            push(&parent, &mut queue);
+        }
+    }
+    // Newest first, and in the order found for the same date
+    results.sort_by_key(|(time, _)| Reverse(*time));
+    results.into_iter().map(|(_, hash)| hash).filter(|hash| flags[hash] & STALE == 0).collect()
+}
+
+/// The best common ancestors of two commits: those common ancestors that aren't themselves
+/// ancestors of another common ancestor.
+pub fn merge_bases(a: &str, b: &str) -> Vec<String> {
+    if a == b {
+        return vec![a.to_string()];
+    }
+    let candidates = paint_down_to_common(a, b);
+    if candidates.len() < 2 {
+        return candidates;
+    }
+    // Clock skew can leave a candidate that is an ancestor of another
+    candidates
+        .iter()
+        .filter(|candidate| !candidates.iter().any(|other| other != *candidate && is_ancestor(candidate, other)))
+        .cloned()
+        .collect()
+}
+
+/// Whether `ancestor` is reachable from `descendant`, counting the commit itself. With a
+/// commit-graph, the walk stops at commits whose generation is no higher than the
+/// ancestor's, since they can't lead to it.
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> bool {
+    let graph = CommitGraph::read();
+    let generation = |hash: &str| graph.as_ref().and_then(|graph| graph.generation(hash));
+    let Some(min_generation) = generation(ancestor) else {
+        return CommitWalk::new(&[descendant.to_string()], &[]).any(|commit| commit.hash == ancestor);
+    };
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::from([(generation(descendant).unwrap_or(u32::MAX), descendant.to_string())]);
+    while let Some((_, hash)) = queue.pop() {
+        if hash == ancestor {
+            return true;
+        }
+        if !seen.insert(hash.clone()) || generation(&hash).is_some_and(|generation| generation <= min_generation) {
+            continue;
+        }
+        queue.extend(Commit::from_hash(&hash).parents.into_iter().map(|parent| (generation(&parent).unwrap_or(u32::MAX), parent)));
+    }
+    false
+}
+
+/// How many commits `a` has that `b` doesn't, and the other way around.
+pub fn ahead_behind(a: &str, b: &str) -> (usize, usize) {
+    let ahead = CommitWalk::new(&[a.to_string()], &[b.to_string()]).count();
+    let behind = CommitWalk::new(&[b.to_string()], &[a.to_string()]).count();
+    (ahead, behind)
+}
+
+/// One revision named on the command line. `excluded` revisions and their ancestors are
+/// left out of history walks, as with `^A` or the left side of `A..B`. `left` is the left
+/// side of `A...B`, whose commits `--left-right` marks with `<`.
+#[derive(Debug, Clone)]
+pub struct RevisionSpec {
+    pub hash: String,
+    pub excluded: bool,
+    pub left: bool,
+}
+
+impl RevisionSpec {
+    fn new(hash: String, excluded: bool) -> Self {
+        Self { hash, excluded, left: false }
+    }
+}
+
+/// Parses a revision argument, expanding `A..B` to `B ^A` and `A...B` to `A B` minus their
+/// merge bases. An empty side of a range means HEAD.
+pub fn parse_range(arg: &str) -> Option<Vec<RevisionSpec>> {
+    let side = |rev: &str| resolve_commit(if rev.is_empty() { "HEAD" } else { rev });
+    if let Some((left, right)) = arg.split_once("...") {
+        let (left, right) = (side(left), side(right));
+        let mut specs = vec![RevisionSpec::new(right.clone(), false), RevisionSpec { left: true, ..RevisionSpec::new(left.clone(), false) }];
+        specs.extend(merge_bases(&left, &right).into_iter().map(|base| RevisionSpec::new(base, true)));
+        return Some(specs);
+    }
+    if let Some((left, right)) = arg.split_once("..") {
+        return Some(vec![RevisionSpec::new(side(right), false), RevisionSpec::new(side(left), true)]);
+    }
+    match arg.strip_prefix('^') {
+        Some(rev) => Some(vec![RevisionSpec::new(peel(&resolve(rev)?, "commit")?, true)]),
+        None => Some(vec![RevisionSpec::new(resolve(arg)?, false)]),
+    }
+}
+
+/// How many more excluded commits are walked once only excluded ones are queued, in case
+/// clock skew puts an included commit under them.
+const SLOP: usize = 5;
+
+/// Finds the excluded commits as git's `limit_list` does, without walking all of their
+/// history: commits are walked newest first from both sides, excluded ones passing that on
+/// to their parents, until nothing queued is included or newer than the last included
+/// commit. The excluded commits found include every excluded parent of an included one.
+fn mark_excluded(include: &[String], exclude: &[String]) -> HashSet<String> {
+    let mut excluded: HashSet<String> = exclude.iter().cloned().collect();
+    let mut queue = BinaryHeap::new();
+    // The parents of queued commits, and then of walked ones
+    let mut queued: HashMap<String, Vec<String>> = HashMap::new();
+    let mut walked: HashMap<String, Vec<String>> = HashMap::new();
+    let mut inserted = 0;
+    let mut push = |hash: &str, queue: &mut BinaryHeap<(i64, Reverse<usize>, String)>, queued: &mut HashMap<String, Vec<String>>, walked: &HashMap<String, Vec<String>>| {
+        if queued.contains_key(hash) || walked.contains_key(hash) {
+            return;
+        }
+        let commit = Commit::from_hash(hash);
+        queue.push((commit.committer.time, Reverse(inserted), hash.to_string()));
+        inserted += 1;
+        queued.insert(hash.to_string(), commit.parents);
+    };
+    for hash in exclude.iter().chain(include) {
+        push(hash, &mut queue, &mut queued, &walked);
+    }
+    let mut slop = SLOP;
+    let mut last_included = i64::MAX;
+    while let Some((time, _, hash)) = queue.pop() {
+        let parents = queued.remove(&hash).unwrap();
+        walked.insert(hash.clone(), parents.clone());
+        for parent in parents.iter() {
+            push(parent, &mut queue, &mut queued, &walked);
+        }
+        if !excluded.contains(&hash) {
+            last_included = time;
+            continue;
+        }
+        // Parents already walked pass it on to theirs
+        let mut marking = parents;
+        while let Some(parent) = marking.pop() {
+            if excluded.insert(parent.clone()) {
+                marking.extend(walked.get(&parent).into_iter().flatten().cloned());
+            }
+        }
+        let still_included = queue.iter().any(|(time, _, hash)| *time >= last_included || !excluded.contains(hash));
+        slop = if queue.is_empty() { 0 } else if still_included { SLOP } else { slop - 1 };
+        if slop == 0 {
+            break;
+        }
+    }
+    excluded
+}
+
+/// How the commits of a history walk are ordered. They are walked newest first by commit
+/// date, and `--topo-order` and `--date-order` also hold each commit back until its children
+/// are shown; `--date-order` then shows the newest ready commit first.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WalkOrder {
+    #[default]
+    Walked,
+    Topo,
+    Date,
+}
+
+impl WalkOrder {
+    /// Parses an ordering option, returning whether it was one. The last one given wins.
+    pub fn parse(&mut self, arg: &str) -> bool {
+        match arg {
+            "--topo-order" => *self = WalkOrder::Topo,
+            "--date-order" => *self = WalkOrder::Date,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether no commit is shown before its children.
+    pub fn is_topological(self) -> bool {
+        self != WalkOrder::Walked
+    }
+
+    /// Sets up `walk` to be ordered this way once it is topologically sorted.
+    pub fn apply(self, walk: CommitWalk) -> CommitWalk {
+        if self == WalkOrder::Date { walk.date_order() } else { walk }
+    }
+}
+
+/// Walks commits newest first by date, starting at `include` and stopping at anything
+/// reachable from `exclude`. Every parent of a merge is followed, and each commit is
+/// returned once.
+///
+/// A walk limited to paths leaves out commits that don't change them, and simplifies history
+/// as git does: unless the full history is wanted, a merge that has the same paths as one of
+/// its parents is taken to come from that parent alone, and the others aren't walked.
+pub struct CommitWalk {
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    pending: HashMap<String, Commit>,
+    seen: HashSet<String>,
+    excluded: HashSet<String>,
+    /// The excluded commits named on the command line, which still count when simplifying
+    /// the history that leads up to them.
+    bottoms: HashSet<String>,
+    inserted: usize,
+    first_parent: bool,
+    pathspec: Option<Pathspec>,
+    simplify_history: bool,
+    simplify_merges: bool,
+    rewrite_parents: bool,
+    by_date: bool,
+    /// Commits whose paths are the same as in their parents, which git calls TREESAME.
+    treesame: HashSet<String>,
+    /// For merges of the full history, whether each parent has the same paths.
+    parent_treesame: HashMap<String, Vec<bool>>,
+}
+
+impl CommitWalk {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        let mut walk = Self {
+            queue: BinaryHeap::new(),
+            pending: HashMap::new(),
+            seen: HashSet::new(),
+            excluded: HashSet::new(),
+            bottoms: exclude.iter().cloned().collect(),
+            inserted: 0,
+            first_parent: false,
+            pathspec: None,
+            simplify_history: true,
+            simplify_merges: false,
+            rewrite_parents: false,
+            by_date: false,
+            treesame: HashSet::new(),
+            parent_treesame: HashMap::new(),
+        };
+        if !exclude.is_empty() {
+            walk.excluded = mark_excluded(include, exclude);
+            walk.seen.extend(walk.excluded.iter().cloned());
+        }
+        for hash in include {
+            walk.push(hash);
+        }
+        walk
+    }
+
+    /// Only follows the first parent of merges, as with `--first-parent`.
+    pub fn first_parent(mut self) -> Self {
+        self.first_parent = true;
+        self
+    }
+
+    /// Only shows commits that change the paths of `pathspec`.
+    pub fn limit_to_paths(mut self, pathspec: Pathspec) -> Self {
+        self.pathspec = Some(pathspec);
+        self
+    }
+
+    /// Follows every parent of merges even when one of them has the same paths, as with
+    /// `--full-history`.
+    pub fn full_history(mut self) -> Self {
+        self.simplify_history = false;
+        self
+    }
+
+    /// Keeps shown commits connected by rewriting their parents to their nearest shown
+    /// ancestors, as `--graph` needs. Only applies to topological order.
+    pub fn rewrite_parents(mut self) -> Self {
+        self.rewrite_parents = true;
+        self
+    }
+
+    /// Orders `into_topo_order` newest first wherever children allow, as `--date-order` does.
+    pub fn date_order(mut self) -> Self {
+        self.by_date = true;
+        self
+    }
+
+    /// Drops the merges of the full history that bring nothing from one of their sides once
+    /// parents are rewritten, as with `--simplify-merges`.
+    pub fn simplify_merges(mut self) -> Self {
+        self.simplify_history = false;
+        self.simplify_merges = true;
+        self.rewrite_parents = true;
+        self
+    }
+
+    fn push(&mut self, hash: &str) {
+        if !self.seen.insert(hash.to_string()) {
+            return;
+        }
+        let commit = Commit::from_hash(hash);
+        let timestamp = commit.committer.time;
+        self.queue.push((timestamp, Reverse(self.inserted), hash.to_string()));
+        self.pending.insert(hash.to_string(), commit);
+        self.inserted += 1;
+    }
+
+    fn is_relevant(&self, hash: &str) -> bool {
+        !self.excluded.contains(hash) || self.bottoms.contains(hash)
+    }
+
+    fn same_paths(&self, old_tree: Option<&str>, new_tree: &str) -> bool {
+        let pathspec = self.pathspec.as_ref().unwrap();
+        tree_diff::diff_trees(old_tree, Some(new_tree), pathspec).is_empty()
+    }
+
+    /// Marks `commit` TREESAME if it doesn't change the paths, and when simplifying history
+    /// cuts a merge down to the first parent it has the same paths as. A merge is TREESAME
+    /// when it has the same paths as all of its parents that aren't excluded.
+    fn simplify_commit(&mut self, commit: &mut Commit) {
+        if commit.parents.is_empty() {
+            if self.same_paths(None, &commit.tree) {
+                self.treesame.insert(commit.hash.clone());
+            }
+            return;
+        }
+        let (mut relevant_parents, mut relevant_change, mut irrelevant_change) = (0, false, false);
+        let mut parent_treesame = None;
+        for (nth, parent) in commit.parents.clone().into_iter().enumerate() {
+            let relevant = self.is_relevant(&parent);
+            if relevant {
+                relevant_parents += 1;
+            }
+            if nth == 1 {
+                if self.first_parent {
+                    break;
+                }
+                if !self.simplify_history {
+                    let mut same = vec![false; commit.parents.len()];
+                    same[0] = !(relevant_change || irrelevant_change);
+                    parent_treesame = Some(same);
+                }
+            }
+            let parent_tree = match self.pending.get(&parent) {
+                Some(parent) => parent.tree.clone(),
+                None => Commit::from_hash(&parent).tree,
+            };
+            if self.same_paths(Some(&parent_tree), &commit.tree) {
+                if !self.simplify_history || !relevant {
+                    if let Some(same) = &mut parent_treesame {
+                        same[nth] = true;
+                    }
+                    continue;
+                }
+                commit.parents = vec![parent];
+                self.treesame.insert(commit.hash.clone());
+                return;
+            }
+            if relevant {
+                relevant_change = true;
+            } else {
+                irrelevant_change = true;
+            }
+        }
+        if let Some(same) = parent_treesame {
+            self.parent_treesame.insert(commit.hash.clone(), same);
+        }
+        let changed = if relevant_parents > 0 { relevant_change } else { irrelevant_change };
+        if !changed {
+            self.treesame.insert(commit.hash.clone());
+        }
+    }
+
+    /// Returns the next commit walked, whether or not it is shown.
+    fn walk_next(&mut self) -> Option<Commit> {
+        let (_, _, hash) = self.queue.pop()?;
+        let mut commit = self.pending.remove(&hash)?;
+        if self.pathspec.is_some() {
+            self.simplify_commit(&mut commit);
+        }
+        let parents = if self.first_parent { &commit.parents[..commit.parents.len().min(1)] } else { &commit.parents[..] };
+        for parent in parents {
+            self.push(parent);
+        }
+        Some(commit)
+    }
+
+    /// The only parent of a merge that isn't excluded, or the first parent of other commits.
+    fn one_relevant_parent(&self, parents: &[String]) -> Option<String> {
+        if self.first_parent || parents.len() == 1 {
+            return parents.first().cloned();
+        }
+        let mut relevant = parents.iter().filter(|parent| self.is_relevant(parent));
+        match (relevant.next(), relevant.next()) {
+            (Some(parent), None) => Some(parent.clone()),
+            _ => None,
+        }
+    }
+
+    /// Recomputes whether a merge is TREESAME after some of its parents were dropped.
+    fn update_treesame(&mut self, commit: &Commit) {
+        let Some(same) = self.parent_treesame.get(&commit.hash) else {
+            return;
+        };
+        let relevant: Vec<bool> = commit.parents.iter().map(|parent| self.is_relevant(parent)).collect();
+        // Parents that are excluded only count when all of them are
+        let counted = relevant.contains(&true);
+        if same.iter().zip(&relevant).any(|(same, relevant)| *relevant == counted && !same) {
+            self.treesame.remove(&commit.hash);
+        } else {
+            self.treesame.insert(commit.hash.clone());
+        }
+    }
+
+    /// Drops the parent at `nth` from a merge's TREESAME state, deciding the commit's TREESAME
+    /// right away when it is left with one parent.
+    fn compact_treesame(&mut self, hash: &str, nth: usize) {
+        let Some(same) = self.parent_treesame.get_mut(hash) else {
+            return;
+        };
+        same.remove(nth);
+        if same.len() == 1 {
+            if same[0] {
+                self.treesame.insert(hash.to_string());
+            } else {
+                self.treesame.remove(hash);
+            }
+            self.parent_treesame.remove(hash);
+        }
+    }
+
+    fn remove_duplicate_parents(&mut self, commit: &mut Commit) {
+        let mut seen = HashSet::new();
+        let mut nth = 0;
+        for parent in std::mem::take(&mut commit.parents) {
+            if seen.insert(parent.clone()) {
+                commit.parents.push(parent);
+                nth += 1;
+            } else {
+                self.compact_treesame(&commit.hash, nth);
+            }
+        }
+    }
+
+    /// Works out what one commit simplifies to for `--simplify-merges`, once all of its
+    /// parents have: itself if it is shown, or else what its parent simplifies to. Returns
+    /// the commits that have to be done first when its parents aren't ready yet.
+    fn simplify_one(&mut self, hash: &str, commits: &mut HashMap<String, Commit>, simplified: &mut HashMap<String, String>) -> Vec<String> {
+        if simplified.contains_key(hash) {
+            return Vec::new();
+        }
+        // Commits outside the walk, like root commits, simplify to themselves
+        let Some(mut commit) = commits.remove(hash) else {
+            simplified.insert(hash.to_string(), hash.to_string());
+            return Vec::new();
+        };
+        if commit.parents.is_empty() {
+            simplified.insert(hash.to_string(), hash.to_string());
+            commits.insert(hash.to_string(), commit);
+            return Vec::new();
+        }
+        let followed = if self.first_parent { 1 } else { commit.parents.len() };
+        let mut todo: Vec<String> = commit.parents[..followed].iter().filter(|parent| !simplified.contains_key(*parent)).cloned().collect();
+        if !todo.is_empty() {
+            todo.push(hash.to_string());
+            commits.insert(hash.to_string(), commit);
+            return todo;
+        }
+        for parent in &mut commit.parents[..followed] {
+            *parent = simplified[parent.as_str()].clone();
+        }
+        if !self.first_parent {
+            self.remove_duplicate_parents(&mut commit);
+        }
+        if commit.parents.len() > 1 && !self.first_parent {
+            self.remove_redundant_parents(&mut commit, commits);
+        }
+        let parent = if self.treesame.contains(hash) { self.one_relevant_parent(&commit.parents) } else { None };
+        let target = match parent {
+            Some(parent) => simplified.get(&parent).cloned().unwrap_or(parent),
+            None => hash.to_string(),
+        };
+        simplified.insert(hash.to_string(), target);
+        commits.insert(hash.to_string(), commit);
+        Vec::new()
+    }
+
+    /// Drops the parents of a merge that are ancestors of its other parents, and root
+    /// parents that don't have the paths, but keeps the first parent the merge has the same
+    /// paths as if all of those would go.
+    fn remove_redundant_parents(&mut self, commit: &mut Commit, commits: &HashMap<String, Commit>) {
+        let parents = commit.parents.clone();
+        let mut marked: Vec<bool> = parents
+            .iter()
+            .enumerate()
+            .map(|(i, parent)| parents.iter().enumerate().any(|(j, other)| i != j && other != parent && is_ancestor(parent, other)))
+            .collect();
+        for (i, parent) in parents.iter().enumerate() {
+            if commits.get(parent).is_some_and(|parent| parent.parents.is_empty()) && self.treesame.contains(parent) {
+                marked[i] = true;
+            }
+        }
+        if !marked.contains(&true) {
+            return;
+        }
+        if let Some(same) = self.parent_treesame.get(&commit.hash) {
+            let treesame_parents: Vec<usize> = (0..parents.len()).filter(|&i| same[i]).collect();
+            if !treesame_parents.is_empty() && treesame_parents.iter().all(|&i| marked[i]) {
+                marked[treesame_parents[0]] = false;
+            }
+        }
+        let mut nth = 0;
+        commit.parents.clear();
+        for (parent, marked) in parents.into_iter().zip(marked) {
+            if marked {
+                self.compact_treesame(&commit.hash, nth);
+            } else {
+                commit.parents.push(parent);
+                nth += 1;
+            }
+        }
+        if !self.treesame.contains(&commit.hash) {
+            self.update_treesame(commit);
+        }
+    }
+
+    /// Simplifies the merges of topologically ordered commits for `--simplify-merges`,
+    /// keeping only the commits that simplify to themselves. Also returns the simplified
+    /// parents of every commit.
+    fn simplify_merge_list(&mut self, ordered: Vec<Commit>) -> (Vec<Commit>, HashMap<String, Vec<String>>) {
+        let order: Vec<String> = ordered.iter().map(|commit| commit.hash.clone()).collect();
+        let mut commits: HashMap<String, Commit> = ordered.into_iter().map(|commit| (commit.hash.clone(), commit)).collect();
+        let mut simplified = HashMap::new();
+        // Parents come after their children, so going backwards mostly finds them done
+        let mut todo: Vec<String> = order.iter().rev().cloned().collect();
+        while !todo.is_empty() {
+            let mut next = Vec::new();
+            for hash in todo {
+                next.extend(self.simplify_one(&hash, &mut commits, &mut simplified));
+            }
+            todo = next;
+        }
+        let parents = commits.values().map(|commit| (commit.hash.clone(), commit.parents.clone())).collect();
+        (order.into_iter().filter(|hash| simplified[hash] == *hash).filter_map(|hash| commits.remove(&hash)).collect(), parents)
+    }
+
+    /// Follows a parent through ancestors that don't change the paths, to the nearest one
+    /// that is shown. Returns `None` when the history ends first.
+    fn rewrite_one(&self, mut parent: String, parents: &HashMap<String, Vec<String>>) -> Option<String> {
+        loop {
+            if self.excluded.contains(&parent) || !self.treesame.contains(&parent) {
+                return Some(parent);
+            }
+            let Some(grandparents) = parents.get(&parent) else {
+                return Some(parent);
+            };
+            if grandparents.is_empty() {
+                return None;
+            }
+            match self.one_relevant_parent(grandparents) {
+                Some(grandparent) => parent = grandparent,
+                None => return Some(parent),
+            }
+        }
+    }
+
+    /// The commits left out of the walk, down to where it stops.
+    pub fn excluded(&self) -> &HashSet<String> {
+        &self.excluded
+    }
+
+    /// Walks the whole history and returns it topologically ordered, as `--topo-order` does.
+    /// When limited to paths, commits that don't change them are left out, except merges
+    /// that join shown lines of history when parents are rewritten.
+    pub fn into_topo_order(mut self) -> Vec<Commit> {
+        if !self.by_date && !self.simplify_merges {
+            return self.into_topo_walk().collect();
+        }
+        let mut walked = Vec::new();
+        while let Some(commit) = self.walk_next() {
+            walked.push(commit);
+        }
+        let ordered = sort_topologically(walked, self.by_date);
+        if self.pathspec.is_none() {
+            return ordered;
+        }
+        let (ordered, parents) = if self.simplify_merges {
+            self.simplify_merge_list(ordered)
+        } else {
+            let parents = ordered.iter().map(|commit| (commit.hash.clone(), commit.parents.clone())).collect();
+            (ordered, parents)
+        };
+        self.show_and_rewrite(ordered, parents)
+    }
+
+    /// Streams the history in the order `into_topo_order` returns it, for walks that aren't
+    /// by date and don't simplify merges.
+    pub fn into_topo_walk(self) -> TopoWalk {
+        TopoWalk {
+            walk: self,
+            commit_graph: CommitGraph::read(),
+            walked: HashMap::new(),
+            parents: HashMap::new(),
+            children: HashMap::new(),
+            tips: VecDeque::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Whether a commit of a walk limited to paths is left out for not changing them. Merges
+    /// that join lines of history are kept when parents are rewritten.
+    fn is_hidden(&self, commit: &Commit) -> bool {
+        if !self.treesame.contains(&commit.hash) {
+            return false;
+        }
+        let relevant = commit.parents.iter().filter(|parent| self.is_relevant(parent)).count();
+        !self.rewrite_parents || relevant < 2
+    }
+
+    fn show_and_rewrite(&mut self, ordered: Vec<Commit>, parents: HashMap<String, Vec<String>>) -> Vec<Commit> {
+        let mut shown = Vec::new();
+        for mut commit in ordered {
+            if self.is_hidden(&commit) {
+                continue;
+            }
+            if self.rewrite_parents {
+                commit.parents = commit.parents.iter().filter_map(|parent| self.rewrite_one(parent.clone(), &parents)).collect();
+                self.remove_duplicate_parents(&mut commit);
+            }
+            shown.push(commit);
+        }
+        shown
+    }
+}
+
+impl Iterator for CommitWalk {
+    type Item = Commit;
+
+    fn next(&mut self) -> Option<Commit> {
+        loop {
+            let commit = self.walk_next()?;
+            if !self.treesame.contains(&commit.hash) {
+                return Some(commit);
+            }
+        }
+    }
+}
+
+/// A walk in topological order that hands out commits as soon as their place is known,
+/// rather than once the whole history is walked.
+///
+/// A commit is ordered once all of its children are walked. Children have higher
+/// generations than their parents in the commit-graph, and nothing still to be walked is
+/// higher than what is queued, so generations tell this early. Commits the commit-graph
+/// doesn't have, and every commit when there is none, wait for the end of the walk.
+pub struct TopoWalk {
+    walk: CommitWalk,
+    commit_graph: Option<CommitGraph>,
+    /// Walked commits that haven't been ordered yet.
+    walked: HashMap<String, Commit>,
+    /// The parents of every walked commit, which parents are rewritten through.
+    parents: HashMap<String, Vec<String>>,
+    /// For each commit with walked children, how many of them haven't been ordered yet.
+    children: HashMap<String, usize>,
+    /// Walked commits in the order they were walked. Those without children are the tips,
+    /// ordered in that order once nothing else is ready.
+    tips: VecDeque<String>,
+    /// Commits whose children have all been ordered, the last to get there coming first.
+    ready: Vec<String>,
+}
+
+impl TopoWalk {
+    fn generation(&self, hash: &str) -> u32 {
+        self.commit_graph.as_ref().and_then(|graph| graph.generation(hash)).unwrap_or(u32::MAX)
+    }
+
+    /// The highest generation queued, which nothing left to walk is above.
+    fn queued_generation(&self) -> Option<u32> {
+        self.walk.queue.iter().map(|(_, _, hash)| self.generation(hash)).max()
+    }
+
+    /// Whether everything that will be walked and could be `hash` or a child of it has been.
+    fn is_settled(&self, hash: &str) -> bool {
+        let Some(queued) = self.queued_generation() else {
+            return true;
+        };
+        let generation = self.generation(hash);
+        if self.parents.contains_key(hash) {
+            queued < generation || (queued == generation && generation != u32::MAX)
+        } else {
+            self.walk.excluded.contains(hash) || queued < generation
+        }
+    }
+
+    /// The parents whose children are counted. Following first parents, git only counts
+    /// those when it streams with generation numbers, and every parent otherwise.
+    fn counted_parents<'a>(&self, parents: &'a [String]) -> &'a [String] {
+        if self.walk.first_parent && self.commit_graph.is_some() { &parents[..parents.len().min(1)] } else { parents }
+    }
+
+    fn walk_one(&mut self) -> bool {
+        let Some(commit) = self.walk.walk_next() else {
+            return false;
+        };
+        for parent in self.counted_parents(&commit.parents) {
+            *self.children.entry(parent.clone()).or_default() += 1;
+        }
+        self.parents.insert(commit.hash.clone(), commit.parents.clone());
+        self.tips.push_back(commit.hash.clone());
+        self.walked.insert(commit.hash.clone(), commit);
+        true
+    }
+
+    /// The next commit in topological order, walking as far as it takes to know it.
+    fn next_ordered(&mut self) -> Option<Commit> {
+        let hash = loop {
+            if let Some(hash) = self.ready.last() {
+                break hash.clone();
+            }
+            let Some(tip) = self.tips.front().cloned() else {
+                if !self.walk_one() {
+                    return None;
+                }
+                continue;
+            };
+            if !self.walked.contains_key(&tip) || self.children.contains_key(&tip) {
+                self.tips.pop_front();
+            } else if self.is_settled(&tip) {
+                break tip;
+            } else {
+                self.walk_one();
+            }
+        };
+        // Parents have to be settled too, to know whether ordering this commit readies them
+        while !self.counted_parents(&self.parents[&hash]).iter().all(|parent| self.is_settled(parent)) && self.walk_one() {}
+        if self.ready.last() == Some(&hash) {
+            self.ready.pop();
+        } else {
+            self.tips.pop_front();
+        }
+        let commit = self.walked.remove(&hash)?;
+        for parent in self.counted_parents(&commit.parents) {
+            if self.walked.contains_key(parent) {
+                let count = self.children.get_mut(parent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.ready.push(parent.clone());
+                }
+            }
+        }
+        Some(commit)
+    }
+
+    /// Rewrites a parent as `rewrite_one` does, walking on when it gets to commits that
+    /// haven't been walked yet.
+    fn rewrite(&mut self, parent: &str) -> Option<String> {
+        let mut parent = parent.to_string();
+        loop {
+            parent = self.walk.rewrite_one(parent, &self.parents)?;
+            if self.parents.contains_key(&parent) || self.is_settled(&parent) || !self.walk_one() {
+                return Some(parent);
+            }
+        }
+    }
+}
+
+impl Iterator for TopoWalk {
+    type Item = Commit;
+
+    fn next(&mut self) -> Option<Commit> {
+        loop {
+            let mut commit = self.next_ordered()?;
+            if self.walk.pathspec.is_none() {
+                return Some(commit);
+            }
+            if self.walk.is_hidden(&commit) {
+                continue;
+            }
+            if self.walk.rewrite_parents {
+                let parents = std::mem::take(&mut commit.parents);
+                commit.parents = parents.iter().filter_map(|parent| self.rewrite(parent)).collect();
+                self.walk.remove_duplicate_parents(&mut commit);
+            }
+            return Some(commit);
+        }
+    }
+}
+
+/// Orders walked commits so that no commit comes before any of its children, as git's
+/// `--topo-order` does for `--graph`. Tips keep the order they were walked in, and the
+/// last parent to become ready is shown first, so that each line of history stays
+/// together.
+pub fn topo_order(commits: Vec<Commit>) -> Vec<Commit> {
+    sort_topologically(commits, false)
+}
+
+/// Orders walked commits so that no commit comes before any of its children, and otherwise
+/// newest first by commit date, as `--date-order` does.
+pub fn date_order(commits: Vec<Commit>) -> Vec<Commit> {
+    sort_topologically(commits, true)
+}
+
+fn sort_topologically(commits: Vec<Commit>, by_date: bool) -> Vec<Commit> {
+    // Commits in the walk start at 1 so that 0 can mean a parent outside it
+    let mut indegree: HashMap<String, usize> = commits.iter().map(|commit| (commit.hash.clone(), 1)).collect();
+    for parent in commits.iter().flat_map(|commit| commit.parents.iter()) {
+        if let Some(count) = indegree.get_mut(parent) {
+            *count += 1;
+        }
+    }
+    let mut tips: Vec<&Commit> = commits.iter().filter(|commit| indegree[&commit.hash] == 1).collect();
+    if !by_date {
+        tips.reverse();
+    }
+    // Ready commits come out newest first, ties in the order they became ready, or else
+    // the last to become ready first
+    let mut ready = BinaryHeap::new();
+    let mut inserted = 0;
+    let mut put = |commit: &Commit, ready: &mut BinaryHeap<((i64, i64), String)>| {
+        inserted += 1;
+        let key = if by_date { (commit.committer.time, -inserted) } else { (0, inserted) };
+        ready.push((key, commit.hash.clone()));
+    };
+    for tip in tips {
+        put(tip, &mut ready);
+    }
+    let mut pending: HashMap<String, Commit> = commits.into_iter().map(|commit| (commit.hash.clone(), commit)).collect();
+    let mut ordered = Vec::new();
+    while let Some((_, hash)) = ready.pop() {
+        let Some(commit) = pending.remove(&hash) else {
+            continue;
+        };
+        for parent in &commit.parents {
+            match indegree.get_mut(parent) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    if *count == 1 {
+                        if let Some(parent) = pending.get(parent) {
+                            put(parent, &mut ready);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        ordered.push(commit);
+    }
+    ordered
+}
+
+/// Which commits of a walk are shown, as the filtering options of `log` say.
+#[derive(Default, Clone)]
+pub struct CommitFilter {
+    /// `--author` patterns, any of which has to match the author's `Name <email>`.
+    pub authors: Vec<Regex>,
+    pub committers: Vec<Regex>,
+    /// `--grep` patterns, any of which, or all of them with `all_match`, has to match a
+    /// line of the message.
+    pub greps: Vec<Regex>,
+    pub all_match: bool,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub min_parents: usize,
+    pub max_parents: Option<usize>,
+}
+
+impl CommitFilter {
+    pub fn matches(&self, commit: &Commit) -> bool {
+        let time = commit.committer.time;
+        if self.since.is_some_and(|since| time < since) || self.until.is_some_and(|until| time > until) {
+            return false;
+        }
+        let parents = commit.parents.len();
+        if parents < self.min_parents || self.max_parents.is_some_and(|max| parents > max) {
+            return false;
+        }
+        let identity_matches = |patterns: &[Regex], signature: &Signature| {
+            patterns.is_empty() || patterns.iter().any(|pattern| pattern.is_match(&signature.identity()))
+        };
+        if !identity_matches(&self.authors, &commit.author) || !identity_matches(&self.committers, &commit.committer) {
+            return false;
+        }
+        if self.greps.is_empty() {
+            return true;
+        }
+        let message = commit.message();
+        let grep_matches = |pattern: &Regex| message.lines().any(|line| pattern.is_match(line));
+        if self.all_match {
+            self.greps.iter().all(grep_matches)
+        } else {
+            self.greps.iter().any(grep_matches)
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeSet;
+use std::fs;
+
+use crate::git::config::Config;
+
+pub const SPARSE_CHECKOUT_PATH: &str = ".git/info/sparse-checkout";
+
+/// A cone mode sparse checkout. Every file at the top level is included, along with
+/// everything below the recursive `directories` and the files directly inside their
+/// parent directories.
+#[derive(Debug, Default)]
+pub struct SparseCheckout {
+    pub directories: BTreeSet<String>,
+    parents: BTreeSet<String>,
+}
+
+impl SparseCheckout {
+    pub fn new(directories: BTreeSet<String>) -> Self {
+        let mut parents = BTreeSet::new();
+        for directory in directories.iter() {
+            for (slash, _) in directory.match_indices('/') {
+                parents.insert(directory[..slash].to_string());
+            }
+        }
+        // A directory already included recursively doesn't need its own entry
+        let directories: BTreeSet<String> = directories.iter()
+            .filter(|directory| !directories.iter().any(|other| directory.starts_with(&format!("{}/", other))))
+            .cloned()
+            .collect();
+        parents.retain(|parent| !directories.iter().any(|directory| parent == directory || parent.starts_with(&format!("{}/", directory))));
+        Self { directories, parents }
+    }
+
+    /// Normalizes a directory argument like `./src/lib/` to `src/lib`.
+    pub fn normalize_directory(directory: &str) -> String {
+        directory.trim_start_matches("./").trim_matches('/').to_string()
+    }
+
+    /// Recovers the recursive directories from cone mode patterns. A `/dir/` pattern followed
+    /// by `!/dir/*/` only includes the directory's own files, so it is a parent, not a cone.
+    pub fn parse(content: &str) -> Self {
+        let lines: Vec<&str> = content.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+        let mut directories = BTreeSet::new();
+        for line in lines.iter() {
+            if line.starts_with('!') || *line == "/*" {
+                continue;
+            }
+            let directory = line.trim_start_matches('/').trim_end_matches('/');
+            let parent_pattern = format!("!/{}/*/", directory);
+            if !directory.is_empty() && !lines.contains(&parent_pattern.as_str()) {
+                directories.insert(directory.replace('\\', ""));
+            }
+        }
+        Self::new(directories)
+    }
+
+    /// Reads the sparse checkout patterns, or `None` when `core.sparseCheckout` is not enabled.
+    pub fn from_file() -> Option<Self> {
+        let enabled = Config::read().get("core.sparseCheckout").and_then(|value| Config::parse_bool(&value));
+        if enabled != Some(true) {
+            return None;
+        }
+        let content = fs::read_to_string(SPARSE_CHECKOUT_PATH).unwrap_or_else(|_| "/*\n!/*/\n".to_string());
+        Some(Self::parse(&content))
+    }
+
+    fn escape(directory: &str) -> String {
+        directory.chars().fold(String::new(), |mut escaped, c| {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\' | '!' | '#') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+    }
+
+    pub fn to_patterns(&self) -> String {
+        let mut patterns = String::from("/*\n!/*/\n");
+        for parent in self.parents.iter() {
+            let parent = Self::escape(parent);
+            patterns.push_str(&format!("/{}/\n!/{}/*/\n", parent, parent));
+        }
+        for directory in self.directories.iter() {
+            patterns.push_str(&format!("/{}/\n", Self::escape(directory)));
+        }
+        patterns
+    }
+
+    pub fn write(&self) {
+        fs::create_dir_all(".git/info").unwrap();
+        fs::write(SPARSE_CHECKOUT_PATH, self.to_patterns()).unwrap();
+    }
+
+    fn in_recursive_directory(&self, path: &str) -> bool {
+        self.directories.iter().any(|directory| path == directory || path.starts_with(&format!("{}/", directory)))
+    }
+
+    /// Whether the file at `path` is inside the cone.
+    pub fn contains(&self, path: &str) -> bool {
+        match path.rfind('/') {
+            None => true,
+            Some(slash) => self.parents.contains(&path[..slash]) || self.in_recursive_directory(&path[..slash]),
+        }
+    }
+
+    /// Whether any file below `directory` can be inside the cone.
+    pub fn contains_directory(&self, directory: &str) -> bool {
+        self.parents.contains(directory) || self.in_recursive_directory(directory)
+    }
+}
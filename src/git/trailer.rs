@@ -0,0 +1,462 @@
+use crate::git::config::Config;
+
+/// Trailers git itself writes, which make a block count as trailers even when most of its
+/// lines aren't.
+const GIT_GENERATED_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+const SCISSORS: &str = "# ------------------------ >8 ------------------------\n";
+
+/// A line of a trailer block together with its continuation lines. Lines that aren't
+/// trailers but were tolerated in the block have no token.
+#[derive(Debug, Clone)]
+pub struct Trailer {
+    pub token: Option<String>,
+    pub value: String,
+}
+
+impl Trailer {
+    /// The trailer as `<token>: <value>`, using the token's own separator when it ends
+    /// with one.
+    pub fn format(&self, separators: &str) -> Option<String> {
+        let Some(token) = &self.token else {
+            return Some(self.value.clone());
+        };
+        let last = token.trim_end().chars().last()?;
+        if separators.contains(last) {
+            Some(format!("{}{}", token, self.value))
+        } else {
+            Some(format!("{}{} {}", token, separators.chars().next().unwrap_or(':'), self.value))
+        }
+    }
+}
+
+/// The trailer block at the end of a message.
+pub struct TrailerBlock {
+    /// Where the block starts, or the end of the message proper when there is none.
+    pub start: usize,
+    /// Where the block ends: trailing comments, blank lines and any patch come after.
+    pub end: usize,
+    pub blank_line_before: bool,
+    /// The block's lines, with continuation lines joined to the trailer they continue.
+    pub lines: Vec<String>,
+}
+
+impl TrailerBlock {
+    /// The trailers of the block, along with its other lines unless `only_trailers`.
+    /// Comments are left out.
+    pub fn trailers(&self, separators: &str, only_trailers: bool, unfold: bool) -> Vec<Trailer> {
+        let mut trailers = Vec::new();
+        for line in self.lines.iter().filter(|line| !line.starts_with('#')) {
+            match find_separator(line, separators) {
+                Some(position) if position >= 1 => {
+                    let (token, value) = split_trailer(line, position);
+                    let value = if unfold { unfold_value(&value) } else { value };
+                    trailers.push(Trailer { token: Some(token), value });
+                }
+                _ if !only_trailers => trailers.push(Trailer { token: None, value: line.trim_end_matches('\n').to_string() }),
+                _ => {}
+            }
+        }
+        trailers
+    }
+}
+
+/// The separators trailers may use, `trailer.separators` or just `:`.
+pub fn separators() -> String {
+    Config::read().get("trailer.separators").unwrap_or(":".to_string())
+}
+
+/// The position of the separator ending a trailer's token, which is made of letters,
+/// digits and dashes and may be followed by whitespace.
+pub fn find_separator(line: &str, separators: &str) -> Option<usize> {
+    let mut whitespace_found = false;
+    for (i, c) in line.char_indices() {
+        if separators.contains(c) {
+            return Some(i);
+        }
+        if !whitespace_found && (c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if i != 0 && (c == ' ' || c == '\t') {
+            whitespace_found = true;
+            continue;
+        }
+        break;
+    }
+    None
+}
+
+/// Splits a trailer at its separator into the trimmed token and value.
+pub fn split_trailer(line: &str, separator: usize) -> (String, String) {
+    let value_start = separator + line[separator..].chars().next().map_or(0, |c| c.len_utf8());
+    (line[..separator].trim().to_string(), line[value_start..].trim().to_string())
+}
+
+/// Joins a value's continuation lines into one line.
+pub fn unfold_value(value: &str) -> String {
+    let mut unfolded = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            unfolded.push(' ');
+        } else {
+            unfolded.push(c);
+        }
+    }
+    unfolded.trim().to_string()
+}
+
+fn is_blank_line(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+/// Where the last line of `text` starts, a final newline being part of that line.
+fn last_line(text: &str, len: usize) -> Option<usize> {
+    match len {
+        0 => None,
+        1 => Some(0),
+        _ => Some(text.as_bytes()[..len - 1].iter().rposition(|&byte| byte == b'\n').map_or(0, |i| i + 1)),
+    }
+}
+
+/// Where a patch starts: the first line that is `---` followed by whitespace.
+fn find_patch_start(message: &str) -> usize {
+    let mut position = 0;
+    for line in message.split_inclusive('\n') {
+        if line.starts_with("---") && line[3..].starts_with(char::is_whitespace) {
+            return position;
+        }
+        position += line.len();
+    }
+    message.len()
+}
+
+/// How much of the end of `message` is comments, blank lines, the `Conflicts:` list of old
+/// merge messages or below a scissors line, none of which can hold trailers.
+fn ignored_tail_len(message: &str) -> usize {
+    let cutoff = if message.starts_with(SCISSORS) {
+        0
+    } else {
+        message.find(&format!("\n{}", SCISSORS)).map_or(message.len(), |position| position + 1)
+    };
+    // Like git, a run starting at the very beginning doesn't count as a run
+    let mut comments_start = 0;
+    let mut in_conflicts = false;
+    let mut position = 0;
+    for line in message[..cutoff].split_inclusive('\n') {
+        if line.starts_with('#') || line.starts_with('\n') {
+            if comments_start == 0 {
+                comments_start = position;
+            }
+        } else if line.starts_with("Conflicts:\n") {
+            in_conflicts = true;
+            if comments_start == 0 {
+                comments_start = position;
+            }
+        } else if !(in_conflicts && line.starts_with('\t')) && comments_start != 0 {
+            comments_start = 0;
+            in_conflicts = false;
+        }
+        position += line.len();
+    }
+    message.len() - if comments_start != 0 { comments_start } else { cutoff }
+}
+
+/// Where the trailer block in `message[..end]` starts, or `end` when there is none. The
+/// block is the last paragraph, which must not be the title and must either be all
+/// trailers or hold a git-generated one among at least 25% trailers.
+fn find_block_start(message: &str, end: usize, separators: &str) -> usize {
+    let mut end_of_title = 0;
+    for line in message[..end].split_inclusive('\n') {
+        if !line.starts_with('#') && is_blank_line(line) {
+            break;
+        }
+        end_of_title += line.len();
+    }
+
+    let mut only_spaces = true;
+    let mut recognized_prefix = false;
+    let mut trailer_lines = 0;
+    let mut non_trailer_lines = 0;
+    let mut possible_continuation_lines = 0;
+    let mut next = last_line(message, end);
+    while let Some(start) = next.filter(|&start| start >= end_of_title) {
+        next = last_line(message, start);
+        let line = message[start..end].split_inclusive('\n').next().unwrap_or("");
+        if line.starts_with('#') {
+            non_trailer_lines += possible_continuation_lines;
+            possible_continuation_lines = 0;
+            continue;
+        }
+        if is_blank_line(line) {
+            if only_spaces {
+                continue;
+            }
+            non_trailer_lines += possible_continuation_lines;
+            if (recognized_prefix && trailer_lines * 3 >= non_trailer_lines) || (trailer_lines > 0 && non_trailer_lines == 0) {
+                return start + line.len();
+            }
+            return end;
+        }
+        only_spaces = false;
+        if GIT_GENERATED_PREFIXES.iter().any(|prefix| line.starts_with(prefix)) {
+            trailer_lines += 1;
+            possible_continuation_lines = 0;
+            recognized_prefix = true;
+        } else if find_separator(line, separators).is_some_and(|position| position >= 1) && !line.starts_with(char::is_whitespace) {
+            trailer_lines += 1;
+            possible_continuation_lines = 0;
+        } else if line.starts_with(char::is_whitespace) {
+            possible_continuation_lines += 1;
+        } else {
+            non_trailer_lines += 1 + possible_continuation_lines;
+            possible_continuation_lines = 0;
+        }
+    }
+    end
+}
+
+/// Finds the trailer block of `message`. Unless `no_divider`, a `---` line is taken to
+/// start a patch, below which trailers aren't looked for.
+pub fn parse(message: &str, separators: &str, no_divider: bool) -> TrailerBlock {
+    let patch_start = if no_divider { message.len() } else { find_patch_start(message) };
+    let end = patch_start - ignored_tail_len(&message[..patch_start]);
+    let start = find_block_start(message, end, separators);
+    let mut lines: Vec<String> = Vec::new();
+    let mut continues_trailer = false;
+    for line in message[start..end].split_inclusive('\n') {
+        if continues_trailer && line.starts_with(char::is_whitespace) {
+            lines.last_mut().unwrap().push_str(line);
+            continue;
+        }
+        continues_trailer = find_separator(line, separators).is_some_and(|position| position >= 1);
+        lines.push(line.to_string());
+    }
+    let blank_line_before = last_line(message, start).is_some_and(|line_start| is_blank_line(&message[line_start..start]));
+    TrailerBlock { start, end, blank_line_before, lines }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Where {
+    After,
+    Before,
+    End,
+    Start,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IfExists {
+    AddIfDifferentNeighbor,
+    AddIfDifferent,
+    Add,
+    Replace,
+    DoNothing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IfMissing {
+    Add,
+    DoNothing,
+}
+
+impl Where {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "after" => Some(Self::After),
+            "before" => Some(Self::Before),
+            "end" => Some(Self::End),
+            "start" => Some(Self::Start),
+            _ => None,
+        }
+    }
+
+    fn after_or_end(self) -> bool {
+        matches!(self, Self::After | Self::End)
+    }
+}
+
+impl IfExists {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "addifdifferentneighbor" => Some(Self::AddIfDifferentNeighbor),
+            "addifdifferent" => Some(Self::AddIfDifferent),
+            "add" => Some(Self::Add),
+            "replace" => Some(Self::Replace),
+            "donothing" => Some(Self::DoNothing),
+            _ => None,
+        }
+    }
+}
+
+impl IfMissing {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "add" => Some(Self::Add),
+            "donothing" => Some(Self::DoNothing),
+            _ => None,
+        }
+    }
+}
+
+/// A trailer to add, with the rules for where it goes and what to do about existing
+/// trailers with the same token.
+#[derive(Clone)]
+pub struct NewTrailer {
+    pub token: String,
+    pub value: String,
+    pub placement: Where,
+    pub if_exists: IfExists,
+    pub if_missing: IfMissing,
+}
+
+/// Tokens are the same if they agree, ignoring case, for as long as the shorter one goes
+/// without its trailing separator, so that `Sign` stands for `Signed-off-by`.
+fn same_token(a: &str, b: &str) -> bool {
+    let a = a.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()).as_bytes();
+    let b = b.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()).as_bytes();
+    let len = a.len().min(b.len());
+    a[..len].eq_ignore_ascii_case(&b[..len])
+}
+
+fn same_trailer(trailer: &Trailer, new: &NewTrailer) -> bool {
+    trailer.token.as_ref().is_some_and(|token| same_token(token, &new.token)) && trailer.value.eq_ignore_ascii_case(&new.value)
+}
+
+/// Adds `new` to `trailers` as git's `--where`, `--if-exists` and `--if-missing` rules say.
+pub fn add(trailers: &mut Vec<Trailer>, new: NewTrailer) {
+    let backwards = new.placement.after_or_end();
+    let middle = matches!(new.placement, Where::After | Where::Before);
+    let order: Vec<usize> = if backwards { (0..trailers.len()).rev().collect() } else { (0..trailers.len()).collect() };
+    let existing = order.iter().copied().find(|&i| trailers[i].token.as_ref().is_some_and(|token| same_token(token, &new.token)));
+    let trailer = Trailer { token: Some(new.token.clone()), value: new.value.clone() };
+    let Some(existing) = existing else {
+        if new.if_missing == IfMissing::Add {
+            let position = if backwards { trailers.len() } else { 0 };
+            trailers.insert(position, trailer);
+        }
+        return;
+    };
+    let neighbor = if middle { existing } else { order[0] };
+    let position = if backwards { neighbor + 1 } else { neighbor };
+    match new.if_exists {
+        IfExists::DoNothing => {}
+        IfExists::Replace => {
+            trailers.insert(position, trailer);
+            trailers.remove(if position <= existing { existing + 1 } else { existing });
+        }
+        IfExists::Add => trailers.insert(position, trailer),
+        IfExists::AddIfDifferent => {
+            // Only the trailers on the side the new one is added from are compared
+            let compared = if backwards { &trailers[..=existing] } else { &trailers[existing..] };
+            if !compared.iter().any(|trailer| same_trailer(trailer, &new)) {
+                trailers.insert(position, trailer);
+            }
+        }
+        IfExists::AddIfDifferentNeighbor => {
+            if !same_trailer(&trailers[neighbor], &new) {
+                trailers.insert(position, trailer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `git interpret-trailers --parse` prints for `message`.
+    fn parsed(message: &str, no_divider: bool) -> Vec<String> {
+        let block = parse(message, ":", no_divider);
+        block.trailers(":", true, true).iter().filter_map(|trailer| trailer.format(":")).collect()
+    }
+
+    fn new_trailer(token: &str, value: &str, placement: Where, if_exists: IfExists, if_missing: IfMissing) -> NewTrailer {
+        NewTrailer { token: token.to_string(), value: value.to_string(), placement, if_exists, if_missing }
+    }
+
+    /// The trailers of `message` after adding `new` to them, one per line.
+    fn added(message: &str, new: &[NewTrailer]) -> Vec<String> {
+        let mut trailers = parse(message, ":", false).trailers(":", false, false);
+        for new in new {
+            add(&mut trailers, new.clone());
+        }
+        trailers.iter().filter_map(|trailer| trailer.format(":")).collect()
+    }
+
+    #[test]
+    fn finds_separators_after_tokens() {
+        assert_eq!(find_separator("Signed-off-by: A", ":"), Some(13));
+        assert_eq!(find_separator("Key  : v", ":"), Some(5));
+        assert_eq!(find_separator("Key = v", ":="), Some(4));
+        assert_eq!(find_separator("Two words: v", ":"), None);
+        assert_eq!(find_separator(" Indented: v", ":"), None);
+        assert_eq!(split_trailer("Key  :  value ", 5), ("Key".to_string(), "value".to_string()));
+    }
+
+    #[test]
+    fn parses_the_last_paragraph_as_git_does() {
+        // Expected output from git 2.39's `interpret-trailers --parse`
+        let message = "Title\n\nBody text\n\nSigned-off-by: A <a@e>\nAcked-by: B\n  continued\n";
+        assert_eq!(parsed(message, false), ["Signed-off-by: A <a@e>", "Acked-by: B continued"]);
+        let block = parse(message, ":", false);
+        assert_eq!(&message[block.start..block.end], "Signed-off-by: A <a@e>\nAcked-by: B\n  continued\n");
+        assert!(block.blank_line_before);
+        assert_eq!(block.lines.len(), 2);
+    }
+
+    #[test]
+    fn git_generated_trailers_allow_other_lines() {
+        let message = "Title\n\nSigned-off-by: A\nnot a trailer\nFixes: 1\nmore text\nmore\n";
+        assert_eq!(parsed(message, false), ["Signed-off-by: A", "Fixes: 1"]);
+        assert!(parsed("Title\n\nFixes: 1\nnot a trailer\n", false).is_empty());
+    }
+
+    #[test]
+    fn the_title_is_never_a_trailer_block() {
+        assert!(parsed("Fixes: 1\n", false).is_empty());
+        assert!(parsed("Title\n\nKey = v\n", false).is_empty());
+    }
+
+    #[test]
+    fn comments_and_patches_come_after_the_block() {
+        let message = "Title\n\nFixes: 1\n# comment\n\n---\nPatch: x\n";
+        assert_eq!(parsed(message, false), ["Fixes: 1"]);
+        assert_eq!(&message[parse(message, ":", false).end..], "# comment\n\n---\nPatch: x\n");
+        assert!(parsed("Title\n\nFixes: 1\n---\nPatch: x\n", true).is_empty());
+        let scissors = format!("Title\n\nFixes: 1\n{}Other: 2\n", SCISSORS);
+        assert_eq!(parsed(&scissors, false), ["Fixes: 1"]);
+    }
+
+    #[test]
+    fn unfolds_continuation_lines() {
+        assert_eq!(unfold_value("a\n  b\n\tc"), "a b c");
+        assert_eq!(unfold_value(" a "), "a");
+    }
+
+    #[test]
+    fn tokens_match_by_prefix_ignoring_case() {
+        assert!(same_token("Sign", "Signed-off-by"));
+        assert!(same_token("acked-by:", "Acked-By"));
+        assert!(!same_token("Acked-by", "Reviewed-by"));
+    }
+
+    #[test]
+    fn adds_trailers_as_git_does() {
+        // Each expectation is what git 2.39's `interpret-trailers` wrote for the same options
+        let message = "T\n\nA: 1\nB: 2\n";
+        let after = new_trailer("A", "3", Where::After, IfExists::Add, IfMissing::Add);
+        assert_eq!(added(message, &[after]), ["A: 1", "A: 3", "B: 2"]);
+        let replace = new_trailer("b", "9", Where::Start, IfExists::Replace, IfMissing::Add);
+        assert_eq!(added(message, &[replace]), ["b: 9", "A: 1"]);
+        let missing = new_trailer("C", "9", Where::End, IfExists::AddIfDifferentNeighbor, IfMissing::DoNothing);
+        assert_eq!(added(message, &[missing]), ["A: 1", "B: 2"]);
+
+        let neighbors = [
+            new_trailer("a", "1", Where::End, IfExists::AddIfDifferentNeighbor, IfMissing::Add),
+            new_trailer("b", "2", Where::End, IfExists::AddIfDifferentNeighbor, IfMissing::Add),
+        ];
+        assert_eq!(added("T\n\nA: 1\nB: 2\nA: 1\n", &neighbors), ["A: 1", "B: 2", "A: 1", "b: 2"]);
+        let different = new_trailer("B", "2", Where::End, IfExists::AddIfDifferent, IfMissing::Add);
+        assert_eq!(added("T\n\nA: 1\nB: 2\nA: 1\n", &[different]), ["A: 1", "B: 2", "A: 1"]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::git::object::blob::Blob;
+use crate::git::object::tree::{Tree, TreeEntry};
+use crate::git::pathspec::Pathspec;
+
+/// Similarity scores run up to this, as in git, and renames need at least half of it.
+const MAX_SCORE: u64 = 60000;
+const MIN_SCORE: u64 = MAX_SCORE / 2;
+const HASHBASE: u32 = 107927;
+
+/// One side of a changed file.
+#[derive(Debug, Clone)]
+pub struct DiffFile {
+    pub path: String,
+    pub mode: String,
+    pub hash: String,
+}
+
+/// A file that differs between two trees. `status` is `A`dded, `D`eleted, `M`odified,
+/// `T`ype changed or `R`enamed, and `score` how similar the sides of a rename are.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub status: char,
+    pub old: Option<DiffFile>,
+    pub new: Option<DiffFile>,
+    pub score: u64,
+}
+
+impl FileChange {
+    /// The path after the change, or before it for deletions.
+    pub fn path(&self) -> &str {
+        &self.new.as_ref().or(self.old.as_ref()).unwrap().path
+    }
+
+    /// The similarity of a rename in percent, as git shows it.
+    pub fn similarity_index(&self) -> u64 {
+        self.score * 100 / MAX_SCORE
+    }
+}
+
+fn is_tree(entry: &TreeEntry) -> bool {
+    entry.mode == "40000"
+}
+
+fn file_type(mode: &str) -> &str {
+    match mode {
+        "120000" => "symlink",
+        "160000" => "gitlink",
+        _ => "file",
+    }
+}
+
+fn entries(tree: Option<&str>) -> Vec<TreeEntry> {
+    tree.map(|hash| Tree::from_hash(hash).entries).unwrap_or_default()
+}
+
+/// Trees sort their entries by name, with a `/` after the names of subtrees.
+fn sort_key(entry: &TreeEntry) -> String {
+    if is_tree(entry) { format!("{}/", entry.name) } else { entry.name.clone() }
+}
+
+fn diff_entries(old: Option<&str>, new: Option<&str>, prefix: &str, pathspec: &Pathspec, changes: &mut Vec<FileChange>) {
+    let (old_entries, new_entries) = (entries(old), entries(new));
+    let (mut old_entries, mut new_entries) = (old_entries.iter().peekable(), new_entries.iter().peekable());
+    loop {
+        let (old_entry, new_entry) = match (old_entries.peek(), new_entries.peek()) {
+            (None, None) => break,
+            (Some(old_entry), Some(new_entry)) => match sort_key(old_entry).cmp(&sort_key(new_entry)) {
+                std::cmp::Ordering::Less => (old_entries.next(), None),
+                std::cmp::Ordering::Greater => (None, new_entries.next()),
+                std::cmp::Ordering::Equal => (old_entries.next(), new_entries.next()),
+            },
+            (Some(_), None) => (old_entries.next(), None),
+            (None, Some(_)) => (None, new_entries.next()),
+        };
+        let entry = old_entry.or(new_entry).unwrap();
+        let path = format!("{}{}", prefix, entry.name);
+        if old_entry.zip(new_entry).is_some_and(|(old_entry, new_entry)| old_entry.hash == new_entry.hash && old_entry.mode == new_entry.mode) {
+            continue;
+        }
+        if is_tree(entry) {
+            if pathspec.matches_dir(&path) {
+                let hash = |entry: Option<&TreeEntry>| entry.map(|entry| entry.hash.clone());
+                diff_entries(hash(old_entry).as_deref(), hash(new_entry).as_deref(), &format!("{}/", path), pathspec, changes);
+            }
+            continue;
+        }
+        if !pathspec.matches(&path) {
+            continue;
+        }
+        let side = |entry: Option<&TreeEntry>| entry.map(|entry| DiffFile { path: path.clone(), mode: entry.mode.clone(), hash: entry.hash.clone() });
+        let status = match (old_entry, new_entry) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(old_entry), Some(new_entry)) if file_type(&old_entry.mode) != file_type(&new_entry.mode) => 'T',
+            _ => 'M',
+        };
+        changes.push(FileChange { status, old: side(old_entry), new: side(new_entry), score: 0 });
+    }
+}
+
+/// Lists the files within `pathspec` that differ between two trees, in tree order. A
+/// missing tree is empty, as for the parent of a root commit.
+pub fn diff_trees(old: Option<&str>, new: Option<&str>, pathspec: &Pathspec) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    if old != new {
+        diff_entries(old, new, "", pathspec, &mut changes);
+    }
+    changes
+}
+
+/// Counts the bytes of content in each chunk of a file, hashed as git's diffcore-delta does.
+/// Chunks end at a newline or after 64 bytes, and in text a CR before a newline is ignored.
+fn hash_chunks(content: &[u8]) -> HashMap<u32, u64> {
+    let is_text = !content[..content.len().min(8000)].contains(&0);
+    let mut chunks = HashMap::new();
+    let (mut accum1, mut accum2, mut length) = (0u32, 0u32, 0u64);
+    for (i, &byte) in content.iter().enumerate() {
+        if is_text && byte == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old_accum1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old_accum1 >> 25);
+        accum1 = accum1.wrapping_add(byte as u32);
+        length += 1;
+        if length < 64 && byte != b'\n' {
+            continue;
+        }
+        *chunks.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE).or_insert(0) += length;
+        (accum1, accum2, length) = (0, 0, 0);
+    }
+    if length > 0 {
+        *chunks.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE).or_insert(0) += length;
+    }
+    chunks
+}
+
+/// How much of `new` comes from `old`, out of `MAX_SCORE`, or 0 when their sizes differ
+/// too much for `new` to be a rename of `old`.
+fn similarity(old: &[u8], new: &[u8]) -> u64 {
+    let (old_size, new_size) = (old.len() as u64, new.len() as u64);
+    let max_size = old_size.max(new_size);
+    if new_size == 0 || max_size * (MAX_SCORE - MIN_SCORE) < (max_size - old_size.min(new_size)) * MAX_SCORE {
+        return 0;
+    }
+    let new_chunks = hash_chunks(new);
+    let copied: u64 = hash_chunks(old).iter().map(|(hash, &count)| count.min(new_chunks.get(hash).copied().unwrap_or(0))).sum();
+    copied * MAX_SCORE / max_size
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Pairs deleted files with added ones whose content is at least half the same, and
+/// replaces each pair with a rename. Identical content pairs first, then the most similar
+/// files, preferring files with the same name. Only additions that `wanted` accepts are
+/// considered as rename destinations.
+pub fn detect_renames(changes: Vec<FileChange>, wanted: impl Fn(&str) -> bool) -> Vec<FileChange> {
+    let sources: Vec<usize> = (0..changes.len()).filter(|&i| changes[i].status == 'D' && file_type(&changes[i].old.as_ref().unwrap().mode) == "file").collect();
+    let destinations: Vec<usize> =
+        (0..changes.len()).filter(|&i| changes[i].status == 'A' && file_type(&changes[i].new.as_ref().unwrap().mode) == "file" && wanted(changes[i].path())).collect();
+    if sources.is_empty() || destinations.is_empty() {
+        return changes;
+    }
+    let old = |i: usize| changes[i].old.as_ref().unwrap();
+    let new = |i: usize| changes[i].new.as_ref().unwrap();
+    let same_name = |source: usize, destination: usize| basename(&old(source).path) == basename(&new(destination).path);
+    // Sources of renames by destination
+    let mut renames: HashMap<usize, (usize, u64)> = HashMap::new();
+    let mut used = vec![false; changes.len()];
+    for &destination in &destinations {
+        let exact = sources.iter().filter(|&&source| !used[source] && old(source).hash == new(destination).hash);
+        let source = exact.clone().find(|&&source| same_name(source, destination)).or(exact.clone().next());
+        if let Some(&source) = source {
+            used[source] = true;
+            renames.insert(destination, (source, MAX_SCORE));
+        }
+    }
+    let mut contents: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut content = |hash: &str| contents.entry(hash.to_string()).or_insert_with(|| Blob::from_hash(hash).content).clone();
+    let mut candidates = Vec::new();
+    for &destination in destinations.iter().filter(|destination| !renames.contains_key(destination)) {
+        let new_content = content(&new(destination).hash);
+        for &source in sources.iter().filter(|&&source| !used[source]) {
+            let score = similarity(&content(&old(source).hash), &new_content);
+            if score >= MIN_SCORE {
+                candidates.push((Reverse(score), Reverse(same_name(source, destination)), destination, source));
+            }
+        }
+    }
+    candidates.sort();
+    for (Reverse(score), _, destination, source) in candidates {
+        if !used[source] && !renames.contains_key(&destination) {
+            used[source] = true;
+            renames.insert(destination, (source, score));
+        }
+    }
+    let mut result = Vec::new();
+    for (i, change) in changes.iter().enumerate() {
+        if let Some(&(source, score)) = renames.get(&i) {
+            result.push(FileChange { status: 'R', old: changes[source].old.clone(), new: change.new.clone(), score });
+        } else if !used[i] {
+            result.push(change.clone());
+        }
+    }
+    result
+}
+
+/// Diffs two trees for `log --follow <path>`, which only looks at the one path. When the
+/// path is added, a deleted file it was renamed from is looked for, and the rename is
+/// returned in place of the addition.
+pub fn diff_followed(old: Option<&str>, new: Option<&str>, path: &str) -> Vec<FileChange> {
+    let changes = diff_trees(old, new, &Pathspec::new(&[path.to_string()]));
+    if !changes.iter().any(|change| change.status == 'A') {
+        return changes;
+    }
+    let all_changes = diff_trees(old, new, &Pathspec::default());
+    match detect_renames(all_changes, |destination| destination == path).into_iter().find(|change| change.status == 'R' && change.path() == path) {
+        Some(rename) => vec![rename],
+        None => changes,
+    }
+}
@@ -0,0 +1,361 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use crate::git::ewah::EwahBitmap;
+use crate::git::object::blob::compute_file_hash;
+
+const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// Untracked directories are reported as `dir/` without recursing into them and empty ones
+/// are still shown, which is git's `DIR_SHOW_OTHER_DIRECTORIES` on its own. git's own status
+/// also hides empty directories, so it rebuilds a cache written by rgit instead of trusting it.
+pub const DIR_SHOW_OTHER_DIRECTORIES: u32 = 1 << 1;
+
+/// The stat fields git keeps for directories and exclude files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StatData {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub device: u32,
+    pub inode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl StatData {
+    /// Stat data of `path`, all zero when it does not exist.
+    pub fn from_path(path: &str) -> Self {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Self::default();
+        };
+        Self {
+            ctime: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            device: metadata.dev() as u32,
+            inode: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let field = |i: usize| u32::from_be_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            ctime: field(0),
+            ctime_nsec: field(1),
+            mtime: field(2),
+            mtime_nsec: field(3),
+            device: field(4),
+            inode: field(5),
+            uid: field(6),
+            gid: field(7),
+            size: field(8),
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [self.ctime, self.ctime_nsec, self.mtime, self.mtime_nsec, self.device, self.inode, self.uid, self.gid, self.size]
+            .iter()
+            .flat_map(|field| field.to_be_bytes())
+            .collect()
+    }
+}
+
+/// Cached untracked entries of one directory. `untracked` holds names relative to the
+/// directory, with untracked directories ending in `/`.
+#[derive(Debug, Default)]
+pub struct UntrackedCacheDir {
+    pub name: String,
+    pub untracked: Vec<String>,
+    pub dirs: Vec<UntrackedCacheDir>,
+    pub valid: bool,
+    pub check_only: bool,
+    pub stat: StatData,
+    pub exclude_hash: String,
+}
+
+impl UntrackedCacheDir {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), exclude_hash: NULL_HASH.to_string(), ..Default::default() }
+    }
+
+    fn count(&self) -> usize {
+        1 + self.dirs.iter().map(|dir| dir.count()).sum::<usize>()
+    }
+
+    fn collect<'a>(&'a self, dirs: &mut Vec<&'a UntrackedCacheDir>) {
+        dirs.push(self);
+        for dir in self.dirs.iter() {
+            dir.collect(dirs);
+        }
+    }
+
+    fn for_each_mut(&mut self, visit: &mut dyn FnMut(&mut UntrackedCacheDir)) {
+        visit(self);
+        for dir in self.dirs.iter_mut() {
+            dir.for_each_mut(visit);
+        }
+    }
+
+    fn write_block(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&encode_varint(self.untracked.len() as u64));
+        bytes.extend_from_slice(&encode_varint(self.dirs.len() as u64));
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.push(b'\0');
+        for name in self.untracked.iter() {
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(b'\0');
+        }
+        for dir in self.dirs.iter() {
+            dir.write_block(bytes);
+        }
+    }
+
+    fn read_block(bytes: &[u8], position: &mut usize) -> Self {
+        let untracked_count = decode_varint(bytes, position);
+        let dirs_count = decode_varint(bytes, position);
+        let mut dir = Self::new(&read_string(bytes, position));
+        for _ in 0..untracked_count {
+            dir.untracked.push(read_string(bytes, position));
+        }
+        for _ in 0..dirs_count {
+            dir.dirs.push(Self::read_block(bytes, position));
+        }
+        dir
+    }
+
+    /// Marks this directory and every directory on the way to `path` as needing a rescan.
+    fn invalidate(&mut self, path: &str) {
+        self.valid = false;
+        let Some((component, rest)) = path.split_once('/') else {
+            return;
+        };
+        if let Some(dir) = self.dirs.iter_mut().find(|dir| dir.name == component) {
+            dir.invalidate(rest);
+        }
+    }
+}
+
+/// The UNTR index extension.
+#[derive(Debug)]
+pub struct UntrackedCache {
+    pub idents: Vec<String>,
+    pub info_exclude_stat: StatData,
+    pub excludes_file_stat: StatData,
+    pub dir_flags: u32,
+    pub info_exclude_hash: String,
+    pub excludes_file_hash: String,
+    pub exclude_per_dir: String,
+    pub root: Option<UntrackedCacheDir>,
+}
+
+/// git's offset varint: seven bits per byte, most significant group first, with each
+/// continuation adding one so there is a single encoding per value.
+fn encode_varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![(value & 127) as u8];
+    loop {
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+        value -= 1;
+        bytes.insert(0, 128 | (value & 127) as u8);
+    }
+    bytes
+}
+
+fn decode_varint(bytes: &[u8], position: &mut usize) -> u64 {
+    let mut c = bytes[*position];
+    *position += 1;
+    let mut value = (c & 127) as u64;
+    while c & 128 != 0 {
+        c = bytes[*position];
+        *position += 1;
+        value = ((value + 1) << 7) + (c & 127) as u64;
+    }
+    value
+}
+
+fn read_string(bytes: &[u8], position: &mut usize) -> String {
+    let len = bytes[*position..].iter().position(|&x| x == b'\0').unwrap();
+    let string = String::from_utf8_lossy(&bytes[*position..*position + len]).to_string();
+    *position += len + 1;
+    string
+}
+
+fn file_hash(path: &str) -> String {
+    if fs::metadata(path).is_ok() { compute_file_hash(path) } else { NULL_HASH.to_string() }
+}
+
+impl UntrackedCache {
+    /// Identifies the worktree location the cache was built for, in git's format.
+    pub fn ident() -> String {
+        let system = fs::read_to_string("/proc/sys/kernel/ostype").map(|s| s.trim().to_string()).unwrap_or_else(|_| env::consts::OS.to_string());
+        format!("Location {}, system {}", env::current_dir().unwrap().display(), system)
+    }
+
+    pub fn new() -> Self {
+        Self {
+            idents: vec![Self::ident()],
+            info_exclude_stat: StatData::from_path(".git/info/exclude"),
+            excludes_file_stat: StatData::default(),
+            dir_flags: DIR_SHOW_OTHER_DIRECTORIES,
+            info_exclude_hash: file_hash(".git/info/exclude"),
+            excludes_file_hash: NULL_HASH.to_string(),
+            exclude_per_dir: ".gitignore".to_string(),
+            root: None,
+        }
+    }
+
+    /// Whether the cache was built by rgit in this location and can be reused.
+    pub fn is_usable(&self) -> bool {
+        self.dir_flags == DIR_SHOW_OTHER_DIRECTORIES && self.idents.contains(&Self::ident())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut position = 0;
+        let ident_len = decode_varint(bytes, &mut position) as usize;
+        let idents = bytes[position..position + ident_len]
+            .split(|&x| x == b'\0')
+            .filter(|ident| !ident.is_empty())
+            .map(|ident| String::from_utf8_lossy(ident).to_string())
+            .collect();
+        position += ident_len;
+        let info_exclude_stat = StatData::from_bytes(&bytes[position..position + 36]);
+        let excludes_file_stat = StatData::from_bytes(&bytes[position + 36..position + 72]);
+        let dir_flags = u32::from_be_bytes(bytes[position + 72..position + 76].try_into().unwrap());
+        let info_exclude_hash = hex::encode(&bytes[position + 76..position + 96]);
+        let excludes_file_hash = hex::encode(&bytes[position + 96..position + 116]);
+        position += 116;
+        let exclude_per_dir = read_string(bytes, &mut position);
+        let mut cache = Self { idents, info_exclude_stat, excludes_file_stat, dir_flags, info_exclude_hash, excludes_file_hash, exclude_per_dir, root: None };
+        let block_count = decode_varint(bytes, &mut position);
+        if block_count == 0 {
+            return cache;
+        }
+        let mut root = UntrackedCacheDir::read_block(bytes, &mut position);
+        let read_bitmap = |position: &mut usize| {
+            let (bitmap, len) = EwahBitmap::from_bytes(&bytes[*position..]);
+            *position += len;
+            bitmap
+        };
+        let valid = read_bitmap(&mut position);
+        let check_only = read_bitmap(&mut position);
+        let hash_valid = read_bitmap(&mut position);
+        // Stat data is stored for valid directories, hashes for directories with an exclude file
+        let mut i = 0;
+        root.for_each_mut(&mut |dir| {
+            dir.check_only = check_only.get(i);
+            if valid.get(i) {
+                dir.valid = true;
+                dir.stat = StatData::from_bytes(&bytes[position..position + 36]);
+                position += 36;
+            }
+            i += 1;
+        });
+        let mut i = 0;
+        root.for_each_mut(&mut |dir| {
+            if hash_valid.get(i) {
+                dir.exclude_hash = hex::encode(&bytes[position..position + 20]);
+                position += 20;
+            }
+            i += 1;
+        });
+        cache.root = Some(root);
+        cache
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let idents: Vec<u8> = self.idents.iter().flat_map(|ident| ident.bytes().chain([b'\0'])).collect();
+        bytes.extend_from_slice(&encode_varint(idents.len() as u64));
+        bytes.extend_from_slice(&idents);
+        bytes.extend_from_slice(&self.info_exclude_stat.to_bytes());
+        bytes.extend_from_slice(&self.excludes_file_stat.to_bytes());
+        bytes.extend_from_slice(&self.dir_flags.to_be_bytes());
+        bytes.extend_from_slice(&hex::decode(&self.info_exclude_hash).unwrap());
+        bytes.extend_from_slice(&hex::decode(&self.excludes_file_hash).unwrap());
+        bytes.extend_from_slice(self.exclude_per_dir.as_bytes());
+        bytes.push(b'\0');
+        let Some(root) = &self.root else {
+            bytes.extend_from_slice(&encode_varint(0));
+            bytes.push(b'\0');
+            return bytes;
+        };
+        bytes.extend_from_slice(&encode_varint(root.count() as u64));
+        root.write_block(&mut bytes);
+        let mut dirs = Vec::new();
+        root.collect(&mut dirs);
+        let bitmap = |bit: &dyn Fn(&UntrackedCacheDir) -> bool| EwahBitmap::new(dirs.iter().map(|dir| bit(dir)).collect()).trimmed().to_bytes();
+        bytes.extend_from_slice(&bitmap(&|dir| dir.valid));
+        bytes.extend_from_slice(&bitmap(&|dir| dir.check_only));
+        bytes.extend_from_slice(&bitmap(&|dir| dir.exclude_hash != NULL_HASH));
+        for dir in dirs.iter().filter(|dir| dir.valid) {
+            bytes.extend_from_slice(&dir.stat.to_bytes());
+        }
+        for dir in dirs.iter().filter(|dir| dir.exclude_hash != NULL_HASH) {
+            bytes.extend_from_slice(&hex::decode(&dir.exclude_hash).unwrap());
+        }
+        bytes.push(b'\0');
+        bytes
+    }
+
+    /// Returns the cached directory for `path` (`.` for the worktree root), creating empty
+    /// invalid entries along the way.
+    pub fn dir_mut(&mut self, path: &str) -> &mut UntrackedCacheDir {
+        let mut dir = self.root.get_or_insert_with(|| UntrackedCacheDir::new(""));
+        if path == "." {
+            return dir;
+        }
+        for component in path.split('/') {
+            let position = match dir.dirs.iter().position(|child| child.name == component) {
+                Some(position) => position,
+                None => {
+                    dir.dirs.push(UntrackedCacheDir::new(component));
+                    dir.dirs.len() - 1
+                }
+            };
+            dir = &mut dir.dirs[position];
+        }
+        dir
+    }
+
+    /// Drops cached directories that are no longer in `keep`, given as full paths.
+    pub fn prune(&mut self, keep: &dyn Fn(&str) -> bool) {
+        fn prune_dir(dir: &mut UntrackedCacheDir, prefix: &str, keep: &dyn Fn(&str) -> bool) {
+            dir.dirs.retain(|child| keep(&format!("{}{}", prefix, child.name)));
+            for child in dir.dirs.iter_mut() {
+                let prefix = format!("{}{}/", prefix, child.name);
+                prune_dir(child, &prefix, keep);
+            }
+        }
+        if let Some(root) = &mut self.root {
+            prune_dir(root, "", keep);
+        }
+    }
+
+    /// Drops every cached directory when the top-level ignore file is not the one the cache
+    /// was built with, since its patterns apply everywhere.
+    pub fn check_exclude_hash(&mut self, gitignore_hash: Option<&str>) {
+        let gitignore_hash = gitignore_hash.unwrap_or(NULL_HASH);
+        if self.root.as_ref().is_some_and(|root| root.exclude_hash != gitignore_hash) {
+            self.root = None;
+        }
+        self.dir_mut(".").exclude_hash = gitignore_hash.to_string();
+    }
+
+    /// Invalidates the directories whose untracked lists change when `path` starts or stops
+    /// being tracked.
+    pub fn invalidate_path(&mut self, path: &str) {
+        if let Some(root) = &mut self.root {
+            root.invalidate(path);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use crate::git::object::blob::{compute_file_hash, Blob};
+
+/// Whether the worktree file at `path` already has the given index mode and blob content.
+/// A submodule only has to have its directory.
+pub fn file_matches(path: &str, mode: u32, hash: &str) -> bool {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return false;
+    };
+    if mode == 0o160000 {
+        return metadata.is_dir();
+    }
+    if mode == 0o120000 {
+        return metadata.file_type().is_symlink()
+            && Blob::from_hash(hash).content == fs::read_link(path).unwrap().to_string_lossy().as_bytes();
+    }
+    let executable = metadata.permissions().mode() & 0o111 != 0;
+    metadata.is_file() && executable == (mode == 0o100755) && compute_file_hash(path) == hash
+}
+
+/// Writes a blob to the worktree with the permissions or symlink type its mode describes.
+/// A submodule's commit isn't an object of this repository, so it only gets an empty
+/// directory, as git leaves for it before the submodule is checked out.
+pub fn write_file(path: &str, mode: u32, hash: &str) {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    if mode == 0o160000 {
+        if !Path::new(path).is_dir() {
+            let _ = fs::remove_file(path);
+            fs::create_dir(path).unwrap();
+        }
+        return;
+    }
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path).unwrap();
+    }
+    let blob = Blob::from_hash(hash);
+    if mode == 0o120000 {
+        symlink(String::from_utf8_lossy(&blob.content).as_ref(), path).unwrap();
+        return;
+    }
+    fs::write(path, &blob.content).unwrap();
+    let permissions = if mode == 0o100755 { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(permissions)).unwrap();
+}
+
+/// Removes a worktree file along with any directories the removal leaves empty.
+pub fn remove_file(path: &str) {
+    if fs::symlink_metadata(path).is_err() {
+        return;
+    }
+    fs::remove_file(path).unwrap();
+    let mut parent = Path::new(path).parent();
+    while let Some(dir) = parent {
+        if dir.as_os_str().is_empty() || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
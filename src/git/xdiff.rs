@@ -0,0 +1,879 @@
+//! Line diffs as git's xdiff makes them, so that patches come out the same: lines that can't
+//! match are set aside first, the rest goes through Myers' algorithm with xdiff's cost
+//! heuristics, and groups of changes are slid to where the indent heuristic likes them.
+
+use std::collections::HashMap;
+
+const MAX_COST_MIN: i64 = 256;
+const HEUR_MIN_COST: i64 = 256;
+const SNAKE_CNT: i64 = 20;
+const K_HEUR: i64 = 4;
+const KPDIS_RUN: i64 = 4;
+const MAX_EQLIMIT: i64 = 1024;
+const SIMSCAN_WINDOW: i64 = 100;
+
+const MAX_INDENT: i64 = 200;
+const MAX_BLANKS: i64 = 20;
+const INDENT_HEURISTIC_MAX_SLIDING: i64 = 100;
+const START_OF_FILE_PENALTY: i64 = 1;
+const END_OF_FILE_PENALTY: i64 = 21;
+const TOTAL_BLANK_WEIGHT: i64 = -30;
+const POST_BLANK_WEIGHT: i64 = 6;
+const RELATIVE_INDENT_PENALTY: i64 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i64 = 10;
+const RELATIVE_OUTDENT_PENALTY: i64 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i64 = 17;
+const RELATIVE_DEDENT_PENALTY: i64 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i64 = 17;
+const INDENT_WEIGHT: i64 = 60;
+
+/// Lines `old_start..old_start + old_count` of the old file replaced by lines
+/// `new_start..new_start + new_count` of the new one, counting from 0.
+#[derive(Debug, Clone, Copy)]
+pub struct Change {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+}
+
+/// Splits content into lines, each with its newline except perhaps the last.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/// A square root approximation, as xdiff uses to set its limits.
+fn bogosqrt(mut n: i64) -> i64 {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// One side of a diff. `classes` numbers lines so that equal lines have equal numbers, and
+/// `changed` has a slot before the first line and after the last, which are never changed.
+struct Side<'a> {
+    lines: Vec<&'a [u8]>,
+    classes: Vec<usize>,
+    changed: Vec<bool>,
+    /// The lines that are left for the diff algorithm, and their classes.
+    kept: Vec<usize>,
+    kept_classes: Vec<usize>,
+}
+
+impl Side<'_> {
+    fn is_changed(&self, line: i64) -> bool {
+        self.changed[(line + 1) as usize]
+    }
+
+    fn set_changed(&mut self, line: i64, changed: bool) {
+        self.changed[(line + 1) as usize] = changed;
+    }
+
+    fn len(&self) -> i64 {
+        self.lines.len() as i64
+    }
+}
+
+/// Whether a line that matches many lines on the other side sits among lines that match
+/// nothing, so that it is better left out of the diff too.
+fn clean_mmatch(dis: &[u8], i: i64, mut start: i64, mut end: i64) -> bool {
+    if i - start > SIMSCAN_WINDOW {
+        start = i - SIMSCAN_WINDOW;
+    }
+    if end - i > SIMSCAN_WINDOW {
+        end = i + SIMSCAN_WINDOW;
+    }
+    let (mut rdis0, mut rpdis0) = (0, 1);
+    let mut r = 1;
+    while i - r >= start {
+        match dis[(i - r) as usize] {
+            0 => rdis0 += 1,
+            2 => rpdis0 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis0 == 0 {
+        return false;
+    }
+    let (mut rdis1, mut rpdis1) = (0, 1);
+    r = 1;
+    while i + r <= end {
+        match dis[(i + r) as usize] {
+            0 => rdis1 += 1,
+            2 => rpdis1 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis1 == 0 {
+        return false;
+    }
+    rdis1 += rdis0;
+    rpdis1 += rpdis0;
+    rpdis1 * KPDIS_RUN < rpdis1 + rdis1
+}
+
+/// Classifies the lines of both sides, skips the common start and end, and sets aside the
+/// lines that have no match on the other side, marking them changed already.
+fn prepare<'a>(old: &'a [u8], new: &'a [u8]) -> (Side<'a>, Side<'a>) {
+    let mut classes: HashMap<&[u8], usize> = HashMap::new();
+    let mut counts: Vec<[i64; 2]> = Vec::new();
+    let mut make_side = |content: &'a [u8], which: usize| {
+        let lines = split_lines(content);
+        let line_classes = lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                let class = *classes.entry(line).or_insert(next);
+                if class == counts.len() {
+                    counts.push([0, 0]);
+                }
+                counts[class][which] += 1;
+                class
+            })
+            .collect();
+        let changed = vec![false; lines.len() + 2];
+        Side { lines, classes: line_classes, changed, kept: Vec::new(), kept_classes: Vec::new() }
+    };
+    let mut side1 = make_side(old, 0);
+    let mut side2 = make_side(new, 1);
+
+    let limit = side1.lines.len().min(side2.lines.len());
+    let start = (0..limit).find(|&i| side1.classes[i] != side2.classes[i]).unwrap_or(limit) as i64;
+    let limit = limit as i64 - start;
+    let (n1, n2) = (side1.len(), side2.len());
+    let end_common = (0..limit).find(|&i| side1.classes[(n1 - 1 - i) as usize] != side2.classes[(n2 - 1 - i) as usize]).unwrap_or(limit);
+    let ends = [n1 - end_common - 1, n2 - end_common - 1];
+
+    for (which, side) in [&mut side1, &mut side2].into_iter().enumerate() {
+        let mlim = bogosqrt(side.len()).min(MAX_EQLIMIT);
+        let other = 1 - which;
+        let end = ends[which];
+        let mut dis = vec![0u8; side.lines.len() + 1];
+        for i in start..=end {
+            let matches = counts[side.classes[i as usize]][other];
+            dis[i as usize] = if matches == 0 {
+                0
+            } else if matches >= mlim {
+                2
+            } else {
+                1
+            };
+        }
+        for i in start..=end {
+            let class = side.classes[i as usize];
+            if dis[i as usize] == 1 || (dis[i as usize] == 2 && !clean_mmatch(&dis, i, start, end)) {
+                side.kept.push(i as usize);
+                side.kept_classes.push(class);
+            } else {
+                side.set_changed(i, true);
+            }
+        }
+    }
+    (side1, side2)
+}
+
+struct Split {
+    i1: i64,
+    i2: i64,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+/// The state of Myers' algorithm: the furthest reaching paths on each diagonal, forwards
+/// and backwards, indexed from `base` so that diagonals can be negative.
+struct Algorithm<'a> {
+    ha1: &'a [usize],
+    ha2: &'a [usize],
+    kvdf: Vec<i64>,
+    kvdb: Vec<i64>,
+    base: i64,
+    mxcost: i64,
+}
+
+impl Algorithm<'_> {
+    fn f(&self, d: i64) -> i64 {
+        self.kvdf[(d + self.base) as usize]
+    }
+
+    fn set_f(&mut self, d: i64, value: i64) {
+        self.kvdf[(d + self.base) as usize] = value;
+    }
+
+    fn b(&self, d: i64) -> i64 {
+        self.kvdb[(d + self.base) as usize]
+    }
+
+    fn set_b(&mut self, d: i64, value: i64) {
+        self.kvdb[(d + self.base) as usize] = value;
+    }
+
+    fn h1(&self, i: i64) -> usize {
+        self.ha1[i as usize]
+    }
+
+    fn h2(&self, i: i64) -> usize {
+        self.ha2[i as usize]
+    }
+
+    /// Finds where to split the box into two smaller diffs: the middle snake, or when that
+    /// costs too much, a good enough point that the heuristics pick.
+    fn split(&mut self, off1: i64, lim1: i64, off2: i64, lim2: i64, need_min: bool) -> Split {
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+        self.set_f(fmid, off1);
+        self.set_b(bmid, lim1);
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_f(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_f(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.f(d - 1) >= self.f(d + 1) { self.f(d - 1) + 1 } else { self.f(d + 1) };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.h1(i1) == self.h2(i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_f(d, i1);
+                if odd && bmin <= d && d <= bmax && self.b(d) <= i1 {
+                    return Split { i1, i2, min_lo: true, min_hi: true };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_b(bmin - 1, i64::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_b(bmax + 1, i64::MAX);
+            } else {
+                bmax -= 1;
+            }
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.b(d - 1) < self.b(d + 1) { self.b(d - 1) } else { self.b(d + 1) - 1 };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.h1(i1 - 1) == self.h2(i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_b(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.f(d) {
+                    return Split { i1, i2, min_lo: true, min_hi: true };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past the heuristic's trigger, a diagonal that got far with a long snake is
+            // good enough to split at
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut split = Split { i1: 0, i2: 0, min_lo: true, min_hi: false };
+                let mut d = fmax;
+                while d >= fmin {
+                    let dd = if d > fmid { d - fmid } else { fmid - d };
+                    let i1 = self.f(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > K_HEUR * ec && v > best && off1 + SNAKE_CNT <= i1 && i1 < lim1 && off2 + SNAKE_CNT <= i2 && i2 < lim2 {
+                        let mut k = 1;
+                        while self.h1(i1 - k) == self.h2(i2 - k) {
+                            if k == SNAKE_CNT {
+                                best = v;
+                                split.i1 = i1;
+                                split.i2 = i2;
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+                    d -= 2;
+                }
+                if best > 0 {
+                    return split;
+                }
+                let mut split = Split { i1: 0, i2: 0, min_lo: false, min_hi: true };
+                let mut d = bmax;
+                while d >= bmin {
+                    let dd = if d > bmid { d - bmid } else { bmid - d };
+                    let i1 = self.b(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > K_HEUR * ec && v > best && off1 < i1 && i1 <= lim1 - SNAKE_CNT && off2 < i2 && i2 <= lim2 - SNAKE_CNT {
+                        let mut k = 0;
+                        while self.h1(i1 + k) == self.h2(i2 + k) {
+                            if k == SNAKE_CNT - 1 {
+                                best = v;
+                                split.i1 = i1;
+                                split.i2 = i2;
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+                    d -= 2;
+                }
+                if best > 0 {
+                    return split;
+                }
+            }
+
+            // Enough is enough: take the furthest reaching path so far
+            if ec >= self.mxcost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.f(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+                let (mut bbest, mut bbest1) = (i64::MAX, i64::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = off1.max(self.b(d));
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split { i1: fbest1, i2: fbest - fbest1, min_lo: true, min_hi: false }
+                } else {
+                    Split { i1: bbest1, i2: bbest - bbest1, min_lo: false, min_hi: true }
+                };
+            }
+            ec += 1;
+        }
+    }
+
+    /// Marks what changed between `off1..lim1` and `off2..lim2` of the kept lines, dividing
+    /// the problem at the middle snake until one side is empty.
+    #[allow(clippy::too_many_arguments)]
+    fn compare(&mut self, mut off1: i64, mut lim1: i64, mut off2: i64, mut lim2: i64, need_min: bool, changed1: &mut [i64], changed2: &mut [i64]) {
+        while off1 < lim1 && off2 < lim2 && self.h1(off1) == self.h2(off2) {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.h1(lim1 - 1) == self.h2(lim2 - 1) {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+        if off1 == lim1 {
+            for i in off2..lim2 {
+                changed2[i as usize] = 1;
+            }
+        } else if off2 == lim2 {
+            for i in off1..lim1 {
+                changed1[i as usize] = 1;
+            }
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(off1, split.i1, off2, split.i2, split.min_lo, changed1, changed2);
+            self.compare(split.i1, lim1, split.i2, lim2, split.min_hi, changed1, changed2);
+        }
+    }
+}
+
+/// How far a line is indented, with tabs to multiples of 8, or -1 for a blank line.
+fn get_indent(line: &[u8]) -> i64 {
+    let mut indent = 0;
+    for &byte in line {
+        if !byte.is_ascii_whitespace() && byte != 0x0b {
+            return indent;
+        }
+        if byte == b' ' {
+            indent += 1;
+        } else if byte == b'\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: i64,
+    pre_blank: i64,
+    pre_indent: i64,
+    post_blank: i64,
+    post_indent: i64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i64,
+    penalty: i64,
+}
+
+fn measure_split(side: &Side, split: i64) -> SplitMeasurement {
+    let (end_of_file, indent) = if split >= side.len() { (true, -1) } else { (false, get_indent(side.lines[split as usize])) };
+    let (mut pre_blank, mut pre_indent) = (0, -1);
+    let mut i = split - 1;
+    while i >= 0 {
+        pre_indent = get_indent(side.lines[i as usize]);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+        i -= 1;
+    }
+    let (mut post_blank, mut post_indent) = (0, -1);
+    let mut i = split + 1;
+    while i < side.len() {
+        post_indent = get_indent(side.lines[i as usize]);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+        i += 1;
+    }
+    SplitMeasurement { end_of_file, indent, pre_blank, pre_indent, post_blank, post_indent }
+}
+
+/// Adds how bad a place to split a group of changes is, with the weights git found
+/// empirically.
+fn score_add_split(m: &SplitMeasurement, score: &mut SplitScore) {
+    if m.pre_indent == -1 && m.pre_blank == 0 {
+        score.penalty += START_OF_FILE_PENALTY;
+    }
+    if m.end_of_file {
+        score.penalty += END_OF_FILE_PENALTY;
+    }
+    let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+    let total_blank = m.pre_blank + post_blank;
+    score.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+    score.penalty += POST_BLANK_WEIGHT * post_blank;
+    let indent = if m.indent != -1 { m.indent } else { m.post_indent };
+    let any_blanks = total_blank != 0;
+    score.effective_indent += indent;
+    if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+        // Nothing more to weigh
+    } else if indent > m.pre_indent {
+        score.penalty += if any_blanks { RELATIVE_INDENT_WITH_BLANK_PENALTY } else { RELATIVE_INDENT_PENALTY };
+    } else if m.post_indent != -1 && m.post_indent > indent {
+        score.penalty += if any_blanks { RELATIVE_OUTDENT_WITH_BLANK_PENALTY } else { RELATIVE_OUTDENT_PENALTY };
+    } else {
+        score.penalty += if any_blanks { RELATIVE_DEDENT_WITH_BLANK_PENALTY } else { RELATIVE_DEDENT_PENALTY };
+    }
+}
+
+fn score_cmp(s1: &SplitScore, s2: &SplitScore) -> i64 {
+    let cmp_indents = (s1.effective_indent > s2.effective_indent) as i64 - ((s1.effective_indent < s2.effective_indent) as i64);
+    INDENT_WEIGHT * cmp_indents + (s1.penalty - s2.penalty)
+}
+
+/// A run of changed lines, `start..end`, or an empty one above line `start`.
+#[derive(Clone, Copy)]
+struct Group {
+    start: i64,
+    end: i64,
+}
+
+impl Group {
+    fn first(side: &Side) -> Self {
+        let mut end = 0;
+        while side.is_changed(end) {
+            end += 1;
+        }
+        Self { start: 0, end }
+    }
+
+    fn next(&mut self, side: &Side) -> bool {
+        if self.end == side.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while side.is_changed(self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, side: &Side) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while side.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, side: &mut Side) -> bool {
+        if self.end < side.len() && side.classes[self.start as usize] == side.classes[self.end as usize] {
+            side.set_changed(self.start, false);
+            side.set_changed(self.end, true);
+            self.start += 1;
+            self.end += 1;
+            while side.is_changed(self.end) {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, side: &mut Side) -> bool {
+        if self.start > 0 && side.classes[(self.start - 1) as usize] == side.classes[(self.end - 1) as usize] {
+            self.start -= 1;
+            self.end -= 1;
+            side.set_changed(self.start, true);
+            side.set_changed(self.end, false);
+            while side.is_changed(self.start - 1) {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slides each group of changes in `side` up and down as far as it goes, merging groups
+/// that meet, then settles it where it lines up with a change on the `other` side, or
+/// failing that where the indent heuristic scores it best.
+fn change_compact(side: &mut Side, other: &Side) {
+    let mut g = Group::first(side);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            let mut groupsize;
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                groupsize = g.end - g.start;
+                end_matching_other = -1;
+                while g.slide_up(side) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = g.end;
+                }
+                loop {
+                    if !g.slide_down(side) {
+                        break;
+                    }
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = g.end;
+                    }
+                }
+                if groupsize == g.end - g.start {
+                    break;
+                }
+            }
+            if g.end == earliest_end {
+                // It can't move
+            } else if end_matching_other != -1 {
+                while go.end == go.start {
+                    g.slide_up(side);
+                    go.previous(other);
+                }
+            } else {
+                let mut shift = earliest_end.max(g.end - groupsize - 1).max(g.end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best_shift = -1;
+                let mut best_score = SplitScore::default();
+                while shift <= g.end {
+                    let mut score = SplitScore::default();
+                    score_add_split(&measure_split(side, shift), &mut score);
+                    score_add_split(&measure_split(side, shift - groupsize), &mut score);
+                    if best_shift == -1 || score_cmp(&score, &best_score) <= 0 {
+                        best_score = score;
+                        best_shift = shift;
+                    }
+                    shift += 1;
+                }
+                while g.end > best_shift {
+                    g.slide_up(side);
+                    go.previous(other);
+                }
+            }
+        }
+        if !g.next(side) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+/// Diffs two files line by line, as git does by default.
+pub fn diff<'a>(old: &'a [u8], new: &'a [u8]) -> (Vec<&'a [u8]>, Vec<&'a [u8]>, Vec<Change>) {
+    let (mut side1, mut side2) = prepare(old, new);
+    let (n1, n2) = (side1.kept.len() as i64, side2.kept.len() as i64);
+    let ndiags = n1 + n2 + 3;
+    let mut algorithm = Algorithm {
+        ha1: &side1.kept_classes,
+        ha2: &side2.kept_classes,
+        kvdf: vec![0; ndiags as usize],
+        kvdb: vec![0; ndiags as usize],
+        base: n2 + 1,
+        mxcost: bogosqrt(ndiags).max(MAX_COST_MIN),
+    };
+    let mut changed1 = vec![0; n1 as usize];
+    let mut changed2 = vec![0; n2 as usize];
+    algorithm.compare(0, n1, 0, n2, false, &mut changed1, &mut changed2);
+    for (i, _) in changed1.iter().enumerate().filter(|(_, &changed)| changed == 1) {
+        let line = side1.kept[i] as i64;
+        side1.set_changed(line, true);
+    }
+    for (i, _) in changed2.iter().enumerate().filter(|(_, &changed)| changed == 1) {
+        let line = side2.kept[i] as i64;
+        side2.set_changed(line, true);
+    }
+    change_compact(&mut side1, &side2);
+    change_compact(&mut side2, &side1);
+
+    let mut changes = Vec::new();
+    let (mut i1, mut i2) = (side1.len(), side2.len());
+    while i1 >= 0 || i2 >= 0 {
+        if side1.is_changed(i1 - 1) || side2.is_changed(i2 - 1) {
+            let (l1, l2) = (i1, i2);
+            while side1.is_changed(i1 - 1) {
+                i1 -= 1;
+            }
+            while side2.is_changed(i2 - 1) {
+                i2 -= 1;
+            }
+            changes.push(Change { old_start: i1 as usize, old_count: (l1 - i1) as usize, new_start: i2 as usize, new_count: (l2 - i2) as usize });
+        }
+        i1 -= 1;
+        i2 -= 1;
+    }
+    changes.reverse();
+    (side1.lines, side2.lines, changes)
+}
+
+/// One line of a hunk: `origin` is ` `, `-` or `+`, and `text` has no newline. A line
+/// without one at the end of its file is followed by git's `\ No newline at end of file`.
+pub struct HunkLine {
+    pub origin: char,
+    pub text: String,
+    pub missing_newline: bool,
+}
+
+/// A hunk of a unified diff. `header` is the `@@ -a,b +c,d @@` range and `function` the
+/// line before the hunk that looks like the start of a function, if any.
+pub struct Hunk {
+    pub header: String,
+    pub function: String,
+    pub lines: Vec<HunkLine>,
+}
+
+fn hunk_line(origin: char, line: &[u8]) -> HunkLine {
+    let missing_newline = !line.ends_with(b"\n");
+    let text = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line)).to_string();
+    HunkLine { origin, text, missing_newline }
+}
+
+/// The function name git puts after a hunk's range by default: a line that starts with a
+/// letter, `_` or `$`, cut to 80 bytes and without trailing whitespace.
+fn function_name(line: &[u8]) -> Option<String> {
+    let first = *line.first()?;
+    if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+        return None;
+    }
+    let line = &line[..line.len().min(80)];
+    let end = line.iter().rposition(|byte| !byte.is_ascii_whitespace()).map_or(0, |position| position + 1);
+    Some(String::from_utf8_lossy(&line[..end]).to_string())
+}
+
+fn range(start: usize, count: usize) -> String {
+    let start = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+/// Turns changes into unified diff hunks with `context` lines around each, joining hunks
+/// whose context would meet, the way xdiff emits them.
+pub fn hunks(old: &[&[u8]], new: &[&[u8]], changes: &[Change], context: usize) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let mut function = String::new();
+    let mut function_limit: i64 = -1;
+    let mut first = 0;
+    while first < changes.len() {
+        // The hunk takes in the changes that are at most two contexts apart
+        let mut last = first;
+        while last + 1 < changes.len() && changes[last + 1].old_start - (changes[last].old_start + changes[last].old_count) <= 2 * context {
+            last += 1;
+        }
+        let (start, end) = (changes[first], changes[last]);
+        let s1 = start.old_start.saturating_sub(context);
+        let s2 = start.new_start.saturating_sub(context);
+        let after = context.min(old.len() - (end.old_start + end.old_count)).min(new.len() - (end.new_start + end.new_count));
+        let e1 = end.old_start + end.old_count + after;
+        let e2 = end.new_start + end.new_count + after;
+
+        let mut l = s1 as i64 - 1;
+        while l != function_limit && l >= 0 && (l as usize) < old.len() {
+            if let Some(name) = function_name(old[l as usize]) {
+                function = name;
+                break;
+            }
+            l -= if s1 as i64 - 1 > function_limit { 1 } else { -1 };
+        }
+        function_limit = s1 as i64 - 1;
+
+        let header = format!("@@ -{} +{} @@", range(s1, e1 - s1), range(s2, e2 - s2));
+        let room = 127usize.saturating_sub(header.len() + 1);
+        let mut function = function.clone();
+        if function.len() > room {
+            let mut cut = room;
+            while !function.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            function.truncate(cut);
+        }
+        let mut lines = Vec::new();
+        let mut line2 = s2;
+        for change in &changes[first..=last] {
+            while line2 < change.new_start {
+                lines.push(hunk_line(' ', new[line2]));
+                line2 += 1;
+            }
+            lines.extend(old[change.old_start..change.old_start + change.old_count].iter().map(|line| hunk_line('-', line)));
+            lines.extend(new[change.new_start..change.new_start + change.new_count].iter().map(|line| hunk_line('+', line)));
+            line2 = change.new_start + change.new_count;
+        }
+        lines.extend(new[line2..e2].iter().map(|line| hunk_line(' ', line)));
+        hunks.push(Hunk { header, function, lines });
+        first = last + 1;
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hunks of a diff from `old` to `new` as `git diff` prints them.
+    fn unified(old: &str, new: &str) -> String {
+        let (old_lines, new_lines, changes) = diff(old.as_bytes(), new.as_bytes());
+        let mut output = String::new();
+        for hunk in hunks(&old_lines, &new_lines, &changes, 3) {
+            output.push_str(&hunk.header);
+            if !hunk.function.is_empty() {
+                output.push_str(&format!(" {}", hunk.function));
+            }
+            output.push('\n');
+            for line in hunk.lines {
+                output.push_str(&format!("{}{}\n", line.origin, line.text));
+                if line.missing_newline {
+                    output.push_str("\\ No newline at end of file\n");
+                }
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn splits_lines_keeping_newlines() {
+        assert_eq!(split_lines(b"a\nb\n"), [b"a\n".as_slice(), b"b\n"]);
+        assert_eq!(split_lines(b"a\nb"), [b"a\n".as_slice(), b"b"]);
+        assert!(split_lines(b"").is_empty());
+    }
+
+    #[test]
+    fn slides_insertions_as_the_indent_heuristic_does() {
+        // Expected output from git 2.39's `diff --no-index`
+        let old = "int a(void)\n{\n\treturn 1;\n}\n\nint b(void)\n{\n\treturn 2;\n}\n";
+        let new = "int a(void)\n{\n\treturn 1;\n}\n\nint c(void)\n{\n\treturn 3;\n}\n\nint b(void)\n{\n\treturn 2;\n}\n";
+        assert_eq!(unified(old, new), concat!(
+            "@@ -3,6 +3,11 @@ int a(void)\n",
+            " \treturn 1;\n }\n \n+int c(void)\n+{\n+\treturn 3;\n+}\n+\n int b(void)\n {\n \treturn 2;\n",
+        ));
+    }
+
+    #[test]
+    fn splits_distant_changes_into_hunks_with_function_names() {
+        let old = "x\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\nfunc here\n11\n12\n13\n14\ny";
+        let new = "X\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\nfunc here\n11\n12\n13\n14\nz";
+        assert_eq!(unified(old, new), concat!(
+            "@@ -1,4 +1,4 @@\n-x\n+X\n 1\n 2\n 3\n",
+            "@@ -14,4 +14,4 @@ func here\n 12\n 13\n 14\n-y\n\\ No newline at end of file\n+z\n\\ No newline at end of file\n",
+        ));
+    }
+
+    #[test]
+    fn prefers_the_same_matches_as_git() {
+        assert_eq!(unified("a\nb\nc\n", "c\nb\na\nb\nc\n"), "@@ -1,3 +1,5 @@\n+c\n+b\n a\n b\n c\n");
+        let (_, _, changes) = diff(b"same\n", b"same\n");
+        assert!(changes.is_empty());
+        let (_, _, changes) = diff(b"", b"new\n");
+        assert_eq!((changes[0].old_count, changes[0].new_start, changes[0].new_count), (0, 0, 1));
+    }
+
+    #[test]
+    fn ranges_count_from_one_unless_empty() {
+        assert_eq!(range(0, 0), "0,0");
+        assert_eq!(range(4, 1), "5");
+        assert_eq!(range(4, 3), "5,3");
+    }
+}
@@ -23,6 +23,42 @@ fn main() {
         command::read_tree::read_tree(&args);
     } else if args[1] == "diff" {
         command::diff::diff(&args);
+    } else if args[1] == "restore" {
+        command::restore::restore(&args);
+    } else if args[1] == "fsmonitor--daemon" {
+        command::fsmonitor_daemon::fsmonitor_daemon(&args);
+    } else if args[1] == "sparse-checkout" {
+        command::sparse_checkout::sparse_checkout(&args);
+    } else if args[1] == "rev-parse" {
+        command::rev_parse::rev_parse(&args);
+    } else if args[1] == "branch" {
+        command::branch::branch(&args);
+    } else if args[1] == "tag" {
+        command::tag::tag(&args);
+    } else if args[1] == "reflog" {
+        command::reflog::reflog(&args);
+    } else if args[1] == "update-ref" {
+        command::update_ref::update_ref(&args);
+    } else if args[1] == "for-each-ref" {
+        command::for_each_ref::for_each_ref(&args);
+    } else if args[1] == "write-tree" {
+        command::write_tree::write_tree(&args);
+    } else if args[1] == "commit-tree" {
+        command::commit_tree::commit_tree(&args);
+    } else if args[1] == "commit" {
+        command::commit::commit(&args);
+    } else if args[1] == "var" {
+        command::var::var(&args);
+    } else if args[1] == "verify-commit" {
+        command::verify_commit::verify_commit(&args);
+    } else if args[1] == "verify-tag" {
+        command::verify_tag::verify_tag(&args);
+    } else if args[1] == "interpret-trailers" {
+        command::interpret_trailers::interpret_trailers(&args);
+    } else if args[1] == "show" {
+        command::show::show(&args);
+    } else if args[1] == "rev-list" {
+        command::rev_list::rev_list(&args);
     } else {
         println!("Unknown command: {}", args[1]);
         process::exit(1);
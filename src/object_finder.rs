@@ -1,5 +1,29 @@
+use std::fs;
+use std::path::Path;
+
 pub fn find_object_path(hash: &str) -> String {
     let path = format!(".git/objects/{}/{}", &hash[0..2], &hash[2..]);
 
     path
 }
+
+pub fn object_exists(hash: &str) -> bool {
+    Path::new(&find_object_path(hash)).is_file()
+}
+
+/// Lists the ids of every loose object starting with `prefix`, which must be at least two
+/// hex digits long.
+pub fn find_objects_by_prefix(prefix: &str) -> Vec<String> {
+    let prefix = prefix.to_ascii_lowercase();
+    let entries = match fs::read_dir(format!(".git/objects/{}", &prefix[0..2])) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut hashes: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| format!("{}{}", &prefix[0..2], entry.file_name().to_string_lossy()))
+        .filter(|hash| hash.len() == 40 && hash.starts_with(&prefix))
+        .collect();
+    hashes.sort();
+    hashes
+}